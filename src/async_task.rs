@@ -0,0 +1,173 @@
+//! # Async Compression and Extraction
+//! Compresses and extracts archives from async code without blocking the runtime. The work runs on a dedicated
+//! thread while the caller awaits the result, and progress arrives as a `Stream` instead of a callback.
+//! Nothing here depends on a particular runtime, so it works the same under tokio, async-std or a plain executor.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::writer::LZMATarballWriter;
+//!
+//! async fn backup() -> anyhow::Result<()> {
+//!     let mut writer = LZMATarballWriter::new();
+//!     writer.with_path("./", "/")?.set_output("archive.tar.xz");
+//!     // `progress` is a `Stream` of updates, e.g. polled with `StreamExt::next` on another task
+//!     let (compression, _progress) = writer.compress_async();
+//!     let result = compression.await?;
+//!     println!("Compressed to {} bytes", result.size);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Extraction works the same way with `LZMATarballReader::decompress_async`.
+
+#[cfg(feature = "decompression")]
+use crate::reader::{DecompressionProgress, DecompressionResult, LZMATarballReader};
+#[cfg(feature = "compression")]
+use crate::writer::{LZMACallbackResult, LZMAResult, LZMATarballWriter};
+use anyhow::Result;
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// Work running on its own thread, resolving to its result.
+#[must_use = "the result is lost unless it is awaited"]
+pub struct AsyncTask<T> {
+    shared: Arc<Mutex<Outcome<T>>>,
+}
+
+/// The progress updates of an `AsyncTask`, ending when it finishes.
+pub struct ProgressStream<P> {
+    shared: Arc<Mutex<Progress<P>>>,
+}
+
+/// A compression started by `LZMATarballWriter::compress_async`
+#[cfg(feature = "compression")]
+pub type AsyncCompression = AsyncTask<LZMAResult>;
+/// An extraction started by `LZMATarballReader::decompress_async`
+#[cfg(feature = "decompression")]
+pub type AsyncDecompression = AsyncTask<DecompressionResult>;
+
+struct Outcome<T> {
+    result: Option<Result<T>>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+struct Progress<P> {
+    updates: VecDeque<P>,
+    finished: bool,
+    /// Set when the progress stream is dropped, so updates nobody will read aren't kept
+    dropped: bool,
+    waker: Option<Waker>,
+}
+
+impl<P> Progress<P> {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Runs `work` on a dedicated thread, forwarding the progress it reports to the returned stream.
+fn spawn<T, P, W>(work: W) -> (AsyncTask<T>, ProgressStream<P>)
+where
+    T: Send + 'static,
+    P: Send + 'static,
+    W: FnOnce(Box<dyn Fn(P) + Send + Sync>) -> Result<T> + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Outcome { result: None, finished: false, waker: None }));
+    let progress = Arc::new(Mutex::new(Progress { updates: VecDeque::new(), finished: false, dropped: false, waker: None }));
+    let (result, updates) = (shared.clone(), progress.clone());
+    std::thread::spawn(move || {
+        let report = updates.clone();
+        let outcome = work(Box::new(move |update| {
+            let mut progress = report.lock().unwrap();
+            if !progress.dropped {
+                progress.updates.push_back(update);
+                progress.wake();
+            }
+        }));
+        let mut progress = updates.lock().unwrap();
+        progress.finished = true;
+        progress.wake();
+        drop(progress);
+        let mut shared = result.lock().unwrap();
+        shared.result = Some(outcome);
+        shared.finished = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+    (AsyncTask { shared }, ProgressStream { shared: progress })
+}
+
+#[cfg(feature = "compression")]
+impl LZMATarballWriter {
+    /// Starts compressing on a dedicated thread, see `compress`. Returns a future resolving to the result
+    /// and a stream of the progress updates, which may be dropped if progress isn't needed.
+    pub fn compress_async(&self) -> (AsyncCompression, ProgressStream<LZMACallbackResult>) {
+        debug!("Starting async compression");
+        let writer = self.clone();
+        spawn(move |report| writer.compress(report))
+    }
+}
+
+#[cfg(feature = "decompression")]
+impl LZMATarballReader {
+    /// Starts extracting on a dedicated thread, see `decompress_with_callback`. Returns a future resolving to the result
+    /// and a stream of the progress updates, which may be dropped if progress isn't needed.
+    pub fn decompress_async(&self) -> (AsyncDecompression, ProgressStream<DecompressionProgress>) {
+        debug!("Starting async decompression");
+        let reader = self.clone();
+        spawn(move |report| reader.decompress_with_callback(report))
+    }
+}
+
+impl<T> Future for AsyncTask<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None if shared.finished => Poll::Ready(Err(anyhow::anyhow!("The result was already taken"))),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<P> Stream for ProgressStream<P> {
+    type Item = P;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut progress = self.shared.lock().unwrap();
+        if let Some(update) = progress.updates.pop_front() {
+            return Poll::Ready(Some(update));
+        }
+        if progress.finished {
+            return Poll::Ready(None);
+        }
+        progress.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<P> Drop for ProgressStream<P> {
+    fn drop(&mut self) {
+        let mut progress = self.shared.lock().unwrap();
+        progress.dropped = true;
+        progress.updates.clear();
+        progress.waker = None;
+    }
+}
@@ -0,0 +1,117 @@
+//! # Compression Level Benchmark
+//! `suggest_level` compresses a representative sample of a dataset at increasing compression levels
+//! and reports the measured ratio and speed of each, so tools can pick a level per dataset instead of hardcoding one.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::benchmark::suggest_level;
+//! use lzma_tarball::writer::LZMATarballWriter;
+//! use std::time::Duration;
+//!
+//! let suggestion = suggest_level(&["data/sample.log", "data/sample.db"], Duration::from_secs(5)).unwrap();
+//! for measurement in &suggestion.measurements {
+//!     println!("Level {}: {:.2} ratio at {}B/s", measurement.level, measurement.ratio, measurement.bytes_per_second);
+//! }
+//! LZMATarballWriter::new().set_compression_level(suggestion.level);
+//! ```
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use xz2::write::XzEncoder;
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// At most this many bytes of the sample are compressed at each level
+const SAMPLE_LIMIT: u64 = 16 * 1024 * 1024;
+/// Ratios closer than this are considered equal, so the faster level is suggested
+const RATIO_TOLERANCE: f32 = 0.01;
+
+/// The result of compressing the sample at a single level
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelMeasurement {
+    pub level: u8,
+    /// The compressed size divided by the sample size, lower is better
+    pub ratio: f32,
+    /// The number of sample bytes compressed per second
+    pub bytes_per_second: u64,
+    pub elapsed_time: Duration,
+}
+
+/// The level suggested by `suggest_level` along with the measurements it is based on
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelSuggestion {
+    pub level: u8,
+    /// The measured levels in increasing order
+    pub measurements: Vec<LevelMeasurement>,
+}
+
+/// Compresses the sample at levels 0 to 9 until `time_budget` is spent and suggests the level with the best ratio.
+/// A higher level is only suggested if it improves the ratio by more than 0.01 over the best lower level.
+/// At least level 0 is always measured, and at most 16MB of the sample is used.
+///
+/// # Parameters
+/// - `sample_paths`: Files representative of the data that will be archived
+/// - `time_budget`: The time the benchmark may take, levels that don't fit are not measured
+pub fn suggest_level(sample_paths: &[impl AsRef<Path>], time_budget: Duration) -> Result<LevelSuggestion> {
+    let mut sample = Vec::new();
+    for path in sample_paths {
+        let path = path.as_ref();
+        let remaining = SAMPLE_LIMIT - sample.len() as u64;
+        File::open(path)
+            .and_then(|file| file.take(remaining).read_to_end(&mut sample))
+            .with_context(|| format!("Failed to read sample {:?}", path))?;
+    }
+    if sample.is_empty() {
+        error!("The sample is empty");
+        bail!("The sample is empty");
+    }
+    debug!("Benchmarking compression levels with a {} byte sample", sample.len());
+
+    let start = Instant::now();
+    let mut measurements: Vec<LevelMeasurement> = Vec::new();
+    for level in 0..=9u8 {
+        let measurement = measure(&sample, level)?;
+        debug!(
+            "Level {}: ratio {:.3}, {} bytes/s",
+            measurement.level, measurement.ratio, measurement.bytes_per_second
+        );
+        let elapsed_time = measurement.elapsed_time;
+        measurements.push(measurement);
+        // Higher levels are slower, so stop once the next level likely doesn't fit the budget
+        if start.elapsed() + elapsed_time > time_budget {
+            break;
+        }
+    }
+
+    let mut suggested = &measurements[0];
+    for measurement in &measurements[1..] {
+        if measurement.ratio < suggested.ratio - RATIO_TOLERANCE {
+            suggested = measurement;
+        }
+    }
+    info!("Suggested compression level: {}", suggested.level);
+    Ok(LevelSuggestion {
+        level: suggested.level,
+        measurements,
+    })
+}
+
+/// Compresses `sample` in memory at `level`
+fn measure(sample: &[u8], level: u8) -> Result<LevelMeasurement> {
+    let start = Instant::now();
+    let mut encoder = XzEncoder::new(Vec::new(), level as u32);
+    encoder.write_all(sample)?;
+    let compressed = encoder.finish()?;
+    let elapsed_time = start.elapsed();
+    Ok(LevelMeasurement {
+        level,
+        ratio: compressed.len() as f32 / sample.len() as f32,
+        bytes_per_second: (sample.len() as f64 / elapsed_time.as_secs_f64().max(f64::EPSILON)) as u64,
+        elapsed_time,
+    })
+}
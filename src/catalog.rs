@@ -0,0 +1,207 @@
+//! # Catalog Archives
+//! A catalog archive contains the headers of every file but none of their content.
+//! The original size and SHA-256 hash of each file are stored in pax attributes,
+//! so a catalog can be used to verify or diff a directory tree without shipping the data itself.
+
+/// The pax attribute holding the original size of a catalog entry.
+pub const CATALOG_SIZE_KEY: &str = "LZMATARBALL.catalog.size";
+/// The pax attribute holding the hex encoded SHA-256 hash of a catalog entry.
+pub const CATALOG_SHA256_KEY: &str = "LZMATARBALL.catalog.sha256";
+
+#[cfg(feature = "decompression")]
+pub use verify::{verify_tree, Difference, VerificationReport};
+#[cfg(feature = "decompression")]
+pub(crate) use verify::compare_tree;
+
+#[cfg(feature = "decompression")]
+mod verify {
+    use super::{CATALOG_SHA256_KEY, CATALOG_SIZE_KEY};
+    use crate::hash::HashingReader;
+    use crate::manifest::MANIFEST_FILE_NAME;
+    use crate::reader::LZMATarballReader;
+    use anyhow::{Context, Result};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fs::File;
+    use std::io;
+    use std::path::{Component, Path, PathBuf};
+
+    #[cfg(feature = "log")]
+    use crate::logging::*;
+    #[cfg(not(feature = "log"))]
+    use crate::*;
+
+    /// The differences between a directory tree and a catalog.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct VerificationReport {
+        /// Files in the catalog that don't exist in the tree.
+        pub missing: Vec<String>,
+        /// Files whose size or content differ from the catalog.
+        pub changed: Vec<String>,
+        /// Files in the tree that aren't in the catalog.
+        pub extra: Vec<String>,
+    }
+
+    impl VerificationReport {
+        /// Returns whether the tree matches the catalog exactly.
+        pub fn is_clean(&self) -> bool {
+            self.missing.is_empty() && self.changed.is_empty() && self.extra.is_empty()
+        }
+    }
+
+    /// A single difference between an archive and a directory tree, see `LZMATarballReader::compare_with_dir`.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(tag = "kind", rename_all = "snake_case")]
+    pub enum Difference {
+        /// The file is in the archive but not in the tree.
+        Missing { path: String },
+        /// The path exists in the tree but isn't a regular file.
+        NotAFile { path: String },
+        /// The file sizes differ.
+        SizeDiffers { path: String, archived: u64, on_disk: u64 },
+        /// The sizes match but the SHA-256 hashes differ.
+        HashDiffers { path: String },
+        /// The file is in the tree but not in the archive.
+        Extra { path: String },
+    }
+
+    impl Difference {
+        /// Returns the path of the file, relative to the tree and using `/` as separator.
+        pub fn path(&self) -> &str {
+            match self {
+                Difference::Missing { path }
+                | Difference::NotAFile { path }
+                | Difference::SizeDiffers { path, .. }
+                | Difference::HashDiffers { path }
+                | Difference::Extra { path } => path,
+            }
+        }
+    }
+
+    /// The expected state of a file according to the catalog.
+    struct Expected {
+        size: u64,
+        sha256: String,
+    }
+
+    /// Compares the files in `dir` against a catalog archive.
+    /// Regular archives can be used as well, in which case the hashes are computed from the archived content.
+    pub fn verify_tree(catalog: impl AsRef<Path>, dir: impl AsRef<Path>) -> Result<VerificationReport> {
+        let dir = dir.as_ref();
+        info!("Verifying {:?} against catalog {:?}", dir, catalog.as_ref());
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(catalog)?;
+
+        let mut report = VerificationReport::default();
+        for difference in compare_tree(&reader, dir)? {
+            match difference {
+                Difference::Missing { path } => report.missing.push(path),
+                Difference::Extra { path } => report.extra.push(path),
+                Difference::NotAFile { path } | Difference::SizeDiffers { path, .. } | Difference::HashDiffers { path } => {
+                    report.changed.push(path)
+                }
+            }
+        }
+        info!(
+            "Verification complete: {} missing, {} changed, {} extra",
+            report.missing.len(),
+            report.changed.len(),
+            report.extra.len()
+        );
+        Ok(report)
+    }
+
+    /// Compares the files in `dir` against the archive of `reader`.
+    /// Differences are ordered by path, files in the tree that aren't in the archive last.
+    pub(crate) fn compare_tree(reader: &LZMATarballReader, dir: &Path) -> Result<Vec<Difference>> {
+        let (expected, other) = read_catalog(reader)?;
+
+        let mut differences = Vec::new();
+        for (path, expected) in &expected {
+            let file = dir.join(path);
+            let path = path.to_string_lossy().replace('\\', "/");
+            match file.symlink_metadata() {
+                Ok(metadata) if metadata.is_file() => {
+                    if metadata.len() != expected.size {
+                        debug!("Changed file size: {:?}", path);
+                        differences.push(Difference::SizeDiffers { path, archived: expected.size, on_disk: metadata.len() });
+                    } else if hash_file(&file)? != expected.sha256 {
+                        debug!("Changed file content: {:?}", path);
+                        differences.push(Difference::HashDiffers { path });
+                    }
+                }
+                Ok(_) => differences.push(Difference::NotAFile { path }),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    debug!("Missing file: {:?}", path);
+                    differences.push(Difference::Missing { path });
+                }
+                Err(e) => return Err(e).with_context(|| format!("Failed to read metadata of {:?}", file)),
+            }
+        }
+
+        let mut extra = Vec::new();
+        for entry in walkdir::WalkDir::new(dir).min_depth(1) {
+            let entry = entry.context("Failed to walk directory")?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+            if !expected.contains_key(relative) && !other.contains(relative) {
+                extra.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+        extra.sort();
+        differences.extend(extra.into_iter().map(|path| Difference::Extra { path }));
+        Ok(differences)
+    }
+
+    /// Reads the expected size and hash of every file in the catalog, later entries shadowing earlier ones.
+    /// Directories and links are returned separately as they are not compared, but must not be reported as extra either.
+    fn read_catalog(reader: &LZMATarballReader) -> Result<(BTreeMap<PathBuf, Expected>, BTreeSet<PathBuf>)> {
+        let mut archive = reader.get_archive()?;
+        let mut expected = BTreeMap::new();
+        let mut other = BTreeSet::new();
+        for entry in archive.entries().context("Failed to get entries from catalog")? {
+            let mut entry = entry.context("Failed to read catalog entry")?;
+            let path: PathBuf = entry
+                .path()
+                .context("Failed to read catalog entry path")?
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect();
+            if path.as_os_str().is_empty() || path == Path::new(MANIFEST_FILE_NAME) || !entry.header().entry_type().is_file() {
+                other.insert(path);
+                continue;
+            }
+            let mut size = None;
+            let mut sha256 = None;
+            if let Some(extensions) = entry.pax_extensions().context("Failed to read pax attributes")? {
+                for extension in extensions {
+                    let extension = extension.context("Failed to read pax attribute")?;
+                    match extension.key() {
+                        Ok(CATALOG_SIZE_KEY) => size = extension.value().ok().and_then(|value| value.parse().ok()),
+                        Ok(CATALOG_SHA256_KEY) => sha256 = extension.value().ok().map(str::to_string),
+                        _ => {}
+                    }
+                }
+            }
+            let expected_file = match (size, sha256) {
+                (Some(size), Some(sha256)) => Expected { size, sha256 },
+                _ => {
+                    let mut content = HashingReader::new(&mut entry);
+                    io::copy(&mut content, &mut io::sink()).context("Failed to read archived file")?;
+                    let (size, sha256) = content.finish();
+                    Expected { size, sha256 }
+                }
+            };
+            expected.insert(path, expected_file);
+        }
+        Ok((expected, other))
+    }
+
+    fn hash_file(path: &Path) -> Result<String> {
+        let mut file = HashingReader::new(File::open(path).with_context(|| format!("Failed to open {:?}", path))?);
+        io::copy(&mut file, &mut io::sink()).with_context(|| format!("Failed to read {:?}", path))?;
+        Ok(file.finish().1)
+    }
+}
@@ -0,0 +1,95 @@
+//! # Clocks
+//! Archives store the time they were created, in the manifest and in the headers of generated entries,
+//! and the modification time of every file. A `Clock` decides both, so tests and reproducible builds can pin them,
+//! e.g. to `SOURCE_DATE_EPOCH`, and archive the same files into byte-identical archives.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::clock::FixedClock;
+//! use lzma_tarball::writer::LZMATarballWriter;
+//!
+//! let mut writer = LZMATarballWriter::new();
+//! writer.set_clock(FixedClock::from_source_date_epoch().unwrap_or(FixedClock::from_unix(0)));
+//! ```
+
+use std::fmt::Debug;
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The source of the times stored in archives.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time
+    fn now(&self) -> SystemTime;
+    /// Returns the modification time stored for a file, the one on disk unless overridden
+    fn file_mtime(&self, metadata: &Metadata) -> Option<SystemTime> {
+        metadata.modified().ok()
+    }
+}
+
+/// The system clock and the modification times on disk, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock stopped at a single time, which is also stored as the modification time of every file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock(pub SystemTime);
+
+impl FixedClock {
+    /// Creates a clock stopped `seconds` after the Unix epoch
+    pub fn from_unix(seconds: u64) -> Self {
+        FixedClock(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+    /// Creates a clock stopped at the `SOURCE_DATE_EPOCH` environment variable of reproducible builds, if it is set
+    pub fn from_source_date_epoch() -> Option<Self> {
+        std::env::var("SOURCE_DATE_EPOCH").ok()?.trim().parse().ok().map(Self::from_unix)
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+    fn file_mtime(&self, _metadata: &Metadata) -> Option<SystemTime> {
+        Some(self.0)
+    }
+}
+
+/// Returns the whole seconds since the Unix epoch, 0 for earlier times.
+#[cfg(feature = "compression")]
+pub(crate) fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|since_epoch| since_epoch.as_secs()).unwrap_or(0)
+}
+
+/// Splits seconds since the Unix epoch into the UTC year, month, day, hour, minute and second.
+#[cfg(any(feature = "compression", feature = "decompression"))]
+pub(crate) fn utc_parts(seconds: i64) -> (i64, u32, u32, u32, u32, u32) {
+    let (days, time) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    // Days to a civil date, counting years from March so the leap day comes last
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, (time / 3_600) as u32, (time % 3_600 / 60) as u32, (time % 60) as u32)
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp, e.g. `2023-11-14T22:13:20+00:00`, with nanoseconds if it has any.
+#[cfg(any(feature = "compression", feature = "decompression"))]
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => (since_epoch.as_secs() as i64, since_epoch.subsec_nanos()),
+        Err(_) => (0, 0),
+    };
+    let (year, month, day, hour, minute, second) = utc_parts(seconds);
+    let fraction = if nanos == 0 { String::new() } else { format!(".{:09}", nanos) };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}+00:00", year, month, day, hour, minute, second, fraction)
+}
@@ -0,0 +1,385 @@
+//! # Writer Configuration
+//! `WriterConfig` holds the compression settings and `ArchiveSpec` the entries of a single archive.
+//! They are only combined when compressing, so one tuned configuration can be reused for many archives
+//! and specs can be built, validated and stored independently of how they will be compressed.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::config::{ArchiveSpec, WriterConfig};
+//!
+//! let mut config = WriterConfig::new();
+//! config.set_compression_level(9).set_write_manifest(true);
+//!
+//! for name in ["src", "examples"] {
+//!     let mut spec = ArchiveSpec::new();
+//!     spec.with_path(name, "/").unwrap().set_output(format!("{}.tar.xz", name));
+//!     config.compress(&spec, |_| {}).unwrap();
+//! }
+//! ```
+
+use crate::normalization::UnicodeNormalization;
+use crate::format::CompressionFormat;
+use crate::clock::{Clock, SystemClock};
+use crate::skip::SkippedEntry;
+use crate::writer::{scan_directory, AbsolutePathPolicy, ArchiveEntry, BufferSize, Dispatch, EntrySource, LZMACallbackResult, LZMAResult, LZMATarballWriter, LongPathEncoding, NameEncoding, TempStrategy};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use walkdir::DirEntry;
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// Compression settings that can be reused across archives
+#[derive(Debug, Clone)]
+pub struct WriterConfig {
+    pub compression_level: u8,
+    pub format: CompressionFormat,
+    pub buffer_size: BufferSize,
+    pub write_manifest: bool,
+    pub catalog: bool,
+    pub unicode_normalization: UnicodeNormalization,
+    pub progress_dispatch: Dispatch,
+    pub preserve_btime: bool,
+    pub precise_mtime: bool,
+    pub preserve_xattrs: bool,
+    pub seekable_block_size: Option<u64>,
+    pub preset_dictionary: Option<Vec<u8>>,
+    pub output_queue_depth: Option<usize>,
+    pub output_mode: Option<u32>,
+    pub output_owner: Option<(u32, u32)>,
+    pub keep_partial_output: bool,
+    pub clock: Arc<dyn Clock>,
+    pub lock_source_files: bool,
+    pub background_priority: bool,
+    pub timeout: Option<std::time::Duration>,
+    pub dedupe_identical_files: bool,
+    pub long_path_encoding: LongPathEncoding,
+    pub name_encoding: NameEncoding,
+    pub temp_strategy: TempStrategy,
+    pub absolute_path_policy: AbsolutePathPolicy,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WriterConfig {
+    /// Creates a configuration with the same defaults as `LZMATarballWriter::new`
+    pub fn new() -> Self {
+        WriterConfig {
+            compression_level: 6,
+            format: CompressionFormat::Xz,
+            buffer_size: BufferSize::Kilobytes(64),
+            write_manifest: false,
+            catalog: false,
+            unicode_normalization: UnicodeNormalization::None,
+            progress_dispatch: Dispatch::Inline,
+            preserve_btime: false,
+            precise_mtime: false,
+            preserve_xattrs: false,
+            seekable_block_size: None,
+            preset_dictionary: None,
+            output_queue_depth: None,
+            output_mode: None,
+            output_owner: None,
+            keep_partial_output: false,
+            clock: Arc::new(SystemClock),
+            lock_source_files: false,
+            background_priority: false,
+            timeout: None,
+            dedupe_identical_files: false,
+            long_path_encoding: LongPathEncoding::Gnu,
+            name_encoding: NameEncoding::Error,
+            temp_strategy: TempStrategy::default(),
+            absolute_path_policy: AbsolutePathPolicy::Strip,
+        }
+    }
+    /// Sets the compression level (clamps between 0 and 9)
+    pub fn set_compression_level(&mut self, level: u8) -> &mut Self {
+        self.compression_level = level.clamp(0, 9);
+
+        debug!("Compression level set to: {}", self.compression_level);
+        self
+    }
+    /// Sets the codec archives are compressed with, see `LZMATarballWriter::set_format`
+    pub fn set_format(&mut self, format: CompressionFormat) -> &mut Self {
+        self.format = format;
+
+        debug!("Format set to: {:?}", self.format);
+        self
+    }
+    /// Sets the buffer size in KB, or `BufferSize::Auto` to size it from the input
+    pub fn set_buffer_size(&mut self, size: impl Into<BufferSize>) -> &mut Self {
+        self.buffer_size = size.into();
+
+        debug!("Buffer size set to: {:?}", self.buffer_size);
+        self
+    }
+    /// Sets whether a manifest is embedded in the archive
+    pub fn set_write_manifest(&mut self, write_manifest: bool) -> &mut Self {
+        self.write_manifest = write_manifest;
+
+        debug!("Write manifest set to: {}", self.write_manifest);
+        self
+    }
+    /// Sets whether catalog archives are written, see `LZMATarballWriter::set_catalog`
+    pub fn set_catalog(&mut self, catalog: bool) -> &mut Self {
+        self.catalog = catalog;
+
+        debug!("Catalog set to: {}", self.catalog);
+        self
+    }
+    /// Sets whether creation times are stored, see `LZMATarballWriter::set_preserve_btime`
+    pub fn set_preserve_btime(&mut self, preserve_btime: bool) -> &mut Self {
+        self.preserve_btime = preserve_btime;
+
+        debug!("Preserve btime set to: {}", self.preserve_btime);
+        self
+    }
+    /// Sets whether modification times are stored with nanosecond precision, see `LZMATarballWriter::set_precise_mtime`
+    pub fn set_precise_mtime(&mut self, precise_mtime: bool) -> &mut Self {
+        self.precise_mtime = precise_mtime;
+
+        debug!("Precise mtime set to: {}", self.precise_mtime);
+        self
+    }
+    /// Sets whether extended attributes are stored, see `LZMATarballWriter::set_preserve_xattrs`
+    pub fn set_preserve_xattrs(&mut self, preserve_xattrs: bool) -> &mut Self {
+        self.preserve_xattrs = preserve_xattrs;
+
+        debug!("Preserve xattrs set to: {}", self.preserve_xattrs);
+        self
+    }
+    /// Sets the block size of seekable archives, see `LZMATarballWriter::set_seekable_block_size`
+    pub fn set_seekable_block_size(&mut self, seekable_block_size: Option<u64>) -> &mut Self {
+        self.seekable_block_size = seekable_block_size;
+
+        debug!("Seekable block size set to: {:?}", self.seekable_block_size);
+        self
+    }
+    /// Sets the dictionary the encoder is primed with, see `LZMATarballWriter::set_preset_dictionary`
+    pub fn set_preset_dictionary(&mut self, preset_dictionary: Option<Vec<u8>>) -> &mut Self {
+        self.preset_dictionary = preset_dictionary;
+
+        debug!("Preset dictionary set to: {:?} bytes", self.preset_dictionary.as_ref().map(Vec::len));
+        self
+    }
+    /// Sets how many chunks of compressed data are queued for the output, see `LZMATarballWriter::set_output_queue_depth`
+    pub fn set_output_queue_depth(&mut self, output_queue_depth: Option<usize>) -> &mut Self {
+        self.output_queue_depth = output_queue_depth;
+
+        debug!("Output queue depth set to: {:?}", self.output_queue_depth);
+        self
+    }
+    /// Sets the permissions of the output file, see `LZMATarballWriter::set_output_mode`
+    pub fn set_output_mode(&mut self, mode: u32) -> &mut Self {
+        self.output_mode = Some(mode);
+
+        debug!("Output mode set to: {:o}", mode);
+        self
+    }
+    /// Sets the clock deciding the stored times, see `LZMATarballWriter::set_clock`
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) -> &mut Self {
+        self.clock = Arc::new(clock);
+
+        debug!("Clock set to: {:?}", self.clock);
+        self
+    }
+    /// Sets the owner and group of the output file, see `LZMATarballWriter::set_output_owner`
+    pub fn set_output_owner(&mut self, uid: u32, gid: u32) -> &mut Self {
+        self.output_owner = Some((uid, gid));
+
+        debug!("Output owner set to: {:?}", self.output_owner);
+        self
+    }
+    /// Sets whether failed compressions leave their partial output, see `LZMATarballWriter::set_keep_partial_output`
+    pub fn set_keep_partial_output(&mut self, keep_partial_output: bool) -> &mut Self {
+        self.keep_partial_output = keep_partial_output;
+
+        debug!("Keep partial output set to: {}", self.keep_partial_output);
+        self
+    }
+    /// Sets whether source files are locked while they are read, see `LZMATarballWriter::set_lock_source_files`
+    pub fn set_lock_source_files(&mut self, lock_source_files: bool) -> &mut Self {
+        self.lock_source_files = lock_source_files;
+
+        debug!("Lock source files set to: {}", self.lock_source_files);
+        self
+    }
+    /// Sets whether compression runs with lowered priority, see `LZMATarballWriter::set_background_priority`
+    pub fn set_background_priority(&mut self, background_priority: bool) -> &mut Self {
+        self.background_priority = background_priority;
+
+        debug!("Background priority set to: {}", self.background_priority);
+        self
+    }
+    /// Sets the time compression may take, see `LZMATarballWriter::set_timeout`
+    pub fn set_timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+
+        debug!("Timeout set to: {:?}", self.timeout);
+        self
+    }
+    /// Sets whether identical files are stored once, see `LZMATarballWriter::set_dedupe_identical_files`
+    pub fn set_dedupe_identical_files(&mut self, dedupe_identical_files: bool) -> &mut Self {
+        self.dedupe_identical_files = dedupe_identical_files;
+
+        debug!("Dedupe identical files set to: {}", self.dedupe_identical_files);
+        self
+    }
+    /// Sets how long paths are stored, see `LZMATarballWriter::set_long_path_encoding`
+    pub fn set_long_path_encoding(&mut self, long_path_encoding: LongPathEncoding) -> &mut Self {
+        self.long_path_encoding = long_path_encoding;
+
+        debug!("Long path encoding set to: {:?}", self.long_path_encoding);
+        self
+    }
+    /// Sets how files whose names aren't valid UTF-8 are stored, see `LZMATarballWriter::set_name_encoding`
+    pub fn set_name_encoding(&mut self, name_encoding: NameEncoding) -> &mut Self {
+        self.name_encoding = name_encoding;
+
+        debug!("Name encoding set to: {:?}", self.name_encoding);
+        self
+    }
+    /// Sets where the tar is staged before it is compressed, see `LZMATarballWriter::set_temp_strategy`
+    pub fn set_temp_strategy(&mut self, temp_strategy: TempStrategy) -> &mut Self {
+        self.temp_strategy = temp_strategy;
+
+        debug!("Temp strategy set to: {:?}", self.temp_strategy);
+        self
+    }
+    /// Sets what happens to absolute archive paths, see `LZMATarballWriter::set_absolute_path_policy`
+    pub fn set_absolute_path_policy(&mut self, absolute_path_policy: AbsolutePathPolicy) -> &mut Self {
+        self.absolute_path_policy = absolute_path_policy;
+
+        debug!("Absolute path policy set to: {:?}", self.absolute_path_policy);
+        self
+    }
+    /// Sets the Unicode normalization form applied to archive paths
+    pub fn set_unicode_normalization(&mut self, normalization: UnicodeNormalization) -> &mut Self {
+        self.unicode_normalization = normalization;
+
+        debug!("Unicode normalization set to: {:?}", self.unicode_normalization);
+        self
+    }
+    /// Sets how progress updates are delivered, see `Dispatch`
+    pub fn set_progress_dispatch(&mut self, progress_dispatch: Dispatch) -> &mut Self {
+        self.progress_dispatch = progress_dispatch;
+
+        debug!("Progress dispatch set to: {:?}", self.progress_dispatch);
+        self
+    }
+
+    /// Validates `spec` and compresses it with this configuration
+    pub fn compress<F>(&self, spec: &ArchiveSpec, callback: F) -> Result<LZMAResult>
+    where
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        spec.validate()?;
+        LZMATarballWriter::from_parts(self, spec).compress(callback)
+    }
+}
+
+/// The entries and output file of a single archive
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveSpec {
+    pub output_file: Option<PathBuf>,
+    pub entries: Vec<ArchiveEntry>,
+    pub skip_hidden: bool,
+    #[serde(default)]
+    pub store_directories: bool,
+    /// The files left out while scanning directories
+    #[serde(default)]
+    pub skipped: Vec<SkippedEntry>,
+    /// The call that added each archive path, see `LZMATarballWriter::plan`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<String, EntrySource>,
+}
+
+impl ArchiveSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the output file of the archive
+    pub fn set_output(&mut self, output_file: impl AsRef<Path>) -> &mut Self {
+        self.output_file = Some(output_file.as_ref().to_path_buf());
+
+        debug!("Output file set to: {:?}", self.output_file);
+        self
+    }
+    /// Sets whether hidden files and directories are skipped when scanning directories
+    pub fn set_skip_hidden(&mut self, skip_hidden: bool) -> &mut Self {
+        self.skip_hidden = skip_hidden;
+
+        debug!("Skip hidden set to: {}", self.skip_hidden);
+        self
+    }
+    /// Sets whether scanned directories are stored as entries, see `LZMATarballWriter::set_store_directories`
+    pub fn set_store_directories(&mut self, store_directories: bool) -> &mut Self {
+        self.store_directories = store_directories;
+
+        debug!("Store directories set to: {}", self.store_directories);
+        self
+    }
+    /// Adds a file, or the contents of a directory, under `archive_path`
+    pub fn with_path(&mut self, input_path: impl AsRef<Path>, archive_path: impl AsRef<str>) -> Result<&mut Self> {
+        if input_path.as_ref().metadata()?.is_dir() {
+            Ok(self.with_filtered_directory_contents(input_path, archive_path, &|_| true))
+        } else {
+            Ok(self.with_file(input_path, archive_path))
+        }
+    }
+    /// Adds a single file as `archive_path`
+    pub fn with_file(&mut self, input_file: impl AsRef<Path>, archive_path: impl AsRef<str>) -> &mut Self {
+        debug!("Adding file to spec: {:?} as {}", input_file.as_ref(), archive_path.as_ref());
+        self.sources.insert(archive_path.as_ref().to_string(), EntrySource::File);
+        self.entries.push(ArchiveEntry {
+            filesystem_path: input_file.as_ref().to_path_buf(),
+            archive_path: archive_path.as_ref().to_string(),
+        });
+        self
+    }
+    /// Adds the files in a directory that match `filter` under `archive_path`
+    pub fn with_filtered_directory_contents(
+        &mut self,
+        input_directory: impl AsRef<Path>,
+        archive_path: impl AsRef<str>,
+        filter: &dyn Fn(&DirEntry) -> bool,
+    ) -> &mut Self {
+        let mut entries = scan_directory(input_directory.as_ref(), input_directory.as_ref(), archive_path.as_ref(), self.skip_hidden, self.store_directories, filter, &mut self.skipped);
+        let source = EntrySource::Directory {
+            root: input_directory.as_ref().to_path_buf(),
+            archive_path: archive_path.as_ref().to_string(),
+        };
+        self.sources.extend(entries.iter().map(|entry| (entry.archive_path.clone(), source.clone())));
+        self.entries.append(&mut entries);
+        self
+    }
+
+    /// Checks that the spec has an output file and entries, that every input file or stored directory exists
+    /// and that no two entries share an archive path
+    pub fn validate(&self) -> Result<()> {
+        if self.output_file.is_none() {
+            bail!("Output file not set");
+        }
+        if self.entries.is_empty() {
+            bail!("No files or directories to compress");
+        }
+        let mut archive_paths = HashSet::new();
+        for entry in &self.entries {
+            if !entry.filesystem_path.is_file() && !entry.filesystem_path.is_dir() {
+                bail!("File not found: {:?}", entry.filesystem_path);
+            }
+            if !archive_paths.insert(entry.archive_path.trim_start_matches('/')) {
+                bail!("Duplicate archive path: {}", entry.archive_path);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! # Errors
+//! Typed errors returned (wrapped in `anyhow::Error`) when an archive cannot be decoded or written.
+//! Use `anyhow::Error::downcast_ref::<ReaderError>()` to react to a specific class of corruption.
+
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "decompression")]
+use std::io;
+
+/// Distinguishes the different ways decoding an archive can fail.
+///
+/// Every variant carries the `offset` in the decompressed tar stream at which the failure was detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReaderError {
+    /// The archive ended before the xz stream or the tar data was complete.
+    TruncatedArchive { offset: u64 },
+    /// The xz stream is corrupt or failed its integrity check.
+    BadXzCheck { offset: u64 },
+    /// A tar header failed its checksum or contained unparsable fields.
+    InvalidTarHeader { offset: u64 },
+    /// The archive contains an entry type that cannot be extracted.
+    UnsupportedEntryType { offset: u64, entry_type: u8 },
+}
+
+impl ReaderError {
+    /// Returns the offset in the decompressed tar stream at which the failure was detected.
+    pub fn offset(&self) -> u64 {
+        match self {
+            ReaderError::TruncatedArchive { offset }
+            | ReaderError::BadXzCheck { offset }
+            | ReaderError::InvalidTarHeader { offset }
+            | ReaderError::UnsupportedEntryType { offset, .. } => *offset,
+        }
+    }
+
+    /// Classifies an I/O error raised while decoding an archive.
+    /// Returns `None` if the error is not caused by a corrupt archive (e.g. a permission error on extraction).
+    #[cfg(feature = "decompression")]
+    pub(crate) fn classify(err: &io::Error, offset: u64) -> Option<Self> {
+        let mut current: Option<&(dyn Error + 'static)> = Some(err);
+        while let Some(error) = current {
+            if error.downcast_ref::<xz2::stream::Error>().is_some() {
+                return Some(ReaderError::BadXzCheck { offset });
+            }
+            if let Some(io_error) = error.downcast_ref::<io::Error>() {
+                let message = io_error.to_string();
+                if io_error.kind() == io::ErrorKind::UnexpectedEof
+                    || message.contains("failed to read entire block")
+                {
+                    return Some(ReaderError::TruncatedArchive { offset });
+                }
+                if message.contains("corrupt xz stream") {
+                    return Some(ReaderError::BadXzCheck { offset });
+                }
+                if message.contains("checksum mismatch") || message.contains("numeric field") {
+                    return Some(ReaderError::InvalidTarHeader { offset });
+                }
+                current = io_error.get_ref().map(|e| e as &(dyn Error + 'static));
+            } else {
+                current = error.source();
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReaderError::TruncatedArchive { offset } => {
+                write!(f, "archive is truncated at offset {}", offset)
+            }
+            ReaderError::BadXzCheck { offset } => {
+                write!(f, "xz stream is corrupt at offset {}", offset)
+            }
+            ReaderError::InvalidTarHeader { offset } => {
+                write!(f, "invalid tar header at offset {}", offset)
+            }
+            ReaderError::UnsupportedEntryType { offset, entry_type } => write!(
+                f,
+                "unsupported entry type {:?} at offset {}",
+                *entry_type as char, offset
+            ),
+        }
+    }
+}
+
+impl Error for ReaderError {}
+
+/// Distinguishes failures of `LZMATarballWriter` that callers may want to handle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriterError {
+    /// Compression was stopped through the flag passed to `LZMATarballWriter::set_cancel_flag`.
+    Cancelled,
+    /// The compressed archive exceeded the size cap passed to `LZMATarballWriter::compress_to_vec`.
+    OutputTooLarge { max_size: u64 },
+    /// Two writer options that can't be combined are set, e.g. a preset dictionary with the zstd format.
+    IncompatibleOptions { option: &'static str, conflict: String },
+}
+
+impl fmt::Display for WriterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriterError::Cancelled => write!(f, "compression was cancelled"),
+            WriterError::OutputTooLarge { max_size } => write!(f, "compressed archive exceeds {} bytes", max_size),
+            WriterError::IncompatibleOptions { option, conflict } => write!(f, "{} can't be used with {}", option, conflict),
+        }
+    }
+}
+
+impl Error for WriterError {}
@@ -0,0 +1,74 @@
+//! # Compression Formats
+//! Archives are .tar.xz by default, `CompressionFormat` selects another codec for the tar stream,
+//! e.g. zstd when compression speed matters more than the ratio, like for CI artifacts.
+//! The writer and reader are configured the same way regardless of the format, and the reader detects it
+//! from the first bytes of the archive.
+
+use serde::{Deserialize, Serialize};
+
+/// The codec the tar stream is compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CompressionFormat {
+    /// An .xz stream, written as `.tar.xz`.
+    #[default]
+    Xz,
+    /// A zstd stream, written as `.tar.zst`. Needs the `zstd` feature.
+    Zstd,
+    /// A gzip stream, written as `.tar.gz`, for tools that only read gzip. Needs the `gzip` feature.
+    Gzip,
+    /// No compression, the tar is written as is as `.tar`.
+    None,
+    /// A bzip2 stream, `.tar.bz2`. It is only detected, so the reader can name it, reading or writing it fails.
+    Bzip2,
+}
+
+impl CompressionFormat {
+    /// Returns the usual file extension of archives in this format, e.g. `tar.xz`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompressionFormat::Xz => "tar.xz",
+            CompressionFormat::Zstd => "tar.zst",
+            CompressionFormat::Gzip => "tar.gz",
+            CompressionFormat::None => "tar",
+            CompressionFormat::Bzip2 => "tar.bz2",
+        }
+    }
+
+    /// Detects the format of an archive from its first bytes, at least 512 to recognize a plain tar.
+    /// Returns `None` for unknown data, which includes the raw LZMA2 streams of preset dictionaries.
+    pub fn detect(header: &[u8]) -> Option<CompressionFormat> {
+        if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(CompressionFormat::Xz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(CompressionFormat::Zstd)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Some(CompressionFormat::Gzip)
+        } else if header.starts_with(b"BZh") {
+            Some(CompressionFormat::Bzip2)
+        } else if header.get(257..262) == Some(b"ustar") || has_tar_checksum(header) {
+            Some(CompressionFormat::None)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the zstd level for a compression level between 0 and 9, spreading them over zstd levels 1 to 19.
+    #[cfg(all(feature = "zstd", feature = "compression"))]
+    pub(crate) fn zstd_level(compression_level: u8) -> i32 {
+        compression_level.min(9) as i32 * 2 + 1
+    }
+}
+
+/// Returns whether `header` starts with a tar header with a valid checksum, the only way to recognize old tar headers.
+fn has_tar_checksum(header: &[u8]) -> bool {
+    let Some(header) = header.get(..512) else {
+        return false;
+    };
+    let field = String::from_utf8_lossy(&header[148..156]);
+    let Ok(checksum) = u32::from_str_radix(field.trim_matches(|c: char| c == ' ' || c == '\0'), 8) else {
+        return false;
+    };
+    // The checksum field counts as spaces
+    let sum: u32 = header.iter().enumerate().map(|(index, byte)| if (148..156).contains(&index) { b' ' as u32 } else { *byte as u32 }).sum();
+    sum == checksum
+}
@@ -0,0 +1,55 @@
+//! # Glob Patterns
+//! Matches archive paths against shell style patterns. `*` matches any characters within a path component,
+//! `?` matches a single character and a `**` component matches any number of components, including none.
+//! Lists of paths and patterns, like `tar -T` and `tar -X` take, are read from newline delimited files.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Returns whether `path` matches `pattern`, ignoring leading slashes and the trailing slash of directories.
+pub(crate) fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.trim_start_matches('/').split('/').collect();
+    let path: Vec<&str> = path.trim_start_matches('/').trim_end_matches('/').split('/').collect();
+    match_components(&pattern, &path)
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skipped| match_components(rest, &path[skipped..])),
+        Some((component, rest)) => path.split_first().is_some_and(|(name, path)| {
+            let (component, name): (Vec<char>, Vec<char>) = (component.chars().collect(), name.chars().collect());
+            match_name(&component, &name) && match_components(rest, path)
+        }),
+    }
+}
+
+fn match_name(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skipped| match_name(rest, &name[skipped..])),
+        Some(('?', rest)) => !name.is_empty() && match_name(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_name(rest, &name[1..]),
+    }
+}
+
+/// Reads the lines of a path or pattern list with their line numbers, leaving out blank lines and comments
+pub(crate) fn read_list(list: &Path) -> Result<Vec<(usize, String)>> {
+    let contents = std::fs::read_to_string(list).with_context(|| format!("Failed to read the list {:?}", list))?;
+    Ok(contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim_end_matches('\r')))
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(line, path)| (line, path.to_string()))
+        .collect())
+}
+
+/// The archive path of a path listed in a list: the path without leading `/` and `./`
+pub(crate) fn list_archive_path(path: &str) -> String {
+    let mut path = if cfg!(windows) { path.replace('\\', "/") } else { path.to_string() };
+    while let Some(rest) = path.strip_prefix("./").or_else(|| path.strip_prefix('/')) {
+        path = rest.to_string();
+    }
+    path.trim_end_matches('/').to_string()
+}
@@ -0,0 +1,46 @@
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+
+/// A reader that computes the SHA-256 hash and size of everything read through it.
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    /// `None` when only the size is needed
+    hasher: Option<Sha256>,
+    bytes_read: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Some(Sha256::new()),
+            bytes_read: 0,
+        }
+    }
+
+    /// Creates a reader that only counts the bytes read, for callers that need the hash of some inputs only.
+    #[cfg(feature = "compression")]
+    pub(crate) fn counting(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: None,
+            bytes_read: 0,
+        }
+    }
+
+    /// Returns the number of bytes read and the hex encoded SHA-256 digest, empty if the reader only counts.
+    pub(crate) fn finish(self) -> (u64, String) {
+        (self.bytes_read, self.hasher.map(|hasher| format!("{:x}", hasher.finalize())).unwrap_or_default())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..read]);
+        }
+        self.bytes_read += read as u64;
+        Ok(read)
+    }
+}
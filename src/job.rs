@@ -0,0 +1,302 @@
+//! # Resumable Jobs
+//! A writer job is saved to a JSON state file so a scheduler can checkpoint it, move it to another machine
+//! or resume it after the process restarts. The entries are compressed in batches, each appended to the output
+//! as an independent xz stream, and the state file records how many entries and bytes are complete after each batch.
+//! A resumed job drops whatever was written after the last checkpoint and continues with the next batch.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::writer::LZMATarballWriter;
+//!
+//! let mut writer = LZMATarballWriter::new();
+//! writer.with_path("./", "/").unwrap().set_output("archive.tar.xz");
+//! writer.save_job("archive.job.json").unwrap();
+//!
+//! // Later, possibly in another process
+//! let writer = LZMATarballWriter::load_job("archive.job.json").unwrap();
+//! writer.compress(|_| {}).unwrap();
+//! ```
+
+use crate::config::ArchiveSpec;
+use crate::normalization::UnicodeNormalization;
+use crate::format::CompressionFormat;
+use crate::writer::{AbsolutePathPolicy, BufferSize, LZMACallbackResult, LZMAResult, LZMATarballWriter, LongPathEncoding, NameEncoding, TempStrategy};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// The number of entries compressed between checkpoints unless the job says otherwise
+pub const DEFAULT_CHECKPOINT_ENTRIES: usize = 100;
+
+/// The options of a writer that are kept in a job state file.
+/// Callbacks, handles and snapshots can't be serialized, and jobs never write a manifest.
+/// Options a job can't resume with, like seekable archives, fail `save_job` instead of being dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobOptions {
+    pub compression_level: u8,
+    #[serde(default)]
+    pub format: CompressionFormat,
+    pub buffer_size: BufferSize,
+    pub catalog: bool,
+    pub unicode_normalization: UnicodeNormalization,
+    pub preserve_btime: bool,
+    pub precise_mtime: bool,
+    pub preserve_xattrs: bool,
+    pub no_follow: bool,
+    pub lock_source_files: bool,
+    pub dedupe_identical_files: bool,
+    pub long_path_encoding: LongPathEncoding,
+    #[serde(default)]
+    pub name_encoding: NameEncoding,
+    pub absolute_path_policy: AbsolutePathPolicy,
+    #[serde(default)]
+    pub temp_strategy: TempStrategy,
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    #[serde(default)]
+    pub output_mode: Option<u32>,
+    #[serde(default)]
+    pub output_owner: Option<(u32, u32)>,
+}
+
+/// How far a job has progressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobProgress {
+    /// The number of entries stored in complete streams of the output
+    pub completed_entries: usize,
+    /// The size of the output after those streams, anything past it is discarded on resume
+    pub output_size: u64,
+}
+
+/// A writer job as stored in a state file, see `LZMATarballWriter::save_job`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriterJob {
+    pub options: JobOptions,
+    pub spec: ArchiveSpec,
+    /// The number of entries compressed into each stream, the progress is saved after every stream
+    pub checkpoint_entries: usize,
+    pub progress: JobProgress,
+}
+
+impl WriterJob {
+    /// Reads a job from a state file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Failed to open job file {:?}", path))?;
+        serde_json::from_reader(file).with_context(|| format!("Failed to parse job file {:?}", path))
+    }
+    /// Writes the job to a state file, replacing it atomically so a crash never leaves it half written
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        let json = serde_json::to_vec_pretty(self)?;
+        std::fs::write(&temporary, json).with_context(|| format!("Failed to write job file {:?}", temporary))?;
+        std::fs::rename(&temporary, path).with_context(|| format!("Failed to replace job file {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// The state file a writer loaded with `load_job` checkpoints to
+#[derive(Debug, Clone)]
+pub(crate) struct JobCheckpoint {
+    file: PathBuf,
+    checkpoint_entries: usize,
+    /// Kept in sync with the state file, so compressing the writer again doesn't redo the job
+    progress: Arc<Mutex<JobProgress>>,
+}
+
+impl JobCheckpoint {
+    fn progress(&self) -> JobProgress {
+        *self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl LZMATarballWriter {
+    /// Returns the entries, output file and options of this writer as a job that hasn't started
+    pub fn to_job(&self) -> WriterJob {
+        WriterJob {
+            options: JobOptions {
+                compression_level: self.compression_level,
+                format: self.format,
+                buffer_size: self.buffer_size,
+                catalog: self.catalog,
+                unicode_normalization: self.unicode_normalization,
+                preserve_btime: self.preserve_btime,
+                precise_mtime: self.precise_mtime,
+                preserve_xattrs: self.preserve_xattrs,
+                no_follow: self.no_follow,
+                lock_source_files: self.lock_source_files,
+                dedupe_identical_files: self.dedupe_identical_files,
+                long_path_encoding: self.long_path_encoding,
+                name_encoding: self.name_encoding,
+                absolute_path_policy: self.absolute_path_policy,
+                temp_strategy: self.temp_strategy.clone(),
+                timeout: self.timeout,
+                output_mode: self.output_mode,
+                output_owner: self.output_owner,
+            },
+            spec: ArchiveSpec {
+                output_file: self.output_file.clone(),
+                entries: self.archive_paths.clone(),
+                skip_hidden: self.skip_hidden,
+                store_directories: self.store_directories,
+                skipped: self.skipped.clone(),
+                sources: self.sources.clone(),
+            },
+            checkpoint_entries: DEFAULT_CHECKPOINT_ENTRIES,
+            progress: self.job.as_ref().map(JobCheckpoint::progress).unwrap_or_default(),
+        }
+    }
+    /// Returns the name of the first option that is set but can't be resumed as a job, as its output isn't a plain
+    /// series of appended streams
+    fn unsupported_job_option(&self) -> Option<&'static str> {
+        if self.seekable_block_size.is_some() {
+            Some("a seekable block size")
+        } else if self.preset_dictionary.is_some() {
+            Some("a preset dictionary")
+        } else if self.sidecar_index.is_some() {
+            Some("a sidecar index")
+        } else if self.output_template.is_some() {
+            Some("an output template")
+        } else if self.rotation_policy.is_some() {
+            Some("a rotation policy")
+        } else {
+            None
+        }
+    }
+    /// Saves this writer as a job state file, which `load_job` resumes
+    pub fn save_job(&self, path: impl AsRef<Path>) -> Result<()> {
+        debug!("Saving job to: {:?}", path.as_ref());
+        if let Some(option) = self.unsupported_job_option() {
+            error!("Jobs can't be saved with {}", option);
+            bail!("Jobs can't be saved with {}, a resumed job would write a different archive", option);
+        }
+        let mut job = self.to_job();
+        if let Some(checkpoint) = &self.job {
+            job.checkpoint_entries = checkpoint.checkpoint_entries;
+        }
+        job.save(path)
+    }
+    /// Creates a writer from a job state file. `compress` on the writer resumes the job after its last checkpoint
+    /// and updates the state file after every batch of entries.
+    pub fn load_job(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let job = WriterJob::load(path)?;
+        info!("Loaded job from {:?}, {} of {} entries complete", path, job.progress.completed_entries, job.spec.entries.len());
+        let options = job.options;
+        let mut writer = Self::new();
+        writer
+            .set_compression_level(options.compression_level)
+            .set_format(options.format)
+            .set_buffer_size(options.buffer_size)
+            .set_catalog(options.catalog)
+            .set_unicode_normalization(options.unicode_normalization)
+            .set_preserve_btime(options.preserve_btime)
+            .set_precise_mtime(options.precise_mtime)
+            .set_preserve_xattrs(options.preserve_xattrs)
+            .set_no_follow(options.no_follow)
+            .set_lock_source_files(options.lock_source_files)
+            .set_dedupe_identical_files(options.dedupe_identical_files)
+            .set_long_path_encoding(options.long_path_encoding)
+            .set_name_encoding(options.name_encoding)
+            .set_absolute_path_policy(options.absolute_path_policy)
+            .set_temp_strategy(options.temp_strategy)
+            .set_skip_hidden(job.spec.skip_hidden)
+            .set_store_directories(job.spec.store_directories);
+        writer.timeout = options.timeout;
+        writer.output_mode = options.output_mode;
+        writer.output_owner = options.output_owner;
+        writer.archive_paths = job.spec.entries;
+        writer.skipped = job.spec.skipped;
+        writer.sources = job.spec.sources;
+        writer.output_file = job.spec.output_file;
+        writer.job = Some(JobCheckpoint {
+            file: path.to_path_buf(),
+            checkpoint_entries: job.checkpoint_entries.max(1),
+            progress: Arc::new(Mutex::new(job.progress)),
+        });
+        Ok(writer)
+    }
+    /// Compresses the entries a loaded job hasn't completed, one stream per batch, saving the progress after each.
+    /// Progress callbacks start over for every batch, the order and share of the entries cover the whole result.
+    pub(crate) fn compress_job<F>(&self, checkpoint: &JobCheckpoint, callback: F) -> Result<LZMAResult>
+    where
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        let Some(output_file) = self.output_file.clone() else {
+            error!("Output file not set");
+            bail!("Output file not set");
+        };
+        if !self.output_mirrors.is_empty() {
+            error!("Jobs can't write output mirrors");
+            bail!("Jobs can't write output mirrors, their output is written in batches that can be resumed");
+        }
+        if let Some(option) = self.unsupported_job_option() {
+            error!("Jobs can't be compressed with {}", option);
+            bail!("Jobs can't be compressed with {}, as their output is written in batches that can be resumed", option);
+        }
+        let mut progress = checkpoint.progress();
+        if progress.completed_entries > self.archive_paths.len() {
+            bail!("Job claims {} completed entries but has {}", progress.completed_entries, self.archive_paths.len());
+        }
+        if progress.output_size > 0 {
+            debug!("Discarding output past the last checkpoint at {} bytes", progress.output_size);
+            let output = File::options().write(true).open(&output_file).with_context(|| format!("Failed to open {:?} to resume", output_file))?;
+            output.set_len(progress.output_size)?;
+        }
+        let start = std::time::Instant::now();
+        let callback = Arc::new(callback);
+        let mut result = LZMAResult {
+            output_file: output_file.clone(),
+            size: progress.output_size,
+            original_size: 0,
+            elapsed_time: Default::default(),
+            entries: Vec::new(),
+            skipped: self.skipped.clone(),
+            memory_usage: self.estimated_memory_usage(),
+            mirrors: Vec::new(),
+            pruned: Vec::new(),
+        };
+        for batch in self.archive_paths[progress.completed_entries..].chunks(checkpoint.checkpoint_entries) {
+            let mut writer = self.clone();
+            writer.job = None;
+            writer.write_manifest = false;
+            writer.archive_paths = batch.to_vec();
+            let callback = callback.clone();
+            let callback = move |update| callback(update);
+            let batch_result = if progress.output_size == 0 {
+                writer.compress_entries(callback)?
+            } else {
+                writer.append_stream(&output_file, callback)?
+            };
+            progress.completed_entries += batch.len();
+            progress.output_size = batch_result.size;
+            result.size = batch_result.size;
+            result.original_size += batch_result.original_size;
+            result.entries.extend(batch_result.entries);
+            let mut job = self.to_job();
+            job.checkpoint_entries = checkpoint.checkpoint_entries;
+            job.progress = progress;
+            job.save(&checkpoint.file)?;
+            *checkpoint.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = progress;
+            debug!("Checkpoint: {} of {} entries complete", progress.completed_entries, self.archive_paths.len());
+        }
+        let total_size: u64 = result.entries.iter().map(|entry| entry.size).sum();
+        for (order, entry) in result.entries.iter_mut().enumerate() {
+            entry.order = order;
+            entry.share = if total_size == 0 { 0.0 } else { entry.size as f32 / total_size as f32 };
+        }
+        result.elapsed_time = start.elapsed();
+        info!("Job complete: {:?}", output_file);
+        Ok(result)
+    }
+}
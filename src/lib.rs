@@ -20,6 +20,8 @@
 //! By default the compression level is set to 6 and the buffer size is set to 64 kilobytes. These values can be changed using the `set_compression_level` and `set_buffer_size` methods.
 //! 
 //! ```rust
+//! # use lzma_tarball::writer::LZMATarballWriter;
+//! # LZMATarballWriter::new()
 //! // ...
 //! .set_compression_level(6) // 0-9, where 0 is no compression and 9 is maximum compression
 //! .set_buffer_size(64); // 64 kilobytes
@@ -28,7 +30,9 @@
 //! Next, add the files and directories to the archive using the `with_path` method. The first argument is the path to the file or directory to add to the archive, and the second argument is the path inside the archive. If the second argument is "/", the file or directory will be placed in the root of the archive.
 //! This method will check if the provided path is a directory or file and call the appropriate method to add it to the archive.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::writer::LZMATarballWriter;
+//! # LZMATarballWriter::new()
 //! // ...
 //! .with_path("./", "/")
 //! .unwrap(); // This throws an error if the path does not exist or it could not determine if it is a file or directory
@@ -36,22 +40,32 @@
 //! 
 //! Alternatively you can use the `with_file` and `with_directory` methods to add files and directories to the archive.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::writer::LZMATarballWriter;
+//! # LZMATarballWriter::new()
 //! // ...
 //! .with_file("./file.txt", "/file.txt")
 //! .with_directory_contents("./directory", "/directory")
+//! # ;
 //! ```
 //! 
 //! You can also add all files in a directory using a filter. The filter is a closure that takes a `&DirEntry` and returns a `bool`. If the closure returns `true`, the file will be added to the archive.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::writer::LZMATarballWriter;
+//! # LZMATarballWriter::new()
 //! // ...
 //! .with_filtered_directory_contents("./", "./rs", &|entry| { entry.path().extension().is_some_and(|ext| ext == "rs") })
+//! # ;
 //! ```
 //! 
 //! Or you can add an array of file paths with the `with_files` method.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::writer::LZMATarballWriter;
+//! # use lzma_tarball::writer::ArchiveEntry;
+//! # use std::path::PathBuf;
+//! # LZMATarballWriter::new()
 //! // ...
 //! .with_files(
 //! & mut vec![
@@ -65,13 +79,19 @@
 //! 	}
 //! ]
 //! )
+//! # ;
 //! ```
 //! 
 //! All of these methods can be chained together to add multiple files and directories to the archive.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::writer::LZMATarballWriter;
+//! # use lzma_tarball::writer::ArchiveEntry;
+//! # use std::path::PathBuf;
+//! # LZMATarballWriter::new()
 //! // ...
 //! .with_path("./", "/")
+//! .unwrap()
 //! .with_file("./file.txt", "/file.txt")
 //! .with_directory_contents("./directory", "/directory")
 //! .with_filtered_directory_contents("./", "./rs", &|entry| { entry.path().extension().is_some_and(|ext| ext == "rs") })
@@ -87,13 +107,17 @@
 //! 	}
 //! ]
 //! )
+//! # ;
 //! ```
 //! 
 //! Now set the output file using the `set_output` method. This will create the parent directories if they don't exist.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::writer::LZMATarballWriter;
+//! # LZMATarballWriter::new()
 //! // ...
 //! .set_output("../test/output.tar.xz")
+//! # ;
 //! ```
 //! 
 //! Finally, call the `compress` method to compress the data. This method takes a closure that will be called with a `Progress` struct that contains information about the compression progress. The closure should return a `Result<(), Error>`.
@@ -101,11 +125,14 @@
 //! 
 //! - `percentage`: A float between 0.0 and 1.0 representing the percentage of the compression process that has been completed.
 //! - `bytes_processed`: The number of bytes that have been processed so far.
+//! - `phase`: The `Phase` compression is in: `Scanning`, `Tarring`, `Compressing` or `Finalizing`. An update is sent as each phase starts.
 //! - `bytes_per_second`: The number of bytes processed per second.   
 //!   The callback is called everytime the buffer is filled and the data flushed to disk.
 //!   So the larger the buffer size is, the less often the callback is called.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::writer::LZMATarballWriter;
+//! # LZMATarballWriter::new()
 //! // ...
 //! .compress( | progress| {
 //! // Do something with the progress
@@ -115,7 +142,7 @@
 //! 
 //! ### Full Example
 //! 
-//! ```rust
+//! ```rust,no_run
 //! use lzma_tarball::writer::{ArchiveEntry, LZMATarballWriter};
 //! use std::path::PathBuf;
 //! 
@@ -196,6 +223,8 @@
 //! By default, the reader has some predefined settings, such as not overwriting existing files, not preserving modification times, ownerships, permissions, and not unpacking extended attributes. These settings can be modified using various methods.
 //! 
 //! ```rust
+//! # use lzma_tarball::reader::LZMATarballReader;
+//! # LZMATarballReader::new()
 //! // ...
 //! .set_overwrite(true) // Allow overwriting existing files
 //! .set_mask(0o644) // Set the permission mask for extracted files
@@ -203,25 +232,32 @@
 //! .set_preserve_mtime(true) // Preserve modification times
 //! .set_preserve_ownerships(true) // Preserve ownership information
 //! .set_preserve_permissions(true) // Preserve permissions
+//! # ;
 //! ```
 //! 
 //! Next, set the archive file you want to extract using the `set_archive` method.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::reader::LZMATarballReader;
+//! # LZMATarballReader::new()
 //! // ...
 //! .set_archive("../test/archive.tar.xz").unwrap();
 //! ```
 //! 
 //! Then, set the output directory where the extracted files should be placed using the `set_output_directory` method.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::reader::LZMATarballReader;
+//! # LZMATarballReader::new()
 //! // ...
 //! .set_output_directory("../test/output").unwrap();
 //! ```
 //! 
 //! Finally, call the `decompress` method to extract the data. This method returns a `DecompressionResult` struct that contains information about the decompression process, such as the elapsed time, a list of extracted files, and the total size of all extracted files.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::reader::LZMATarballReader;
+//! # let reader = LZMATarballReader::new();
 //! // ...
 //! let result = reader.decompress().unwrap();
 //! for file in result.files {
@@ -233,7 +269,7 @@
 //! 
 //! Here is a complete example that chains all of the methods together:
 //! 
-//! ```rust
+//! ```rust,no_run
 //! use lzma_tarball::reader::LZMATarballReader;
 //! // ...
 //! let result = LZMATarballReader::new()
@@ -259,7 +295,7 @@
 //! 
 //! First, create a new instance of the `LZMATarballReader` struct and set the archive file using the `set_archive` method.
 //! 
-//! ```rust
+//! ```rust,no_run
 //! use lzma_tarball::reader::LZMATarballReader;
 //! // ...
 //! let mut reader = LZMATarballReader::new();
@@ -268,17 +304,19 @@
 //! 
 //! You can then use the `entries` method to list the entries within the archive. This method returns a `Result` with a vector of strings, each representing a file or directory path inside the archive.
 //! 
-//! ```rust
+//! ```rust,no_run
+//! # use lzma_tarball::reader::LZMATarballReader;
+//! # let mut reader = LZMATarballReader::new();
 //! // ...
 //! let entries = reader.entries().unwrap();
 //! for entry in entries {
-//!     println!("Entry: {}", entry);
+//! 	println!("Entry: {}", entry);
 //! }
 //! ```
 //! 
 //! Here is a complete example showing how to list the entries of an archive:
 //! 
-//! ```rust
+//! ```rust,no_run
 //! use lzma_tarball::reader::LZMATarballReader;
 //! // ...
 //! let entries: Vec<String> = LZMATarballReader::new()
@@ -293,10 +331,78 @@
 //! ```
 //! 
 //! This section shows how to list all the entries in a `.tar.xz` archive, providing a means to inspect the contents before deciding to extract them. This can be especially useful for verifying that the archive contains the files you expect or to simply explore its contents.
+// The examples above are indented with tabs, like the rest of the crate
+#![allow(clippy::tabs_in_doc_comments)]
 #[cfg(feature = "compression")]
 pub mod writer;
+#[cfg(feature = "compression")]
+pub mod stream_writer;
+#[cfg(feature = "compression")]
+pub mod config;
+#[cfg(feature = "compression")]
+pub mod snapshot;
+#[cfg(feature = "compression")]
+pub mod benchmark;
+#[cfg(feature = "compression")]
+pub mod lint;
+#[cfg(feature = "compression")]
+pub mod mirror;
+#[cfg(feature = "compression")]
+mod output_template;
+#[cfg(feature = "compression")]
+mod priority;
+#[cfg(feature = "compression")]
+pub mod rotation;
+#[cfg(feature = "compression")]
+mod xattr;
+#[cfg(feature = "compression")]
+pub mod job;
 #[cfg(feature = "decompression")]
 pub mod reader;
+#[cfg(feature = "decompression")]
+pub mod recovery;
+#[cfg(feature = "decompression")]
+pub mod streaming;
+#[cfg(feature = "decompression")]
+pub mod owner;
+#[cfg(feature = "decompression")]
+#[cfg_attr(not(target_os = "linux"), path = "sandbox_unsupported.rs")]
+mod sandbox;
+#[cfg(feature = "decompression")]
+mod sparse;
+#[cfg(feature = "decompression")]
+mod link_chain;
+#[cfg(feature = "decompression")]
+mod xz_index;
+#[cfg(feature = "decompression")]
+pub mod remote;
+#[cfg(any(feature = "compression", feature = "decompression"))]
+pub mod manifest;
+pub mod clock;
+#[cfg(any(feature = "compression", feature = "decompression"))]
+pub mod catalog;
+pub mod error;
+pub mod normalization;
+pub mod format;
+#[cfg(any(feature = "compression", feature = "decompression"))]
+mod hash;
+#[cfg(any(feature = "compression", feature = "decompression"))]
+mod glob;
+#[cfg(any(feature = "compression", feature = "decompression"))]
+mod preset_dictionary;
+#[cfg(any(feature = "compression", feature = "decompression"))]
+mod pax;
+#[cfg(any(feature = "compression", feature = "decompression"))]
+mod windows_names;
+#[cfg(any(feature = "compression", feature = "decompression"))]
+pub mod skip;
+pub mod phase;
+#[cfg(feature = "indicatif")]
+pub mod progress_bar;
+#[cfg(feature = "async")]
+pub mod async_task;
+#[cfg(feature = "log")]
+pub mod logging;
 #[cfg(not(feature = "log"))]
 #[allow(unused_imports)]
 #[macro_use]
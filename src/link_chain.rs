@@ -0,0 +1,72 @@
+//! # Symlink Chains
+//! Follows the symlinks created earlier in the same extraction, so an archive can't create a link
+//! and then write through it, or through a chain of such links, outside of the output directory.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+/// The number of links followed while resolving a single path before it is considered a loop.
+const MAX_LINK_HOPS: usize = 40;
+
+/// A path component left to resolve.
+enum Step {
+    Parent,
+    Name(OsString),
+}
+
+/// The symlinks extracted so far, by their resolved path relative to the output directory.
+#[derive(Debug, Default)]
+pub(crate) struct LinkChains {
+    links: HashMap<PathBuf, PathBuf>,
+}
+
+impl LinkChains {
+    /// Records a symlink at `path`, already resolved, pointing to `target`.
+    pub(crate) fn record(&mut self, path: PathBuf, target: PathBuf) {
+        self.links.insert(path, target);
+    }
+
+    /// Resolves `path`, relative to the output directory, through the recorded symlinks.
+    /// Returns `None` if it leaves the output directory, follows an absolute link or loops.
+    pub(crate) fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        let mut pending: Vec<Step> = steps(path).collect();
+        pending.reverse();
+        let mut resolved = PathBuf::new();
+        let mut hops = 0;
+        while let Some(step) = pending.pop() {
+            match step {
+                Step::Parent => {
+                    if !resolved.pop() {
+                        return None;
+                    }
+                }
+                Step::Name(name) => {
+                    resolved.push(name);
+                    let Some(target) = self.links.get(&resolved) else {
+                        continue;
+                    };
+                    hops += 1;
+                    if hops > MAX_LINK_HOPS || target.has_root() {
+                        return None;
+                    }
+                    // The target is relative to the directory containing the link
+                    resolved.pop();
+                    let start = pending.len();
+                    pending.extend(steps(target));
+                    pending[start..].reverse();
+                }
+            }
+        }
+        Some(resolved)
+    }
+}
+
+/// Splits `path` into the steps to resolve, ignoring any root.
+fn steps(path: &Path) -> impl Iterator<Item = Step> + '_ {
+    path.components().filter_map(|component| match component {
+        Component::ParentDir => Some(Step::Parent),
+        Component::Normal(name) => Some(Step::Name(name.to_os_string())),
+        Component::Prefix(..) | Component::RootDir | Component::CurDir => None,
+    })
+}
@@ -0,0 +1,113 @@
+//! # Pre-flight Checks
+//! `LZMATarballWriter::lint` looks for inputs that would fail or surprise later, before anything is compressed:
+//! files that can't be read, archive paths that collide once normalized, symlinks leaving the input tree
+//! and names that can't be extracted on Windows.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::writer::LZMATarballWriter;
+//!
+//! let mut writer = LZMATarballWriter::new();
+//! writer.with_path("./", "/").unwrap();
+//! let report = writer.lint();
+//! for issue in &report.issues {
+//!     eprintln!("{:?}", issue);
+//! }
+//! ```
+
+use crate::windows_names::invalid_windows_name;
+use crate::writer::{open_source, LZMATarballWriter};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// A problem found by `LZMATarballWriter::lint`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LintIssue {
+    /// The file can't be opened for reading
+    Unreadable { path: PathBuf, error: String },
+    /// Several files are stored at the same archive path once it is normalized
+    Collision { archive_path: String, sources: Vec<PathBuf> },
+    /// The file is a symlink resolving outside the directory all inputs share
+    SymlinkOutsideTree { path: PathBuf, target: PathBuf },
+    /// A component of the archive path can't be used as a file name on Windows
+    InvalidWindowsName { archive_path: String, reason: String },
+}
+
+/// The problems found by `LZMATarballWriter::lint`, in the order of the entries they concern with collisions last
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LintReport {
+    pub issues: Vec<LintIssue>,
+}
+
+impl LintReport {
+    /// Returns whether no problems were found
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl LZMATarballWriter {
+    /// Checks the added entries for problems before compressing, without modifying anything.
+    /// Symlinks are checked against the deepest directory containing every input.
+    pub fn lint(&self) -> LintReport {
+        debug!("Linting {} archive entries", self.archive_paths.len());
+        let mut report = LintReport::default();
+        let root = common_root(self.archive_paths.iter().map(|entry| entry.filesystem_path.as_path()));
+        let mut sources: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for entry in &self.archive_paths {
+            let path = &entry.filesystem_path;
+            if let Err(e) = open_source(path, self.no_follow) {
+                debug!("Unreadable file: {:?}", path);
+                report.issues.push(LintIssue::Unreadable { path: path.clone(), error: e.to_string() });
+            }
+            if path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+                if let (Ok(target), Some(root)) = (path.canonicalize(), &root) {
+                    if !target.starts_with(root) {
+                        debug!("Symlink {:?} resolves outside of {:?}", path, root);
+                        report.issues.push(LintIssue::SymlinkOutsideTree { path: path.clone(), target });
+                    }
+                }
+            }
+            let normalized = self.unicode_normalization.apply(&entry.archive_path);
+            let archive_path = self.apply_absolute_path_policy(&normalized).unwrap_or(normalized.trim_start_matches('/')).to_string();
+            if let Some(reason) = archive_path.split('/').filter(|name| !name.is_empty()).find_map(invalid_windows_name) {
+                report.issues.push(LintIssue::InvalidWindowsName { archive_path: archive_path.clone(), reason: reason.to_string() });
+            }
+            sources.entry(archive_path).or_default().push(path.clone());
+        }
+        for (archive_path, sources) in sources {
+            if sources.len() > 1 {
+                debug!("{} files collide at {:?}", sources.len(), archive_path);
+                report.issues.push(LintIssue::Collision { archive_path, sources });
+            }
+        }
+        info!("Lint found {} issues", report.issues.len());
+        report
+    }
+}
+
+/// Returns the deepest directory containing the parent directories of all `paths`, resolved to canonical paths
+fn common_root<'a>(paths: impl Iterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut root: Option<PathBuf> = None;
+    for path in paths {
+        let Some(parent) = path.parent().and_then(|parent| parent.canonicalize().ok()) else {
+            continue;
+        };
+        root = Some(match root {
+            None => parent,
+            Some(root) => root
+                .components()
+                .zip(parent.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(component, _)| component)
+                .collect(),
+        });
+    }
+    root
+}
@@ -1,19 +1,37 @@
+// Stands in for the `log` macros when the `log` feature is off. The arguments are still type checked inside a branch
+// that never runs, so values only used in log messages don't trigger unused warnings and the call sites stay valid expressions.
 #[macro_export]
 macro_rules! debug {
-    ($($arg:tt)*) => {};
+    ($($arg:tt)*) => {
+        if false {
+            let _ = ::std::format!($($arg)*);
+        }
+    };
 }
 
 #[macro_export]
 macro_rules! info {
-    ($($arg:tt)*) => {};
+    ($($arg:tt)*) => {
+        if false {
+            let _ = ::std::format!($($arg)*);
+        }
+    };
 }
 
 #[macro_export]
 macro_rules! warn {
-    ($($arg:tt)*) => {};
+    ($($arg:tt)*) => {
+        if false {
+            let _ = ::std::format!($($arg)*);
+        }
+    };
 }
 
 #[macro_export]
 macro_rules! error {
-    ($($arg:tt)*) => {};
+    ($($arg:tt)*) => {
+        if false {
+            let _ = ::std::format!($($arg)*);
+        }
+    };
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+//! # Logging
+//! With the `log` feature enabled, the crate logs through the `log` facade using the module path as the target,
+//! e.g. `lzma_tarball::writer` or `lzma_tarball::reader`, so applications can filter it per module in their logger.
+//! On top of that, the verbosity of the whole crate can be tuned at runtime without touching the global logger.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::logging::{set_log_level, set_quiet};
+//! use log::LevelFilter;
+//!
+//! set_log_level(LevelFilter::Info); // drop the per-entry debug messages
+//! set_quiet(true); // silence the crate entirely
+//! ```
+
+use log::{Level, LevelFilter};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Sets the most verbose level the crate logs at. Defaults to `LevelFilter::Trace`, leaving filtering to the logger.
+pub fn set_log_level(level: LevelFilter) {
+    LOG_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Sets whether the crate logs nothing at all, regardless of the log level.
+/// Clearing it restores the level set with `set_log_level`.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Returns the most verbose level the crate currently logs at, `LevelFilter::Off` when quiet.
+pub fn log_level() -> LevelFilter {
+    if QUIET.load(Ordering::Relaxed) {
+        return LevelFilter::Off;
+    }
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Returns whether messages at `level` are passed on to the logger.
+pub(crate) fn enabled(level: Level) -> bool {
+    level <= log_level()
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        if $crate::logging::enabled(::log::Level::Debug) {
+            ::log::debug!($($arg)+);
+        }
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => {
+        if $crate::logging::enabled(::log::Level::Info) {
+            ::log::info!($($arg)+);
+        }
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        if $crate::logging::enabled(::log::Level::Warn) {
+            ::log::warn!($($arg)+);
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => {
+        if $crate::logging::enabled(::log::Level::Error) {
+            ::log::error!($($arg)+);
+        }
+    };
+}
+
+// Named apart from the builtin `warn` attribute so it can be re-exported
+pub(crate) use {debug, error, info, log_warn as warn};
@@ -0,0 +1,54 @@
+//! # Archive Manifest
+//! The manifest is a JSON document stored inside the archive as [`MANIFEST_FILE_NAME`].
+//! It is written by `LZMATarballWriter::set_write_manifest` and read back by `LZMATarballReader::read_manifest`.
+
+use crate::clock;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+/// The name of the manifest entry in the root of the archive.
+pub const MANIFEST_FILE_NAME: &str = ".lzma_tarball_manifest.json";
+
+/// Describes the contents of an archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The version of `lzma_tarball` that created the archive.
+    pub tool_version: String,
+    /// The creation time of the archive in RFC 3339 format.
+    pub created_at: String,
+    /// The files stored in the archive, in archive order.
+    pub files: Vec<ManifestFile>,
+}
+
+/// A single file listed in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// The path of the file inside the archive.
+    pub path: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The hex encoded SHA-256 hash of the file contents.
+    pub sha256: String,
+}
+
+impl Manifest {
+    /// Creates an empty manifest stamped with the current time and crate version.
+    pub fn new() -> Self {
+        Self::created_at(SystemTime::now())
+    }
+
+    /// Creates an empty manifest stamped with `time` and the crate version.
+    pub fn created_at(time: SystemTime) -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: clock::rfc3339(time),
+            files: Vec::new(),
+        }
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
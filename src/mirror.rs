@@ -0,0 +1,180 @@
+//! # Output Mirrors
+//! An [`OutputMirror`] receives a copy of the compressed stream while `LZMATarballWriter::compress` writes its output,
+//! so an archive can be stored locally and on a network share in a single pass.
+//! A failing mirror is dropped without failing compression, its error is reported in `LZMAResult::mirrors`.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::writer::LZMATarballWriter;
+//!
+//! let result = LZMATarballWriter::new()
+//!     .with_path("./", "/")
+//!     .unwrap()
+//!     .set_output("backup.tar.xz")
+//!     .add_output_mirror("/mnt/nfs/backup.tar.xz")
+//!     .compress(|_| {})
+//!     .unwrap();
+//! for mirror in result.mirrors {
+//!     if let Some(error) = mirror.error {
+//!         eprintln!("Mirroring to {} failed: {}", mirror.destination, error);
+//!     }
+//! }
+//! ```
+
+use crate::writer::PartialOutput;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// A destination receiving a copy of the compressed output.
+#[derive(Clone)]
+pub enum OutputMirror {
+    /// A file created with the same mode and owner as the output
+    Path(PathBuf),
+    /// Any writer, e.g. an upload, shared so the writer stays cloneable
+    Sink(Arc<Mutex<dyn Write + Send>>),
+}
+
+impl OutputMirror {
+    /// Creates a mirror writing to `sink`
+    pub fn sink(sink: impl Write + Send + 'static) -> Self {
+        OutputMirror::Sink(Arc::new(Mutex::new(sink)))
+    }
+
+    /// Describes the mirror for `MirrorResult::destination`, sinks are named by their position among the mirrors
+    pub(crate) fn describe(&self, index: usize) -> String {
+        match self {
+            OutputMirror::Path(path) => path.display().to_string(),
+            OutputMirror::Sink(_) => format!("sink {}", index),
+        }
+    }
+}
+
+impl std::fmt::Debug for OutputMirror {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputMirror::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            OutputMirror::Sink(_) => f.write_str("Sink"),
+        }
+    }
+}
+
+impl From<PathBuf> for OutputMirror {
+    fn from(path: PathBuf) -> Self {
+        OutputMirror::Path(path)
+    }
+}
+
+impl From<&Path> for OutputMirror {
+    fn from(path: &Path) -> Self {
+        OutputMirror::Path(path.to_path_buf())
+    }
+}
+
+impl From<&str> for OutputMirror {
+    fn from(path: &str) -> Self {
+        OutputMirror::Path(PathBuf::from(path))
+    }
+}
+
+/// How writing to an output mirror went, see `LZMATarballWriter::add_output_mirror`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorResult {
+    /// The path of the mirror, or `sink` followed by its position among the mirrors
+    pub destination: String,
+    /// Why the mirror was dropped, `None` if it received the whole archive
+    pub error: Option<String>,
+}
+
+/// A mirror being written, dropped from the output once writing to it fails
+pub(crate) struct MirrorOutput<'a> {
+    destination: String,
+    writer: Option<Box<dyn Write + Send + 'a>>,
+    error: Option<String>,
+    /// Removes a mirror file that wasn't completely written
+    partial: Option<PartialOutput<'a>>,
+}
+
+impl<'a> MirrorOutput<'a> {
+    pub(crate) fn new(destination: String, writer: Box<dyn Write + Send + 'a>, partial: Option<PartialOutput<'a>>) -> Self {
+        Self { destination, writer: Some(writer), error: None, partial }
+    }
+
+    /// A mirror that couldn't be opened
+    pub(crate) fn failed(destination: String, error: impl std::fmt::Display, partial: Option<PartialOutput<'a>>) -> Self {
+        warn!("Failed to open the output mirror {}: {}", destination, error);
+        Self { destination, writer: None, error: Some(error.to_string()), partial }
+    }
+
+    fn fail(&mut self, error: io::Error) {
+        warn!("Dropping the output mirror {}: {}", self.destination, error);
+        self.writer = None;
+        self.error = Some(error.to_string());
+        // Dropping the guard removes a partially written file
+        self.partial = None;
+    }
+
+    /// Writes `buf` to the mirror, dropping it if that fails
+    pub(crate) fn write_all(&mut self, buf: &[u8]) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.write_all(buf) {
+                self.fail(e);
+            }
+        }
+    }
+
+    /// Flushes the mirror and reports how writing to it went, keeping its file if it is complete
+    pub(crate) fn finish(mut self) -> MirrorResult {
+        if let Some(mut writer) = self.writer.take() {
+            match writer.flush() {
+                Ok(()) => {
+                    if let Some(partial) = self.partial.take() {
+                        partial.complete();
+                    }
+                }
+                Err(e) => self.fail(e),
+            }
+        }
+        MirrorResult {
+            destination: self.destination,
+            error: self.error,
+        }
+    }
+}
+
+/// Writes to the output and copies everything written to the mirrors
+pub(crate) struct TeeWriter<'m, 'a, W> {
+    pub(crate) output: W,
+    pub(crate) mirrors: &'m mut [MirrorOutput<'a>],
+}
+
+impl<W: Write> Write for TeeWriter<'_, '_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.output.write(buf)?;
+        for mirror in self.mirrors.iter_mut() {
+            mirror.write_all(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Writes to a sink shared through `OutputMirror::Sink`
+pub(crate) struct SharedSink(pub(crate) Arc<Mutex<dyn Write + Send>>);
+
+impl Write for SharedSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().map_err(|_| io::Error::other("the mirror sink was poisoned"))?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().map_err(|_| io::Error::other("the mirror sink was poisoned"))?.flush()
+    }
+}
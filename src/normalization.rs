@@ -0,0 +1,33 @@
+//! # Unicode Normalization
+//! Archives created on macOS usually store names in NFD while most Linux tools produce NFC.
+//! `UnicodeNormalization` is applied to archive paths when writing and to output paths when extracting
+//! so the same name is produced regardless of where the archive was created.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use unicode_normalization::UnicodeNormalization as _;
+
+/// The Unicode normalization form applied to paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum UnicodeNormalization {
+    /// Paths are kept as they are.
+    #[default]
+    None,
+    /// Canonical composition, used by Linux and Windows.
+    Nfc,
+    /// Canonical decomposition, used by macOS.
+    Nfd,
+}
+
+impl UnicodeNormalization {
+    /// Normalizes `path` to this form.
+    pub fn apply<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        match self {
+            UnicodeNormalization::None => Cow::Borrowed(path),
+            UnicodeNormalization::Nfc if unicode_normalization::is_nfc(path) => Cow::Borrowed(path),
+            UnicodeNormalization::Nfd if unicode_normalization::is_nfd(path) => Cow::Borrowed(path),
+            UnicodeNormalization::Nfc => Cow::Owned(path.nfc().collect()),
+            UnicodeNormalization::Nfd => Cow::Owned(path.nfd().collect()),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! # Output Templates
+//! Expands the output file name set with `LZMATarballWriter::set_output_template`, so recurring backups are
+//! named without generating the names externally. `{date}` and `{hostname}` are expanded anywhere in the template,
+//! `{seq}` and `{hash}` only in the file name, as they depend on the other archives in the directory and on the archive itself.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::clock::{unix_seconds, utc_parts};
+use crate::hash::HashingReader;
+
+const DATE: &str = "{date}";
+const HOSTNAME: &str = "{hostname}";
+const SEQ: &str = "{seq}";
+const HASH: &str = "{hash}";
+
+/// The number of hex digits of the SHA-256 hash of the archive `{hash}` expands to
+const HASH_PREFIX_LEN: usize = 12;
+
+/// A part of a file name template
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Literal(&'a str),
+    Seq,
+    Hash,
+}
+
+/// Expands every token of `template` but `{hash}`, which is left in the file name until `fill_hash` is called on the written archive.
+/// `{seq}` expands to one more than the highest sequence number of the archives already matching the template, starting at 1.
+/// The parent directories of the output are created.
+pub(crate) fn expand(template: &str, now: SystemTime) -> Result<PathBuf> {
+    let (year, month, day, ..) = utc_parts(unix_seconds(now) as i64);
+    let expanded = template
+        .replace(DATE, &format!("{:04}-{:02}-{:02}", year, month, day))
+        .replace(HOSTNAME, &hostname());
+    let path = PathBuf::from(expanded);
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        bail!("The output template {:?} has no file name", template);
+    };
+    if path.parent().is_some_and(|parent| parent.to_string_lossy().contains(SEQ) || parent.to_string_lossy().contains(HASH)) {
+        bail!("The output template {:?} can only use {} and {} in the file name", template, SEQ, HASH);
+    }
+    let segments = segments(name);
+    if let Some(Segment::Literal(token)) = segments.iter().find(|segment| matches!(segment, Segment::Literal(literal) if is_token(literal))) {
+        bail!("Unknown token {} in the output template {:?}", token, template);
+    }
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    std::fs::create_dir_all(parent).with_context(|| format!("Failed to create the output directory {:?}", parent))?;
+    if !segments.contains(&Segment::Seq) {
+        return Ok(path);
+    }
+    let mut seq = 0;
+    for entry in std::fs::read_dir(parent).with_context(|| format!("Failed to read the output directory {:?}", parent))? {
+        let entry = entry?;
+        if let Some(existing) = entry.file_name().to_str().and_then(|existing| match_segments(&segments, existing)) {
+            seq = seq.max(existing);
+        }
+    }
+    Ok(path.with_file_name(name.replace(SEQ, &(seq + 1).to_string())))
+}
+
+/// Renames the written archive at `path` to expand `{hash}` in its name to a prefix of its SHA-256 hash.
+/// Returns the new path, or `path` unchanged if its name has no `{hash}`.
+pub(crate) fn fill_hash(path: &Path) -> Result<PathBuf> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()).filter(|name| name.contains(HASH)) else {
+        return Ok(path.to_path_buf());
+    };
+    let mut reader = HashingReader::new(std::fs::File::open(path)?);
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    let (_, hash) = reader.finish();
+    let renamed = path.with_file_name(name.replace(HASH, &hash[..HASH_PREFIX_LEN]));
+    std::fs::rename(path, &renamed).with_context(|| format!("Failed to rename {:?} to {:?}", path, renamed))?;
+    Ok(renamed)
+}
+
+/// Returns a glob pattern matching the file names `template` expands to, with every token as `*`.
+pub(crate) fn glob_pattern(template: &str) -> String {
+    let name = Path::new(template).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut pattern = String::new();
+    let mut rest = name.as_str();
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end + 1) else {
+            break;
+        };
+        pattern.push_str(&rest[..start]);
+        pattern.push('*');
+        rest = &rest[end..];
+    }
+    pattern.push_str(rest);
+    pattern
+}
+
+/// Splits a file name into literals and the `{seq}` and `{hash}` tokens, unknown tokens are kept as literals.
+fn segments(name: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = name;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end + 1) else {
+            break;
+        };
+        if start > 0 {
+            segments.push(Segment::Literal(&rest[..start]));
+        }
+        segments.push(match &rest[start..end] {
+            SEQ => Segment::Seq,
+            HASH => Segment::Hash,
+            token => Segment::Literal(token),
+        });
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest));
+    }
+    segments
+}
+
+fn is_token(literal: &str) -> bool {
+    literal.starts_with('{') && literal.ends_with('}') && literal.len() > 2
+}
+
+/// Returns the sequence number of `name` if it matches the template `segments`, 0 if the template has no `{seq}`.
+fn match_segments(segments: &[Segment], name: &str) -> Option<u64> {
+    match segments.split_first() {
+        None => name.is_empty().then_some(0),
+        Some((Segment::Literal(literal), rest)) => match_segments(rest, name.strip_prefix(literal)?),
+        Some((Segment::Seq, rest)) => {
+            let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            // The longest number that lets the rest of the name match
+            (1..=digits).rev().find_map(|len| {
+                match_segments(rest, &name[len..]).and_then(|_| name[..len].parse().ok())
+            })
+        }
+        Some((Segment::Hash, rest)) => {
+            let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_hexdigit()).len();
+            (1..=digits).rev().find_map(|len| match_segments(rest, &name[len..]))
+        }
+    }
+}
+
+/// Returns the name of this machine, `localhost` if it can't be read.
+fn hostname() -> String {
+    #[cfg(unix)]
+    let name = {
+        let mut buffer = [0u8; 256];
+        // SAFETY: the buffer is valid for its length, and gethostname writes at most that many bytes
+        let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+        let len = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+        (result == 0).then(|| String::from_utf8_lossy(&buffer[..len]).into_owned())
+    };
+    #[cfg(not(unix))]
+    let name = std::env::var("COMPUTERNAME").ok();
+    name.filter(|name| !name.is_empty())
+        .map(|name| name.replace(['/', '\\'], "_"))
+        .unwrap_or_else(|| "localhost".to_string())
+}
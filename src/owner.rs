@@ -0,0 +1,136 @@
+//! # Owner Name Resolution
+//! Tar headers store both numeric ids and user/group names, but ids are rarely the same across systems.
+//! When extracting as root the names can be mapped to the local ids through the system user database instead.
+
+use anyhow::Result;
+use std::path::Path;
+use tar::Header;
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// What to do when the user or group name of an entry does not exist on the local system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OwnerFallback {
+    /// The numeric id stored in the header is used.
+    #[default]
+    HeaderId,
+    /// The id of the extracting user is used.
+    CurrentUser,
+    /// Extraction fails.
+    Error,
+}
+
+/// What to do when the owner of an extracted file can't be changed, e.g. because the process isn't root.
+/// Owners that can be changed, like a group the extracting user belongs to, are still applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OwnershipFallback {
+    /// The file keeps the extracting user as its owner.
+    Ignore,
+    /// Like `Ignore`, but a warning is logged for every file.
+    Warn,
+    /// Extraction fails.
+    #[default]
+    Error,
+}
+
+/// Returns whether the current process may change file ownership.
+#[cfg(unix)]
+pub(crate) fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_root() -> bool {
+    false
+}
+
+/// Resolves the local uid and gid for the user and group names of `header`.
+#[cfg(unix)]
+pub(crate) fn resolve_owner(header: &Header, fallback: OwnerFallback) -> Result<(u32, u32)> {
+    let uid = match header.username().ok().flatten().filter(|name| !name.is_empty()) {
+        Some(name) => match lookup_uid(name) {
+            Some(uid) => uid,
+            None => fallback_id(fallback, "user", name, header.uid()?, unsafe { libc::geteuid() })?,
+        },
+        None => header.uid()? as u32,
+    };
+    let gid = match header.groupname().ok().flatten().filter(|name| !name.is_empty()) {
+        Some(name) => match lookup_gid(name) {
+            Some(gid) => gid,
+            None => fallback_id(fallback, "group", name, header.gid()?, unsafe { libc::getegid() })?,
+        },
+        None => header.gid()? as u32,
+    };
+    Ok((uid, gid))
+}
+
+/// Applies the fallback policy for a name that has no local id.
+#[cfg(unix)]
+fn fallback_id(fallback: OwnerFallback, kind: &str, name: &str, header_id: u64, current_id: u32) -> Result<u32> {
+    match fallback {
+        OwnerFallback::HeaderId => Ok(header_id as u32),
+        OwnerFallback::CurrentUser => Ok(current_id),
+        OwnerFallback::Error => anyhow::bail!("No local {} named {:?}", kind, name),
+    }
+}
+
+/// Changes the owner of `path` without following symlinks, applying `fallback` if that isn't permitted.
+#[cfg(unix)]
+pub(crate) fn set_owner(path: &Path, uid: u32, gid: u32, fallback: OwnershipFallback) -> Result<()> {
+    check_owner_change(std::os::unix::fs::lchown(path, Some(uid), Some(gid)), path, uid, gid, fallback)
+}
+
+/// Applies `fallback` to a failed attempt to change the owner of `path`, other errors are always returned.
+#[cfg(unix)]
+pub(crate) fn check_owner_change(result: std::io::Result<()>, path: &Path, uid: u32, gid: u32, fallback: OwnershipFallback) -> Result<()> {
+    use anyhow::Context;
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied && fallback != OwnershipFallback::Error => {
+            if fallback == OwnershipFallback::Warn {
+                warn!("Keeping the current owner of {:?} as it can't be changed to {}:{}: {}", path, uid, gid, e);
+            }
+            Ok(())
+        }
+        result => result.with_context(|| format!("Failed to set ownership of {:?} to {}:{}", path, uid, gid)),
+    }
+}
+
+#[cfg(unix)]
+fn lookup_uid(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    loop {
+        // SAFETY: passwd is plain old data and is only read after a successful lookup.
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        // SAFETY: all pointers are valid for the duration of the call and the buffer length is accurate.
+        let status = unsafe { libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+        if status == libc::ERANGE {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        return (status == 0 && !result.is_null()).then_some(passwd.pw_uid);
+    }
+}
+
+#[cfg(unix)]
+fn lookup_gid(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    loop {
+        // SAFETY: group is plain old data and is only read after a successful lookup.
+        let mut group: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        // SAFETY: all pointers are valid for the duration of the call and the buffer length is accurate.
+        let status = unsafe { libc::getgrnam_r(name.as_ptr(), &mut group, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+        if status == libc::ERANGE {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        return (status == 0 && !result.is_null()).then_some(group.gr_gid);
+    }
+}
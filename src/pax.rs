@@ -0,0 +1,115 @@
+//! Helpers for reading and writing pax extended header attributes.
+
+#[cfg(feature = "decompression")]
+use std::collections::HashMap;
+#[cfg(feature = "decompression")]
+use std::io::Read;
+use std::io::Write;
+#[cfg(feature = "decompression")]
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The pax attribute holding the creation time of a file, as used by libarchive.
+pub(crate) const BTIME_KEY: &str = "LIBARCHIVE.creationtime";
+/// The standard pax attribute holding the modification time of a file with sub-second precision.
+pub(crate) const MTIME_KEY: &str = "mtime";
+/// The standard pax attribute holding a path too long for the tar header.
+pub(crate) const PATH_KEY: &str = "path";
+/// The standard pax attribute holding a link target too long for the tar header.
+pub(crate) const LINKPATH_KEY: &str = "linkpath";
+/// The standard pax attribute declaring the encoding of the path attributes, `BINARY` for raw bytes.
+#[cfg(feature = "compression")]
+pub(crate) const HDRCHARSET_KEY: &str = "hdrcharset";
+
+/// Encodes pax extended header records as `<length> <key>=<value>\n`, where the length includes itself.
+/// Values are raw bytes, as extended attribute values need not be UTF-8.
+pub(crate) fn pax_records<K: AsRef<str>, V: AsRef<[u8]>>(records: &[(K, V)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (key, value) in records {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        // " key=value\n"
+        let rest = key.len() + value.len() + 3;
+        let mut length = rest + 1;
+        while length.to_string().len() + rest != length {
+            length = length.to_string().len() + rest;
+        }
+        data.extend_from_slice(format!("{} {}=", length, key).as_bytes());
+        data.extend_from_slice(value);
+        data.push(b'\n');
+    }
+    data
+}
+
+/// Appends a pax extended header holding `records`, which applies to the next entry appended.
+/// The header is named after the last component of `path`, the path of that entry.
+pub(crate) fn append_pax_header<W: Write, K: AsRef<str>, V: AsRef<[u8]>>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    records: &[(K, V)],
+) -> std::io::Result<()> {
+    let records = pax_records(records);
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::XHeader);
+    header.set_size(records.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    let name = format!("PaxHeaders/{}", path.rsplit('/').next().unwrap_or(path));
+    let name = truncate_at_char_boundary(&name, 100);
+    header.set_path(name)?;
+    header.set_cksum();
+    builder.append(&header, records.as_slice())
+}
+
+/// Returns the longest prefix of `value` that is at most `max` bytes long and ends on a char boundary.
+pub(crate) fn truncate_at_char_boundary(value: &str, max: usize) -> &str {
+    if value.len() <= max {
+        return value;
+    }
+    let end = value.char_indices().map(|(index, _)| index).take_while(|index| *index <= max).last().unwrap_or(0);
+    &value[..end]
+}
+
+/// Formats a time as decimal seconds since the epoch with nanosecond precision, e.g. `1700000000.123456789`.
+#[cfg(feature = "compression")]
+pub(crate) fn format_timestamp(time: SystemTime) -> Option<String> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{}.{:09}", since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+/// Parses decimal seconds since the epoch, ignoring digits beyond nanosecond precision.
+#[cfg(feature = "decompression")]
+pub(crate) fn parse_timestamp(value: &str) -> Option<SystemTime> {
+    let (seconds, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let seconds: u64 = seconds.parse().ok()?;
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let nanos = format!("{:0<9}", &fraction[..fraction.len().min(9)]).parse().ok()?;
+    Some(UNIX_EPOCH + Duration::new(seconds, nanos))
+}
+
+/// Returns the modification time of an entry, from its pax attributes if present or its header otherwise.
+#[cfg(all(feature = "decompression", target_os = "linux"))]
+pub(crate) fn entry_mtime<R: Read>(entry: &mut tar::Entry<R>) -> std::io::Result<SystemTime> {
+    match entry_attributes(entry)?.get(MTIME_KEY).and_then(|value| parse_timestamp(value)) {
+        Some(mtime) => Ok(mtime),
+        None => Ok(UNIX_EPOCH + Duration::from_secs(entry.header().mtime()?)),
+    }
+}
+
+/// Returns the pax attributes of an entry as UTF-8 key value pairs.
+#[cfg(feature = "decompression")]
+pub(crate) fn entry_attributes<R: Read>(entry: &mut tar::Entry<R>) -> std::io::Result<HashMap<String, String>> {
+    let mut attributes = HashMap::new();
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            if let (Ok(key), Ok(value)) = (extension.key(), extension.value()) {
+                attributes.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    Ok(attributes)
+}
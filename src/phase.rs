@@ -0,0 +1,39 @@
+//! # Progress Phases
+//! Progress updates name the stage the work is in, so front-ends can label it instead of showing
+//! a single percentage that restarts or stalls between stages.
+
+use serde::{Deserialize, Serialize};
+
+/// The stage of a compression or an extraction a progress update was sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Phase {
+    /// The input files are examined before anything is written, e.g. to size the progress total
+    Scanning,
+    /// The tar is staged as configured by `TempStrategy`, before it is compressed
+    Tarring,
+    /// The tar is compressed into the output, while it is written when it isn't staged
+    Compressing,
+    /// The encoder is flushed and the entry index and output mirrors are completed
+    Finalizing,
+    /// The archive is opened and its decoder set up, before the first entry is extracted
+    Decoding,
+    /// Entries are extracted to the output directory
+    Unpacking,
+    /// Directory permissions, owners and times are applied once their contents are extracted
+    ApplyingMetadata,
+}
+
+impl Phase {
+    /// Returns the name of the phase, e.g. `Compressing`, for progress labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Scanning => "Scanning",
+            Phase::Tarring => "Tarring",
+            Phase::Compressing => "Compressing",
+            Phase::Finalizing => "Finalizing",
+            Phase::Decoding => "Decoding",
+            Phase::Unpacking => "Unpacking",
+            Phase::ApplyingMetadata => "Applying metadata",
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! # Preset Dictionaries
+//! Raw LZMA2 streams primed with a preset dictionary. Small archives with similar contents compress much better
+//! when the encoder starts out knowing a sample of them, but the .xz format can't record a preset dictionary,
+//! so these streams have no container at all and can only be decoded with the same dictionary.
+
+use std::io;
+#[cfg(feature = "decompression")]
+use std::io::{BufRead, Read};
+#[cfg(feature = "compression")]
+use std::io::Write;
+use std::mem;
+
+/// The largest dictionary any compression level uses, so the decoder can read streams of every level
+#[cfg(feature = "decompression")]
+const DECODER_PRESET: u32 = 9;
+#[cfg(feature = "compression")]
+const OUTPUT_BUFFER_SIZE: usize = 32 * 1024;
+
+/// Builds the LZMA2 options of `preset`, primed with `dictionary`.
+fn lzma2_options(preset: u32, dictionary: &[u8]) -> io::Result<lzma_sys::lzma_options_lzma> {
+    // SAFETY: the options are plain data, zeroed is their initial state and the preset fills them in
+    let mut options: lzma_sys::lzma_options_lzma = unsafe { mem::zeroed() };
+    if unsafe { lzma_sys::lzma_lzma_preset(&mut options, preset) } != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported compression level: {}", preset)));
+    }
+    let size = u32::try_from(dictionary.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "The preset dictionary is larger than 4 GiB"))?;
+    if size > 0 {
+        options.preset_dict = dictionary.as_ptr();
+        options.preset_dict_size = size;
+    }
+    Ok(options)
+}
+
+/// Initializes a raw LZMA2 stream with `options`. liblzma copies the preset dictionary while initializing,
+/// so it only has to outlive this call.
+fn raw_stream(
+    options: &mut lzma_sys::lzma_options_lzma,
+    init: unsafe extern "C" fn(*mut lzma_sys::lzma_stream, *const lzma_sys::lzma_filter) -> lzma_sys::lzma_ret,
+) -> io::Result<lzma_sys::lzma_stream> {
+    let filters = [
+        lzma_sys::lzma_filter { id: lzma_sys::LZMA_FILTER_LZMA2, options: options as *mut _ as *mut _ },
+        lzma_sys::lzma_filter { id: lzma_sys::LZMA_VLI_UNKNOWN, options: std::ptr::null_mut() },
+    ];
+    // SAFETY: a zeroed stream is LZMA_STREAM_INIT, and the filter chain is terminated
+    let mut stream: lzma_sys::lzma_stream = unsafe { mem::zeroed() };
+    check(unsafe { init(&mut stream, filters.as_ptr()) })?;
+    Ok(stream)
+}
+
+fn check(ret: lzma_sys::lzma_ret) -> io::Result<lzma_sys::lzma_ret> {
+    match ret {
+        lzma_sys::LZMA_OK | lzma_sys::LZMA_STREAM_END => Ok(ret),
+        lzma_sys::LZMA_MEM_ERROR => Err(io::Error::new(io::ErrorKind::OutOfMemory, "LZMA2 ran out of memory")),
+        lzma_sys::LZMA_OPTIONS_ERROR => Err(io::Error::new(io::ErrorKind::InvalidInput, "Unsupported LZMA2 options")),
+        lzma_sys::LZMA_DATA_ERROR => Err(io::Error::new(io::ErrorKind::InvalidData, "Corrupt LZMA2 data, or it was compressed with a different preset dictionary")),
+        lzma_sys::LZMA_BUF_ERROR => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated LZMA2 data")),
+        other => Err(io::Error::other(format!("LZMA2 failed with code {}", other))),
+    }
+}
+
+/// Compresses everything written to it into a raw LZMA2 stream written to `inner`.
+#[cfg(feature = "compression")]
+pub(crate) struct DictionaryEncoder<W: Write> {
+    stream: lzma_sys::lzma_stream,
+    /// Taken by `finish`
+    inner: Option<W>,
+    buffer: Vec<u8>,
+}
+
+// SAFETY: the stream is only used through `&mut self` and holds no thread bound state
+#[cfg(feature = "compression")]
+unsafe impl<W: Write + Send> Send for DictionaryEncoder<W> {}
+
+#[cfg(feature = "compression")]
+impl<W: Write> DictionaryEncoder<W> {
+    pub(crate) fn new(inner: W, preset: u32, dictionary: &[u8]) -> io::Result<Self> {
+        let mut options = lzma2_options(preset, dictionary)?;
+        let stream = raw_stream(&mut options, lzma_sys::lzma_raw_encoder)?;
+        Ok(Self { stream, inner: Some(inner), buffer: vec![0; OUTPUT_BUFFER_SIZE] })
+    }
+
+    /// Runs the encoder over `input` and writes what it produced, returning whether the stream ended.
+    fn code(&mut self, input: &[u8], action: lzma_sys::lzma_action) -> io::Result<bool> {
+        self.stream.next_in = input.as_ptr();
+        self.stream.avail_in = input.len();
+        loop {
+            self.stream.next_out = self.buffer.as_mut_ptr();
+            self.stream.avail_out = self.buffer.len();
+            // SAFETY: both buffers are valid for the lengths given
+            let ret = check(unsafe { lzma_sys::lzma_code(&mut self.stream, action) })?;
+            let produced = self.buffer.len() - self.stream.avail_out;
+            self.inner.as_mut().ok_or_else(finished)?.write_all(&self.buffer[..produced])?;
+            if ret == lzma_sys::LZMA_STREAM_END {
+                return Ok(true);
+            }
+            // Keep going while output remains, and until the stream ends when finishing
+            if action == lzma_sys::LZMA_RUN && self.stream.avail_in == 0 && produced < self.buffer.len() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Ends the stream and returns the inner writer.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.code(&[], lzma_sys::LZMA_FINISH)?;
+        self.inner.take().ok_or_else(finished)
+    }
+}
+
+#[cfg(feature = "compression")]
+fn finished() -> io::Error {
+    io::Error::other("The stream was already finished")
+}
+
+#[cfg(feature = "compression")]
+impl<W: Write> Write for DictionaryEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.code(buf, lzma_sys::LZMA_RUN)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.as_mut().ok_or_else(finished)?.flush()
+    }
+}
+
+#[cfg(feature = "compression")]
+impl<W: Write> Drop for DictionaryEncoder<W> {
+    fn drop(&mut self) {
+        // SAFETY: the stream was initialized by `new`
+        unsafe { lzma_sys::lzma_end(&mut self.stream) };
+    }
+}
+
+/// Decompresses a raw LZMA2 stream read from `inner`.
+#[cfg(feature = "decompression")]
+pub(crate) struct DictionaryDecoder<R: BufRead> {
+    stream: lzma_sys::lzma_stream,
+    inner: R,
+    finished: bool,
+}
+
+// SAFETY: the stream is only used through `&mut self` and holds no thread bound state
+#[cfg(feature = "decompression")]
+unsafe impl<R: BufRead + Send> Send for DictionaryDecoder<R> {}
+
+#[cfg(feature = "decompression")]
+impl<R: BufRead> DictionaryDecoder<R> {
+    pub(crate) fn new(inner: R, dictionary: &[u8]) -> io::Result<Self> {
+        let mut options = lzma2_options(DECODER_PRESET, dictionary)?;
+        let stream = raw_stream(&mut options, lzma_sys::lzma_raw_decoder)?;
+        Ok(Self { stream, inner, finished: false })
+    }
+}
+
+#[cfg(feature = "decompression")]
+impl<R: BufRead> Read for DictionaryDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let input = self.inner.fill_buf()?;
+            let action = if input.is_empty() { lzma_sys::LZMA_FINISH } else { lzma_sys::LZMA_RUN };
+            self.stream.next_in = input.as_ptr();
+            self.stream.avail_in = input.len();
+            self.stream.next_out = buf.as_mut_ptr();
+            self.stream.avail_out = buf.len();
+            // SAFETY: both buffers are valid for the lengths given
+            let ret = check(unsafe { lzma_sys::lzma_code(&mut self.stream, action) })?;
+            let consumed = input.len() - self.stream.avail_in;
+            self.inner.consume(consumed);
+            let produced = buf.len() - self.stream.avail_out;
+            if ret == lzma_sys::LZMA_STREAM_END {
+                self.finished = true;
+                return Ok(produced);
+            }
+            if produced > 0 {
+                return Ok(produced);
+            }
+            if action == lzma_sys::LZMA_FINISH {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated LZMA2 data"));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "decompression")]
+impl<R: BufRead> Drop for DictionaryDecoder<R> {
+    fn drop(&mut self) {
+        // SAFETY: the stream was initialized by `new`
+        unsafe { lzma_sys::lzma_end(&mut self.stream) };
+    }
+}
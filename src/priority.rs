@@ -0,0 +1,55 @@
+//! Lowers the scheduling priority of the current thread for background work.
+
+use std::io;
+
+/// Lowers the CPU and I/O priority of the current thread.
+/// The change can't be undone without elevated privileges, so this is only called on dedicated threads.
+#[cfg(target_os = "linux")]
+pub(crate) fn lower_current_thread() -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+    // SAFETY: gettid has no preconditions.
+    let thread_id = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+    // On Linux the nice value and the I/O priority of a "process" id apply to that thread only
+    // SAFETY: setpriority and ioprio_set only read their integer arguments.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, thread_id, 19) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Lowers the CPU and I/O priority of the current thread.
+#[cfg(target_vendor = "apple")]
+pub(crate) fn lower_current_thread() -> io::Result<()> {
+    // SAFETY: only affects the calling thread.
+    match unsafe { libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_BACKGROUND, 0) } {
+        0 => Ok(()),
+        error => Err(io::Error::from_raw_os_error(error)),
+    }
+}
+
+/// Lowers the CPU and I/O priority of the current thread.
+#[cfg(windows)]
+pub(crate) fn lower_current_thread() -> io::Result<()> {
+    const THREAD_MODE_BACKGROUND_BEGIN: i32 = 0x0001_0000;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> *mut std::ffi::c_void;
+        fn SetThreadPriority(thread: *mut std::ffi::c_void, priority: i32) -> i32;
+    }
+    // SAFETY: the pseudo handle of the current thread is always valid.
+    if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Lowers the CPU and I/O priority of the current thread.
+#[cfg(not(any(target_os = "linux", target_vendor = "apple", windows)))]
+pub(crate) fn lower_current_thread() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "thread priorities are not supported on this platform"))
+}
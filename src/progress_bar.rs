@@ -0,0 +1,56 @@
+//! # Progress Bars
+//! Ready-made terminal progress bars for the compression and decompression callbacks, labelled with the current phase.
+//! Enabled with the `indicatif` feature.
+
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+
+#[cfg(feature = "decompression")]
+use crate::reader::{DecompressionResult, LZMATarballReader};
+#[cfg(feature = "compression")]
+use crate::writer::{LZMAResult, LZMATarballWriter};
+
+const TEMPLATE: &str =
+    "{spinner:.green} {msg} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})";
+
+/// Creates a progress bar using the shared style
+fn new_progress_bar() -> ProgressBar {
+    let progress_bar = ProgressBar::new(0);
+    if let Ok(style) = ProgressStyle::with_template(TEMPLATE) {
+        progress_bar.set_style(style.progress_chars("=> "));
+    }
+    progress_bar
+}
+
+#[cfg(feature = "compression")]
+impl LZMATarballWriter {
+    /// Compresses the input like `compress`, drawing a terminal progress bar with speed and ETA
+    pub fn compress_with_progress_bar(&self) -> Result<LZMAResult> {
+        let progress_bar = new_progress_bar();
+        let bar = progress_bar.clone();
+        let result = self.compress(move |progress| {
+            bar.set_message(progress.phase.as_str());
+            if progress.percentage > 0.0 {
+                bar.set_length((progress.bytes_processed as f64 / progress.percentage as f64) as u64);
+            }
+            bar.set_position(progress.bytes_processed);
+        });
+        progress_bar.finish_and_clear();
+        result
+    }
+}
+
+#[cfg(feature = "decompression")]
+impl LZMATarballReader {
+    /// Decompresses the archive like `decompress`, drawing a terminal progress bar with speed and ETA
+    pub fn decompress_with_progress_bar(&self) -> Result<DecompressionResult> {
+        let progress_bar = new_progress_bar();
+        let result = self.decompress_with_callback(|progress| {
+            progress_bar.set_message(progress.phase.as_str());
+            progress_bar.set_length(progress.total_bytes);
+            progress_bar.set_position(progress.bytes_processed);
+        });
+        progress_bar.finish_and_clear();
+        result
+    }
+}
@@ -1,12 +1,37 @@
 use anyhow::{Result, Context};
+use std::borrow::Cow;
 use std::fs;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, BufReader, Read, Seek, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::path::{Component, Path, PathBuf};
 use tar::Archive;
-use xz2::read::XzDecoder;
+use xz2::bufread::XzDecoder;
+use crate::catalog::{self, Difference};
+use crate::clock;
+use crate::error::ReaderError;
+use crate::format::CompressionFormat;
+use crate::glob::{glob_matches, list_archive_path, read_list};
+use crate::link_chain::LinkChains;
+use crate::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::normalization::UnicodeNormalization;
+use crate::owner::{self, OwnerFallback, OwnershipFallback};
+use crate::preset_dictionary::DictionaryDecoder;
+use crate::remote::ENTRY_INDEX_FILE_NAME;
+use crate::phase::Phase;
+use crate::pax::{self, parse_timestamp, BTIME_KEY, LINKPATH_KEY, MTIME_KEY, PATH_KEY};
+use crate::sandbox::{Sandbox, UnpackOptions};
+use crate::sparse::{self, SparseMap};
+use crate::recovery::{recover_archive, RecoveryResult};
+use crate::skip::{SkipReason, SkippedEntry};
+use crate::windows_names::{invalid_windows_name, sanitize_windows_name};
+use crate::xz_index::{XzIndex, HEADER_SIZE};
+use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "log")]
-use log::*;
+use crate::logging::*;
 #[cfg(not(feature = "log"))]
 use crate::*;
 
@@ -14,14 +39,60 @@ use crate::*;
 #[derive(Debug, Clone)]
 pub struct LZMATarballReader {
 	archive_file: Option<PathBuf>,
-	output: Option<PathBuf>,
+	/// The archive set with `from_reader`, read instead of the archive file
+	stream: Option<ArchiveStream>,
+	/// A file listing the entries of the archive, see `set_sidecar_index`
+	sidecar_index: Option<PathBuf>,
+	pub(crate) output: Option<PathBuf>,
+	format: CompressionFormat,
+	detected_format: Option<CompressionFormat>,
 	overwrite: bool,
 	mask: u32,
 	ignore_zeros: bool,
 	preserve_mtime: bool,
+	touch: bool,
 	preserve_ownerships: bool,
 	preserve_permissions: bool,
+	strip_special_mode_bits: bool,
+	hardened_links: bool,
 	unpack_xattrs: bool,
+	indexed: bool,
+	buffer_size: Option<u16>,
+	hardlink_policy: HardlinkPolicy,
+	case_collision_policy: CaseCollisionPolicy,
+	unicode_normalization: UnicodeNormalization,
+	extract_nested: u32,
+	max_unpacked_size: Option<u64>,
+	appended_streams: bool,
+	/// Decodes a raw LZMA2 stream primed with this dictionary instead of an xz stream
+	preset_dictionary: Option<Vec<u8>>,
+	resolve_owner_names: bool,
+	owner_fallback: OwnerFallback,
+	ownership_fallback: OwnershipFallback,
+	sandbox: bool,
+	entry_types: Option<HashSet<EntryType>>,
+	/// Patterns read by `set_extract_list_file`, only matching entries are listed and extracted
+	extract_list: Option<Vec<String>>,
+	/// Patterns set with `set_include_globs`, only matching entries are listed and extracted if there are any
+	include_globs: Vec<String>,
+	/// Patterns set with `set_exclude_globs`, matching entries are never listed nor extracted
+	exclude_globs: Vec<String>,
+	flatten: bool,
+	/// Patterns routing matching entries to another output directory, the first match wins
+	routes: Vec<(String, PathBuf)>,
+	flatten_conflict_policy: FlattenConflictPolicy,
+	duplicate_path_policy: DuplicatePathPolicy,
+	dir_creation_mode: Option<u32>,
+	restore_btime: bool,
+	timeout: Option<std::time::Duration>,
+	link_transform: Option<LinkTransform>,
+	permission_mapper: Option<PermissionMapper>,
+	change_detection: ChangeDetection,
+	windows_name_policy: Option<WindowsNamePolicy>,
+	/// Set by `decompress_changed_only` to skip files that are already up to date
+	changed_only: Option<ChangedOnly>,
+	/// Set when the caller stopped waiting for a worker started because of `timeout`
+	timed_out: Arc<AtomicBool>,
 }
 
 /// `DecompressionResult` holds the result of a decompression operation.
@@ -30,6 +101,809 @@ pub struct DecompressionResult {
 	pub elapsed_time: std::time::Duration,
 	pub files: Vec<String>,
 	pub total_size: u64,
+	/// Entries that weren't kept at their path because another entry had the same path, see `DuplicatePathPolicy`.
+	pub shadowed: Vec<ShadowedEntry>,
+	/// Entries that weren't extracted, with the reason
+	pub skipped: Vec<SkippedEntry>,
+}
+
+/// An entry that was replaced by, or skipped in favor of, another entry with the same path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowedEntry {
+	pub path: String,
+	/// The position of the shadowed entry in the archive, starting at 0
+	pub index: usize,
+}
+
+/// `HardlinkPolicy` decides how hard link entries are extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HardlinkPolicy {
+	/// Creates a hard link to the previously extracted target.
+	#[default]
+	Preserve,
+	/// Copies the content of the previously extracted target, for filesystems without hard link support.
+	CopyContent,
+	/// Does not extract hard links.
+	Skip,
+}
+
+/// `CaseCollisionPolicy` decides what happens to entries whose paths differ only by case
+/// when extracting onto a case-insensitive filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseCollisionPolicy {
+	/// Fails the extraction before anything is written.
+	#[default]
+	Error,
+	/// Extracts colliding entries under a new name, e.g. `readme (1)`.
+	Rename,
+	/// The last entry in the archive replaces the earlier ones.
+	LastWins,
+}
+
+/// `FlattenConflictPolicy` decides what happens when files from different directories share a name
+/// while extracting with `set_flatten`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlattenConflictPolicy {
+	/// Fails the extraction.
+	#[default]
+	Error,
+	/// Extracts conflicting files under a new name, e.g. `readme (1)`.
+	Rename,
+	/// The last file in the archive replaces the earlier ones.
+	LastWins,
+	/// The first file in the archive is kept and later ones are skipped.
+	Skip,
+}
+
+/// `DuplicatePathPolicy` decides what happens when the archive contains the same path more than once.
+/// Directories with the same path are always merged, and when flattening the last entry always wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePathPolicy {
+	/// The last entry in the archive replaces the earlier ones, as tar does.
+	#[default]
+	LastWins,
+	/// The first entry in the archive is kept and later ones are skipped.
+	FirstWins,
+	/// Extracts later entries under a new name, e.g. `readme (1)`.
+	KeepAllRenamed,
+	/// Fails the extraction at the first duplicate.
+	Error,
+}
+
+/// `ExtensionStats` counts the files with one extension, see `LZMATarballReader::stats_by_extension`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtensionStats {
+	pub count: usize,
+	/// The sum of the file sizes in bytes
+	pub total_size: u64,
+}
+
+/// `SearchOptions` configures `LZMATarballReader::search_contents`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+	/// Matches the pattern regardless of case
+	pub ignore_case: bool,
+	/// Stops searching once this many hits were found
+	pub max_hits: Option<usize>,
+	/// Searches files with a NUL byte in their first 8 KB as well, which are skipped as binary otherwise
+	pub include_binary: bool,
+}
+
+/// `SearchHit` is a line of a file entry containing the searched pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+	pub path: String,
+	/// The number of the line in the file, starting at 1
+	pub line_number: usize,
+	/// The line without its line break, invalid UTF-8 replaced
+	pub line: String,
+}
+
+/// `LinkTransform` rewrites symlink targets on extraction, see `LZMATarballReader::set_link_transform`.
+#[derive(Clone)]
+pub struct LinkTransform(Arc<dyn Fn(&Path) -> PathBuf + Send + Sync>);
+
+impl std::fmt::Debug for LinkTransform {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("LinkTransform")
+	}
+}
+
+/// `PermissionMapper` decides the mode of extracted entries, see `LZMATarballReader::set_permission_mapper`.
+#[derive(Clone)]
+pub struct PermissionMapper(Arc<dyn Fn(u32, EntryType) -> u32 + Send + Sync>);
+
+impl std::fmt::Debug for PermissionMapper {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("PermissionMapper")
+	}
+}
+
+/// An archive read from a stream, which can only be read once, see `LZMATarballReader::from_reader`.
+#[derive(Clone)]
+struct ArchiveStream(Arc<Mutex<Option<Box<dyn Read + Send>>>>);
+
+impl std::fmt::Debug for ArchiveStream {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("ArchiveStream")
+	}
+}
+
+/// `ChangeDetection` decides how `decompress_changed_only` finds files that are already up to date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChangeDetection {
+	/// A file with the size and modification time of the entry, to the second, is up to date.
+	/// Needs modification times to be preserved when extracting.
+	#[default]
+	SizeAndMtime,
+	/// A file with the size and SHA-256 hash of the entry is up to date.
+	/// The archive is read twice, once to hash the entries.
+	Hash,
+}
+
+/// `WindowsNamePolicy` decides what happens to entries whose path can't be created on Windows,
+/// such as names containing `:` or `?`, ending with a dot or naming a device like `CON`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowsNamePolicy {
+	/// Escapes the invalid path components: invalid characters and a trailing dot or space become `%XX`,
+	/// the uppercase hex of the character, and reserved device names get `_` appended to their stem,
+	/// so `a:b?.txt` is extracted as `a%3Ab%3F.txt` and `con.txt` as `con_.txt`.
+	Sanitize,
+	/// Skips the entry.
+	Skip,
+	/// Fails the extraction at the entry.
+	Error,
+}
+
+/// How `decompress_changed_only` decides which files to skip.
+#[derive(Debug, Clone)]
+enum ChangedOnly {
+	SizeAndMtime,
+	/// The paths of the files that differ, relative to the output directory
+	Changed(Arc<HashSet<PathBuf>>),
+}
+
+/// The type of an entry stored in the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryType {
+	File,
+	Directory,
+	Symlink,
+	Hardlink,
+	CharDevice,
+	BlockDevice,
+	Fifo,
+	Other,
+}
+
+/// `EntryMetadata` holds the header information of a single archive entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryMetadata {
+	pub path: String,
+	pub entry_type: EntryType,
+	pub size: u64,
+	pub mode: u32,
+	pub uid: u64,
+	pub gid: u64,
+	pub username: Option<String>,
+	pub groupname: Option<String>,
+	pub mtime: u64,
+	pub link_name: Option<String>,
+	/// Where the entry is stored, only listed in indexed mode, see `LZMATarballReader::set_indexed`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub offsets: Option<EntryOffsets>,
+}
+
+/// The entries of an archive stored next to it, see `LZMATarballWriter::set_sidecar_index`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SidecarIndex {
+	/// The size of the archive the index was written for, to notice a stale index
+	pub(crate) archive_size: u64,
+	pub(crate) entries: Vec<EntryMetadata>,
+}
+
+/// `HeaderInfo` holds the raw header fields of an entry, see `LZMATarballReader::entry_header`.
+/// Text fields that aren't valid UTF-8 are converted lossily.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderInfo {
+	/// The path of the entry after pax and GNU long name overrides
+	pub path: String,
+	/// The path in the name and prefix fields of the header itself
+	pub header_path: String,
+	/// The format of the header
+	pub header_format: HeaderFormat,
+	/// The typeflag byte, e.g. `b'0'` for a file or `b'S'` for a GNU sparse file
+	pub typeflag: u8,
+	pub mode: u32,
+	pub uid: u64,
+	pub gid: u64,
+	pub username: Option<String>,
+	pub groupname: Option<String>,
+	/// The size field of the header, the stored size of sparse files
+	pub size: u64,
+	pub mtime: u64,
+	/// The checksum field of the header
+	pub checksum: u32,
+	/// The link name field of the header, `None` if it is empty
+	pub link_name: Option<String>,
+	/// The device numbers of ustar and GNU headers, `None` for old headers and when the field is empty
+	pub device_major: Option<u32>,
+	pub device_minor: Option<u32>,
+	/// The pax attributes applying to the entry, overriding the header fields they name
+	pub pax: BTreeMap<String, String>,
+	/// The offset of the header in the decompressed tar stream
+	pub header_offset: u64,
+}
+
+/// The layout of a tar header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+	/// A pre-POSIX header without magic
+	Old,
+	/// A POSIX ustar header, used by pax archives as well
+	Ustar,
+	/// A GNU header
+	Gnu,
+}
+
+/// `EntryPath` is the path of an archive entry as stored, which may not be valid UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntryPath(Vec<u8>);
+
+impl EntryPath {
+	/// Returns the path as stored in the archive.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.0
+	}
+
+	/// Returns the path if it is valid UTF-8.
+	pub fn to_str(&self) -> Option<&str> {
+		std::str::from_utf8(&self.0).ok()
+	}
+
+	/// Returns the path with invalid UTF-8 sequences replaced by U+FFFD.
+	pub fn to_string_lossy(&self) -> Cow<'_, str> {
+		String::from_utf8_lossy(&self.0)
+	}
+
+	/// Returns the path as a filesystem path. On Unix the bytes are kept as they are,
+	/// elsewhere invalid UTF-8 sequences are replaced by U+FFFD.
+	pub fn to_path_buf(&self) -> PathBuf {
+		#[cfg(unix)]
+		return PathBuf::from(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(&self.0));
+		#[cfg(not(unix))]
+		return PathBuf::from(self.to_string_lossy().into_owned());
+	}
+}
+
+impl std::fmt::Display for EntryPath {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.to_string_lossy())
+	}
+}
+
+/// `EntryOffsets` locates an entry in the tar stream and in the compressed archive.
+/// To fetch an entry remotely, request the archive from `compressed_offset`, decode the xz block found there
+/// and skip `header_offset - block_offset` bytes of its output to reach the entry's header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryOffsets {
+	/// The offset of the entry's header in the decompressed tar stream
+	pub header_offset: u64,
+	/// The offset of the entry's data in the decompressed tar stream
+	pub data_offset: u64,
+	/// The offset in the archive file of the xz block holding the header, `None` if the index couldn't be read
+	pub compressed_offset: Option<u64>,
+	/// The offset in the decompressed tar stream at which that block starts
+	pub block_offset: Option<u64>,
+}
+
+/// `ListingStyle` decides how `format_listing` prints each entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingStyle {
+	/// The path alone, like `tar -t`
+	Short,
+	/// The mode, owner, size, modification time and path, like `tar -tv`, e.g.
+	/// `-rw-r--r-- drew/staff        13 2024-01-31 12:00 hello.txt`. Times are in UTC.
+	Long,
+}
+
+/// `ArchiveSummary` describes the contents of an archive, computed from the headers alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveSummary {
+	pub entry_count: usize,
+	/// The sum of the entry sizes in bytes
+	pub total_size: u64,
+	pub counts_by_type: HashMap<EntryType, usize>,
+	/// The path with the most components, the first one in archive order if several share the depth
+	pub deepest_path: Option<String>,
+	/// The number of components of `deepest_path`
+	pub max_depth: usize,
+}
+
+impl From<tar::EntryType> for EntryType {
+	fn from(entry_type: tar::EntryType) -> Self {
+		match entry_type {
+			tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::GNUSparse => EntryType::File,
+			tar::EntryType::Directory => EntryType::Directory,
+			tar::EntryType::Symlink => EntryType::Symlink,
+			tar::EntryType::Link => EntryType::Hardlink,
+			tar::EntryType::Char => EntryType::CharDevice,
+			tar::EntryType::Block => EntryType::BlockDevice,
+			tar::EntryType::Fifo => EntryType::Fifo,
+			_ => EntryType::Other,
+		}
+	}
+}
+
+impl EntryType {
+	/// Returns the name used for this entry type in JSON and CSV listings.
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			EntryType::File => "file",
+			EntryType::Directory => "directory",
+			EntryType::Symlink => "symlink",
+			EntryType::Hardlink => "hardlink",
+			EntryType::CharDevice => "char_device",
+			EntryType::BlockDevice => "block_device",
+			EntryType::Fifo => "fifo",
+			EntryType::Other => "other",
+		}
+	}
+}
+
+impl EntryMetadata {
+	/// Reads the metadata from a tar entry, honoring PAX and GNU long name overrides.
+	pub(crate) fn from_entry<R: Read>(entry: &tar::Entry<R>) -> Result<Self> {
+		let header = entry.header();
+		Ok(Self {
+			path: String::from_utf8_lossy(&entry.path_bytes()).into_owned(),
+			entry_type: header.entry_type().into(),
+			size: entry.size(),
+			mode: header.mode().context("Failed to read entry mode")?,
+			uid: header.uid().context("Failed to read entry uid")?,
+			gid: header.gid().context("Failed to read entry gid")?,
+			username: header.username().ok().flatten().map(|s| s.to_string()),
+			groupname: header.groupname().ok().flatten().map(|s| s.to_string()),
+			mtime: header.mtime().context("Failed to read entry mtime")?,
+			link_name: entry.link_name_bytes().map(|l| String::from_utf8_lossy(&l).into_owned()),
+			offsets: None,
+		})
+	}
+}
+
+impl HeaderInfo {
+	/// Reads the raw header fields of a tar entry.
+	fn from_entry<R: Read>(entry: &mut tar::Entry<R>) -> Result<Self> {
+		let mut pax = BTreeMap::new();
+		if let Some(extensions) = entry.pax_extensions().context("Failed to read pax attributes")? {
+			for extension in extensions {
+				let extension = extension.context("Failed to read pax attributes")?;
+				pax.insert(String::from_utf8_lossy(extension.key_bytes()).into_owned(), String::from_utf8_lossy(extension.value_bytes()).into_owned());
+			}
+		}
+		let header = entry.header();
+		let header_format = if header.as_gnu().is_some() {
+			HeaderFormat::Gnu
+		} else if header.as_ustar().is_some() {
+			HeaderFormat::Ustar
+		} else {
+			HeaderFormat::Old
+		};
+		let text = |bytes: Option<Cow<'_, [u8]>>| bytes.map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+		Ok(Self {
+			path: String::from_utf8_lossy(&entry.path_bytes()).into_owned(),
+			header_path: String::from_utf8_lossy(&header.path_bytes()).into_owned(),
+			header_format,
+			typeflag: header.entry_type().as_byte(),
+			mode: header.mode().context("Failed to read entry mode")?,
+			uid: header.uid().context("Failed to read entry uid")?,
+			gid: header.gid().context("Failed to read entry gid")?,
+			username: header.username_bytes().map(|name| String::from_utf8_lossy(name).into_owned()),
+			groupname: header.groupname_bytes().map(|name| String::from_utf8_lossy(name).into_owned()),
+			size: header.entry_size().context("Failed to read entry size")?,
+			mtime: header.mtime().context("Failed to read entry mtime")?,
+			checksum: header.cksum().context("Failed to read entry checksum")?,
+			link_name: text(header.link_name_bytes()),
+			device_major: header.device_major().ok().flatten(),
+			device_minor: header.device_minor().ok().flatten(),
+			pax,
+			header_offset: entry.raw_header_position(),
+		})
+	}
+}
+
+impl EntryMetadata {
+	/// Formats the entry as a line of a listing, without a line break, see `ListingStyle`.
+	/// Owners without a name are shown by id, and links are followed by their target.
+	pub fn format(&self, style: ListingStyle) -> String {
+		if style == ListingStyle::Short {
+			return self.path.clone();
+		}
+		let owner = format!(
+			"{}/{}",
+			self.username.clone().filter(|name| !name.is_empty()).unwrap_or_else(|| self.uid.to_string()),
+			self.groupname.clone().filter(|name| !name.is_empty()).unwrap_or_else(|| self.gid.to_string())
+		);
+		let (year, month, day, hour, minute, _) = clock::utc_parts(self.mtime as i64);
+		let mtime = format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, hour, minute);
+		let mut line = format!("{} {} {:>width$} {} {}", mode_string(self.entry_type, self.mode), owner, self.size, mtime, self.path, width = 18usize.saturating_sub(owner.len()).max(1));
+		match (self.entry_type, &self.link_name) {
+			(EntryType::Symlink, Some(target)) => line.push_str(&format!(" -> {}", target)),
+			(EntryType::Hardlink, Some(target)) => line.push_str(&format!(" link to {}", target)),
+			_ => {}
+		}
+		line
+	}
+}
+
+/// Formats a mode like `ls -l`, e.g. `drwxr-xr-x`, with the setuid, setgid and sticky bits as `s` and `t`.
+fn mode_string(entry_type: EntryType, mode: u32) -> String {
+	let mut string = String::with_capacity(10);
+	string.push(match entry_type {
+		EntryType::File => '-',
+		EntryType::Directory => 'd',
+		EntryType::Symlink => 'l',
+		EntryType::Hardlink => 'h',
+		EntryType::CharDevice => 'c',
+		EntryType::BlockDevice => 'b',
+		EntryType::Fifo => 'p',
+		EntryType::Other => '?',
+	});
+	for (shift, special, marker) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+		let bits = mode >> shift;
+		string.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+		string.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+		string.push(match (bits & 0o1 != 0, mode & special != 0) {
+			(true, true) => marker,
+			(false, true) => marker.to_ascii_uppercase(),
+			(true, false) => 'x',
+			(false, false) => '-',
+		});
+	}
+	string
+}
+
+/// Quotes a CSV field when it contains a delimiter, quote or line break.
+fn csv_field(field: &str) -> String {
+	if field.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+/// Tracks how far decoding has progressed through the archive.
+#[derive(Debug, Clone, Default)]
+struct Position {
+	decoded: Arc<AtomicU64>,
+	compressed: Arc<AtomicU64>,
+}
+
+impl Position {
+	/// Returns the number of decompressed bytes read, used to report the offset of decoding failures.
+	fn get(&self) -> u64 {
+		self.decoded.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of compressed bytes read from the archive file.
+	fn compressed(&self) -> u64 {
+		self.compressed.load(Ordering::Relaxed)
+	}
+}
+
+/// Counts the bytes read through it.
+struct CountingReader<R> {
+	inner: R,
+	count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = self.inner.read(buf)?;
+		self.count.fetch_add(read as u64, Ordering::Relaxed);
+		Ok(read)
+	}
+}
+
+type DecodedArchive = Archive<CountingReader<Box<dyn Read + Send>>>;
+
+/// Resolves the path an entry is extracted to, or `None` if it would escape the output directory.
+/// Leading `/` and `.` components are ignored, matching `tar::Entry::unpack_in`.
+pub(crate) fn entry_destination(output_dir: &Path, path: &Path) -> Option<PathBuf> {
+	let mut destination = output_dir.to_path_buf();
+	for component in path.components() {
+		match component {
+			Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
+			Component::ParentDir => return None,
+			Component::Normal(part) => destination.push(part),
+		}
+	}
+	if destination == output_dir {
+		None
+	} else {
+		Some(destination)
+	}
+}
+
+/// Unpacks an entry to an explicit destination inside `output_dir`, creating missing parent directories.
+/// Fails if the parent directory resolves outside of `output_dir`, e.g. through a symlink.
+fn unpack_to<R: Read>(entry: &mut tar::Entry<R>, destination: &Path, output_dir: &Path, position: &Position, dir_mode: Option<u32>) -> Result<()> {
+	create_parent(destination, output_dir, dir_mode)?;
+	entry.unpack(destination).map_err(|e| decode_error(e, position.get()))?;
+	Ok(())
+}
+
+/// Creates a symlink to `target` at `destination` inside `output_dir`, replacing an existing entry if `overwrite` is set.
+pub(crate) fn unpack_symlink_to(target: &Path, destination: &Path, output_dir: &Path, overwrite: bool, dir_mode: Option<u32>) -> Result<()> {
+	create_parent(destination, output_dir, dir_mode)?;
+	if overwrite && destination.symlink_metadata().is_ok() {
+		fs::remove_file(destination).context("Failed to remove existing file")?;
+	}
+	#[cfg(unix)]
+	let result = std::os::unix::fs::symlink(target, destination);
+	#[cfg(windows)]
+	let result = std::os::windows::fs::symlink_file(target, destination);
+	result.with_context(|| format!("Failed to create symlink {:?}", destination))
+}
+
+/// Creates the missing parent directories of `destination`, failing if they resolve outside of `output_dir`.
+pub(crate) fn create_parent(destination: &Path, output_dir: &Path, dir_mode: Option<u32>) -> Result<()> {
+	if let Some(parent) = destination.parent() {
+		create_dir_all(parent, dir_mode).context("Failed to create parent directory")?;
+		let canonical_parent = parent.canonicalize().context("Failed to resolve parent directory")?;
+		let canonical_output = output_dir.canonicalize().context("Failed to resolve output directory")?;
+		if !canonical_parent.starts_with(&canonical_output) {
+			error!("Refusing to unpack {:?} outside of the output directory.", destination);
+			anyhow::bail!("Entry {:?} resolves outside of the output directory", destination);
+		}
+	}
+	Ok(())
+}
+
+/// Writes a sparse file entry to `destination` with its holes restored, applying the header mode and modification time.
+/// This mirrors what `tar` does for regular files, it doesn't know the pax sparse formats. Owners are applied afterwards.
+fn unpack_sparse_to<R: Read>(entry: &mut tar::Entry<R>, map: &SparseMap, destination: &Path, options: &UnpackOptions) -> Result<()> {
+	if options.overwrite && destination.symlink_metadata().is_ok() {
+		fs::remove_file(destination).context("Failed to remove existing file")?;
+	}
+	let mut file = fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(destination)
+		.with_context(|| format!("Failed to create {:?}", destination))?;
+	sparse::write_regions(entry, map, &mut file).with_context(|| format!("Failed to write {:?}", destination))?;
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		let mode = match options.mode {
+			Some(mode) => mode,
+			None => (if options.preserve_permissions { entry.header().mode()? } else { entry.header().mode()? & 0o777 }) & !options.mask,
+		};
+		file.set_permissions(fs::Permissions::from_mode(mode)).context("Failed to set permissions")?;
+	}
+	if options.preserve_mtime {
+		let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(entry.header().mtime()?);
+		file.set_times(fs::FileTimes::new().set_modified(mtime).set_accessed(mtime)).context("Failed to set modification time")?;
+	}
+	Ok(())
+}
+
+/// Returns `destination` relative to `output_dir`.
+fn strip_output_dir<'a>(destination: &'a Path, output_dir: &Path) -> &'a Path {
+	destination.strip_prefix(output_dir).unwrap_or(destination)
+}
+
+/// Returns the name of the directory a nested archive is extracted into, or `None` if `path` is not an archive.
+fn nested_archive_stem(path: &str) -> Option<&str> {
+	[".tar.xz", ".txz", ".tar.gz", ".tgz", ".tar.zst", ".tar"]
+		.iter()
+		.find_map(|extension| path.strip_suffix(extension))
+		.map(|stem| stem.rsplit('/').next().unwrap_or(stem))
+		.filter(|stem| !stem.is_empty())
+}
+
+/// Removes the previously extracted file at `path` an entry with the same path is about to replace.
+fn remove_shadowed(output_dir: &Path, path: &Path, sandbox: Option<&Sandbox>) -> Result<()> {
+	if let Some(sandbox) = sandbox {
+		return sandbox.remove(path);
+	}
+	let Some(destination) = entry_destination(output_dir, path) else {
+		return Ok(());
+	};
+	if destination.symlink_metadata().is_ok_and(|m| !m.is_dir()) {
+		fs::remove_file(&destination).context("Failed to remove shadowed file")?;
+	}
+	Ok(())
+}
+
+/// Creates `dir` and its missing parents, applying `mode` to every directory created if set.
+fn create_dir_all(dir: &Path, mode: Option<u32>) -> io::Result<()> {
+	let Some(mode) = mode else {
+		return fs::create_dir_all(dir);
+	};
+	let missing: Vec<&Path> = dir.ancestors().take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists()).collect();
+	for directory in missing.into_iter().rev() {
+		match fs::create_dir(directory) {
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+			result => result?,
+		}
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			fs::set_permissions(directory, fs::Permissions::from_mode(mode))?;
+		}
+		#[cfg(not(unix))]
+		let _ = mode;
+	}
+	Ok(())
+}
+
+/// Opens a file or directory with just enough access to change its timestamps, even if it is read-only.
+fn open_for_times(path: &Path) -> io::Result<File> {
+	#[cfg(windows)]
+	{
+		use std::os::windows::fs::OpenOptionsExt;
+		// FILE_WRITE_ATTRIBUTES, with FILE_FLAG_BACKUP_SEMANTICS to allow opening directories
+		File::options().access_mode(0x100).custom_flags(0x0200_0000).open(path)
+	}
+	#[cfg(not(windows))]
+	File::open(path)
+}
+
+/// Sets the creation time of a file on platforms that support it.
+#[cfg(any(windows, target_os = "macos"))]
+fn set_created(file: &File, _destination: &Path, created: std::time::SystemTime) -> io::Result<()> {
+	#[cfg(target_os = "macos")]
+	use std::os::macos::fs::FileTimesExt;
+	#[cfg(windows)]
+	use std::os::windows::fs::FileTimesExt;
+	file.set_times(fs::FileTimes::new().set_created(created))
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn set_created(_file: &File, destination: &Path, _created: std::time::SystemTime) -> io::Result<()> {
+	debug!("Creation time of {:?} can't be restored on this platform.", destination);
+	Ok(())
+}
+
+/// Removes the file at `destination` if it exists, through the sandbox if one is open.
+fn remove_existing(destination: &Path, output_dir: &Path, sandbox: Option<&Sandbox>) -> Result<()> {
+	if let Some(sandbox) = sandbox {
+		sandbox.remove(strip_output_dir(destination, output_dir))
+	} else if destination.symlink_metadata().is_ok() {
+		fs::remove_file(destination).context("Failed to remove existing file")
+	} else {
+		Ok(())
+	}
+}
+
+/// Removes all but the last occurrence of every path, as later entries shadow earlier ones.
+fn shadow_duplicates<T: Clone + Eq + std::hash::Hash>(mut paths: Vec<T>) -> Vec<T> {
+	let mut seen = HashSet::new();
+	paths.reverse();
+	paths.retain(|path| seen.insert(path.clone()));
+	paths.reverse();
+	paths
+}
+
+/// Checks whether the filesystem of `dir` treats file names case-insensitively.
+/// The probe file has a name unique to this call, so existing files are never touched.
+fn is_case_insensitive(dir: &Path) -> bool {
+	static PROBE_COUNT: AtomicUsize = AtomicUsize::new(0);
+	for _ in 0..16 {
+		let name = format!(".lzma_tarball_case_probe_{}_{}", std::process::id(), PROBE_COUNT.fetch_add(1, Ordering::Relaxed));
+		let upper = dir.join(name.to_uppercase());
+		// A file already named like the upper case probe would look like the probe itself
+		if upper.symlink_metadata().is_ok() {
+			continue;
+		}
+		let probe = dir.join(&name);
+		match fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+			Ok(_) => {}
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+			Err(_) => return false,
+		}
+		let insensitive = upper.symlink_metadata().is_ok();
+		let _ = fs::remove_file(&probe);
+		return insensitive;
+	}
+	false
+}
+
+/// Appends ` (n)` to the file name until it doesn't collide with an existing file.
+fn renamed_destination(destination: &Path) -> PathBuf {
+	let stem = destination.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+	let extension = destination.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+	(1..)
+		.map(|n| destination.with_file_name(format!("{} ({}){}", stem, n, extension)))
+		.find(|candidate| candidate.symlink_metadata().is_err())
+		.unwrap()
+}
+
+/// Picks a decode buffer size in bytes based on the size of the compressed archive.
+fn adaptive_buffer_size(archive_size: u64) -> usize {
+	match archive_size {
+		0..=1_048_576 => 8 * 1024,
+		1_048_577..=67_108_864 => 64 * 1024,
+		67_108_865..=1_073_741_824 => 256 * 1024,
+		_ => 1024 * 1024,
+	}
+}
+
+/// Wraps `source` in a zstd decoder, reading every concatenated frame if `appended_streams` is set.
+#[cfg(feature = "zstd")]
+fn zstd_decoder<R: BufRead + Send + 'static>(source: R, appended_streams: bool) -> Result<Box<dyn Read + Send>> {
+	let decoder = zstd::Decoder::with_buffer(source).context("Failed to initialize the zstd decoder")?;
+	Ok(Box::new(if appended_streams { decoder } else { decoder.single_frame() }))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decoder<R: BufRead + Send + 'static>(_source: R, _appended_streams: bool) -> Result<Box<dyn Read + Send>> {
+	error!("The zstd format needs the zstd feature.");
+	anyhow::bail!("The zstd format needs the zstd feature")
+}
+
+/// Wraps `source` in a gzip decoder, reading every concatenated member if `appended_streams` is set.
+#[cfg(feature = "gzip")]
+fn gzip_decoder<R: BufRead + Send + 'static>(source: R, appended_streams: bool) -> Result<Box<dyn Read + Send>> {
+	Ok(if appended_streams {
+		Box::new(flate2::bufread::MultiGzDecoder::new(source))
+	} else {
+		Box::new(flate2::bufread::GzDecoder::new(source))
+	})
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_decoder<R: BufRead + Send + 'static>(_source: R, _appended_streams: bool) -> Result<Box<dyn Read + Send>> {
+	error!("The gzip format needs the gzip feature.");
+	anyhow::bail!("The gzip format needs the gzip feature")
+}
+
+/// Converts an I/O error raised while decoding into a typed `ReaderError` when it indicates a corrupt archive.
+fn decode_error(err: io::Error, position: u64) -> anyhow::Error {
+	match ReaderError::classify(&err, position) {
+		Some(ReaderError::InvalidTarHeader { .. }) => {
+			let offset = position.saturating_sub(512);
+			error!("Invalid tar header at offset {}: {}", offset, err);
+			anyhow::Error::new(err).context(ReaderError::InvalidTarHeader { offset })
+		}
+		Some(reader_error) => {
+			error!("Failed to decode archive: {}", reader_error);
+			anyhow::Error::new(err).context(reader_error)
+		}
+		None => anyhow::Error::new(err).context("Failed to unpack archive"),
+	}
+}
+
+/// Messages sent by the worker thread of a decompression with a timeout
+enum WorkerMessage {
+	Progress(DecompressionProgress),
+	Done(Result<DecompressionResult>),
+}
+
+/// `DecompressionProgress` reports how much of the compressed archive has been decoded.
+#[derive(Debug, Clone)]
+pub struct DecompressionProgress {
+	pub bytes_processed: u64,
+	pub total_bytes: u64,
+	pub bytes_per_second: u64,
+	pub percentage: f32,
+	/// The stage extraction is in, an update is sent as soon as each phase starts
+	pub phase: Phase,
+}
+
+impl DecompressionProgress {
+	fn new(bytes_processed: u64, total_bytes: u64, elapsed: std::time::Duration, phase: Phase) -> Self {
+		let elapsed_millis = elapsed.as_millis() as u64;
+		Self {
+			bytes_processed,
+			total_bytes,
+			bytes_per_second: (bytes_processed * 1000).checked_div(elapsed_millis).unwrap_or(0),
+			percentage: if total_bytes == 0 { 1.0 } else { (bytes_processed as f32 / total_bytes as f32).min(1.0) },
+			phase,
+		}
+	}
 }
 
 impl Default for LZMATarballReader {
@@ -45,17 +919,69 @@ impl LZMATarballReader {
 		debug!("Initializing a new LZMATarballReader with default settings.");
 		Self {
 			archive_file: None,
+			stream: None,
+			sidecar_index: None,
 			output: None,
+			format: CompressionFormat::Xz,
+			detected_format: None,
 			overwrite: false,
 			mask: 0,
 			ignore_zeros: false,
 			preserve_mtime: true,
+			touch: false,
 			preserve_ownerships: true,
 			preserve_permissions: true,
+			strip_special_mode_bits: false,
+			hardened_links: false,
 			unpack_xattrs: false,
+			indexed: false,
+			buffer_size: None,
+			hardlink_policy: HardlinkPolicy::Preserve,
+			case_collision_policy: CaseCollisionPolicy::Error,
+			unicode_normalization: UnicodeNormalization::None,
+			extract_nested: 0,
+			max_unpacked_size: None,
+			appended_streams: true,
+			preset_dictionary: None,
+			resolve_owner_names: false,
+			owner_fallback: OwnerFallback::HeaderId,
+			ownership_fallback: OwnershipFallback::Error,
+			sandbox: false,
+			entry_types: None,
+			extract_list: None,
+			include_globs: Vec::new(),
+			exclude_globs: Vec::new(),
+			flatten: false,
+			routes: Vec::new(),
+			flatten_conflict_policy: FlattenConflictPolicy::Error,
+			duplicate_path_policy: DuplicatePathPolicy::LastWins,
+			dir_creation_mode: None,
+			restore_btime: false,
+			timeout: None,
+			link_transform: None,
+			permission_mapper: None,
+			change_detection: ChangeDetection::SizeAndMtime,
+			windows_name_policy: None,
+			changed_only: None,
+			timed_out: Arc::new(AtomicBool::new(false)),
 		}
 	}
 
+	/// Creates a reader decoding the archive read from `source`, e.g. a socket, stdin or an in-memory cursor,
+	/// without persisting it to a file first. The format is detected from the first bytes, which are read right away.
+	/// A stream can only be read once, so only one method reading the archive can be called, e.g. `decompress` or `entries`,
+	/// and case collisions aren't detected ahead of extraction.
+	pub fn from_reader<R: Read + Send + 'static>(mut source: R) -> Result<Self> {
+		debug!("Creating LZMATarballReader reading from a stream.");
+		let mut header = Vec::with_capacity(512);
+		source.by_ref().take(512).read_to_end(&mut header).context("Failed to read the archive header")?;
+		let mut reader = Self::new();
+		reader.detect_format(&header);
+		let stream: Box<dyn Read + Send> = Box::new(io::Cursor::new(header).chain(source));
+		reader.stream = Some(ArchiveStream(Arc::new(Mutex::new(Some(stream)))));
+		Ok(reader)
+	}
+
 	/// Sets the archive file path.
 	pub fn set_archive(&mut self, archive: impl AsRef<Path>) -> Result<&mut Self> {
 		debug!("Attempting to set archive file: {:?}", archive.as_ref());
@@ -64,20 +990,68 @@ impl LZMATarballReader {
 			anyhow::bail!("File not found: {:?}", archive.as_ref());
 		}
 		self.archive_file = Some(archive.as_ref().to_path_buf());
+		self.stream = None;
 		info!("Archive file set to: {:?}", archive.as_ref());
+		let mut header = Vec::with_capacity(512);
+		self.open_archive_file()?.take(512).read_to_end(&mut header).context("Failed to read the archive header")?;
+		self.detect_format(&header);
 		Ok(self)
 	}
 
+	/// Returns the format `set_archive` or `from_reader` detected from the first bytes of the archive,
+	/// `None` if they weren't recognized, in which case the format set with `set_format` is used.
+	pub fn detected_format(&self) -> Option<CompressionFormat> {
+		self.detected_format
+	}
+
+	/// Detects the format of the archive from its first bytes and uses it to read the archive.
+	fn detect_format(&mut self, header: &[u8]) {
+		self.detected_format = CompressionFormat::detect(header);
+		match self.detected_format {
+			Some(format) => {
+				debug!("Detected format: {:?}.", format);
+				self.format = format;
+			}
+			None => debug!("Unknown archive header, keeping format: {:?}.", self.format),
+		}
+	}
+
 	/// Sets the output directory for decompressed files.
 	pub fn set_output_directory(&mut self, output_dir: impl AsRef<Path>) -> Result<&mut Self> {
 		let output_dir = output_dir.as_ref().to_path_buf();
 		info!("Setting output directory: {:?}", &output_dir);
 		debug!("Attempting to create output directory if it doesn't exist.");
-		fs::create_dir_all(&output_dir).context("Failed to create output directory")?;
+		create_dir_all(&output_dir, self.dir_creation_mode).context("Failed to create output directory")?;
 		self.output = Some(output_dir);
 		Ok(self)
 	}
 
+	/// Sets the permissions of directories created that are not part of the archive,
+	/// such as the output directory and missing parents of entries. By default the umask applies.
+	/// Only supported on Unix, where it should be set before `set_output_directory`.
+	pub fn set_dir_creation_mode(&mut self, mode: u32) -> &mut Self {
+		debug!("Setting directory creation mode to: {:o}.", mode);
+		self.dir_creation_mode = Some(mode);
+		self
+	}
+
+	/// Sets whether file creation times stored in `LIBARCHIVE.creationtime` pax attributes are restored.
+	/// Only Windows and macOS allow setting the creation time, it is ignored elsewhere.
+	pub fn set_restore_btime(&mut self, restore_btime: bool) -> &mut Self {
+		debug!("Setting restore_btime flag to: {}.", restore_btime);
+		self.restore_btime = restore_btime;
+		self
+	}
+
+	/// Sets the time `decompress` may take before it fails with an `io::ErrorKind::TimedOut` error,
+	/// so stuck I/O, e.g. on a hung network filesystem, doesn't block the caller indefinitely.
+	/// Extraction then runs on a worker thread, which stops at the next entry once its stuck I/O returns.
+	pub fn set_timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+		debug!("Setting timeout to: {:?}.", timeout);
+		self.timeout = Some(timeout);
+		self
+	}
+
 	/// Sets the overwrite flag.
 	pub fn set_overwrite(&mut self, overwrite: bool) -> &mut Self {
 		debug!("Setting overwrite flag to: {}", overwrite);
@@ -100,12 +1074,26 @@ impl LZMATarballReader {
 	}
 
 	/// Sets the preserve modification time flag.
+	/// Modification times stored in `mtime` pax attributes are restored with sub-second precision.
 	pub fn set_preserve_mtime(&mut self, preserve_mtime: bool) -> &mut Self {
 		debug!("Setting preserve_mtime flag to: {}.", preserve_mtime);
 		self.preserve_mtime = preserve_mtime;
 		self
 	}
 
+	/// Sets whether extracted files and directories get the current time instead of their archived
+	/// modification times, like `tar -m`, so build systems treat restored files as fresh. Overrides `set_preserve_mtime`.
+	pub fn set_touch(&mut self, touch: bool) -> &mut Self {
+		debug!("Setting touch flag to: {}.", touch);
+		self.touch = touch;
+		self
+	}
+
+	/// Returns whether archived modification times are restored.
+	fn restores_mtime(&self) -> bool {
+		self.preserve_mtime && !self.touch
+	}
+
 	/// Sets the preserve ownerships flag.
 	pub fn set_preserve_ownerships(&mut self, preserve_ownerships: bool) -> &mut Self {
 		debug!("Setting preserve_ownerships flag to: {}.", preserve_ownerships);
@@ -113,6 +1101,38 @@ impl LZMATarballReader {
 		self
 	}
 
+	/// Sets whether user and group names in entry headers are mapped to local ids when extracting as root.
+	/// Names without a local user or group are handled according to `set_owner_fallback`.
+	pub fn set_resolve_owner_names(&mut self, resolve_owner_names: bool) -> &mut Self {
+		debug!("Setting resolve_owner_names flag to: {}.", resolve_owner_names);
+		self.resolve_owner_names = resolve_owner_names;
+		self
+	}
+
+	/// Sets what happens when the owner of an extracted file can't be changed, `OwnershipFallback::Error` by default.
+	/// Extracting with `preserve_ownerships` as a regular user only succeeds when ignoring such failures.
+	pub fn set_ownership_fallback(&mut self, ownership_fallback: OwnershipFallback) -> &mut Self {
+		debug!("Setting ownership fallback to: {:?}.", ownership_fallback);
+		self.ownership_fallback = ownership_fallback;
+		self
+	}
+
+	/// Sets the id used when an owner name cannot be resolved, `OwnerFallback::HeaderId` by default.
+	pub fn set_owner_fallback(&mut self, owner_fallback: OwnerFallback) -> &mut Self {
+		debug!("Setting owner fallback to: {:?}.", owner_fallback);
+		self.owner_fallback = owner_fallback;
+		self
+	}
+
+	/// Sets whether all destination paths are resolved with `openat2` and `RESOLVE_BENEATH`.
+	/// This makes escaping the output directory through symlinks impossible even for hostile archives,
+	/// but is only supported on Linux 5.6 and newer.
+	pub fn set_sandbox(&mut self, sandbox: bool) -> &mut Self {
+		debug!("Setting sandbox flag to: {}.", sandbox);
+		self.sandbox = sandbox;
+		self
+	}
+
 	/// Sets the preserve permissions flag.
 	pub fn set_preserve_permissions(&mut self, preserve_permissions: bool) -> &mut Self {
 		debug!("Setting preserve_permissions flag to: {}.", preserve_permissions);
@@ -120,81 +1140,1611 @@ impl LZMATarballReader {
 		self
 	}
 
-	/// Lists entries in the tarball archive.
-	pub fn entries(&self) -> Result<Vec<String>> {
-		debug!("Fetching entries from archive.");
-		let archive = &mut self.get_archive()?;
-		let files = archive.entries().context("Failed to get entries from archive")?;
-		let files: Vec<String> = files
-			.filter_map(|file| {
-				file.ok().and_then(|f| {
-					f.path().ok().and_then(|p| {
-						let path_str = p.to_str().map(|s| s.to_string());
-						if let Some(ref s) = path_str {
-							debug!("Found file: {}", s);
-						}
-						path_str
-					})
-				})
-			})
-			.collect();
-		info!("Total entries fetched: {}", files.len());
-		Ok(files)
+	/// Sets whether extended attributes stored in `SCHILY.xattr.*` pax attributes are restored, on Unix only.
+	/// See `LZMATarballWriter::set_preserve_xattrs`.
+	pub fn set_unpack_xattrs(&mut self, unpack_xattrs: bool) -> &mut Self {
+		debug!("Setting unpack_xattrs flag to: {}.", unpack_xattrs);
+		self.unpack_xattrs = unpack_xattrs;
+		self
 	}
 
-	/// Returns an `Archive` object for the tarball file.
-	pub fn get_archive(&self) -> Result<Archive<XzDecoder<File>>> {
-		debug!("Retrieving archive from LZMATarballReader.");
-		if let Some(archive) = &self.archive_file {
-			debug!("Opening archive file: {:?}", archive);
-			let file = File::open(archive).context("Failed to open archive file")?;
-			let mut archive = Archive::new(XzDecoder::new(file));
-			archive.set_overwrite(self.overwrite);
-			archive.set_mask(self.mask);
-			archive.set_ignore_zeros(self.ignore_zeros);
-			archive.set_preserve_mtime(self.preserve_mtime);
-			archive.set_preserve_ownerships(self.preserve_ownerships);
-			archive.set_preserve_permissions(self.preserve_permissions);
-			archive.set_unpack_xattrs(self.unpack_xattrs);
-			info!("Archive successfully initialized with provided configurations.");
-			Ok(archive)
-		} else {
-			error!("No archive file specified in LZMATarballReader.");
-			anyhow::bail!("No archive file specified");
-		}
+	/// Sets whether `entries_metadata` includes the offsets of each entry in the tar stream and in the
+	/// compressed archive, see `EntryOffsets`. The compressed offsets come from the xz index at the end of every stream.
+	pub fn set_indexed(&mut self, indexed: bool) -> &mut Self {
+		debug!("Setting indexed flag to: {}.", indexed);
+		self.indexed = indexed;
+		self
 	}
 
-	/// Decompresses the tarball archive to the specified output directory.
-	pub fn decompress(&self) -> Result<DecompressionResult> {
-		debug!("Starting decompression process.");
-		if let Some(output_dir) = &self.output {
-			info!("Using output directory: {:?}", output_dir);
-			let start = std::time::Instant::now();
-			if !output_dir.exists() {
-				debug!("Output directory does not exist; attempting to create: {:?}", output_dir);
-				fs::create_dir_all(output_dir).context("Failed to create output directory")?;
-			}
-			let files = self.entries()?;
-			debug!("Unpacking archive into output directory.");
-			let mut archive = self.get_archive()?;
-			archive.unpack(output_dir).context("Failed to unpack archive")?;
-			let mut size = 0;
-			for file in &files {
-				let file_path = output_dir.join(file);
-				debug!("Processing file: {:?}", file_path);
-				let metadata = fs::metadata(&file_path).context("Failed to get metadata for file")?;
-				size += metadata.len();
-			}
-			let elapsed = start.elapsed();
-			info!("Decompression completed in {:?}", elapsed);
-			Ok(DecompressionResult {
-				elapsed_time: elapsed,
-				files,
-				total_size: size,
-			})
-		} else {
-			error!("Output directory not specified when decompress() was called.");
-			anyhow::bail!("No output directory specified");
+	/// Sets a sidecar index written by `LZMATarballWriter::set_sidecar_index`, so `entries`, `entry_paths` and `contains`
+	/// list the archive without decoding it. The index of seekable archives is used the same way without one.
+	/// An index written for an archive of another size is ignored, and the archive is scanned instead.
+	pub fn set_sidecar_index(&mut self, sidecar_index: Option<PathBuf>) -> &mut Self {
+		debug!("Setting sidecar index to: {:?}.", sidecar_index);
+		self.sidecar_index = sidecar_index;
+		self
+	}
+
+	/// Sets whether the setuid, setgid and sticky bits are cleared from every extracted entry,
+	/// even when permissions are preserved or set by the permission mapper. Use it for archives from untrusted sources.
+	pub fn set_strip_special_mode_bits(&mut self, strip_special_mode_bits: bool) -> &mut Self {
+		debug!("Setting strip_special_mode_bits flag to: {}.", strip_special_mode_bits);
+		self.strip_special_mode_bits = strip_special_mode_bits;
+		self
+	}
+
+	/// Sets the hardened link mode, following the symlinks created earlier in the archive when checking each entry.
+	/// Extraction fails on a symlink whose target, or an entry whose path, resolves outside of the output directory
+	/// through a chain of such links, and on any symlink with an absolute target.
+	pub fn set_hardened_links(&mut self, hardened_links: bool) -> &mut Self {
+		debug!("Setting hardened_links flag to: {}.", hardened_links);
+		self.hardened_links = hardened_links;
+		self
+	}
+
+	/// Returns whether modes are kept whole, including the setuid, setgid and sticky bits.
+	fn preserves_special_mode_bits(&self) -> bool {
+		self.preserve_permissions && !self.strip_special_mode_bits
+	}
+
+	/// Sets the decode buffer size in KB.
+	/// By default the buffer size is picked based on the size of the archive.
+	pub fn set_buffer_size(&mut self, size: u16) -> &mut Self {
+		debug!("Setting buffer size to: {} KB.", size);
+		self.buffer_size = Some(size.max(1));
+		self
+	}
+
+	/// Sets a function rewriting the target of every extracted symlink, e.g. to make absolute targets
+	/// like `/opt/app/lib` relative to the output directory when relocating a system image.
+	pub fn set_link_transform<F>(&mut self, transform: F) -> &mut Self
+	where
+		F: Fn(&Path) -> PathBuf + Send + Sync + 'static,
+	{
+		debug!("Setting link transform.");
+		self.link_transform = Some(LinkTransform(Arc::new(transform)));
+		self
+	}
+
+	/// Sets a function deciding the mode of every extracted file and directory from its archived mode and type,
+	/// e.g. to strip setuid bits or force group-readable files. The mask and `preserve_permissions` don't apply
+	/// to the returned mode. Links keep their mode. Only has an effect on Unix.
+	pub fn set_permission_mapper<F>(&mut self, mapper: F) -> &mut Self
+	where
+		F: Fn(u32, EntryType) -> u32 + Send + Sync + 'static,
+	{
+		debug!("Setting permission mapper.");
+		self.permission_mapper = Some(PermissionMapper(Arc::new(mapper)));
+		self
+	}
+
+	/// Sets how `decompress_changed_only` finds files that are already up to date.
+	pub fn set_change_detection(&mut self, change_detection: ChangeDetection) -> &mut Self {
+		debug!("Setting change detection to: {:?}.", change_detection);
+		self.change_detection = change_detection;
+		self
+	}
+
+	/// Sets how entries with paths that can't be created on Windows are extracted, on every platform.
+	/// Unset by default, extracting paths as they are.
+	pub fn set_windows_name_policy(&mut self, policy: WindowsNamePolicy) -> &mut Self {
+		debug!("Setting windows name policy to: {:?}.", policy);
+		self.windows_name_policy = Some(policy);
+		self
+	}
+
+	/// Sets how hard link entries are extracted.
+	pub fn set_hardlink_policy(&mut self, hardlink_policy: HardlinkPolicy) -> &mut Self {
+		debug!("Setting hardlink policy to: {:?}.", hardlink_policy);
+		self.hardlink_policy = hardlink_policy;
+		self
+	}
+
+	/// Sets how entries that differ only by case are handled on case-insensitive filesystems.
+	pub fn set_case_collision_policy(&mut self, case_collision_policy: CaseCollisionPolicy) -> &mut Self {
+		debug!("Setting case collision policy to: {:?}.", case_collision_policy);
+		self.case_collision_policy = case_collision_policy;
+		self
+	}
+
+	/// Sets how entries sharing a path with an earlier entry are extracted.
+	pub fn set_duplicate_path_policy(&mut self, duplicate_path_policy: DuplicatePathPolicy) -> &mut Self {
+		debug!("Setting duplicate path policy to: {:?}.", duplicate_path_policy);
+		self.duplicate_path_policy = duplicate_path_policy;
+		self
+	}
+
+	/// Sets whether every file is extracted into the root of the output directory using only its file name.
+	/// Directories, links and special files are not extracted when flattening.
+	pub fn set_flatten(&mut self, flatten: bool) -> &mut Self {
+		debug!("Setting flatten flag to: {}.", flatten);
+		self.flatten = flatten;
+		self
+	}
+
+	/// Extracts the entries matching a glob pattern, e.g. `etc/**`, into `destination` instead of the output directory,
+	/// keeping their full path. Patterns are tried in the order they were added and entries matching none go to the
+	/// output directory, so one archive can be split across several roots in a single pass.
+	pub fn route(&mut self, pattern: &str, destination: impl AsRef<Path>) -> &mut Self {
+		debug!("Routing entries matching {:?} to: {:?}.", pattern, destination.as_ref());
+		self.routes.push((pattern.to_string(), destination.as_ref().to_path_buf()));
+		self
+	}
+
+	/// Returns the destination of the first route matching `path`.
+	fn route_destination(&self, path: &str) -> Option<&PathBuf> {
+		self.routes.iter().find(|(pattern, _)| glob_matches(pattern, path)).map(|(_, destination)| destination)
+	}
+
+	/// Sets how files sharing a name are handled when flattening.
+	pub fn set_flatten_conflict_policy(&mut self, flatten_conflict_policy: FlattenConflictPolicy) -> &mut Self {
+		debug!("Setting flatten conflict policy to: {:?}.", flatten_conflict_policy);
+		self.flatten_conflict_policy = flatten_conflict_policy;
+		self
+	}
+
+	/// Sets the Unicode normalization form applied to output paths on extraction.
+	pub fn set_unicode_normalization(&mut self, normalization: UnicodeNormalization) -> &mut Self {
+		debug!("Setting unicode normalization to: {:?}.", normalization);
+		self.unicode_normalization = normalization;
+		self
+	}
+
+	/// Sets how many levels of archives inside the archive are extracted.
+	/// A nested `name.tar.xz`, `.txz`, `.tar.gz`, `.tgz`, `.tar.zst` or `.tar` is extracted into a `name` directory next to it.
+	/// The format of a nested archive is detected like by `set_archive`. Extraction fails if that directory holds other entries
+	/// of the archive, or already exists and overwriting is off. Defaults to 0.
+	pub fn set_extract_nested(&mut self, depth: u32) -> &mut Self {
+		debug!("Setting nested extraction depth to: {}.", depth);
+		self.extract_nested = depth;
+		self
+	}
+
+	/// Sets the maximum number of bytes extraction may write, protecting against archive bombs.
+	/// Nested archives count towards the same limit.
+	pub fn set_max_unpacked_size(&mut self, max_unpacked_size: Option<u64>) -> &mut Self {
+		debug!("Setting maximum unpacked size to: {:?}.", max_unpacked_size);
+		self.max_unpacked_size = max_unpacked_size;
+		self
+	}
+
+	/// Sets whether xz streams appended with `LZMATarballWriter::append_stream` are read.
+	/// All streams are presented as one logical archive where later entries shadow earlier entries with the same path.
+	/// Enabled by default.
+	pub fn set_appended_streams(&mut self, appended_streams: bool) -> &mut Self {
+		debug!("Setting appended_streams flag to: {}.", appended_streams);
+		self.appended_streams = appended_streams;
+		self
+	}
+
+	/// Sets the codec the archive was compressed with, see `CompressionFormat`. Defaults to xz.
+	/// `set_archive` detects the format, so this is only needed for archives it doesn't recognize or to override it afterwards.
+	/// Compressed offsets are only available for xz archives.
+	pub fn set_format(&mut self, format: CompressionFormat) -> &mut Self {
+		debug!("Setting format to: {:?}.", format);
+		self.format = format;
+		self
+	}
+
+	/// Sets the preset dictionary archives written with `LZMATarballWriter::set_preset_dictionary` were primed with.
+	/// Such archives are raw LZMA2 streams that can only be read with the same dictionary, and reading a regular
+	/// .tar.xz archive fails while one is set. Compressed offsets are unavailable for them.
+	pub fn set_preset_dictionary(&mut self, preset_dictionary: Option<Vec<u8>>) -> &mut Self {
+		debug!("Setting preset dictionary to: {:?} bytes.", preset_dictionary.as_ref().map(Vec::len));
+		self.preset_dictionary = preset_dictionary;
+		self
+	}
+
+	/// Limits listing and extraction to entries of the given types, all other entries are ignored.
+	/// Directories are still created as needed when their contents are extracted.
+	pub fn set_entry_types(&mut self, entry_types: &[EntryType]) -> &mut Self {
+		debug!("Setting entry types to: {:?}.", entry_types);
+		self.entry_types = Some(entry_types.iter().copied().collect());
+		self
+	}
+
+	/// Returns whether entries of this type are listed and extracted.
+	fn includes_entry_type(&self, kind: impl Into<EntryType>) -> bool {
+		let kind = kind.into();
+		self.entry_types.as_ref().is_none_or(|entry_types| entry_types.contains(&kind))
+	}
+
+	/// Limits listing and extraction to the entries named in a newline delimited file, like `tar -T`, so extraction
+	/// can be driven by file lists generated by other tools. Each line is a glob pattern as in `route` and a listed
+	/// directory includes everything beneath it. Leading `/` and `./` are ignored, as are blank lines and lines
+	/// starting with `#`. Like tar, extraction fails after extracting the listed entries if a line matched no entry.
+	pub fn set_extract_list_file(&mut self, list: impl AsRef<Path>) -> Result<&mut Self> {
+		debug!("Reading the extract list: {:?}.", list.as_ref());
+		let patterns: Vec<String> = read_list(list.as_ref())?.into_iter().map(|(_, pattern)| list_archive_path(&pattern)).collect();
+		debug!("Extract list set to: {:?}.", patterns);
+		self.extract_list = Some(patterns);
+		Ok(self)
+	}
+
+	/// Returns the indices of the extract list patterns matching `path`, `None` if there is no extract list.
+	fn listed_by(&self, path: &str) -> Option<Vec<usize>> {
+		let path = list_archive_path(path);
+		let patterns = self.extract_list.as_ref()?;
+		Some(
+			patterns
+				.iter()
+				.enumerate()
+				.filter(|(_, pattern)| glob_matches(pattern, &path) || glob_matches(&format!("{}/**", pattern), &path))
+				.map(|(index, _)| index)
+				.collect(),
+		)
+	}
+
+	/// Limits listing and extraction to the entries matching one of the glob patterns, e.g. `&["docs/**", "*.toml"]`,
+	/// so a few files can be extracted from a large archive. Patterns are as in `route` and a matching directory includes
+	/// everything beneath it. An empty list includes every entry, the default.
+	pub fn set_include_globs(&mut self, patterns: &[&str]) -> &mut Self {
+		debug!("Setting include globs to: {:?}.", patterns);
+		self.include_globs = patterns.iter().map(|pattern| list_archive_path(pattern)).collect();
+		self
+	}
+
+	/// Leaves the entries matching one of the glob patterns out of listing and extraction, even if they are included
+	/// by `set_include_globs` or the extract list. A matching directory excludes everything beneath it.
+	pub fn set_exclude_globs(&mut self, patterns: &[&str]) -> &mut Self {
+		debug!("Setting exclude globs to: {:?}.", patterns);
+		self.exclude_globs = patterns.iter().map(|pattern| list_archive_path(pattern)).collect();
+		self
+	}
+
+	/// Returns whether `path` passes the include and exclude globs.
+	fn matches_globs(&self, path: &str) -> bool {
+		let path = list_archive_path(path);
+		let matches = |pattern: &String| glob_matches(pattern, &path) || glob_matches(&format!("{}/**", pattern), &path);
+		(self.include_globs.is_empty() || self.include_globs.iter().any(matches)) && !self.exclude_globs.iter().any(matches)
+	}
+
+	/// Returns whether an entry is included by the include and exclude globs and the extract list, every entry is if there are none.
+	fn is_listed(&self, path: &str) -> bool {
+		self.matches_globs(path) && self.listed_by(path).is_none_or(|patterns| !patterns.is_empty())
+	}
+
+	/// Lists entries in the tarball archive, paths that aren't valid UTF-8 are converted lossily, see `entry_paths`.
+	pub fn entries(&self) -> Result<Vec<String>> {
+		Ok(self.entry_paths()?.iter().map(|path| path.to_string_lossy().into_owned()).collect())
+	}
+
+	/// Lists entries in the tarball archive with their paths as stored, including paths that aren't valid UTF-8.
+	/// With a sidecar index or the index of a seekable archive, the entries are read from it without decoding the archive,
+	/// and paths that aren't valid UTF-8 are converted lossily.
+	pub fn entry_paths(&self) -> Result<Vec<EntryPath>> {
+		if let Some(entries) = self.read_stored_index() {
+			let files: Vec<EntryPath> = entries
+				.into_iter()
+				.filter(|entry| self.includes_entry_type(entry.entry_type) && self.is_listed(&entry.path))
+				.map(|entry| EntryPath(entry.path.into_bytes()))
+				.collect();
+			info!("Total entries read from the index: {}", files.len());
+			return Ok(files);
+		}
+		debug!("Fetching entries from archive.");
+		let (mut archive, position) = self.open_archive()?;
+		let mut files = Vec::new();
+		for entry in archive.entries().context("Failed to get entries from archive")? {
+			let entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			if !self.includes_entry_type(entry.header().entry_type()) || !self.is_listed(&String::from_utf8_lossy(&entry.path_bytes())) {
+				continue;
+			}
+			let path = EntryPath(entry.path_bytes().into_owned());
+			debug!("Found file: {}", path);
+			files.push(path);
+		}
+		let files = shadow_duplicates(files);
+		info!("Total entries fetched: {}", files.len());
+		Ok(files)
+	}
+
+	/// Lists entries in the tarball archive along with their header metadata.
+	pub fn entries_metadata(&self) -> Result<Vec<EntryMetadata>> {
+		debug!("Fetching entry metadata from archive.");
+		let index = if self.indexed { Some(self.read_index()) } else { None };
+		let (mut archive, position) = self.open_archive()?;
+		let mut entries = Vec::new();
+		for entry in archive.entries().context("Failed to get entries from archive")? {
+			let entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			if !self.includes_entry_type(entry.header().entry_type()) {
+				continue;
+			}
+			let mut metadata = EntryMetadata::from_entry(&entry)?;
+			if !self.is_listed(&metadata.path) {
+				continue;
+			}
+			if let Some(index) = &index {
+				let header_offset = entry.raw_header_position();
+				let block = index.block_at(header_offset);
+				metadata.offsets = Some(EntryOffsets {
+					header_offset,
+					data_offset: entry.raw_file_position(),
+					compressed_offset: block.map(|block| block.compressed_offset),
+					block_offset: block.map(|block| block.uncompressed_offset),
+				});
+			}
+			entries.push(metadata);
+		}
+		let mut seen = HashSet::new();
+		entries.reverse();
+		entries.retain(|entry| seen.insert(entry.path.clone()));
+		entries.reverse();
+		info!("Total entry metadata fetched: {}", entries.len());
+		Ok(entries)
+	}
+
+	/// Summarizes the archive in a single pass over the headers, without reading entry data.
+	/// Like `entries_metadata`, the entry type and list filters apply and only the last entry with a path is counted.
+	pub fn summary(&self) -> Result<ArchiveSummary> {
+		debug!("Summarizing archive.");
+		let (mut archive, position) = self.open_archive()?;
+		let mut summary = ArchiveSummary::default();
+		let mut counted: HashMap<String, (EntryType, u64)> = HashMap::new();
+		for entry in archive.entries().context("Failed to get entries from archive")? {
+			let entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			let kind = entry.header().entry_type();
+			if !self.includes_entry_type(kind) {
+				continue;
+			}
+			let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+			if !self.is_listed(&path) {
+				continue;
+			}
+			let (entry_type, size) = (EntryType::from(kind), entry.size());
+			if let Some((previous_type, previous_size)) = counted.insert(path.clone(), (entry_type, size)) {
+				debug!("Entry {} shadows an earlier entry.", path);
+				summary.total_size -= previous_size;
+				*summary.counts_by_type.entry(previous_type).or_default() -= 1;
+				summary.entry_count -= 1;
+			}
+			summary.entry_count += 1;
+			summary.total_size += size;
+			*summary.counts_by_type.entry(entry_type).or_default() += 1;
+			let depth = Path::new(&path).components().filter(|component| matches!(component, Component::Normal(_))).count();
+			if depth > summary.max_depth {
+				summary.max_depth = depth;
+				summary.deepest_path = Some(path);
+			}
+		}
+		summary.counts_by_type.retain(|_, count| *count > 0);
+		info!("Archive summary: {} entries, {} bytes.", summary.entry_count, summary.total_size);
+		Ok(summary)
+	}
+
+	/// Compares the files in `dir` against the archive, reporting files missing from `dir`,
+	/// files whose size or SHA-256 hash differ and files in `dir` that aren't in the archive.
+	/// Directories and links in the archive are not compared.
+	pub fn compare_with_dir(&self, dir: impl AsRef<Path>) -> Result<Vec<Difference>> {
+		debug!("Comparing archive with directory: {:?}", dir.as_ref());
+		let differences = catalog::compare_tree(self, dir.as_ref())?;
+		info!("Found {} differences.", differences.len());
+		Ok(differences)
+	}
+
+	/// Extracts the archive into `dir`, only writing files that are missing from `dir` or differ
+	/// from the archive as decided by the change detection, see `set_change_detection`.
+	/// Changed files are overwritten, directories and links are always extracted and flattened extraction writes every file.
+	/// The result only lists the files that were written.
+	pub fn decompress_changed_only(&self, dir: impl AsRef<Path>) -> Result<DecompressionResult> {
+		let dir = dir.as_ref();
+		debug!("Extracting changed files into: {:?}", dir);
+		let mut reader = self.clone();
+		reader.set_output_directory(dir)?.set_overwrite(true);
+		reader.changed_only = Some(match self.change_detection {
+			ChangeDetection::SizeAndMtime => ChangedOnly::SizeAndMtime,
+			ChangeDetection::Hash => {
+				let changed = catalog::compare_tree(self, dir)?
+					.into_iter()
+					.filter(|difference| !matches!(difference, Difference::Extra { .. }))
+					.map(|difference| PathBuf::from(difference.path()))
+					.collect();
+				ChangedOnly::Changed(Arc::new(changed))
+			}
+		});
+		reader.decompress()
+	}
+
+	/// Copies the entries accepted by `filter` into `builder` without extracting them, e.g. to filter or split an archive.
+	/// Headers, pax attributes and data are copied as is, long paths are rewritten as GNU long names.
+	/// Returns the number of entries copied.
+	pub fn copy_entries_to<W: Write>(&self, builder: &mut tar::Builder<W>, mut filter: impl FnMut(&EntryMetadata) -> bool) -> Result<usize> {
+		debug!("Copying entries to tar builder.");
+		let (mut archive, position) = self.open_archive()?;
+		let mut copied = 0;
+		for entry in archive.entries().context("Failed to get entries from archive")? {
+			let mut entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			let kind = entry.header().entry_type();
+			if kind.is_pax_global_extensions() || !self.includes_entry_type(kind) {
+				continue;
+			}
+			let metadata = EntryMetadata::from_entry(&entry)?;
+			if !filter(&metadata) {
+				continue;
+			}
+			debug!("Copying entry: {:?}", metadata.path);
+			let mut header = entry.header().clone();
+			if kind.is_gnu_sparse() {
+				// `tar` reads old GNU sparse files with their holes filled in
+				header.set_entry_type(tar::EntryType::Regular);
+				header.set_size(entry.size());
+			}
+			// Records are kept in order, some keys like the GNU sparse map of format 0.0 repeat
+			let mut records = Vec::new();
+			if let Some(extensions) = entry.pax_extensions().context("Failed to read pax attributes")? {
+				for extension in extensions {
+					let extension = extension.context("Failed to read pax attribute")?;
+					let key = String::from_utf8_lossy(extension.key_bytes()).into_owned();
+					if key != PATH_KEY && key != LINKPATH_KEY {
+						records.push((key, extension.value_bytes().to_vec()));
+					}
+				}
+			}
+			if !records.is_empty() {
+				pax::append_pax_header(builder, &metadata.path, &records).context("Failed to copy pax attributes")?;
+			}
+			match &metadata.link_name {
+				Some(link_name) => builder.append_link(&mut header, &metadata.path, link_name),
+				None => builder.append_data(&mut header, &metadata.path, &mut entry),
+			}
+			.with_context(|| format!("Failed to copy {:?}", metadata.path))?;
+			copied += 1;
+		}
+		info!("Copied {} entries.", copied);
+		Ok(copied)
+	}
+
+	/// Searches the lines of every file entry for `pattern` without extracting the archive.
+	/// Entries are streamed, so only one line is held in memory at a time.
+	pub fn search_contents(&self, pattern: &str, options: &SearchOptions) -> Result<Vec<SearchHit>> {
+		debug!("Searching archive contents for: {:?}", pattern);
+		let pattern = if options.ignore_case { pattern.to_lowercase() } else { pattern.to_string() };
+		let (mut archive, position) = self.open_archive()?;
+		let mut hits = Vec::new();
+		for entry in archive.entries().context("Failed to get entries from archive")? {
+			let mut entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			let kind = entry.header().entry_type();
+			if !kind.is_file() || !self.includes_entry_type(kind) {
+				continue;
+			}
+			let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+			let mut lines = BufReader::new(&mut entry);
+			if !options.include_binary && lines.fill_buf().map_err(|e| decode_error(e, position.get()))?.contains(&0) {
+				debug!("Skipping binary file: {:?}", path);
+				continue;
+			}
+			let mut line = Vec::new();
+			let mut line_number = 0;
+			while lines.read_until(b'\n', &mut line).map_err(|e| decode_error(e, position.get()))? > 0 {
+				line_number += 1;
+				let text = String::from_utf8_lossy(&line);
+				let text = text.trim_end_matches(['\n', '\r']);
+				let found = if options.ignore_case { text.to_lowercase().contains(&pattern) } else { text.contains(&pattern) };
+				if found {
+					hits.push(SearchHit { path: path.clone(), line_number, line: text.to_string() });
+					if options.max_hits.is_some_and(|max_hits| hits.len() >= max_hits) {
+						info!("Stopped searching after {} hits.", hits.len());
+						return Ok(hits);
+					}
+				}
+				line.clear();
+			}
+		}
+		info!("Found {} hits.", hits.len());
+		Ok(hits)
+	}
+
+	/// Returns the `n` largest entries in the tarball archive, largest first.
+	/// Entries of the same size keep their archive order.
+	pub fn largest_entries(&self, n: usize) -> Result<Vec<EntryMetadata>> {
+		let mut entries = self.entries_metadata()?;
+		entries.sort_by_key(|entry| std::cmp::Reverse(entry.size));
+		entries.truncate(n);
+		debug!("Found {} largest entries.", entries.len());
+		Ok(entries)
+	}
+
+	/// Counts the files and their total size per extension, e.g. for dashboards summarizing a backup.
+	/// Extensions are lowercase without the dot, files without an extension are counted under an empty string.
+	pub fn stats_by_extension(&self) -> Result<BTreeMap<String, ExtensionStats>> {
+		let mut stats: BTreeMap<String, ExtensionStats> = BTreeMap::new();
+		for entry in self.entries_metadata()? {
+			if entry.entry_type != EntryType::File {
+				continue;
+			}
+			let extension = Path::new(&entry.path).extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+			let extension_stats = stats.entry(extension).or_default();
+			extension_stats.count += 1;
+			extension_stats.total_size += entry.size;
+		}
+		debug!("Found {} extensions.", stats.len());
+		Ok(stats)
+	}
+
+	/// Lists entries in the tarball archive as a JSON array of `EntryMetadata` objects.
+	pub fn entries_to_json(&self) -> Result<String> {
+		let entries = self.entries_metadata()?;
+		serde_json::to_string_pretty(&entries).context("Failed to serialize entries to JSON")
+	}
+
+	/// Lists entries in the tarball archive as CSV with a header row.
+	pub fn entries_to_csv(&self) -> Result<String> {
+		let entries = self.entries_metadata()?;
+		let mut csv = String::from("path,entry_type,size,mode,uid,gid,username,groupname,mtime,link_name\n");
+		for entry in entries {
+			let row = [
+				csv_field(&entry.path),
+				entry.entry_type.as_str().to_string(),
+				entry.size.to_string(),
+				entry.mode.to_string(),
+				entry.uid.to_string(),
+				entry.gid.to_string(),
+				csv_field(entry.username.as_deref().unwrap_or_default()),
+				csv_field(entry.groupname.as_deref().unwrap_or_default()),
+				entry.mtime.to_string(),
+				csv_field(entry.link_name.as_deref().unwrap_or_default()),
+			];
+			csv.push_str(&row.join(","));
+			csv.push('\n');
+		}
+		Ok(csv)
+	}
+
+	/// Lists entries in the tarball archive one per line, see `ListingStyle`.
+	pub fn format_listing(&self, style: ListingStyle) -> Result<String> {
+		let mut listing = String::new();
+		for entry in self.entries_metadata()? {
+			listing.push_str(&entry.format(style));
+			listing.push('\n');
+		}
+		Ok(listing)
+	}
+
+	/// Reads the `.lzma_tarball_manifest.json` entry without extracting the archive.
+	/// Returns `None` if the archive was created without a manifest.
+	pub fn read_manifest(&self) -> Result<Option<Manifest>> {
+		debug!("Searching archive for manifest entry.");
+		let (mut archive, position) = self.open_archive()?;
+		for entry in archive.entries().context("Failed to get entries from archive")? {
+			let mut entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			if entry.path_bytes().as_ref() != MANIFEST_FILE_NAME.as_bytes() {
+				continue;
+			}
+			let mut json = String::new();
+			entry.read_to_string(&mut json).map_err(|e| decode_error(e, position.get()))?;
+			let manifest: Manifest = serde_json::from_str(&json).context("Failed to parse manifest")?;
+			info!("Manifest found with {} files.", manifest.files.len());
+			return Ok(Some(manifest));
+		}
+		info!("No manifest found in archive.");
+		Ok(None)
+	}
+
+	/// Returns the raw header fields of the entry at `path`, e.g. to debug archives written by other tools.
+	/// Leading `/` and `./` and a trailing `/` are ignored, and the last entry wins if several share the path.
+	pub fn entry_header(&self, path: &str) -> Result<HeaderInfo> {
+		debug!("Reading the header of {:?}.", path);
+		let wanted = list_archive_path(path);
+		let (mut archive, position) = self.open_archive()?;
+		let mut found = None;
+		for entry in archive.entries().context("Failed to get entries from archive")? {
+			let mut entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			if list_archive_path(&String::from_utf8_lossy(&entry.path_bytes())) == wanted {
+				found = Some(HeaderInfo::from_entry(&mut entry)?);
+			}
+		}
+		match found {
+			Some(header) => Ok(header),
+			None => {
+				error!("Entry {:?} not found in archive.", path);
+				anyhow::bail!("Entry {:?} not found in archive", path)
+			}
+		}
+	}
+
+	/// Returns whether the archive holds an entry at `path`, ignoring the entry type and list filters.
+	/// Leading `/` and `./` and a trailing `/` are ignored. Archives with an index are answered from it, see `set_sidecar_index`,
+	/// others are scanned until the first match.
+	pub fn contains(&self, path: &str) -> Result<bool> {
+		debug!("Checking whether the archive contains {:?}.", path);
+		let wanted = list_archive_path(path);
+		if let Some(entries) = self.read_stored_index() {
+			return Ok(entries.iter().any(|entry| list_archive_path(&entry.path) == wanted));
+		}
+		let (mut archive, position) = self.open_archive()?;
+		for entry in archive.entries().context("Failed to get entries from archive")? {
+			let entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			if list_archive_path(&String::from_utf8_lossy(&entry.path_bytes())) == wanted {
+				return Ok(true);
+			}
+		}
+		Ok(false)
+	}
+
+	/// Reads the sidecar index, or the entry index of a seekable archive without one, `None` if neither is usable.
+	fn read_stored_index(&self) -> Option<Vec<EntryMetadata>> {
+		let Some(sidecar_index) = &self.sidecar_index else {
+			return self.read_entry_index();
+		};
+		let read = || -> Result<Option<Vec<EntryMetadata>>> {
+			let index: SidecarIndex = serde_json::from_reader(BufReader::new(File::open(sidecar_index)?)).context("Failed to parse the sidecar index")?;
+			let archive_size = self.open_archive_file()?.metadata()?.len();
+			if index.archive_size != archive_size {
+				warn!("The sidecar index {:?} was written for another archive, scanning the archive instead.", sidecar_index);
+				return Ok(None);
+			}
+			Ok(Some(index.entries))
+		};
+		read().unwrap_or_else(|e| {
+			warn!("Failed to read the sidecar index {:?}, scanning the archive instead: {:#}", sidecar_index, e);
+			None
+		})
+	}
+
+	/// Reads the entry index appended to seekable archives, `None` if the archive has none.
+	fn read_entry_index(&self) -> Option<Vec<EntryMetadata>> {
+		let index = self.read_index();
+		let (first, last) = (index.blocks().first()?, index.blocks().last()?);
+		// The entry index is a stream of its own, the single block of a regular archive is the whole archive
+		if first.stream_offset == last.stream_offset {
+			return None;
+		}
+		let read = || -> Result<Option<Vec<EntryMetadata>>> {
+			let mut file = self.open_archive_file()?;
+			let mut header = vec![0; HEADER_SIZE as usize];
+			file.seek(io::SeekFrom::Start(last.stream_offset))?;
+			file.read_exact(&mut header)?;
+			let mut data = vec![0; last.compressed_size as usize];
+			file.seek(io::SeekFrom::Start(last.compressed_offset))?;
+			file.read_exact(&mut data)?;
+			// Archives with appended streams end with a regular stream, only its first header is decoded to tell
+			let first_header = last.decode(&header, &data, 512)?;
+			if first_header.len() < 512 || tar::Header::from_byte_slice(&first_header).path_bytes().as_ref() != ENTRY_INDEX_FILE_NAME.as_bytes() {
+				return Ok(None);
+			}
+			let tar = last.decode(&header, &data, last.uncompressed_size)?;
+			let mut tar = Archive::new(tar.as_slice());
+			let mut entry = tar.entries()?.next().context("The entry index stream is empty")??;
+			Ok(Some(serde_json::from_reader(&mut entry).context("Failed to parse the entry index")?))
+		};
+		read().unwrap_or_else(|e| {
+			warn!("Failed to read the entry index, scanning the archive instead: {:#}", e);
+			None
+		})
+	}
+
+	/// Returns an `Archive` object for the tarball file.
+	pub fn get_archive(&self) -> Result<Archive<Box<dyn Read + Send>>> {
+		debug!("Retrieving archive from LZMATarballReader.");
+		let mut archive = Archive::new(self.open_decoder(&Arc::default())?);
+		self.configure_archive(&mut archive);
+		Ok(archive)
+	}
+
+	/// Opens the archive with a counter tracking how many decompressed bytes have been read,
+	/// used to report the offset of decoding failures.
+	fn open_archive(&self) -> Result<(DecodedArchive, Position)> {
+		if let Some(stream) = self.take_stream()? {
+			return self.archive_from(stream, 0);
+		}
+		let file = self.open_archive_file()?;
+		let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+		self.archive_from(Box::new(file), size)
+	}
+
+	/// Decodes an archive read from `source`, whose compressed size is `size` or 0 if it is unknown.
+	fn archive_from(&self, source: Box<dyn Read + Send>, size: u64) -> Result<(DecodedArchive, Position)> {
+		let position = Position::default();
+		let reader = CountingReader {
+			inner: self.decoder_from(source, size, &position.compressed)?,
+			count: position.decoded.clone(),
+		};
+		let mut archive = Archive::new(reader);
+		self.configure_archive(&mut archive);
+		// Owners are applied after unpacking, so a failure can go through the ownership fallback
+		archive.set_preserve_ownerships(false);
+		Ok((archive, position))
+	}
+
+	/// Opens the archive file and wraps it in a buffered xz decoder.
+	/// `compressed` counts the bytes read from the archive file.
+	fn open_decoder(&self, compressed: &Arc<AtomicU64>) -> Result<Box<dyn Read + Send>> {
+		if let Some(stream) = self.take_stream()? {
+			return self.decoder_from(stream, 0, compressed);
+		}
+		let file = self.open_archive_file()?;
+		let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+		self.decoder_from(Box::new(file), size, compressed)
+	}
+
+	/// Wraps `source` in a buffered xz decoder, `compressed` counts the bytes read from it.
+	fn decoder_from(&self, source: Box<dyn Read + Send>, size: u64, compressed: &Arc<AtomicU64>) -> Result<Box<dyn Read + Send>> {
+		let buffer_size = match self.buffer_size {
+			Some(kb) => kb as usize * 1024,
+			None => adaptive_buffer_size(size),
+		};
+		debug!("Decoding archive with a {} byte buffer.", buffer_size);
+		let file = CountingReader { inner: source, count: compressed.clone() };
+		let decoder: Box<dyn Read + Send> = if self.format == CompressionFormat::Zstd {
+			zstd_decoder(BufReader::with_capacity(buffer_size, file), self.appended_streams)?
+		} else if self.format == CompressionFormat::Gzip {
+			gzip_decoder(BufReader::with_capacity(buffer_size, file), self.appended_streams)?
+		} else if self.format == CompressionFormat::None {
+			Box::new(file)
+		} else if self.format == CompressionFormat::Bzip2 {
+			error!("Reading bzip2 archives isn't supported.");
+			anyhow::bail!("The archive is a bzip2 archive, which can't be read");
+		} else if let Some(dictionary) = &self.preset_dictionary {
+			let decoder = DictionaryDecoder::new(BufReader::with_capacity(buffer_size, file), dictionary).context("Failed to initialize the preset dictionary decoder")?;
+			Box::new(decoder)
+		} else if self.appended_streams {
+			Box::new(XzDecoder::new_multi_decoder(BufReader::with_capacity(buffer_size, file)))
+		} else {
+			Box::new(XzDecoder::new(BufReader::with_capacity(buffer_size, file)))
+		};
+		Ok(Box::new(BufReader::with_capacity(buffer_size, decoder)))
+	}
+
+	/// Reads the xz index of the archive file, empty if it is damaged or the archive is read from a stream.
+	fn read_index(&self) -> XzIndex {
+		if self.preset_dictionary.is_some() || self.format != CompressionFormat::Xz || self.stream.is_some() {
+			return XzIndex::default();
+		}
+		let index = self.open_archive_file().and_then(|mut file| XzIndex::read(&mut file));
+		index.unwrap_or_else(|e| {
+			warn!("Failed to read the xz index, compressed offsets are unavailable: {:#}", e);
+			XzIndex::default()
+		})
+	}
+
+	/// Takes the stream set with `from_reader`, `None` if the archive is read from a file.
+	fn take_stream(&self) -> Result<Option<Box<dyn Read + Send>>> {
+		let Some(ArchiveStream(stream)) = &self.stream else {
+			return Ok(None);
+		};
+		match stream.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+			Some(stream) => Ok(Some(stream)),
+			None => {
+				error!("The archive stream was already read.");
+				anyhow::bail!("The archive stream was already read, a stream can only be read once")
+			}
+		}
+	}
+
+	fn open_archive_file(&self) -> Result<File> {
+		if let Some(archive) = &self.archive_file {
+			debug!("Opening archive file: {:?}", archive);
+			File::open(archive).context("Failed to open archive file")
+		} else {
+			error!("No archive file specified in LZMATarballReader.");
+			anyhow::bail!("No archive file specified");
+		}
+	}
+
+	fn configure_archive<R: Read>(&self, archive: &mut Archive<R>) {
+		archive.set_overwrite(self.overwrite);
+		archive.set_mask(self.mask);
+		// The end-of-archive blocks of every appended stream must be skipped to reach the next one
+		archive.set_ignore_zeros(self.ignore_zeros || self.appended_streams);
+		archive.set_preserve_mtime(self.restores_mtime());
+		archive.set_preserve_ownerships(self.preserve_ownerships);
+		// Without it tar only keeps the permission bits, dropping the special ones
+		archive.set_preserve_permissions(self.preserves_special_mode_bits());
+		archive.set_unpack_xattrs(self.unpack_xattrs);
+		info!("Archive successfully initialized with provided configurations.");
+	}
+
+	/// Copies the content of a hard link's target instead of linking to it.
+	fn copy_hardlink_content<R: Read>(&self, entry: &tar::Entry<R>, output_dir: &Path, sandbox: Option<&Sandbox>) -> Result<()> {
+		let link_name = entry.link_name().context("Failed to read hard link target")?;
+		let (Some(destination), Some(target)) = (
+			entry_destination(output_dir, &entry.path().context("Failed to read hard link path")?),
+			link_name.and_then(|link| entry_destination(output_dir, &link)),
+		) else {
+			warn!("Skipping hard link with an invalid path: {:?}", entry.path_bytes());
+			return Ok(());
+		};
+		debug!("Copying hard link target {:?} to {:?}", target, destination);
+		if let Some(sandbox) = sandbox {
+			return sandbox.copy(strip_output_dir(&target, output_dir), strip_output_dir(&destination, output_dir), self.overwrite);
+		}
+		if destination.exists() {
+			if !self.overwrite {
+				anyhow::bail!("File already exists: {:?}", destination);
+			}
+			fs::remove_file(&destination).context("Failed to remove existing file")?;
+		}
+		if let Some(parent) = destination.parent() {
+			create_dir_all(parent, self.dir_creation_mode).context("Failed to create parent directory")?;
+		}
+		fs::copy(&target, &destination).with_context(|| format!("Failed to copy hard link target {:?}", target))?;
+		Ok(())
+	}
+
+	/// Returns whether output paths can differ from entry paths, which `unpack_in` can't handle.
+	fn rewrites_paths(&self) -> bool {
+		self.unicode_normalization != UnicodeNormalization::None || self.windows_name_policy == Some(WindowsNamePolicy::Sanitize)
+	}
+
+	/// Applies the Unicode normalization option and, with `WindowsNamePolicy::Sanitize`, escapes each component of `path`.
+	fn local_path<'a>(&self, path: &'a str) -> Cow<'a, str> {
+		let path = self.unicode_normalization.apply(path);
+		if self.windows_name_policy != Some(WindowsNamePolicy::Sanitize) || !path.split('/').any(|name| invalid_windows_name(name).is_some()) {
+			return path;
 		}
+		Cow::Owned(path.split('/').map(|name| if name.is_empty() { Cow::Borrowed(name) } else { sanitize_windows_name(name) }).collect::<Vec<_>>().join("/"))
 	}
-}
\ No newline at end of file
+
+	/// Resolves the output path of an entry after applying the Unicode normalization and Windows name options.
+	fn normalized_destination<R: Read>(&self, entry: &tar::Entry<R>, output_dir: &Path) -> Result<Option<PathBuf>> {
+		let path = entry.path().context("Failed to read entry path")?;
+		let destination = match path.to_str() {
+			Some(name) => entry_destination(output_dir, Path::new(self.local_path(name).as_ref())),
+			None => entry_destination(output_dir, &path),
+		};
+		Ok(destination)
+	}
+
+	/// Returns whether a file entry can be skipped by `decompress_changed_only` as its destination is up to date.
+	fn is_unchanged<R: Read>(&self, entry: &tar::Entry<R>, output_dir: &Path) -> Result<bool> {
+		match &self.changed_only {
+			None => Ok(false),
+			Some(ChangedOnly::Changed(changed)) => {
+				let path = entry.path().context("Failed to read entry path")?;
+				let path: PathBuf = path.components().filter(|component| matches!(component, Component::Normal(_))).collect();
+				Ok(!changed.contains(&path))
+			}
+			Some(ChangedOnly::SizeAndMtime) => {
+				let Some(destination) = self.normalized_destination(entry, output_dir)? else {
+					return Ok(false);
+				};
+				let Ok(metadata) = destination.symlink_metadata() else {
+					return Ok(false);
+				};
+				let mtime = metadata.modified().ok().and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok());
+				let header_mtime = entry.header().mtime().context("Failed to read entry mtime")?;
+				Ok(metadata.is_file() && metadata.len() == entry.size() && mtime.map(|mtime| mtime.as_secs()) == Some(header_mtime))
+			}
+		}
+	}
+
+	/// Unpacks an entry whose path collides with an earlier entry, under a new name if `rename` is set
+	/// and replacing the earlier entry otherwise.
+	fn unpack_collision<R: Read>(
+		&self,
+		entry: &mut tar::Entry<R>,
+		output_dir: &Path,
+		position: &Position,
+		sandbox: Option<&Sandbox>,
+		resolve_owners: bool,
+		rename: bool,
+	) -> Result<Option<PathBuf>> {
+		let Some(destination) = self.normalized_destination(entry, output_dir)? else {
+			return Ok(None);
+		};
+		let destination = if rename {
+			renamed_destination(&destination)
+		} else {
+			remove_existing(&destination, output_dir, sandbox)?;
+			destination
+		};
+		debug!("Unpacking colliding entry to: {:?}", destination);
+		self.unpack_entry(entry, &destination, output_dir, position, sandbox, resolve_owners)?;
+		Ok(Some(destination))
+	}
+
+	/// Resolves the output path of a flattened file, or `None` if it is skipped by the conflict policy.
+	/// `flattened` maps the file names extracted so far to the archive path they came from.
+	fn flattened_destination<R: Read>(
+		&self,
+		entry: &tar::Entry<R>,
+		output_dir: &Path,
+		flattened: &mut HashMap<PathBuf, String>,
+		sandbox: Option<&Sandbox>,
+	) -> Result<Option<PathBuf>> {
+		let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+		let Some(name) = Path::new(&path).file_name().and_then(|name| name.to_str()) else {
+			return Ok(None);
+		};
+		let name = PathBuf::from(self.local_path(name).as_ref());
+		let mut destination = output_dir.join(&name);
+		match flattened.get(&name) {
+			None => {}
+			// A later entry with the same path always shadows the earlier one
+			Some(previous) if *previous == path => remove_existing(&destination, output_dir, sandbox)?,
+			Some(previous) => match self.flatten_conflict_policy {
+				FlattenConflictPolicy::Error => {
+					error!("Entries {:?} and {:?} both flatten to {:?}.", previous, path, name);
+					anyhow::bail!("Entries {:?} and {:?} both flatten to {:?}", previous, path, name);
+				}
+				FlattenConflictPolicy::Skip => {
+					debug!("Skipping {:?} as {:?} was already extracted.", path, name);
+					return Ok(None);
+				}
+				FlattenConflictPolicy::LastWins => remove_existing(&destination, output_dir, sandbox)?,
+				FlattenConflictPolicy::Rename => destination = renamed_destination(&destination),
+			},
+		}
+		flattened.insert(name, path);
+		Ok(Some(destination))
+	}
+
+	/// Restores the timestamps that `tar` doesn't apply itself: the sub-second modification time stored in pax attributes,
+	/// the modification time of directories and, if enabled, the creation time.
+	fn restore_times<R: Read>(&self, entry: &mut tar::Entry<R>, destination: &Path) -> Result<()> {
+		let kind = EntryType::from(entry.header().entry_type());
+		// The sandbox applies the precise modification time itself
+		let restore_mtime = self.restores_mtime() && !self.sandbox;
+		if !matches!(kind, EntryType::File | EntryType::Directory) || !(restore_mtime || self.touch || self.restore_btime) {
+			return Ok(());
+		}
+		let attributes = pax::entry_attributes(entry).context("Failed to read pax attributes")?;
+		let mtime = attributes.get(MTIME_KEY).and_then(|value| parse_timestamp(value)).or_else(|| {
+			// Directories are restored after their contents, which would have changed an earlier modification time
+			let seconds = entry.header().mtime().ok().filter(|_| kind == EntryType::Directory)?;
+			Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds))
+		});
+		// Existing directories aren't rewritten, so the current time is set explicitly
+		let mtime = if self.touch { Some(std::time::SystemTime::now()) } else { mtime.filter(|_| restore_mtime) };
+		let btime = attributes.get(BTIME_KEY).and_then(|value| parse_timestamp(value)).filter(|_| self.restore_btime);
+		if mtime.is_none() && btime.is_none() {
+			return Ok(());
+		}
+		let file = open_for_times(destination).with_context(|| format!("Failed to open {:?}", destination))?;
+		if let Some(mtime) = mtime {
+			debug!("Restoring modification time of {:?}", destination);
+			file.set_times(fs::FileTimes::new().set_modified(mtime).set_accessed(mtime))
+				.with_context(|| format!("Failed to set modification time of {:?}", destination))?;
+		}
+		if let Some(btime) = btime {
+			if let Err(e) = set_created(&file, destination, btime) {
+				warn!("Failed to restore creation time of {:?}: {}", destination, e);
+			}
+		}
+		Ok(())
+	}
+
+	/// Unpacks an entry to an explicit destination, through the sandbox if one is open.
+	fn unpack_entry<R: Read>(
+		&self,
+		entry: &mut tar::Entry<R>,
+		destination: &Path,
+		output_dir: &Path,
+		position: &Position,
+		sandbox: Option<&Sandbox>,
+		resolve_owners: bool,
+	) -> Result<()> {
+		let Some(sandbox) = sandbox else {
+			if let Some(target) = self.link_target(entry)? {
+				return unpack_symlink_to(&target, destination, output_dir, self.overwrite, self.dir_creation_mode);
+			}
+			return unpack_to(entry, destination, output_dir, position, self.dir_creation_mode);
+		};
+		let options = self.unpack_options(entry, resolve_owners)?;
+		sandbox.unpack(entry, strip_output_dir(destination, output_dir), &options).map_err(|e| {
+			if let Some(io) = e.downcast_ref::<io::Error>() {
+				if let Some(reader_error) = ReaderError::classify(io, position.get()) {
+					return e.context(reader_error);
+				}
+			}
+			e
+		})
+	}
+
+	/// Unpacks a sparse file entry to `destination` with its holes restored.
+	fn unpack_sparse<R: Read>(
+		&self,
+		entry: &mut tar::Entry<R>,
+		map: &SparseMap,
+		destination: &Path,
+		output_dir: &Path,
+		sandbox: Option<&Sandbox>,
+		resolve_owners: bool,
+	) -> Result<()> {
+		debug!("Unpacking sparse file with {} regions to: {:?}", map.regions.len(), destination);
+		let options = self.unpack_options(entry, resolve_owners)?;
+		if let Some(sandbox) = sandbox {
+			return sandbox.unpack_sparse(entry, map, strip_output_dir(destination, output_dir), &options);
+		}
+		create_dir_all(destination.parent().unwrap_or(output_dir), self.dir_creation_mode).context("Failed to create parent directory")?;
+		unpack_sparse_to(entry, map, destination, &options)
+	}
+
+	/// Collects the options the sandbox applies to an unpacked entry.
+	fn unpack_options<R: Read>(&self, entry: &tar::Entry<R>, resolve_owners: bool) -> Result<UnpackOptions> {
+		#[cfg(unix)]
+		let resolved_owner = if resolve_owners { Some(owner::resolve_owner(entry.header(), self.owner_fallback)?) } else { None };
+		#[cfg(not(unix))]
+		let resolved_owner: Option<(u32, u32)> = None;
+		let header = entry.header();
+		let owner = match resolved_owner {
+			Some(owner) => Some(owner),
+			None if self.preserve_ownerships => Some((header.uid()? as u32, header.gid()? as u32)),
+			None => None,
+		};
+		let options = UnpackOptions {
+			mask: self.mask,
+			overwrite: self.overwrite,
+			preserve_permissions: self.preserves_special_mode_bits(),
+			preserve_mtime: self.restores_mtime(),
+			owner,
+			ownership_fallback: self.ownership_fallback,
+			link_target: self.link_target(entry)?,
+			mode: self.mapped_mode(entry)?,
+		};
+		Ok(options)
+	}
+
+	/// Returns the target of a symlink entry as rewritten by the link transform, or `None` if it is kept.
+	fn link_target<R: Read>(&self, entry: &tar::Entry<R>) -> Result<Option<PathBuf>> {
+		let Some(transform) = &self.link_transform else {
+			return Ok(None);
+		};
+		if !entry.header().entry_type().is_symlink() {
+			return Ok(None);
+		}
+		let target = entry.link_name().context("Failed to read symlink target")?.context("Symlink entry has no target")?;
+		let transformed = (transform.0)(&target);
+		debug!("Rewriting symlink target {:?} to {:?}", target, transformed);
+		Ok(Some(transformed))
+	}
+
+	/// Fails if `entry`, at `path`, would be written or point outside of the output directory through the symlinks
+	/// in `chains`, then records it if it is a symlink.
+	fn follow_link_chains<R: Read>(&self, entry: &tar::Entry<R>, path: &str, chains: &mut LinkChains) -> Result<()> {
+		let path = Path::new(path);
+		let kind = entry.header().entry_type();
+		// A symlink replaces an existing link at its path instead of following it
+		let resolved = match (kind.is_symlink(), path.file_name()) {
+			(true, Some(name)) => path.parent().and_then(|parent| chains.resolve(parent)).map(|parent| parent.join(name)),
+			_ => chains.resolve(path),
+		};
+		let Some(resolved) = resolved else {
+			error!("Entry {:?} resolves outside of the output directory through a symlink.", path);
+			anyhow::bail!("Entry {:?} resolves outside of the output directory through a symlink", path);
+		};
+		if kind.is_symlink() {
+			let parent = resolved.parent().unwrap_or(Path::new(""));
+			let target = match self.link_target(entry)? {
+				Some(target) => target,
+				None => entry.link_name().context("Failed to read symlink target")?.context("Symlink entry has no target")?.into_owned(),
+			};
+			if target.has_root() || chains.resolve(&parent.join(&target)).is_none() {
+				error!("Symlink {:?} points outside of the output directory: {:?}", path, target);
+				anyhow::bail!("Symlink {:?} points outside of the output directory: {:?}", path, target);
+			}
+			chains.record(resolved, target);
+		} else if kind.is_hard_link() {
+			let target = entry.link_name().context("Failed to read hard link target")?.context("Hard link entry has no target")?;
+			if chains.resolve(&target).is_none() {
+				error!("Hard link {:?} points outside of the output directory: {:?}", path, target);
+				anyhow::bail!("Hard link {:?} points outside of the output directory: {:?}", path, target);
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns the mode of an entry as decided by the permission mapper, or `None` if the header mode is used.
+	fn mapped_mode<R: Read>(&self, entry: &tar::Entry<R>) -> Result<Option<u32>> {
+		let Some(mapper) = &self.permission_mapper else {
+			return Ok(None);
+		};
+		let kind = entry.header().entry_type();
+		if kind.is_symlink() || kind.is_hard_link() {
+			return Ok(None);
+		}
+		let mode = (mapper.0)(entry.header().mode().context("Failed to read entry mode")?, EntryType::from(kind));
+		Ok(Some(if self.strip_special_mode_bits { mode & 0o777 } else { mode }))
+	}
+
+	/// Sets the mode decided by the permission mapper on an unpacked entry, after its owner changed.
+	#[cfg(unix)]
+	fn apply_mapped_mode<R: Read>(&self, entry: &tar::Entry<R>, destination: &Path) -> Result<()> {
+		if let Some(mode) = self.mapped_mode(entry)? {
+			use std::os::unix::fs::PermissionsExt;
+			debug!("Setting mode of {:?} to {:o}", destination, mode);
+			fs::set_permissions(destination, fs::Permissions::from_mode(mode)).context("Failed to set permissions")?;
+		}
+		Ok(())
+	}
+
+	#[cfg(not(unix))]
+	fn apply_mapped_mode<R: Read>(&self, _entry: &tar::Entry<R>, _destination: &Path) -> Result<()> {
+		Ok(())
+	}
+
+	/// Changes the owner of an unpacked entry, to the local ids of its user and group names if `resolve_owners` is set
+	/// and to the ids in its header otherwise. `tar` never changes owners itself, so failures go through the ownership fallback.
+	#[cfg(unix)]
+	fn apply_owner<R: Read>(&self, entry: &tar::Entry<R>, destination: &Path, resolve_owners: bool) -> Result<()> {
+		let (uid, gid) = if resolve_owners {
+			owner::resolve_owner(entry.header(), self.owner_fallback).with_context(|| format!("Failed to resolve owner of {:?}", destination))?
+		} else if self.preserve_ownerships {
+			(entry.header().uid()? as u32, entry.header().gid()? as u32)
+		} else {
+			return Ok(());
+		};
+		debug!("Setting owner of {:?} to {}:{}", destination, uid, gid);
+		owner::set_owner(destination, uid, gid, self.ownership_fallback)?;
+		// Changing the owner clears the setuid and setgid bits
+		let mode = entry.header().mode()?;
+		if self.preserves_special_mode_bits() && mode & 0o6000 != 0 && !entry.header().entry_type().is_symlink() {
+			use std::os::unix::fs::PermissionsExt;
+			fs::set_permissions(destination, fs::Permissions::from_mode(mode & !self.mask)).context("Failed to restore permissions")?;
+		}
+		Ok(())
+	}
+
+	#[cfg(not(unix))]
+	fn apply_owner<R: Read>(&self, _entry: &tar::Entry<R>, _destination: &Path, _resolve_owners: bool) -> Result<()> {
+		Ok(())
+	}
+
+	/// Returns whether the extraction has to look for case collisions, which it does on case-insensitive filesystems.
+	/// Flattened entries have their own conflict policy, and overwriting already lets the last entry win.
+	fn detects_case_collisions(&self) -> bool {
+		let last_wins_anyway = self.case_collision_policy == CaseCollisionPolicy::LastWins && self.overwrite;
+		!self.flatten && !last_wins_anyway
+	}
+
+	/// Finds the indices of entries whose paths collide, ignoring case, with an earlier entry.
+	fn find_case_collisions(&self) -> Result<HashSet<usize>> {
+		let (mut archive, position) = self.open_archive()?;
+		let mut seen: HashMap<String, String> = HashMap::new();
+		let mut collisions = HashSet::new();
+		for (index, entry) in archive.entries().context("Failed to get entries from archive")?.enumerate() {
+			let entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			let kind = entry.header().entry_type();
+			if kind.is_dir() || !self.includes_entry_type(kind) {
+				continue;
+			}
+			let path = String::from_utf8_lossy(&entry.path_bytes()).trim_start_matches('/').to_string();
+			match seen.get(&path.to_lowercase()) {
+				Some(previous) if *previous != path => {
+					if self.case_collision_policy == CaseCollisionPolicy::Error {
+						error!("Entries {:?} and {:?} differ only by case.", previous, path);
+						anyhow::bail!("Entries {:?} and {:?} differ only by case", previous, path);
+					}
+					debug!("Entry {:?} collides with {:?}.", path, previous);
+					collisions.insert(index);
+				}
+				_ => {
+					seen.insert(path.to_lowercase(), path);
+				}
+			}
+		}
+		Ok(collisions)
+	}
+
+	/// Extracts as much as possible from a truncated or partially corrupted archive into the output directory.
+	/// Damaged regions are skipped by re-synchronizing on the next valid tar header or xz stream,
+	/// and the result reports which entries were recovered and which were lost.
+	pub fn recover(&self) -> Result<RecoveryResult> {
+		debug!("Starting recovery process.");
+		let Some(output_dir) = &self.output else {
+			error!("Output directory not specified when recover() was called.");
+			anyhow::bail!("No output directory specified");
+		};
+		let compressed: Box<dyn Read + Send> = match self.take_stream()? {
+			Some(stream) => stream,
+			None => Box::new(self.open_archive_file()?),
+		};
+		fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+		recover_archive(BufReader::new(compressed), output_dir, self.overwrite)
+	}
+
+	/// Extracts archives found among the extracted files into a directory next to them,
+	/// recursing until the nesting depth is exhausted. Every level applies the same limits as the outer archive.
+	fn extract_nested_archives(&self, output_dir: &Path, files: &mut Vec<String>, size: &mut u64) -> Result<()> {
+		let nested_archives: Vec<String> = files.iter().filter(|file| nested_archive_stem(file).is_some()).cloned().collect();
+		for nested in nested_archives {
+			let Some(archive_path) = entry_destination(output_dir, Path::new(&nested)) else {
+				continue;
+			};
+			let nested_dir = archive_path.with_file_name(nested_archive_stem(&nested).unwrap_or_default());
+			let prefix = nested_dir.strip_prefix(output_dir).unwrap_or(&nested_dir).to_string_lossy().into_owned();
+			// Extracting into a directory the outer archive or another nested archive wrote to would mix their files
+			if files.iter().any(|file| file.trim_end_matches('/') == prefix || file.starts_with(&format!("{}/", prefix))) {
+				error!("Nested archive {} would be extracted into {:?}, which holds other extracted entries.", nested, nested_dir);
+				anyhow::bail!("Nested archive {} would be extracted into {:?}, which holds other extracted entries", nested, nested_dir);
+			}
+			if !self.overwrite && nested_dir.symlink_metadata().is_ok() {
+				error!("Nested archive {} would be extracted into {:?}, which already exists.", nested, nested_dir);
+				anyhow::bail!("Nested archive {} would be extracted into {:?}, which already exists", nested, nested_dir);
+			}
+			info!("Extracting nested archive {:?} into {:?}", archive_path, nested_dir);
+			let mut reader = self.clone();
+			// The filters name entries of the outer archive, the nested one is extracted whole
+			reader.include_globs.clear();
+			reader.exclude_globs.clear();
+			reader.extract_list = None;
+			reader.entry_types = None;
+			reader.sidecar_index = None;
+			reader.set_archive(&archive_path)?;
+			reader.set_output_directory(&nested_dir)?;
+			reader.extract_nested = self.extract_nested - 1;
+			if let Some(max_unpacked_size) = self.max_unpacked_size {
+				reader.max_unpacked_size = Some(max_unpacked_size.saturating_sub(*size));
+			}
+			let result = reader.decompress().with_context(|| format!("Failed to extract nested archive {}", nested))?;
+			files.extend(result.files.into_iter().map(|file| format!("{}/{}", prefix, file)));
+			*size += result.total_size;
+		}
+		Ok(())
+	}
+
+	/// Reads the contents of every file entry into memory without touching the filesystem, e.g. to inspect small archives in tests.
+	/// The entry type and list filters apply, and the last entry wins if several share a path.
+	/// Fails without reading further once the files would hold more than `max_total_size` bytes.
+	pub fn decompress_to_memory(&self, max_total_size: Option<u64>) -> Result<HashMap<PathBuf, Vec<u8>>> {
+		debug!("Decompressing archive to memory.");
+		let (mut archive, position) = self.open_archive()?;
+		let mut files: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+		let mut total_size = 0;
+		for entry in archive.entries().context("Failed to get entries from archive")? {
+			let mut entry = entry.map_err(|e| decode_error(e, position.get()))?;
+			let kind = entry.header().entry_type();
+			if !kind.is_file() || !self.includes_entry_type(kind) || !self.is_listed(&String::from_utf8_lossy(&entry.path_bytes())) {
+				continue;
+			}
+			let path = entry.path().map_err(|e| decode_error(e, position.get()))?.into_owned();
+			if let Some(max_total_size) = max_total_size {
+				let replaced = files.get(&path).map_or(0, |contents| contents.len() as u64);
+				if total_size - replaced + entry.size() > max_total_size {
+					error!("Archive exceeds the maximum in-memory size of {} bytes.", max_total_size);
+					anyhow::bail!("Archive exceeds the maximum in-memory size of {} bytes", max_total_size);
+				}
+			}
+			let mut contents = Vec::new();
+			entry.read_to_end(&mut contents).map_err(|e| decode_error(e, position.get()))?;
+			debug!("Read file: {:?} ({} bytes)", path, contents.len());
+			total_size += contents.len() as u64;
+			if let Some(replaced) = files.insert(path, contents) {
+				total_size -= replaced.len() as u64;
+			}
+		}
+		info!("Read {} files ({} bytes) into memory.", files.len(), total_size);
+		Ok(files)
+	}
+
+	/// Decompresses the tarball archive to the specified output directory.
+	pub fn decompress(&self) -> Result<DecompressionResult> {
+		self.decompress_with_callback(|_| {})
+	}
+
+	/// Decompresses the tarball archive to the specified output directory, reporting progress after each entry.
+	pub fn decompress_with_callback<F>(&self, callback: F) -> Result<DecompressionResult>
+	where
+		F: Fn(DecompressionProgress),
+	{
+		let Some(timeout) = self.timeout else {
+			return self.decompress_in_place(callback);
+		};
+		// Extraction runs on a worker so a stuck read or write can be abandoned, progress is forwarded to this thread
+		let deadline = std::time::Instant::now() + timeout;
+		let mut worker = self.clone();
+		worker.timed_out = Arc::new(AtomicBool::new(false));
+		let timed_out = worker.timed_out.clone();
+		let (sender, receiver) = mpsc::channel();
+		std::thread::spawn(move || {
+			let progress = sender.clone();
+			let result = worker.decompress_in_place(|update| {
+				let _ = progress.send(WorkerMessage::Progress(update));
+			});
+			let _ = sender.send(WorkerMessage::Done(result));
+		});
+		loop {
+			match receiver.recv_timeout(deadline.saturating_duration_since(std::time::Instant::now())) {
+				Ok(WorkerMessage::Progress(progress)) => callback(progress),
+				Ok(WorkerMessage::Done(result)) => return result,
+				Err(mpsc::RecvTimeoutError::Timeout) => {
+					// The worker stops at the next entry once its stuck I/O returns
+					timed_out.store(true, Ordering::Relaxed);
+					error!("Decompression timed out after {:?}", timeout);
+					return Err(anyhow::Error::new(io::Error::new(io::ErrorKind::TimedOut, format!("decompression timed out after {:?}", timeout))));
+				}
+				Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("Decompression thread panicked"),
+			}
+		}
+	}
+
+	fn decompress_in_place<F>(&self, callback: F) -> Result<DecompressionResult>
+	where
+		F: Fn(DecompressionProgress),
+	{
+		self.extract(self.take_stream()?, callback)
+	}
+
+	/// Extracts the archive read from `stream`, or from the archive file if there is none.
+	/// A stream can only be read once, so case collisions aren't detected ahead of extraction.
+	pub(crate) fn extract<F>(&self, stream: Option<Box<dyn Read + Send>>, callback: F) -> Result<DecompressionResult>
+	where
+		F: Fn(DecompressionProgress),
+	{
+		debug!("Starting decompression process.");
+		if let Some(output_dir) = &self.output {
+			info!("Using output directory: {:?}", output_dir);
+			let start = std::time::Instant::now();
+			if !output_dir.exists() {
+				debug!("Output directory does not exist; attempting to create: {:?}", output_dir);
+				create_dir_all(output_dir, self.dir_creation_mode).context("Failed to create output directory")?;
+			}
+			let case_collisions = if self.detects_case_collisions() && is_case_insensitive(output_dir) {
+				if stream.is_some() {
+					warn!("Output directory is case-insensitive; case collisions can't be detected in a stream.");
+					HashSet::new()
+				} else {
+					debug!("Output directory is case-insensitive; checking for case collisions.");
+					self.find_case_collisions()?
+				}
+			} else {
+				HashSet::new()
+			};
+			debug!("Unpacking archive into output directory.");
+			let (total_bytes, (mut archive, position)) = match stream {
+				Some(stream) => (0, self.archive_from(stream, 0)?),
+				None => (self.open_archive_file()?.metadata().map(|m| m.len()).unwrap_or(0), self.open_archive()?),
+			};
+			for (_, destination) in &self.routes {
+				create_dir_all(destination, self.dir_creation_mode).with_context(|| format!("Failed to create route destination {:?}", destination))?;
+			}
+			// One sandbox for the output directory and for every route destination
+			let mut sandboxes = Vec::new();
+			if self.sandbox {
+				debug!("Resolving all destination paths beneath the output directory.");
+				for root in std::iter::once(output_dir).chain(self.routes.iter().map(|(_, destination)| destination)) {
+					sandboxes.push((root, Sandbox::open(root, self.dir_creation_mode)?));
+				}
+			}
+			let sandbox_for = |root: &Path| sandboxes.iter().find(|(dir, _)| *dir == root).map(|(_, sandbox)| sandbox);
+			let resolve_owners = self.resolve_owner_names && owner::is_root();
+			if self.resolve_owner_names && !resolve_owners {
+				warn!("Owner names are only resolved when extracting as root.");
+			}
+			let mut files = Vec::new();
+			// The index of the entry currently extracted at each path
+			let mut extracted = HashMap::new();
+			let mut shadowed = Vec::new();
+			let mut skipped = Vec::new();
+			let mut flattened = HashMap::new();
+			let mut link_chains = self.hardened_links.then(LinkChains::default);
+			// The extract list patterns that haven't matched an entry yet
+			let mut unmatched: HashSet<usize> = (0..self.extract_list.as_ref().map_or(0, Vec::len)).collect();
+			let mut size = 0;
+			// Directories are applied last so their permissions don't prevent writing their contents
+			let mut directories = Vec::new();
+			callback(DecompressionProgress::new(position.compressed(), total_bytes, start.elapsed(), Phase::Decoding));
+			for (index, entry) in archive.entries().context("Failed to get entries from archive")?.enumerate() {
+				if self.timed_out.load(Ordering::Relaxed) {
+					anyhow::bail!("Decompression timed out");
+				}
+				let mut entry = entry.map_err(|e| decode_error(e, position.get()))?;
+				let kind = entry.header().entry_type();
+				if !self.includes_entry_type(kind) {
+					skipped.push(SkippedEntry::new(String::from_utf8_lossy(&entry.path_bytes()), SkipReason::Filtered));
+					continue;
+				}
+				if EntryType::from(kind) == EntryType::Other && !kind.is_pax_global_extensions() {
+					let offset = entry.raw_header_position();
+					error!("Unsupported entry type {:?} at offset {}", kind, offset);
+					return Err(anyhow::Error::new(ReaderError::UnsupportedEntryType { offset, entry_type: kind.as_byte() }));
+				}
+				let sparse = if kind.is_file() { sparse::read_map(&mut entry)? } else { None };
+				// Holes count towards the unpacked size, they are only free on filesystems supporting them
+				let unpacked_size = sparse.as_ref().map_or(entry.size(), |map| map.real_size);
+				let path = match sparse.as_ref().and_then(|map| map.name.clone()) {
+					Some(name) => name,
+					None => String::from_utf8_lossy(&entry.path_bytes()).into_owned(),
+				};
+				if !self.matches_globs(&path) {
+					skipped.push(SkippedEntry::new(path, SkipReason::Filtered));
+					continue;
+				}
+				if let Some(patterns) = self.listed_by(&path) {
+					if patterns.is_empty() {
+						skipped.push(SkippedEntry::new(path, SkipReason::Filtered));
+						continue;
+					}
+					unmatched.retain(|index| !patterns.contains(index));
+				}
+				if let Some(max_unpacked_size) = self.max_unpacked_size {
+					if size + unpacked_size > max_unpacked_size {
+						error!("Archive exceeds the maximum unpacked size of {} bytes.", max_unpacked_size);
+						anyhow::bail!("Archive exceeds the maximum unpacked size of {} bytes", max_unpacked_size);
+					}
+				}
+				debug!("Processing file: {:?}", path);
+				let output_dir = self.route_destination(&path).unwrap_or(output_dir);
+				let sandbox = sandbox_for(output_dir);
+				if matches!(self.windows_name_policy, Some(WindowsNamePolicy::Skip | WindowsNamePolicy::Error)) {
+					// Only the file name is extracted when flattening
+					let names = if self.flatten { path.rsplit('/').take(1).collect::<Vec<_>>() } else { path.split('/').collect() };
+					if let Some(reason) = names.into_iter().filter(|name| !name.is_empty()).find_map(invalid_windows_name) {
+						if self.windows_name_policy == Some(WindowsNamePolicy::Skip) {
+							debug!("Skipping {:?} as its path {}.", path, reason);
+							skipped.push(SkippedEntry::new(path, SkipReason::InvalidPath));
+							continue;
+						}
+						error!("Entry {:?} {}.", path, reason);
+						anyhow::bail!("Entry {:?} {}", path, reason);
+					}
+				}
+				if self.flatten {
+					if EntryType::from(kind) != EntryType::File {
+						debug!("Skipping {:?} as only files are extracted when flattening.", path);
+						skipped.push(SkippedEntry::new(path, SkipReason::NotAFile));
+						continue;
+					}
+					if let Some(destination) = self.flattened_destination(&entry, output_dir, &mut flattened, sandbox)? {
+						size += unpacked_size;
+						match &sparse {
+							Some(map) => self.unpack_sparse(&mut entry, map, &destination, output_dir, sandbox, resolve_owners)?,
+							None => self.unpack_entry(&mut entry, &destination, output_dir, &position, sandbox, resolve_owners)?,
+						}
+						if sandbox.is_none() {
+							self.apply_owner(&entry, &destination, resolve_owners)?;
+							self.apply_mapped_mode(&entry, &destination)?;
+						}
+						self.restore_times(&mut entry, &destination)?;
+						files.push(strip_output_dir(&destination, output_dir).to_string_lossy().into_owned());
+					} else {
+						let reason = if Path::new(&path).file_name().is_some() { SkipReason::Conflict } else { SkipReason::InvalidPath };
+						skipped.push(SkippedEntry::new(path, reason));
+					}
+					callback(DecompressionProgress::new(position.compressed(), total_bytes, start.elapsed(), Phase::Unpacking));
+					continue;
+				}
+				let mut rename_duplicate = false;
+				// Entries are duplicates if they are written to the same file, after normalization and sanitizing
+				let local = self.local_path(&path).into_owned();
+				let mut listed = match self.windows_name_policy {
+					Some(WindowsNamePolicy::Sanitize) => local.clone(),
+					_ => path.clone(),
+				};
+				if !kind.is_dir() {
+					match extracted.insert(local.clone(), index) {
+						None => {}
+						Some(previous) => match self.duplicate_path_policy {
+							DuplicatePathPolicy::LastWins => {
+								debug!("Entry {:?} shadows an earlier entry with the same path.", path);
+								remove_shadowed(output_dir, Path::new(&local), sandbox)?;
+								shadowed.push(ShadowedEntry { path: path.clone(), index: previous });
+							}
+							DuplicatePathPolicy::FirstWins => {
+								debug!("Skipping {:?} as an earlier entry with the same path was extracted.", path);
+								extracted.insert(local, previous);
+								skipped.push(SkippedEntry::new(path.clone(), SkipReason::Conflict));
+								shadowed.push(ShadowedEntry { path, index });
+								continue;
+							}
+							DuplicatePathPolicy::KeepAllRenamed => rename_duplicate = true,
+							DuplicatePathPolicy::Error => {
+								error!("Entries {} and {} both have the path {:?}.", previous, index, path);
+								anyhow::bail!("Entries {} and {} both have the path {:?}", previous, index, path);
+							}
+						},
+					}
+				}
+				if kind.is_file() && sparse.is_none() && !rename_duplicate && self.is_unchanged(&entry, output_dir)? {
+					debug!("Skipping unchanged file: {:?}", path);
+					skipped.push(SkippedEntry::new(path, SkipReason::Unchanged));
+					continue;
+				}
+				if let Some(chains) = &mut link_chains {
+					self.follow_link_chains(&entry, &path, chains)?;
+				}
+				if kind.is_dir() {
+					directories.push((entry, output_dir));
+				} else if kind.is_hard_link() && self.hardlink_policy != HardlinkPolicy::Preserve {
+					if self.hardlink_policy == HardlinkPolicy::Skip {
+						debug!("Skipping hard link: {:?}", path);
+						skipped.push(SkippedEntry::new(path, SkipReason::HardLink));
+						continue;
+					}
+					self.copy_hardlink_content(&entry, output_dir, sandbox)?;
+				} else {
+					let destination = if let Some(map) = &sparse {
+						let name = self.local_path(&path);
+						let destination = entry_destination(output_dir, Path::new(name.as_ref()))
+							.map(|destination| if rename_duplicate { renamed_destination(&destination) } else { destination });
+						if let Some(destination) = &destination {
+							self.unpack_sparse(&mut entry, map, destination, output_dir, sandbox, resolve_owners)?;
+							listed = strip_output_dir(destination, output_dir).to_string_lossy().into_owned();
+						}
+						destination
+					} else if rename_duplicate {
+						let destination = self.unpack_collision(&mut entry, output_dir, &position, sandbox, resolve_owners, true)?;
+						if let Some(destination) = &destination {
+							debug!("Extracted duplicate {:?} to {:?}.", path, destination);
+							listed = strip_output_dir(destination, output_dir).to_string_lossy().into_owned();
+						}
+						destination
+					} else if case_collisions.contains(&index) {
+						let rename = self.case_collision_policy == CaseCollisionPolicy::Rename;
+						self.unpack_collision(&mut entry, output_dir, &position, sandbox, resolve_owners, rename)?
+					} else if self.rewrites_paths() || sandbox.is_some() || (kind.is_symlink() && self.link_transform.is_some()) {
+						let destination = self.normalized_destination(&entry, output_dir)?;
+						if let Some(destination) = &destination {
+							self.unpack_entry(&mut entry, destination, output_dir, &position, sandbox, resolve_owners)?;
+						}
+						destination
+					} else {
+						// unpack_in creates missing parents itself, ignoring the directory creation mode
+						if let Some(destination) = self.dir_creation_mode.and(self.normalized_destination(&entry, output_dir)?) {
+							create_dir_all(destination.parent().unwrap_or(output_dir), self.dir_creation_mode).context("Failed to create parent directory")?;
+						}
+						entry.unpack_in(output_dir).map_err(|e| decode_error(e, position.get()))?;
+						self.normalized_destination(&entry, output_dir)?
+					};
+					let Some(destination) = destination else {
+						debug!("Skipping {:?} as it can't be extracted inside the output directory.", path);
+						skipped.push(SkippedEntry::new(path, SkipReason::InvalidPath));
+						continue;
+					};
+					size += unpacked_size;
+					// The sandbox applies resolved owners itself
+					if sandbox.is_none() {
+						self.apply_owner(&entry, &destination, resolve_owners)?;
+						self.apply_mapped_mode(&entry, &destination)?;
+					}
+					self.restore_times(&mut entry, &destination)?;
+				}
+				files.push(listed);
+				callback(DecompressionProgress::new(position.compressed(), total_bytes, start.elapsed(), Phase::Unpacking));
+			}
+			callback(DecompressionProgress::new(position.compressed(), total_bytes, start.elapsed(), Phase::ApplyingMetadata));
+			directories.sort_by(|(a, _), (b, _)| b.path_bytes().cmp(&a.path_bytes()));
+			for (mut directory, output_dir) in directories {
+				let sandbox = sandbox_for(output_dir);
+				if self.rewrites_paths() || sandbox.is_some() {
+					if let Some(destination) = self.normalized_destination(&directory, output_dir)? {
+						self.unpack_entry(&mut directory, &destination, output_dir, &position, sandbox, resolve_owners)?;
+					}
+				} else {
+					directory.unpack_in(output_dir).map_err(|e| decode_error(e, position.get()))?;
+				}
+				if let Some(destination) = self.normalized_destination(&directory, output_dir)? {
+					if sandbox.is_none() {
+						self.apply_owner(&directory, &destination, resolve_owners)?;
+						self.apply_mapped_mode(&directory, &destination)?;
+					}
+					self.restore_times(&mut directory, &destination)?;
+				}
+			}
+			let mut files = shadow_duplicates(files);
+			if self.extract_nested > 0 {
+				self.extract_nested_archives(output_dir, &mut files, &mut size)?;
+			}
+			if let Some(patterns) = &self.extract_list {
+				let mut missing: Vec<&String> = unmatched.into_iter().map(|index| &patterns[index]).collect();
+				if !missing.is_empty() {
+					missing.sort();
+					error!("Not found in archive: {:?}", missing);
+					anyhow::bail!("Not found in archive: {:?}", missing);
+				}
+			}
+			let elapsed = start.elapsed();
+			info!("Decompression completed in {:?}", elapsed);
+			Ok(DecompressionResult {
+				elapsed_time: elapsed,
+				files,
+				total_size: size,
+				shadowed,
+				skipped,
+			})
+		} else {
+			error!("Output directory not specified when decompress() was called.");
+			anyhow::bail!("No output directory specified");
+		}
+	}
+}
+
+// Configured readers are moved across threads by job queues, so they must stay `Send + Sync`
+const _: fn() = || {
+	fn assert_send_sync<T: Send + Sync>() {}
+	assert_send_sync::<LZMATarballReader>();
+	assert_send_sync::<DecompressionResult>();
+	assert_send_sync::<DecompressionProgress>();
+	assert_send_sync::<EntryMetadata>();
+	assert_send_sync::<RecoveryResult>();
+};
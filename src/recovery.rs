@@ -0,0 +1,390 @@
+//! # Archive Recovery
+//! Extracts as much as possible from truncated or partially corrupted archives.
+//!
+//! Every xz stream in the archive is decoded independently, so a corrupt stream only loses its own entries.
+//! Inside a stream the tar data is parsed block by block. When a header fails its checksum the parser
+//! re-synchronizes by scanning forward for the next valid header.
+//!
+//! The archive and the entry data are streamed, so a damaged size field can't exhaust memory.
+//! Symlinks and hard links are recovered unless they lead outside of the output directory, through the same
+//! link chains as a hardened extraction.
+
+use crate::link_chain::LinkChains;
+use crate::reader::{create_parent, entry_destination, unpack_symlink_to};
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use tar::{EntryType, Header};
+use xz2::bufread::XzDecoder;
+
+#[cfg(feature = "log")]
+use crate::logging::*;
+#[cfg(not(feature = "log"))]
+use crate::*;
+
+const BLOCK_SIZE: usize = 512;
+const XZ_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+/// The size of the chunks entry data is copied in
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+/// The largest long name or pax header read into memory, larger ones are taken for damage and skipped
+const MAX_EXTENSION_SIZE: u64 = 1024 * 1024;
+
+/// `RecoveryResult` lists which entries could be recovered from a damaged archive.
+#[derive(Debug, Clone)]
+pub struct RecoveryResult {
+    pub elapsed_time: std::time::Duration,
+    /// Entries that were extracted completely.
+    pub recovered: Vec<String>,
+    /// Entries that were found but could not be extracted.
+    pub lost: Vec<LostEntry>,
+    /// Number of bytes of tar data skipped while re-synchronizing on the next valid header.
+    pub skipped_bytes: u64,
+}
+
+/// An entry that could not be recovered.
+#[derive(Debug, Clone)]
+pub struct LostEntry {
+    pub path: String,
+    /// Offset of the entry header in the decompressed tar stream.
+    pub offset: u64,
+    pub reason: String,
+}
+
+/// Recovers the entries of the xz compressed tar data read from `compressed` into `output_dir`.
+/// The data is streamed, so neither the archive nor an entry is ever held in memory.
+pub(crate) fn recover_archive(compressed: impl BufRead, output_dir: &Path, overwrite: bool) -> Result<RecoveryResult> {
+    let start = std::time::Instant::now();
+    let mut result = RecoveryResult {
+        elapsed_time: Default::default(),
+        recovered: Vec::new(),
+        lost: Vec::new(),
+        skipped_bytes: 0,
+    };
+    let mut input = CountingBufRead { inner: compressed, position: 0 };
+    let mut chains = LinkChains::default();
+    let mut decoded_offset = 0;
+    while skip_to_stream(&mut input).context("Failed to read archive")? {
+        debug!("Recovering xz stream at offset {}", input.position - XZ_MAGIC.len() as u64);
+        // The magic was consumed while searching for it, so it is put back in front of the stream
+        let mut decoder = XzDecoder::new(io::Cursor::new(XZ_MAGIC).chain(&mut input));
+        let mut parser = Parser {
+            reader: &mut decoder,
+            offset: decoded_offset,
+            output_dir,
+            overwrite,
+            chains: &mut chains,
+            result: &mut result,
+        };
+        parser.run()?;
+        decoded_offset = parser.offset;
+    }
+    result.elapsed_time = start.elapsed();
+    info!(
+        "Recovery completed: {} recovered, {} lost",
+        result.recovered.len(),
+        result.lost.len()
+    );
+    Ok(result)
+}
+
+/// Consumes `input` up to and including the next xz stream header, returning `false` if there is none.
+fn skip_to_stream(input: &mut impl BufRead) -> io::Result<bool> {
+    let mut matched = 0;
+    loop {
+        let buffer = input.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(false);
+        }
+        let mut used = 0;
+        for byte in buffer {
+            used += 1;
+            matched = if *byte == XZ_MAGIC[matched] {
+                matched + 1
+            } else if *byte == XZ_MAGIC[0] {
+                1
+            } else {
+                0
+            };
+            if matched == XZ_MAGIC.len() {
+                break;
+            }
+        }
+        input.consume(used);
+        if matched == XZ_MAGIC.len() {
+            return Ok(true);
+        }
+    }
+}
+
+/// Counts the bytes consumed from a buffered reader, to report the offset of the xz streams.
+struct CountingBufRead<R> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: BufRead> Read for CountingBufRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingBufRead<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position += amount as u64;
+        self.inner.consume(amount);
+    }
+}
+
+/// Checks the header checksum, treating the checksum field itself as spaces.
+fn valid_checksum(header: &Header) -> bool {
+    let bytes = header.as_bytes();
+    let sum: u32 = bytes[..148]
+        .iter()
+        .chain(&[b' '; 8])
+        .chain(&bytes[156..])
+        .map(|b| *b as u32)
+        .sum();
+    header.cksum().map(|cksum| cksum == sum).unwrap_or(false)
+}
+
+struct Parser<'a, R> {
+    reader: &'a mut R,
+    offset: u64,
+    output_dir: &'a Path,
+    overwrite: bool,
+    /// The symlinks recovered so far, so no entry is written or links outside of the output directory through them
+    chains: &'a mut LinkChains,
+    result: &'a mut RecoveryResult,
+}
+
+impl<R: Read> Parser<'_, R> {
+    /// Reads a full block, returning `false` if the stream ended or failed to decode.
+    fn read_block(&mut self, block: &mut [u8; BLOCK_SIZE]) -> bool {
+        let mut filled = 0;
+        while filled < BLOCK_SIZE {
+            match self.reader.read(&mut block[filled..]) {
+                Ok(0) => return false,
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("Decoding stopped at offset {}: {}", self.offset + filled as u64, e);
+                    return false;
+                }
+            }
+        }
+        self.offset += BLOCK_SIZE as u64;
+        true
+    }
+
+    /// Copies `size` bytes of entry data to `sink` and skips the padding after them,
+    /// returning `false` if the stream ended or failed to decode first. Only failing to write to `sink` is an error.
+    fn copy_data(&mut self, size: u64, sink: &mut impl Write) -> Result<bool> {
+        let padding = (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64;
+        Ok(self.copy_exact(size, sink)? && self.copy_exact(padding, &mut io::sink())?)
+    }
+
+    fn copy_exact(&mut self, size: u64, sink: &mut impl Write) -> Result<bool> {
+        let mut buffer = vec![0; (size.min(COPY_BUFFER_SIZE as u64)) as usize];
+        let mut remaining = size;
+        while remaining > 0 {
+            let wanted = remaining.min(buffer.len() as u64) as usize;
+            let read = match self.reader.read(&mut buffer[..wanted]) {
+                Ok(0) => return Ok(false),
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("Decoding stopped at offset {}: {}", self.offset, e);
+                    return Ok(false);
+                }
+            };
+            self.offset += read as u64;
+            remaining -= read as u64;
+            sink.write_all(&buffer[..read])?;
+        }
+        Ok(true)
+    }
+
+    fn run(&mut self) -> Result<()> {
+        let mut block = [0; BLOCK_SIZE];
+        let mut long_name: Option<Vec<u8>> = None;
+        let mut long_link: Option<Vec<u8>> = None;
+        let mut resyncing = false;
+        while self.read_block(&mut block) {
+            if block.iter().all(|b| *b == 0) {
+                continue;
+            }
+            let header = Header::from_byte_slice(&block).clone();
+            let header_offset = self.offset - BLOCK_SIZE as u64;
+            if !valid_checksum(&header) {
+                if !resyncing {
+                    warn!("Invalid tar header at offset {}, re-synchronizing", header_offset);
+                }
+                resyncing = true;
+                self.result.skipped_bytes += BLOCK_SIZE as u64;
+                continue;
+            }
+            resyncing = false;
+
+            let size = header.entry_size().unwrap_or(0);
+            let path_bytes = long_name.take().unwrap_or_else(|| header.path_bytes().into_owned());
+            let path = String::from_utf8_lossy(&path_bytes).into_owned();
+            let link = long_link.take().or_else(|| header.link_name_bytes().map(|link| link.into_owned()));
+            let kind = header.entry_type();
+            if matches!(kind, EntryType::GNULongName | EntryType::GNULongLink | EntryType::XHeader) && size <= MAX_EXTENSION_SIZE {
+                let mut data = Vec::new();
+                if !self.copy_data(size, &mut data)? {
+                    self.lost(path, header_offset, "archive ended before the entry data");
+                    return Ok(());
+                }
+                match kind {
+                    EntryType::GNULongName => long_name = Some(data.split(|b| *b == 0).next().unwrap_or_default().to_vec()),
+                    EntryType::GNULongLink => long_link = Some(data.split(|b| *b == 0).next().unwrap_or_default().to_vec()),
+                    _ => {
+                        long_name = pax_value(&data, b"path");
+                        long_link = pax_value(&data, b"linkpath");
+                    }
+                }
+                continue;
+            }
+            if matches!(kind, EntryType::Regular | EntryType::Continuous) {
+                let Some(resolved) = self.resolve(&path, false) else {
+                    if !self.copy_data(size, &mut io::sink())? {
+                        self.lost(path, header_offset, "archive ended before the entry data");
+                        return Ok(());
+                    }
+                    self.lost(path, header_offset, "path escapes the output directory");
+                    continue;
+                };
+                if !self.write_file(&self.output_dir.join(resolved), size, &header)? {
+                    self.lost(path, header_offset, "archive ended before the entry data");
+                    return Ok(());
+                }
+                self.result.recovered.push(path);
+                continue;
+            }
+            // Other entries have no data to extract
+            if !self.copy_data(size, &mut io::sink())? {
+                self.lost(path, header_offset, "archive ended before the entry data");
+                return Ok(());
+            }
+            let link = link.map(|link| PathBuf::from(String::from_utf8_lossy(&link).into_owned()));
+            match (kind, link) {
+                (EntryType::XGlobalHeader, _) => {}
+                (EntryType::Directory, _) => match self.resolve(&path, false) {
+                    Some(resolved) => {
+                        fs::create_dir_all(self.output_dir.join(resolved)).context("Failed to create directory")?;
+                        self.result.recovered.push(path);
+                    }
+                    None => self.lost(path, header_offset, "path escapes the output directory"),
+                },
+                (EntryType::Symlink, Some(target)) => self.recover_symlink(path, header_offset, target)?,
+                (EntryType::Link, Some(target)) => self.recover_hard_link(path, header_offset, &target)?,
+                (EntryType::Symlink | EntryType::Link, None) => self.lost(path, header_offset, "link has no target"),
+                (EntryType::GNULongName | EntryType::GNULongLink | EntryType::XHeader, _) => self.lost(path, header_offset, "extended header is too large"),
+                (other, _) => self.lost(path, header_offset, &format!("unsupported entry type {:?}", other)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves `path` relative to the output directory through the symlinks recovered so far,
+    /// returning `None` if it escapes the output directory. The last component of a symlink isn't followed, as it replaces it.
+    fn resolve(&self, path: &str, symlink: bool) -> Option<PathBuf> {
+        let path = Path::new(path);
+        entry_destination(self.output_dir, path)?;
+        match (symlink, path.file_name()) {
+            (true, Some(name)) => path.parent().and_then(|parent| self.chains.resolve(parent)).map(|parent| parent.join(name)),
+            _ => self.chains.resolve(path),
+        }
+    }
+
+    /// Writes the `size` bytes of entry data to `destination`, returning `false` and removing it if the stream ends first.
+    fn write_file(&mut self, destination: &Path, size: u64, header: &Header) -> Result<bool> {
+        if destination.exists() && !self.overwrite {
+            anyhow::bail!("File already exists: {:?}", destination);
+        }
+        create_parent(destination, self.output_dir, None)?;
+        let mut file = File::create(destination).context("Failed to create recovered file")?;
+        if !self.copy_data(size, &mut file).context("Failed to write recovered file")? {
+            drop(file);
+            let _ = fs::remove_file(destination);
+            return Ok(false);
+        }
+        #[cfg(unix)]
+        if let Ok(mode) = header.mode() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(destination, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        #[cfg(not(unix))]
+        let _ = header;
+        debug!("Recovered file: {:?}", destination);
+        Ok(true)
+    }
+
+    /// Recovers a symlink, unless it or its target resolves outside of the output directory.
+    fn recover_symlink(&mut self, path: String, offset: u64, target: PathBuf) -> Result<()> {
+        let Some(resolved) = self.resolve(&path, true) else {
+            self.lost(path, offset, "path escapes the output directory");
+            return Ok(());
+        };
+        let parent = resolved.parent().unwrap_or(Path::new(""));
+        if target.has_root() || self.chains.resolve(&parent.join(&target)).is_none() {
+            self.lost(path, offset, "symlink points outside of the output directory");
+            return Ok(());
+        }
+        unpack_symlink_to(&target, &self.output_dir.join(&resolved), self.output_dir, self.overwrite, None)?;
+        debug!("Recovered symlink: {:?} -> {:?}", resolved, target);
+        self.chains.record(resolved, target);
+        self.result.recovered.push(path);
+        Ok(())
+    }
+
+    /// Recovers a hard link to an entry recovered earlier.
+    fn recover_hard_link(&mut self, path: String, offset: u64, target: &Path) -> Result<()> {
+        let (Some(resolved), Some(resolved_target)) = (self.resolve(&path, false), self.resolve(&target.to_string_lossy(), false)) else {
+            self.lost(path, offset, "path escapes the output directory");
+            return Ok(());
+        };
+        let source = self.output_dir.join(resolved_target);
+        if !source.is_file() {
+            self.lost(path, offset, "link target was not recovered");
+            return Ok(());
+        }
+        let destination = self.output_dir.join(resolved);
+        create_parent(&destination, self.output_dir, None)?;
+        if destination.symlink_metadata().is_ok() {
+            if !self.overwrite {
+                anyhow::bail!("File already exists: {:?}", destination);
+            }
+            fs::remove_file(&destination).context("Failed to remove existing file")?;
+        }
+        fs::hard_link(&source, &destination).with_context(|| format!("Failed to create hard link {:?}", destination))?;
+        debug!("Recovered hard link: {:?} -> {:?}", destination, source);
+        self.result.recovered.push(path);
+        Ok(())
+    }
+
+    fn lost(&mut self, path: String, offset: u64, reason: &str) {
+        warn!("Lost entry {:?} at offset {}: {}", path, offset, reason);
+        self.result.lost.push(LostEntry {
+            path,
+            offset,
+            reason: reason.to_string(),
+        });
+    }
+}
+
+/// Extracts the record named `key` from PAX extended header data.
+fn pax_value(data: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+    tar::PaxExtensions::new(data)
+        .filter_map(|extension| extension.ok())
+        .find(|extension| extension.key_bytes() == key)
+        .map(|extension| extension.value_bytes().to_vec())
+}
@@ -0,0 +1,276 @@
+//! # Remote Archives
+//! Lists and extracts seekable archives over HTTP range requests, fetching only the xz blocks that hold the requested files.
+//! Archives are made seekable by `LZMATarballWriter::set_seekable_block_size`, which splits the compressed data into
+//! independent blocks and appends a last stream holding the offsets of every entry as [`ENTRY_INDEX_FILE_NAME`].
+//! Opening an archive reads the xz indexes at the end of the file and that last stream, nothing else.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::remote::RemoteArchive;
+//!
+//! let archive = RemoteArchive::open("http://example.com/release.tar.xz").unwrap();
+//! for entry in archive.entries() {
+//!     println!("{}", entry.path);
+//! }
+//! archive.extract_file("bin/tool", "./tool").unwrap();
+//! ```
+//!
+//! The built-in client speaks plain HTTP/1.1 only. For HTTPS, implement [`RangeSource`] on top of an HTTP client
+//! and open the archive with `RemoteArchive::from_source`.
+
+use crate::reader::{EntryMetadata, EntryType};
+use crate::xz_index::{XzBlock, XzIndex, HEADER_SIZE};
+use anyhow::{bail, Context, Result};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// The name of the entry holding the offsets of every entry, stored alone in the last stream of a seekable archive.
+pub const ENTRY_INDEX_FILE_NAME: &str = ".lzma_tarball_index.json";
+
+/// Random access to the bytes of a remote archive.
+pub trait RangeSource: Send + Sync {
+    /// Returns the size of the archive in bytes
+    fn size(&self) -> Result<u64>;
+    /// Returns the `length` bytes of the archive starting at `offset`
+    fn read_range(&self, offset: u64, length: u64) -> Result<Vec<u8>>;
+}
+
+/// A `RangeSource` fetching `http://` URLs with `Range` requests, one connection per request.
+#[derive(Debug, Clone)]
+pub struct HttpRangeSource {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+/// A seekable archive read through a `RangeSource`.
+pub struct RemoteArchive {
+    source: Arc<dyn RangeSource>,
+    index: XzIndex,
+    entries: Vec<EntryMetadata>,
+}
+
+impl HttpRangeSource {
+    /// Parses an `http://host[:port]/path` URL, nothing is requested until the source is read.
+    pub fn new(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            bail!("Only http:// URLs are supported, use `RemoteArchive::from_source` with another client for {}", url);
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().with_context(|| format!("Invalid port in {}", url))?),
+            None => (authority, 80),
+        };
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    fn get(&self, range: &str) -> Result<HttpResponse> {
+        debug!("Requesting bytes {} of http://{}:{}{}", range, self.host, self.port, self.path);
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).with_context(|| format!("Failed to connect to {}:{}", self.host, self.port))?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}:{}\r\nRange: bytes={}\r\nConnection: close\r\n\r\n",
+            self.path, self.host, self.port, range
+        )?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        HttpResponse::parse(&response)
+    }
+}
+
+impl RangeSource for HttpRangeSource {
+    fn size(&self) -> Result<u64> {
+        let response = self.get("0-0")?;
+        match response.status {
+            206 => {
+                let range = response.header("content-range").context("Missing Content-Range header")?;
+                range.rsplit_once('/').and_then(|(_, size)| size.parse().ok()).with_context(|| format!("Invalid Content-Range header: {}", range))
+            }
+            200 => bail!("The server doesn't support range requests for {}", self.path),
+            status => bail!("HTTP {} requesting {}", status, self.path),
+        }
+    }
+
+    fn read_range(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        let response = self.get(&format!("{}-{}", offset, offset + length - 1))?;
+        if response.status != 206 {
+            bail!("HTTP {} requesting bytes {} to {} of {}", response.status, offset, offset + length, self.path);
+        }
+        if response.body.len() as u64 != length {
+            bail!("Expected {} bytes from {} but got {}", length, self.path, response.body.len());
+        }
+        Ok(response.body)
+    }
+}
+
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn parse(response: &[u8]) -> Result<Self> {
+        let end = response.windows(4).position(|window| window == b"\r\n\r\n").context("Incomplete HTTP response")?;
+        let head = std::str::from_utf8(&response[..end]).context("Invalid HTTP response headers")?;
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|status| status.parse().ok())
+            .context("Invalid HTTP status line")?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        let mut response = HttpResponse { status, headers, body: response[end + 4..].to_vec() };
+        if response.header("transfer-encoding").is_some_and(|encoding| encoding.contains("chunked")) {
+            response.body = decode_chunked(&response.body)?;
+        } else if let Some(length) = response.header("content-length").and_then(|length| length.parse().ok()) {
+            response.body.truncate(length);
+        }
+        Ok(response)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str())
+    }
+}
+
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n").context("Truncated chunked HTTP body")?;
+        let size = std::str::from_utf8(&body[..line_end])?.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).with_context(|| format!("Invalid chunk size: {}", size))?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size).context("Truncated chunked HTTP body")?);
+        body = body.get(size + 2..).context("Truncated chunked HTTP body")?;
+    }
+}
+
+/// Reads a `RangeSource` as a seekable file, one request per read.
+struct RangeReader<'a> {
+    source: &'a dyn RangeSource,
+    size: u64,
+    position: u64,
+}
+
+impl Read for RangeReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = (buf.len() as u64).min(self.size.saturating_sub(self.position));
+        if length == 0 {
+            return Ok(0);
+        }
+        let data = self.source.read_range(self.position, length).map_err(io::Error::other)?;
+        buf[..data.len()].copy_from_slice(&data);
+        self.position += data.len() as u64;
+        Ok(data.len())
+    }
+}
+
+impl Seek for RangeReader<'_> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start of the archive"))?;
+        Ok(self.position)
+    }
+}
+
+impl RemoteArchive {
+    /// Opens a seekable archive at an `http://` URL, see `HttpRangeSource`.
+    pub fn open(url: &str) -> Result<Self> {
+        Self::from_source(HttpRangeSource::new(url)?)
+    }
+
+    /// Opens a seekable archive read through `source`, fetching its xz indexes and entry index.
+    pub fn from_source(source: impl RangeSource + 'static) -> Result<Self> {
+        let source: Arc<dyn RangeSource> = Arc::new(source);
+        let size = source.size()?;
+        debug!("Reading the xz index of a {} byte remote archive.", size);
+        let index = XzIndex::read(&mut RangeReader { source: source.as_ref(), size, position: 0 })?;
+        let mut archive = RemoteArchive { source, index, entries: Vec::new() };
+        let Some(last) = archive.index.blocks().last().copied() else {
+            bail!("The remote archive has no xz blocks");
+        };
+        let tar = archive.fetch_block(&last, last.uncompressed_size)?;
+        let mut tar = tar::Archive::new(tar.as_slice());
+        let mut entry = tar.entries()?.next().context("The last stream of the remote archive is empty")??;
+        if entry.path_bytes().as_ref() != ENTRY_INDEX_FILE_NAME.as_bytes() {
+            bail!("The remote archive has no entry index, create it with `LZMATarballWriter::set_seekable_block_size`");
+        }
+        archive.entries = serde_json::from_reader(&mut entry).context("Failed to parse the entry index")?;
+        info!("Opened remote archive with {} entries in {} blocks.", archive.entries.len(), archive.index.blocks().len());
+        Ok(archive)
+    }
+
+    /// Returns the entries of the archive with their offsets, without the entry index itself.
+    pub fn entries(&self) -> &[EntryMetadata] {
+        &self.entries
+    }
+
+    /// Fetches the contents of the file at `path` in the archive, requesting only the blocks holding it.
+    /// Sparse files can't be read remotely.
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let entry = self.entries.iter().rev().find(|entry| entry.path == path).with_context(|| format!("{} not found in the remote archive", path))?;
+        if entry.entry_type != EntryType::File {
+            bail!("{} is not a file", path);
+        }
+        let offsets = entry.offsets.with_context(|| format!("{} has no offsets in the entry index", path))?;
+        let (start, end) = (offsets.data_offset, offsets.data_offset + entry.size);
+        let mut data = Vec::with_capacity(entry.size as usize);
+        for block in self.index.blocks().iter().filter(|block| block.uncompressed_offset < end && block.uncompressed_offset + block.uncompressed_size > start) {
+            let decoded = self.fetch_block(block, end - block.uncompressed_offset)?;
+            let skip = start.saturating_sub(block.uncompressed_offset) as usize;
+            data.extend_from_slice(&decoded[skip..]);
+        }
+        debug!("Read {} bytes of {} from the remote archive.", data.len(), path);
+        Ok(data)
+    }
+
+    /// Fetches the file at `path` in the archive and writes it to `destination`, creating its parent directories.
+    pub fn extract_file(&self, path: &str, destination: impl AsRef<Path>) -> Result<()> {
+        let destination = destination.as_ref();
+        let data = self.read_file(path)?;
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(destination, data).with_context(|| format!("Failed to write {:?}", destination))
+    }
+
+    /// Fetches a block with the header of its stream and decodes its first `length` bytes.
+    fn fetch_block(&self, block: &XzBlock, length: u64) -> Result<Vec<u8>> {
+        // The first block of a stream directly follows the header, so both come in one request
+        if block.compressed_offset == block.stream_offset + HEADER_SIZE {
+            let data = self.source.read_range(block.stream_offset, HEADER_SIZE + block.compressed_size)?;
+            let (header, data) = data.split_at(HEADER_SIZE as usize);
+            return block.decode(header, data, length);
+        }
+        let header = self.source.read_range(block.stream_offset, HEADER_SIZE)?;
+        let data = self.source.read_range(block.compressed_offset, block.compressed_size)?;
+        block.decode(&header, &data, length)
+    }
+}
@@ -0,0 +1,102 @@
+//! # Rotation
+//! A [`RotationPolicy`] prunes old archives from the output directory, the usual companion of recurring backups.
+//! Set with `LZMATarballWriter::set_rotation_policy`, it is applied after each successful `compress` to the archives
+//! next to the output, and the removed archives are reported in `LZMAResult::pruned`.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::rotation::RotationPolicy;
+//! use lzma_tarball::writer::LZMATarballWriter;
+//!
+//! let result = LZMATarballWriter::new()
+//!     .with_path("./", "/")
+//!     .unwrap()
+//!     .set_output_template("backups/backup-{date}-{seq}.tar.xz")
+//!     .set_rotation_policy(Some(RotationPolicy::keep_last(7)))
+//!     .compress(|_| {})
+//!     .unwrap();
+//! for archive in result.pruned {
+//!     println!("Pruned {:?}", archive);
+//! }
+//! ```
+//!
+//! Archives are the files whose name matches the output template with its tokens as `*`, so other files in the directory
+//! are never touched. A policy therefore needs an output template. The archive just written is never pruned.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use crate::glob::glob_matches;
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// Decides which archives in a directory are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationPolicy {
+    /// Keeps the given number of most recently modified archives
+    KeepLast(usize),
+    /// Keeps the archives modified within the given number of days
+    KeepDays(u64),
+}
+
+impl RotationPolicy {
+    /// Keeps the `n` most recently modified archives.
+    pub fn keep_last(n: usize) -> Self {
+        RotationPolicy::KeepLast(n)
+    }
+
+    /// Keeps the archives modified within the last `days` days.
+    pub fn keep_days(days: u64) -> Self {
+        RotationPolicy::KeepDays(days)
+    }
+
+    /// Removes the archives in `dir` whose file name matches the glob `pattern` and that the policy doesn't keep.
+    /// `now` is the time the age of archives is measured from. Returns the removed archives, oldest first.
+    /// Archives that can't be removed are logged and left out.
+    pub fn apply(&self, dir: impl AsRef<Path>, pattern: &str, now: SystemTime) -> Result<Vec<PathBuf>> {
+        self.apply_except(dir.as_ref(), pattern, now, None)
+    }
+
+    /// Applies the policy like `apply`, never removing `current`, which counts as the newest archive.
+    pub(crate) fn apply_except(&self, dir: &Path, pattern: &str, now: SystemTime, current: Option<&Path>) -> Result<Vec<PathBuf>> {
+        debug!("Applying rotation policy {:?} to {:?} matching {:?}", self, dir, pattern);
+        let mut archives = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read the directory {:?}", dir))? {
+            let entry = entry?;
+            let path = entry.path();
+            if !glob_matches(pattern, &entry.file_name().to_string_lossy()) || current.is_some_and(|current| current == path) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                archives.push((metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), path));
+            }
+        }
+        // Newest first, by name for archives modified at the same time
+        archives.sort_by(|a, b| b.cmp(a));
+        let expired: Vec<PathBuf> = match *self {
+            RotationPolicy::KeepLast(n) => {
+                let kept = n.saturating_sub(current.is_some() as usize);
+                archives.into_iter().skip(kept).map(|(_, path)| path).collect()
+            }
+            RotationPolicy::KeepDays(days) => {
+                let cutoff = now.checked_sub(Duration::from_secs(days.saturating_mul(86_400))).unwrap_or(SystemTime::UNIX_EPOCH);
+                archives.into_iter().filter(|(modified, _)| *modified < cutoff).map(|(_, path)| path).collect()
+            }
+        };
+        let mut pruned = Vec::new();
+        for path in expired.into_iter().rev() {
+            match std::fs::remove_file(&path) {
+                Ok(()) => {
+                    debug!("Pruned {:?}", path);
+                    pruned.push(path);
+                }
+                Err(e) => warn!("Failed to prune {:?}: {}", path, e),
+            }
+        }
+        info!("Pruned {} archives from {:?}", pruned.len(), dir);
+        Ok(pruned)
+    }
+}
@@ -0,0 +1,264 @@
+//! # Sandboxed Extraction
+//! Every destination path is resolved relative to a handle of the output directory with `openat2` and `RESOLVE_BENEATH`,
+//! so symlinks, absolute paths and `..` components can never make a file operation leave the output directory,
+//! even if the tree is modified while the archive is being extracted.
+
+use anyhow::{Context, Result};
+use std::ffi::{CString, OsStr};
+use std::fs::{File, FileTimes, Permissions};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use crate::owner::{self, OwnershipFallback};
+use crate::pax;
+use crate::sparse::{self, SparseMap};
+
+/// Resolution is confined to the directory handle and never follows `/proc` style magic links.
+const RESOLVE_FLAGS: u64 = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+
+/// Options applied to every unpacked entry.
+pub(crate) struct UnpackOptions {
+    pub(crate) mask: u32,
+    pub(crate) overwrite: bool,
+    pub(crate) preserve_permissions: bool,
+    pub(crate) preserve_mtime: bool,
+    pub(crate) owner: Option<(u32, u32)>,
+    pub(crate) ownership_fallback: OwnershipFallback,
+    /// Replaces the target of a symlink entry, see `LZMATarballReader::set_link_transform`
+    pub(crate) link_target: Option<PathBuf>,
+    /// Replaces the mode derived from the header, see `LZMATarballReader::set_permission_mapper`
+    pub(crate) mode: Option<u32>,
+}
+
+/// A handle to the output directory all paths are resolved beneath.
+#[derive(Debug)]
+pub(crate) struct Sandbox {
+    root: OwnedFd,
+    dir_mode: Option<u32>,
+}
+
+impl Sandbox {
+    /// Opens the sandbox rooted at `output_dir`, creating missing directories with `dir_mode` if set.
+    pub(crate) fn open(output_dir: &Path, dir_mode: Option<u32>) -> Result<Self> {
+        let root = File::open(output_dir).context("Failed to open output directory")?;
+        Ok(Self { root: root.into(), dir_mode })
+    }
+
+    /// Unpacks an entry to `relative`, a path inside the output directory.
+    pub(crate) fn unpack<R: Read>(&self, entry: &mut tar::Entry<R>, relative: &Path, options: &UnpackOptions) -> Result<()> {
+        let (parent, name) = self.parent(relative).with_context(|| format!("Failed to resolve parent directory of {:?}", relative))?;
+        let mtime = pax::entry_mtime(entry)?;
+        let header = entry.header();
+        let kind = header.entry_type();
+        let mode = match options.mode {
+            Some(mode) => mode,
+            None => (if options.preserve_permissions { header.mode()? } else { header.mode()? & 0o777 }) & !options.mask,
+        };
+        if kind.is_dir() {
+            mkdirat(&parent, &name)?;
+            let directory = File::from(open_at(&parent, &name, libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW, 0)?);
+            return set_metadata(&directory, relative, mode, mtime, options);
+        }
+        if options.overwrite {
+            unlinkat(&parent, &name)?;
+        }
+        if kind.is_symlink() {
+            let target = match &options.link_target {
+                Some(target) => target.clone(),
+                None => entry.link_name()?.context("Symlink entry has no target")?.into_owned(),
+            };
+            let target = cstring(target.as_os_str())?;
+            // SAFETY: both strings are nul terminated and the directory handle is open.
+            check(unsafe { libc::symlinkat(target.as_ptr(), parent.as_raw_fd(), name.as_ptr()) })
+                .with_context(|| format!("Failed to create symlink {:?}", relative))?;
+            if let Some((uid, gid)) = options.owner {
+                // SAFETY: the name is nul terminated and the directory handle is open.
+                let result = check(unsafe { libc::fchownat(parent.as_raw_fd(), name.as_ptr(), uid, gid, libc::AT_SYMLINK_NOFOLLOW) });
+                owner::check_owner_change(result, relative, uid, gid, options.ownership_fallback)?;
+            }
+            Ok(())
+        } else if kind.is_hard_link() {
+            let target = entry.link_name()?.context("Hard link entry has no target")?;
+            let (target_parent, target_name) = self.parent(&target).with_context(|| format!("Failed to resolve hard link target {:?}", target))?;
+            // SAFETY: both names are nul terminated and both directory handles are open.
+            check(unsafe { libc::linkat(target_parent.as_raw_fd(), target_name.as_ptr(), parent.as_raw_fd(), name.as_ptr(), 0) })
+                .with_context(|| format!("Failed to create hard link {:?}", relative))?;
+            Ok(())
+        } else if kind.is_fifo() || kind.is_character_special() || kind.is_block_special() {
+            let file_type = if kind.is_fifo() {
+                libc::S_IFIFO
+            } else if kind.is_character_special() {
+                libc::S_IFCHR
+            } else {
+                libc::S_IFBLK
+            };
+            let device = libc::makedev(header.device_major()?.unwrap_or(0), header.device_minor()?.unwrap_or(0));
+            // SAFETY: the name is nul terminated and the directory handle is open.
+            check(unsafe { libc::mknodat(parent.as_raw_fd(), name.as_ptr(), file_type | 0o600, device) })
+                .with_context(|| format!("Failed to create special file {:?}", relative))?;
+            let node = File::from(open_at(&parent, &name, libc::O_PATH | libc::O_NOFOLLOW, 0)?);
+            if let Some((uid, gid)) = options.owner {
+                let result = std::os::unix::fs::fchown(&node, Some(uid), Some(gid));
+                owner::check_owner_change(result, relative, uid, gid, options.ownership_fallback)?;
+            }
+            // SAFETY: the name is nul terminated and the directory handle is open.
+            check(unsafe { libc::fchmodat(parent.as_raw_fd(), name.as_ptr(), mode, 0) })
+                .with_context(|| format!("Failed to set permissions of {:?}", relative))?;
+            Ok(())
+        } else if kind.is_file() || kind.is_contiguous() || kind.is_gnu_sparse() {
+            let mut file = create_at(&parent, &name).with_context(|| format!("Failed to create {:?}", relative))?;
+            io::copy(entry, &mut file).with_context(|| format!("Failed to write {:?}", relative))?;
+            set_metadata(&file, relative, mode, mtime, options)
+        } else {
+            anyhow::bail!("Unsupported entry type {:?} for {:?}", kind, relative)
+        }
+    }
+
+    /// Unpacks the data of a sparse file entry to `relative`, restoring the holes described by `map`.
+    pub(crate) fn unpack_sparse<R: Read>(&self, entry: &mut tar::Entry<R>, map: &SparseMap, relative: &Path, options: &UnpackOptions) -> Result<()> {
+        let (parent, name) = self.parent(relative).with_context(|| format!("Failed to resolve parent directory of {:?}", relative))?;
+        let mtime = pax::entry_mtime(entry)?;
+        let header = entry.header();
+        let mode = match options.mode {
+            Some(mode) => mode,
+            None => (if options.preserve_permissions { header.mode()? } else { header.mode()? & 0o777 }) & !options.mask,
+        };
+        if options.overwrite {
+            unlinkat(&parent, &name)?;
+        }
+        let mut file = create_at(&parent, &name).with_context(|| format!("Failed to create {:?}", relative))?;
+        sparse::write_regions(entry, map, &mut file).with_context(|| format!("Failed to write {:?}", relative))?;
+        set_metadata(&file, relative, mode, mtime, options)
+    }
+
+    /// Copies the file at `source` to `destination`, both inside the output directory.
+    pub(crate) fn copy(&self, source: &Path, destination: &Path, overwrite: bool) -> Result<()> {
+        let (source_parent, source_name) = self.parent(source).with_context(|| format!("Failed to resolve {:?}", source))?;
+        let mut source_file = File::from(open_at(&source_parent, &source_name, libc::O_RDONLY | libc::O_NOFOLLOW, 0)?);
+        let (parent, name) = self.parent(destination).with_context(|| format!("Failed to resolve {:?}", destination))?;
+        if overwrite {
+            unlinkat(&parent, &name)?;
+        }
+        let mut file = create_at(&parent, &name).with_context(|| format!("Failed to create {:?}", destination))?;
+        io::copy(&mut source_file, &mut file).with_context(|| format!("Failed to copy {:?} to {:?}", source, destination))?;
+        Ok(())
+    }
+
+    /// Removes the file at `relative` if it exists.
+    pub(crate) fn remove(&self, relative: &Path) -> Result<()> {
+        let (parent, name) = self.parent(relative).with_context(|| format!("Failed to resolve {:?}", relative))?;
+        unlinkat(&parent, &name)
+    }
+
+    /// Opens the parent directory of `relative`, creating missing directories, and returns it with the file name.
+    fn parent(&self, relative: &Path) -> io::Result<(OwnedFd, CString)> {
+        let mut parts = Vec::new();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => parts.push(part),
+                Component::CurDir => continue,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path leaves the output directory")),
+            }
+        }
+        let Some(name) = parts.pop() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"));
+        };
+        let mut prefix = PathBuf::from(".");
+        let mut directory = self.open_beneath(&prefix)?;
+        for part in parts {
+            prefix.push(part);
+            directory = match self.open_beneath(&prefix) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    let name = cstring(part)?;
+                    let created = mkdirat(&directory, &name).map_err(io::Error::other)?;
+                    if let Some(mode) = self.dir_mode.filter(|_| created) {
+                        // SAFETY: the name is nul terminated and the directory handle is open.
+                        check(unsafe { libc::fchmodat(directory.as_raw_fd(), name.as_ptr(), mode, 0) })?;
+                    }
+                    self.open_beneath(&prefix)?
+                }
+                result => result?,
+            };
+        }
+        Ok((directory, cstring(name)?))
+    }
+
+    /// Opens a directory handle for `relative` without ever resolving outside of the root.
+    fn open_beneath(&self, relative: &Path) -> io::Result<OwnedFd> {
+        let path = cstring(relative.as_os_str())?;
+        // SAFETY: open_how is plain old data and all fields not set below must be zero.
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = (libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC) as u64;
+        how.resolve = RESOLVE_FLAGS;
+        // SAFETY: the path is nul terminated, `how` outlives the call and its size is accurate.
+        let fd = unsafe { libc::syscall(libc::SYS_openat2, self.root.as_raw_fd(), path.as_ptr(), &how, std::mem::size_of::<libc::open_how>()) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the syscall returned a new file descriptor that nothing else owns.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+    }
+}
+
+/// Creates a new file in `parent`, failing if `name` already exists.
+fn create_at(parent: &OwnedFd, name: &CString) -> io::Result<File> {
+    open_at(parent, name, libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW, 0o600).map(File::from)
+}
+
+/// Opens a single path component in `parent`.
+fn open_at(parent: &OwnedFd, name: &CString, flags: i32, mode: libc::mode_t) -> io::Result<OwnedFd> {
+    // SAFETY: the name is nul terminated and the directory handle is open.
+    let fd = unsafe { libc::openat(parent.as_raw_fd(), name.as_ptr(), flags | libc::O_CLOEXEC, mode as libc::c_uint) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: openat returned a new file descriptor that nothing else owns.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Creates a directory in `parent`, ignoring directories that already exist.
+/// Returns whether the directory was created.
+fn mkdirat(parent: &OwnedFd, name: &CString) -> Result<bool> {
+    // SAFETY: the name is nul terminated and the directory handle is open.
+    match check(unsafe { libc::mkdirat(parent.as_raw_fd(), name.as_ptr(), 0o755) }) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to create directory {:?}", name)),
+    }
+}
+
+/// Removes a file from `parent`, ignoring files that don't exist.
+fn unlinkat(parent: &OwnedFd, name: &CString) -> Result<()> {
+    // SAFETY: the name is nul terminated and the directory handle is open.
+    match check(unsafe { libc::unlinkat(parent.as_raw_fd(), name.as_ptr(), 0) }) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.with_context(|| format!("Failed to remove existing file {:?}", name)),
+    }
+}
+
+/// Applies ownership, permissions and modification time to an open file at `relative`.
+fn set_metadata(file: &File, relative: &Path, mode: u32, mtime: std::time::SystemTime, options: &UnpackOptions) -> Result<()> {
+    // Ownership is changed first as it clears the setuid and setgid bits
+    if let Some((uid, gid)) = options.owner {
+        owner::check_owner_change(std::os::unix::fs::fchown(file, Some(uid), Some(gid)), relative, uid, gid, options.ownership_fallback)?;
+    }
+    file.set_permissions(Permissions::from_mode(mode)).context("Failed to set permissions")?;
+    if options.preserve_mtime {
+        file.set_times(FileTimes::new().set_modified(mtime).set_accessed(mtime)).context("Failed to set modification time")?;
+    }
+    Ok(())
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn cstring(part: &OsStr) -> io::Result<CString> {
+    CString::new(part.as_bytes()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte"))
+}
@@ -0,0 +1,48 @@
+//! # Sandboxed Extraction
+//! `openat2` is only available on Linux, so enabling the sandbox fails on other platforms.
+
+use anyhow::Result;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use crate::owner::OwnershipFallback;
+use crate::sparse::SparseMap;
+
+/// Options applied to every unpacked entry.
+pub(crate) struct UnpackOptions {
+    pub(crate) mask: u32,
+    pub(crate) overwrite: bool,
+    pub(crate) preserve_permissions: bool,
+    pub(crate) preserve_mtime: bool,
+    pub(crate) owner: Option<(u32, u32)>,
+    pub(crate) ownership_fallback: OwnershipFallback,
+    /// Replaces the target of a symlink entry, see `LZMATarballReader::set_link_transform`
+    pub(crate) link_target: Option<PathBuf>,
+    /// Replaces the mode derived from the header, see `LZMATarballReader::set_permission_mapper`
+    pub(crate) mode: Option<u32>,
+}
+
+/// Never constructed on this platform.
+#[derive(Debug)]
+pub(crate) enum Sandbox {}
+
+impl Sandbox {
+    pub(crate) fn open(_output_dir: &Path, _dir_mode: Option<u32>) -> Result<Self> {
+        anyhow::bail!("Sandboxed extraction is only supported on Linux")
+    }
+
+    pub(crate) fn unpack<R: Read>(&self, _entry: &mut tar::Entry<R>, _relative: &Path, _options: &UnpackOptions) -> Result<()> {
+        match *self {}
+    }
+
+    pub(crate) fn unpack_sparse<R: Read>(&self, _entry: &mut tar::Entry<R>, _map: &SparseMap, _relative: &Path, _options: &UnpackOptions) -> Result<()> {
+        match *self {}
+    }
+
+    pub(crate) fn copy(&self, _source: &Path, _destination: &Path, _overwrite: bool) -> Result<()> {
+        match *self {}
+    }
+
+    pub(crate) fn remove(&self, _relative: &Path) -> Result<()> {
+        match *self {}
+    }
+}
@@ -0,0 +1,41 @@
+//! # Skipped Entries
+//! Archiving and extraction report everything they leave out and why, so automation can tell
+//! entries that were filtered on purpose from ones that were lost.
+
+use serde::{Deserialize, Serialize};
+
+/// Why an entry was left out of an archive or an extraction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Excluded by a filter: the directory filter or an exclude pattern when archiving, the entry type filter when extracting
+    Filtered,
+    /// A hidden file or directory, see `LZMATarballWriter::set_skip_hidden`
+    Hidden,
+    /// Not a regular file: symlinks and special files when scanning directories, anything but files when flattening
+    NotAFile,
+    /// The path can't be extracted, as it leaves the output directory or is invalid on Windows
+    InvalidPath,
+    /// Another entry with the same path or flattened file name was kept
+    Conflict,
+    /// The file on disk is already up to date, see `LZMATarballReader::decompress_changed_only`
+    Unchanged,
+    /// A hard link skipped by `HardlinkPolicy::Skip`
+    HardLink,
+    /// The entry couldn't be read
+    Error(String),
+}
+
+/// An entry left out of an archive or an extraction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedEntry {
+    /// The filesystem path when archiving, the path inside the archive when extracting
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+impl SkippedEntry {
+    pub(crate) fn new(path: impl Into<String>, reason: SkipReason) -> Self {
+        SkippedEntry { path: path.into(), reason }
+    }
+}
@@ -0,0 +1,56 @@
+//! # Snapshots
+//! A [`SnapshotProvider`] lets `LZMATarballWriter` read files from a filesystem snapshot instead of the live tree,
+//! so a backup of files that change while it is written is crash-consistent.
+//! The crate doesn't create snapshots itself, the provider wraps whatever the platform offers (LVM, btrfs, ZFS, VSS, ...).
+//!
+//! ```rust,no_run
+//! use anyhow::Result;
+//! use lzma_tarball::snapshot::SnapshotProvider;
+//! use lzma_tarball::writer::LZMATarballWriter;
+//! use std::path::{Path, PathBuf};
+//!
+//! struct BtrfsSnapshot;
+//!
+//! impl SnapshotProvider for BtrfsSnapshot {
+//!     fn create(&self, _sources: &[&Path]) -> Result<()> {
+//!         // btrfs subvolume snapshot -r /data /data/.snapshot
+//!         Ok(())
+//!     }
+//!     fn remap(&self, path: &Path) -> PathBuf {
+//!         Path::new("/data/.snapshot").join(path.strip_prefix("/data").unwrap_or(path))
+//!     }
+//!     fn release(&self) -> Result<()> {
+//!         // btrfs subvolume delete /data/.snapshot
+//!         Ok(())
+//!     }
+//! }
+//!
+//! LZMATarballWriter::new()
+//!     .set_snapshot_provider(BtrfsSnapshot)
+//!     .with_directory_contents("/data", "/data")
+//!     .set_output("backup.tar.xz")
+//!     .compress(|_| {})
+//!     .unwrap();
+//! ```
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Creates a filesystem snapshot before the tar is written and maps source paths into it.
+pub trait SnapshotProvider: Send + Sync {
+    /// Creates the snapshot, called once before any file is read.
+    /// `sources` are the paths of every file that will be archived, as they were added to the writer.
+    fn create(&self, sources: &[&Path]) -> Result<()>;
+
+    /// Returns the path `path` is read from inside the snapshot.
+    fn remap(&self, path: &Path) -> PathBuf;
+
+    /// Releases the snapshot, called once after the tar is written, even if writing it failed.
+    fn release(&self) -> Result<()>;
+}
+
+impl std::fmt::Debug for dyn SnapshotProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SnapshotProvider")
+    }
+}
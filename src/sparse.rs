@@ -0,0 +1,117 @@
+//! Parses the sparse file maps GNU tar stores in pax attributes (formats 0.0, 0.1 and 1.0)
+//! and restores the holes of sparse files when they are extracted.
+//! The older sparse headers of GNU tar archives are already handled by `tar`.
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// The regions with data of a sparse file, everything else is a hole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SparseMap {
+    /// The real path of the file, the header holds a placeholder like `GNUSparseFile.0/name`
+    pub(crate) name: Option<String>,
+    /// The size of the file including its holes
+    pub(crate) real_size: u64,
+    /// The offset and length of each region with data, in increasing order
+    pub(crate) regions: Vec<(u64, u64)>,
+}
+
+/// Reads the sparse map of an entry, or `None` if it isn't a pax sparse file.
+/// With format 1.0 the map is stored at the start of the entry data, which is consumed here.
+pub(crate) fn read_map<R: Read>(entry: &mut tar::Entry<R>) -> Result<Option<SparseMap>> {
+    let Some(extensions) = entry.pax_extensions().context("Failed to read pax attributes")? else {
+        return Ok(None);
+    };
+    let mut major = None;
+    let mut name = None;
+    let mut real_size = None;
+    let mut map = None;
+    // Format 0.0 repeats these keys once per region
+    let mut offsets = Vec::new();
+    let mut lengths = Vec::new();
+    for extension in extensions {
+        let extension = extension.context("Failed to read pax attributes")?;
+        let (Ok(key), Ok(value)) = (extension.key(), extension.value()) else {
+            continue;
+        };
+        match key {
+            "GNU.sparse.major" => major = Some(parse_number(value)?),
+            "GNU.sparse.name" => name = Some(value.to_string()),
+            "GNU.sparse.realsize" | "GNU.sparse.size" => real_size = Some(parse_number(value)?),
+            "GNU.sparse.map" => map = Some(value.to_string()),
+            "GNU.sparse.offset" => offsets.push(parse_number(value)?),
+            "GNU.sparse.numbytes" => lengths.push(parse_number(value)?),
+            _ => {}
+        }
+    }
+    let Some(real_size) = real_size else {
+        return Ok(None);
+    };
+
+    let numbers = if major == Some(1) {
+        read_data_map(entry)?
+    } else if let Some(map) = map {
+        map.split(',').filter(|number| !number.is_empty()).map(parse_number).collect::<Result<Vec<_>>>()?
+    } else {
+        if offsets.len() != lengths.len() {
+            bail!("Sparse map has {} offsets but {} lengths", offsets.len(), lengths.len());
+        }
+        offsets.into_iter().zip(lengths).flat_map(|(offset, length)| [offset, length]).collect()
+    };
+    if numbers.len() % 2 != 0 {
+        bail!("Sparse map has an odd number of values");
+    }
+    let regions: Vec<(u64, u64)> = numbers.chunks(2).map(|region| (region[0], region[1])).collect();
+    let mut end = 0;
+    for &(offset, length) in &regions {
+        if offset < end || offset.checked_add(length).is_none_or(|region_end| region_end > real_size) {
+            bail!("Sparse map region {}+{} is out of order or beyond the file size of {}", offset, length, real_size);
+        }
+        end = offset + length;
+    }
+    Ok(Some(SparseMap { name, real_size, regions }))
+}
+
+/// Writes the data of a sparse entry to `file`, seeking over the holes so the filesystem can leave them unallocated.
+pub(crate) fn write_regions<R: Read>(entry: &mut R, map: &SparseMap, file: &mut File) -> io::Result<()> {
+    for &(offset, length) in &map.regions {
+        file.seek(SeekFrom::Start(offset))?;
+        if io::copy(&mut entry.take(length), file)? != length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "sparse file data ends early"));
+        }
+    }
+    file.set_len(map.real_size)
+}
+
+/// Reads the format 1.0 map at the start of the data: the number of regions followed by
+/// the offset and length of each, one decimal number per line, padded to a 512 byte block.
+fn read_data_map<R: Read>(entry: &mut R) -> Result<Vec<u64>> {
+    let mut consumed = 0;
+    let mut read_line = || -> Result<u64> {
+        let mut line = Vec::new();
+        let mut byte = [0u8];
+        loop {
+            entry.read_exact(&mut byte).context("Sparse map ends early")?;
+            consumed += 1;
+            match byte[0] {
+                b'\n' => break,
+                byte if line.len() < 20 => line.push(byte),
+                _ => bail!("Sparse map contains an invalid number"),
+            }
+        }
+        parse_number(std::str::from_utf8(&line).unwrap_or_default())
+    };
+    let count = read_line()?;
+    let mut numbers = Vec::new();
+    for _ in 0..count.checked_mul(2).context("Sparse map has too many regions")? {
+        numbers.push(read_line()?);
+    }
+    let padding = (512 - consumed % 512) % 512;
+    io::copy(&mut entry.take(padding), &mut io::sink()).context("Sparse map ends early")?;
+    Ok(numbers)
+}
+
+fn parse_number(value: &str) -> Result<u64> {
+    value.trim().parse().with_context(|| format!("Invalid number {:?} in sparse map", value))
+}
@@ -0,0 +1,193 @@
+//! # Archive Stream Writer
+//! `ArchiveStreamWriter` writes an LZMA-compressed tarball directly to any `Write` sink, one entry at a time.
+//! Unlike `LZMATarballWriter` no temporary tar file is created and entries don't have to be known up front,
+//! so files, in-memory data and directories can be added as they are discovered.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::stream_writer::ArchiveStreamWriter;
+//! use std::fs::File;
+//!
+//! let mut writer = ArchiveStreamWriter::new(File::create("output.tar.xz").unwrap(), 6);
+//! writer.add_dir("/data").unwrap();
+//! writer.add_file("Cargo.toml", "/data/Cargo.toml").unwrap();
+//! writer.add_data("/data/generated.txt", "generated at runtime").unwrap();
+//! let summary = writer.finish().unwrap();
+//! println!("Wrote {} entries in {}B", summary.entries, summary.compressed_size);
+//! ```
+
+use crate::clock::{self, Clock, SystemClock};
+use crate::hash::HashingReader;
+use crate::manifest::ManifestFile;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tar::{Builder, EntryType, Header, HeaderMode};
+use xz2::write::XzEncoder;
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// Summary of an archive written by `ArchiveStreamWriter`
+#[derive(Debug, Clone)]
+pub struct Summary {
+    /// The number of entries written, including directories
+    pub entries: usize,
+    /// The files and data entries written, in archive order
+    pub files: Vec<ManifestFile>,
+    /// The size of the uncompressed tar stream in bytes
+    pub original_size: u64,
+    /// The number of compressed bytes written to the sink
+    pub compressed_size: u64,
+    /// The time elapsed between creating the writer and finishing it
+    pub elapsed_time: std::time::Duration,
+}
+
+/// Writes an LZMA-compressed tarball to a sink incrementally
+pub struct ArchiveStreamWriter<W: Write> {
+    builder: Builder<CountingWriter<XzEncoder<CountingWriter<W>>>>,
+    entries: usize,
+    files: Vec<ManifestFile>,
+    start: Instant,
+    clock: Arc<dyn Clock>,
+}
+
+impl<W: Write> ArchiveStreamWriter<W> {
+    /// Creates a writer compressing into `sink` with the given compression level (0-9)
+    pub fn new(sink: W, compression_level: u8) -> Self {
+        let compression_level = compression_level.clamp(0, 9);
+        debug!("Creating archive stream writer with compression level {}", compression_level);
+        let encoder = XzEncoder::new(CountingWriter::new(sink), compression_level as u32);
+        Self {
+            builder: Builder::new(CountingWriter::new(encoder)),
+            entries: 0,
+            files: Vec::new(),
+            start: Instant::now(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the clock deciding the time of data entries and directories and the modification time stored for files,
+    /// see `FixedClock` for reproducible archives
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) -> &mut Self {
+        self.clock = Arc::new(clock);
+        debug!("Clock set to: {:?}", self.clock);
+        self
+    }
+
+    /// Adds a file from the filesystem, keeping its metadata
+    pub fn add_file(&mut self, input_file: impl AsRef<Path>, archive_path: impl AsRef<str>) -> Result<&mut Self> {
+        let input_file = input_file.as_ref();
+        let archive_path = archive_path.as_ref().trim_start_matches('/');
+        debug!("Adding file {:?} as {}", input_file, archive_path);
+        let file = File::open(input_file).with_context(|| format!("Failed to open {:?}", input_file))?;
+        let metadata = file.metadata()?;
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&metadata, HeaderMode::Complete);
+        if let Some(mtime) = self.clock.file_mtime(&metadata) {
+            header.set_mtime(clock::unix_seconds(mtime));
+        }
+        let mut stream = HashingReader::new(file);
+        self.builder
+            .append_data(&mut header, archive_path, &mut stream)
+            .with_context(|| format!("Failed to append {:?}", input_file))?;
+        self.record(archive_path, stream);
+        Ok(self)
+    }
+
+    /// Adds a file with the given content, owned by root with mode 0644 and the current time of the clock
+    pub fn add_data(&mut self, archive_path: impl AsRef<str>, data: impl AsRef<[u8]>) -> Result<&mut Self> {
+        let archive_path = archive_path.as_ref().trim_start_matches('/');
+        let data = data.as_ref();
+        debug!("Adding {} bytes of data as {}", data.len(), archive_path);
+        let mut header = self.new_header(EntryType::Regular, 0o644);
+        header.set_size(data.len() as u64);
+        let mut stream = HashingReader::new(data);
+        self.builder
+            .append_data(&mut header, archive_path, &mut stream)
+            .with_context(|| format!("Failed to append {}", archive_path))?;
+        self.record(archive_path, stream);
+        Ok(self)
+    }
+
+    /// Adds an empty directory, owned by root with mode 0755 and the current time of the clock
+    pub fn add_dir(&mut self, archive_path: impl AsRef<str>) -> Result<&mut Self> {
+        let archive_path = format!("{}/", archive_path.as_ref().trim_matches('/'));
+        debug!("Adding directory {}", archive_path);
+        let mut header = self.new_header(EntryType::Directory, 0o755);
+        header.set_size(0);
+        self.builder
+            .append_data(&mut header, &archive_path, io::empty())
+            .with_context(|| format!("Failed to append {}", archive_path))?;
+        self.entries += 1;
+        Ok(self)
+    }
+
+    /// Writes the end of the archive, flushes the sink and returns a summary
+    pub fn finish(self) -> Result<Summary> {
+        let tar = self.builder.into_inner().context("Failed to finish tar stream")?;
+        let original_size = tar.count;
+        let mut sink = tar.inner.finish().context("Failed to finish xz stream")?;
+        sink.flush()?;
+        let summary = Summary {
+            entries: self.entries,
+            files: self.files,
+            original_size,
+            compressed_size: sink.count,
+            elapsed_time: self.start.elapsed(),
+        };
+        debug!(
+            "Archive stream finished with {} entries, original size: {} bytes, compressed size: {} bytes",
+            summary.entries, summary.original_size, summary.compressed_size
+        );
+        Ok(summary)
+    }
+
+    fn new_header(&self, entry_type: EntryType, mode: u32) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(clock::unix_seconds(self.clock.now()));
+        header
+    }
+
+    fn record<R: io::Read>(&mut self, archive_path: &str, stream: HashingReader<R>) {
+        let (size, sha256) = stream.finish();
+        self.entries += 1;
+        self.files.push(ManifestFile {
+            path: archive_path.to_string(),
+            size,
+            sha256,
+        });
+    }
+}
+
+/// A writer that counts the bytes written through it
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    pub(crate) count: u64,
+}
+
+impl<W> CountingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,115 @@
+//! # Streaming Extraction
+//! `StreamingExtractor` extracts an archive from data pushed to it in chunks, e.g. as it arrives from a download,
+//! so extraction overlaps with the transfer and no intermediate archive file is needed.
+//!
+//! ```rust,no_run
+//! use lzma_tarball::reader::LZMATarballReader;
+//! use lzma_tarball::streaming::StreamingExtractor;
+//!
+//! let mut reader = LZMATarballReader::new();
+//! reader.set_output_directory("output").unwrap();
+//! let mut extractor = StreamingExtractor::new(&reader).unwrap();
+//! for chunk in [&b"..."[..], &b"..."[..]] {
+//!     extractor.feed(chunk).unwrap();
+//! }
+//! let result = extractor.finish().unwrap();
+//! println!("Extracted {} files", result.files.len());
+//! ```
+
+use crate::reader::{DecompressionResult, LZMATarballReader};
+use anyhow::{bail, Result};
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::JoinHandle;
+
+#[cfg(not(feature = "log"))]
+use crate::*;
+#[cfg(feature = "log")]
+use crate::logging::*;
+
+/// The number of fed chunks buffered before `feed` blocks, so a slow disk slows down the download
+const QUEUED_CHUNKS: usize = 16;
+
+/// Extracts an archive from chunks of data as they are fed to it
+pub struct StreamingExtractor {
+    sender: Option<SyncSender<Vec<u8>>>,
+    worker: Option<JoinHandle<Result<DecompressionResult>>>,
+}
+
+impl StreamingExtractor {
+    /// Starts extracting into the output directory of `reader`, using its settings.
+    /// Case collisions on case-insensitive filesystems aren't detected, as the stream can only be read once.
+    pub fn new(reader: &LZMATarballReader) -> Result<Self> {
+        if reader.output.is_none() {
+            error!("Output directory not specified when the streaming extractor was created.");
+            bail!("No output directory specified");
+        }
+        let reader = reader.clone();
+        let (sender, receiver) = mpsc::sync_channel(QUEUED_CHUNKS);
+        let worker = std::thread::spawn(move || {
+            let source = ChunkReader { receiver, chunk: Vec::new(), position: 0 };
+            reader.extract(Some(Box::new(source)), |_| {})
+        });
+        debug!("Streaming extractor started.");
+        Ok(Self {
+            sender: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    /// Feeds the next chunk of the archive, blocking while previous chunks are still being extracted.
+    /// Fails with the extraction error if extraction already failed.
+    pub fn feed(&mut self, data: &[u8]) -> Result<()> {
+        let Some(sender) = &self.sender else {
+            bail!("Extraction already failed");
+        };
+        if sender.send(data.to_vec()).is_err() {
+            // The worker only drops the receiver when it stops, so its result explains why
+            self.sender = None;
+            return match self.join() {
+                Ok(_) => bail!("Extraction finished before the end of the data"),
+                Err(e) => Err(e),
+            };
+        }
+        Ok(())
+    }
+
+    /// Signals the end of the archive and waits for extraction to complete.
+    pub fn finish(mut self) -> Result<DecompressionResult> {
+        self.sender = None;
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<DecompressionResult> {
+        match self.worker.take() {
+            Some(worker) => worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            None => bail!("Extraction already failed"),
+        }
+    }
+}
+
+/// Reads the chunks received from a `StreamingExtractor` as one continuous stream
+struct ChunkReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                // The sender is dropped when the stream is finished
+                Err(_) => return Ok(0),
+            }
+        }
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
@@ -0,0 +1,69 @@
+//! Checks for file names that can't be created on Windows, and escapes them for extraction.
+
+#[cfg(feature = "decompression")]
+use std::borrow::Cow;
+
+/// Device names Windows reserves in every directory, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4",
+    "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters that can't appear in a Windows file name, besides control characters.
+const INVALID_CHARACTERS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Returns whether `c` can't appear anywhere in a Windows file name.
+fn is_invalid_character(c: char) -> bool {
+    INVALID_CHARACTERS.contains(&c) || c.is_ascii_control() || c == '\\'
+}
+
+/// Returns why `name`, a single path component, can't be used as a file name on Windows, or `None` if it can.
+pub(crate) fn invalid_windows_name(name: &str) -> Option<&'static str> {
+    if name.chars().any(is_invalid_character) {
+        return Some("contains a character that is invalid on Windows");
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some("ends with a dot or space");
+    }
+    if is_reserved(name) {
+        return Some("is a reserved device name on Windows");
+    }
+    None
+}
+
+/// Returns whether the part of `name` before the first dot is a reserved device name.
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// Escapes `name`, a single path component, so it can be used as a file name on Windows:
+/// - invalid characters, backslashes and control characters become `%XX`, the uppercase hex of the character, e.g. `a:b` becomes `a%3Ab`
+/// - a trailing dot or space becomes `%2E` or `%20`
+/// - a reserved device name gets `_` appended to the part before the first dot, e.g. `con.txt` becomes `con_.txt`
+///
+/// Valid names are returned unchanged, so names already containing `%XX` can't always be told apart from escaped ones.
+#[cfg(feature = "decompression")]
+pub(crate) fn sanitize_windows_name(name: &str) -> Cow<'_, str> {
+    if invalid_windows_name(name).is_none() {
+        return Cow::Borrowed(name);
+    }
+    let reserved = is_reserved(name);
+    let stem_length = name.split('.').next().unwrap_or(name).len();
+    let mut sanitized = String::with_capacity(name.len() + 3);
+    for (index, c) in name.char_indices() {
+        if reserved && index == stem_length {
+            sanitized.push('_');
+        }
+        let last = index + c.len_utf8() == name.len();
+        if is_invalid_character(c) || (last && (c == '.' || c == ' ')) {
+            sanitized.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            sanitized.push(c);
+        }
+    }
+    if reserved && stem_length == name.len() {
+        sanitized.push('_');
+    }
+    Cow::Owned(sanitized)
+}
@@ -4,7 +4,7 @@
 //! ## Example
 //! Below is a basic example demonstrating how to use the `LZMATarballWriter` to compress a directory or file.
 //!
-//! ```rust
+//! ```rust,no_run
 //! use lzma_tarball::writer::LZMATarballWriter;
 //!
 //! // The input path can be any directory or file, specified as a relative or absolute path.
@@ -14,8 +14,7 @@
 //! let output = "../test/test.tar.xz";
 //!
 //! // Create a new LZMATarballWriter and configure it
-//! let result = LZMATarballWriter::new(input_path, output)
-//!  .unwrap()
+//! let result = LZMATarballWriter::new()
 //!  // Set the compression level to 6 - this is the default
 //!  // The range is 0-9, where 0 is no compression and 9 is the maximum compression
 //!  .set_compression_level(6)
@@ -24,6 +23,10 @@
 //!  // A larger buffer size speeds up compression but uses more memory
 //!  // A smaller buffer size slows down compression but uses less memory
 //!  .set_buffer_size(64)
+//!  // Add the input path to the root of the archive
+//!  .with_path(input_path, "/")
+//!  .unwrap()
+//!  .set_output(output)
 //!  // Compress the data and report progress
 //!  .compress(|progress| {
 //!      // The percentage of compression completed, ranging between 0.0 and 1.0
@@ -55,48 +58,133 @@
 //! ## Detailed Explanation
 //!
 //! ### LZMATarballWriter::new
-//! - `new(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<Self, Box<dyn Error>>`
-//! - Creates a new instance of the `LZMATarballWriter`.
-//! - It takes the path of the input (file or directory) to be compressed and the path of the output file.
-//! - The method ensures that the input path exists and resolves the output directory.
+//! - `new() -> Self`
+//! - Creates a new instance of the `LZMATarballWriter` with default settings.
+//! - Inputs are added with the `with_*` methods and the output file is set with `set_output`.
 //!
-//! ### LZMATarballWriter::with_compression_level
-//! - `with_compression_level(&mut self, level: u8) -> &mut Self`
+//! ### LZMATarballWriter::set_compression_level
+//! - `set_compression_level(&mut self, level: u8) -> &mut Self`
 //! - Sets the compression level, clamping it between 0 (no compression) and 9 (maximum compression).
 //! - The default compression level is 6.
 //!
-//! ### LZMATarballWriter::with_buffer_size
-//! - `with_buffer_size(&mut self, size: u16) -> &mut Self`
+//! ### LZMATarballWriter::set_buffer_size
+//! - `set_buffer_size(&mut self, size: impl Into<BufferSize>) -> &mut Self`
 //! - Sets the buffer size for reading and writing data during compression.
 //! - The buffer size is in kilobytes (KB). The default is 64KB.
+//! - `BufferSize::Auto` picks the buffer size from the input size and the available memory.
 //!
 //! ### LZMATarballWriter::compress
 //! - `compress<F>(&self, callback: F) -> Result<LZMAResult, Box<dyn Error>> where F: Fn(LZMACallbackResult) + 'static + Send + Sync`
 //! - Compresses the input path into an LZMA-compressed tarball.
 //! - A callback function is provided to report progress, which includes the percentage completed, bytes processed, and the speed in bytes per second (converted to megabytes per second).
 //! - Returns an `LZMAResult` on success, containing details about the compressed file size, original file size, and elapsed time of compression.
+//!
+//! ### LZMATarballWriter::compress_to_writer
+//! - `compress_to_writer<W, F>(&self, writer: W, callback: F) -> Result<LZMAResult> where W: Write + Send + 'static`
+//! - Compresses the input like `compress`, streaming the archive into any writer, e.g. a socket, instead of the output file.
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashMap};
 use std::env::temp_dir;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use tar::Builder;
+use std::time::SystemTime;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use crate::clock::{self, Clock, SystemClock};
+use crate::config::{ArchiveSpec, WriterConfig};
+use crate::catalog::{CATALOG_SHA256_KEY, CATALOG_SIZE_KEY};
+use crate::error::WriterError;
+use crate::format::CompressionFormat;
+use crate::output_template;
+use crate::priority;
+use crate::rotation::RotationPolicy;
+use crate::snapshot::SnapshotProvider;
+use crate::pax::{append_pax_header, format_timestamp, truncate_at_char_boundary, BTIME_KEY, HDRCHARSET_KEY, LINKPATH_KEY, MTIME_KEY, PATH_KEY};
+use crate::xattr::{read_xattrs, XATTR_PREFIX};
+use crate::hash::HashingReader;
+use crate::manifest::{Manifest, ManifestFile, MANIFEST_FILE_NAME};
+use crate::normalization::UnicodeNormalization;
+use crate::phase::Phase;
+use crate::skip::{SkipReason, SkippedEntry};
+use crate::glob::{glob_matches, list_archive_path, read_list};
+use crate::job::JobCheckpoint;
+use crate::mirror::{MirrorOutput, MirrorResult, OutputMirror, SharedSink, TeeWriter};
+use crate::stream_writer::CountingWriter;
+use crate::preset_dictionary::DictionaryEncoder;
+#[cfg(feature = "decompression")]
+use crate::reader::{LZMATarballReader, SidecarIndex};
+#[cfg(feature = "decompression")]
+use crate::remote::ENTRY_INDEX_FILE_NAME;
+use serde::{Deserialize, Serialize};
+use tar::{Builder, EntryType, Header, HeaderMode};
 use walkdir::DirEntry;
+use xz2::stream::MtStreamBuilder;
 use xz2::write::XzEncoder;
 
 #[cfg(not(feature = "log"))]
 use crate::*;
 #[cfg(feature = "log")]
-use log::*;
+use crate::logging::*;
 /// Options for LZMA compression
 #[derive(Debug, Clone)]
 pub struct LZMATarballWriter {
     pub compression_level: u8,
-    pub buffer_size: u16,
+    pub format: CompressionFormat,
+    pub buffer_size: BufferSize,
     pub output_file: Option<PathBuf>,
+    /// The template the output file is named after, see `set_output_template`
+    pub output_template: Option<String>,
+    /// Prunes old archives next to the output after compression, see `set_rotation_policy`
+    pub rotation_policy: Option<RotationPolicy>,
+    /// Destinations receiving a copy of the output, see `add_output_mirror`
+    pub output_mirrors: Vec<OutputMirror>,
     pub tar_file: PathBuf,
     pub archive_paths: Vec<ArchiveEntry>,
+    pub write_manifest: bool,
+    pub unicode_normalization: UnicodeNormalization,
+    pub skip_hidden: bool,
+    pub store_directories: bool,
+    /// The files left out while scanning directories
+    pub skipped: Vec<SkippedEntry>,
+    /// The call that added each archive path, see `plan`
+    pub sources: BTreeMap<String, EntrySource>,
+    /// Patterns of archive paths left out of the archive, see `set_exclude_file`
+    pub exclude_patterns: Vec<String>,
+    pub catalog: bool,
+    pub progress_dispatch: Dispatch,
+    pub preserve_btime: bool,
+    pub precise_mtime: bool,
+    pub preserve_xattrs: bool,
+    pub seekable_block_size: Option<u64>,
+    pub sidecar_index: Option<PathBuf>,
+    pub preset_dictionary: Option<Vec<u8>>,
+    pub output_queue_depth: Option<usize>,
+    pub output_mode: Option<u32>,
+    pub output_owner: Option<(u32, u32)>,
+    pub keep_partial_output: bool,
+    pub clock: Arc<dyn Clock>,
+    pub cancel_flag: Option<Arc<AtomicBool>>,
+    pub file_progress: Option<FileProgressCallback>,
+    pub open_at_scan: bool,
+    pub no_follow: bool,
+    pub lock_source_files: bool,
+    pub snapshot_provider: Option<Arc<dyn SnapshotProvider>>,
+    pub background_priority: bool,
+    pub timeout: Option<std::time::Duration>,
+    pub dedupe_identical_files: bool,
+    pub long_path_encoding: LongPathEncoding,
+    pub name_encoding: NameEncoding,
+    pub temp_strategy: TempStrategy,
+    pub absolute_path_policy: AbsolutePathPolicy,
+    /// Set when the caller stopped waiting for a clone started because of `timeout`
+    timed_out: Arc<AtomicBool>,
+    /// The state file and progress of a job loaded with `load_job`
+    pub(crate) job: Option<JobCheckpoint>,
+    /// Files opened when they were added, `None` if opening them failed
+    handles: HashMap<PathBuf, Option<Arc<File>>>,
 }
 /// Result of an LZMA compression operation
 #[derive(Debug, Clone)]
@@ -105,6 +193,28 @@ pub struct LZMAResult {
     pub size: u64,
     pub original_size: u64,
     pub elapsed_time: std::time::Duration,
+    /// Statistics for every file in the archive, in archive order
+    pub entries: Vec<EntryStatistics>,
+    /// The files left out while scanning directories, with the reason
+    pub skipped: Vec<SkippedEntry>,
+    /// The memory used by the xz encoder in bytes, as computed by liblzma for the settings it ran with
+    pub memory_usage: u64,
+    /// How writing to each output mirror went, in the order they were added
+    pub mirrors: Vec<MirrorResult>,
+    /// The archives removed by the rotation policy, oldest first, see `set_rotation_policy`
+    pub pruned: Vec<PathBuf>,
+}
+/// Statistics of a single file collected while creating the tar
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryStatistics {
+    /// The path of the file inside the archive
+    pub path: String,
+    /// The size of the input file in bytes
+    pub size: u64,
+    /// The fraction of the total input size taken up by this file, between 0.0 and 1.0
+    pub share: f32,
+    /// The position of the file in the archive, starting at 0
+    pub order: usize,
 }
 /// Callback result for reporting progress
 #[derive(Debug, Clone)]
@@ -112,12 +222,296 @@ pub struct LZMACallbackResult {
     pub bytes_processed: u64,
     pub bytes_per_second: u64,
     pub percentage: f32,
+    /// The number of compressed bytes written to the output so far
+    pub bytes_written: u64,
+    /// Whether compression is blocked because the output queue is full, see `set_output_queue_depth`
+    pub waiting_on_output: bool,
+    /// The stage compression is in, an update is sent as soon as each phase starts
+    pub phase: Phase,
 }
+/// Progress of a single file while it is copied into the tar
 #[derive(Debug, Clone)]
+pub struct FileProgress {
+    /// The path of the file inside the archive
+    pub path: String,
+    pub bytes_processed: u64,
+    pub total_bytes: u64,
+}
+/// A callback receiving `FileProgress` updates, see `LZMATarballWriter::set_file_progress`
+#[derive(Clone)]
+pub struct FileProgressCallback(Arc<dyn Fn(FileProgress) + Send + Sync>);
+impl std::fmt::Debug for FileProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FileProgressCallback")
+    }
+}
+/// How paths and link targets longer than the 100 bytes of a plain tar header are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LongPathEncoding {
+    /// GNU long name entries, read by GNU tar and most other tools
+    #[default]
+    Gnu,
+    /// Paths that don't fit the ustar name and prefix fields are stored in pax `path` and `linkpath` attributes
+    Pax,
+    /// Only the ustar name and prefix fields, compression fails if a path doesn't fit them
+    Ustar,
+}
+/// How files whose names aren't valid UTF-8 are stored, their archive paths are always UTF-8 strings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum NameEncoding {
+    /// Compression fails with an error naming the file
+    #[default]
+    Error,
+    /// Invalid sequences are replaced with U+FFFD, as the archive path already has them
+    Lossy,
+    /// The original bytes of the name are stored in a pax `path` attribute marked `hdrcharset=BINARY`
+    Raw,
+}
+/// What happens to archive paths starting with `/`, e.g. from adding absolute filesystem paths under a `/` root
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AbsolutePathPolicy {
+    /// Leading slashes are removed, so the entry is extracted relative to the output directory
+    #[default]
+    Strip,
+    /// The path is stored with a single leading slash, as `tar --absolute-names` does
+    Keep,
+    /// Compression fails if an archive path is absolute
+    Error,
+}
+/// Where the tar is staged before it is compressed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TempStrategy {
+    /// The tar is kept in memory, spilling to the temporary tar file once it grows beyond `max_bytes`
+    Memory { max_bytes: u64 },
+    /// The tar is written to a temporary file in this directory
+    Disk(PathBuf),
+    /// The tar is compressed while it is written, so nothing is staged.
+    /// Progress is reported against the planned `input_size` as the tar size isn't known upfront.
+    None,
+}
+impl Default for TempStrategy {
+    fn default() -> Self {
+        TempStrategy::Disk(temp_dir())
+    }
+}
+/// How progress updates are delivered during compression
+#[derive(Debug, Clone, Default)]
+pub enum Dispatch {
+    /// The callback is called on the compression thread, so a slow callback slows down compression
+    #[default]
+    Inline,
+    /// The callback is called on a dedicated thread, compression waits for it to finish before returning
+    DedicatedThread,
+    /// Progress is sent to a channel instead of the callback, see `Dispatch::channel`
+    Channel(Sender<LZMACallbackResult>),
+}
+impl Dispatch {
+    /// Creates a `Dispatch::Channel` along with the receiver progress updates are sent to
+    pub fn channel() -> (Self, Receiver<LZMACallbackResult>) {
+        let (sender, receiver) = mpsc::channel();
+        (Dispatch::Channel(sender), receiver)
+    }
+}
+/// Size of the buffer used to read and write data during compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BufferSize {
+    /// Picks the buffer size based on the input size and the available memory
+    Auto,
+    /// A fixed buffer size in KB
+    Kilobytes(u16),
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ArchiveEntry {
     pub filesystem_path: PathBuf,
     pub archive_path: String,
 }
+/// The `with_*` call that added an entry, shown by `LZMATarballWriter::plan` and in compression errors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntrySource {
+    /// Added on its own by `with_file`, or by `with_path` with a file
+    File,
+    /// Added in a list by `with_files`
+    Files,
+    /// Found scanning `root` by `with_directory_contents`, `with_filtered_directory_contents`
+    /// or `with_path` with a directory, and added under `archive_path`
+    Directory { root: PathBuf, archive_path: String },
+    /// Listed on `line` of the file read by `set_include_file`
+    IncludeFile { list: PathBuf, line: usize },
+}
+impl std::fmt::Display for EntrySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntrySource::File => f.write_str("added by with_file"),
+            EntrySource::Files => f.write_str("added by with_files"),
+            EntrySource::Directory { root, archive_path } => write!(f, "found scanning {:?} for {:?}", root, archive_path),
+            EntrySource::IncludeFile { list, line } => write!(f, "listed on line {} of {:?}", line, list),
+        }
+    }
+}
+/// What `compress` will do, see `LZMATarballWriter::plan`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionPlan {
+    pub entries: Vec<PlannedEntry>,
+    /// The memory the xz encoder will use in bytes, see `LZMATarballWriter::estimated_memory_usage`
+    pub estimated_memory_usage: u64,
+}
+/// An entry `compress` will store, see `LZMATarballWriter::plan`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedEntry {
+    pub filesystem_path: PathBuf,
+    pub archive_path: String,
+    /// The call that added the entry, `None` if it was pushed to `archive_paths` directly
+    pub source: Option<EntrySource>,
+}
+
+impl From<u16> for BufferSize {
+    fn from(size: u16) -> Self {
+        BufferSize::Kilobytes(size)
+    }
+}
+impl BufferSize {
+    const MIN_AUTO_SIZE: usize = 16 * 1024;
+    const MAX_AUTO_SIZE: usize = 8 * 1024 * 1024;
+
+    /// Resolves the buffer size in bytes for an input of `input_size` bytes
+    pub fn resolve(&self, input_size: u64) -> usize {
+        match self {
+            BufferSize::Kilobytes(size) => 1024 * (*size).max(1) as usize,
+            BufferSize::Auto => {
+                // Aim for roughly 256 reads over the input, bounded by 1/64th of the available memory
+                let target = (input_size / 256) as usize;
+                let max = match available_memory() {
+                    Some(available) => ((available / 64) as usize).clamp(Self::MIN_AUTO_SIZE, Self::MAX_AUTO_SIZE),
+                    None => Self::MAX_AUTO_SIZE,
+                };
+                target
+                    .clamp(Self::MIN_AUTO_SIZE, max)
+                    .next_power_of_two()
+                    .min(max)
+            }
+        }
+    }
+}
+/// Checks whether a directory entry is hidden
+fn is_hidden(entry: &DirEntry) -> bool {
+    if entry.file_name().to_string_lossy().starts_with('.') {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if let Ok(metadata) = entry.metadata() {
+            return metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
+        }
+    }
+    false
+}
+/// Sets the path of a header, writing absolute paths to the name field as is since `tar` refuses them.
+/// Absolute paths aren't split between the name and prefix fields.
+fn set_header_path(header: &mut Header, path: &str) -> std::io::Result<()> {
+    if !path.starts_with('/') {
+        return header.set_path(path);
+    }
+    let name = &mut header.as_old_mut().name;
+    if path.len() > name.len() {
+        return Err(std::io::Error::other(format!("absolute path {:?} is longer than {} bytes", path, name.len())));
+    }
+    name.fill(0);
+    name[..path.len()].copy_from_slice(path.as_bytes());
+    Ok(())
+}
+
+/// Lists the files in a directory as archive entries under `archive_path`, adding what is left out to `skipped`.
+/// Archive paths are relative to `strip_prefix`, the directory itself or one of its ancestors.
+/// Directories are listed before their contents if `store_directories` is set.
+/// A skipped hidden directory is reported once, not for every file in it.
+pub(crate) fn scan_directory(
+    input_directory: &Path,
+    strip_prefix: &Path,
+    archive_path: &str,
+    skip_hidden: bool,
+    store_directories: bool,
+    filter: &dyn Fn(&DirEntry) -> bool,
+    skipped: &mut Vec<SkippedEntry>,
+) -> Vec<ArchiveEntry> {
+    let mut entries = Vec::new();
+    let mut walker = walkdir::WalkDir::new(input_directory).into_iter();
+    while let Some(e) = walker.next() {
+        let e = match e {
+            Ok(e) => e,
+            Err(e) => {
+                let path = e.path().unwrap_or(input_directory).display().to_string();
+                warn!("Skipping {:?} while scanning: {}", path, e);
+                skipped.push(SkippedEntry::new(path, SkipReason::Error(e.to_string())));
+                continue;
+            }
+        };
+        let reason = if skip_hidden && e.depth() > 0 && is_hidden(&e) {
+            if e.file_type().is_dir() {
+                walker.skip_current_dir();
+            }
+            SkipReason::Hidden
+        } else if e.file_type().is_dir() {
+            let path = format!("{}/{}", archive_path, e.path().strip_prefix(strip_prefix).unwrap().to_string_lossy());
+            let path = path.trim_end_matches('/');
+            // A directory added under the archive root has no path of its own
+            if store_directories && !path.trim_start_matches('/').is_empty() {
+                debug!("Adding directory: {:?}", e.path());
+                entries.push(ArchiveEntry {
+                    filesystem_path: e.path().to_path_buf(),
+                    archive_path: path.to_string(),
+                });
+            }
+            continue;
+        } else if !e.file_type().is_file() {
+            SkipReason::NotAFile
+        } else if !filter(&e) {
+            SkipReason::Filtered
+        } else {
+            debug!("Adding file from directory: {:?}", e.path());
+            entries.push(ArchiveEntry {
+                filesystem_path: e.path().to_path_buf(),
+                archive_path: format!(
+                    "{}/{}",
+                    archive_path,
+                    e.path().strip_prefix(strip_prefix).unwrap().to_string_lossy()
+                ),
+            });
+            continue;
+        };
+        debug!("Skipping {:?} while scanning: {:?}", e.path(), reason);
+        skipped.push(SkippedEntry::new(e.path().display().to_string(), reason));
+    }
+    entries
+}
+/// Opens a source file for reading, failing on symlinks if `no_follow` is set
+pub(crate) fn open_source(path: &Path, no_follow: bool) -> std::io::Result<File> {
+    let mut options = File::options();
+    options.read(true);
+    if no_follow {
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NOFOLLOW);
+        // FILE_FLAG_OPEN_REPARSE_POINT opens the link itself, which is rejected below
+        #[cfg(windows)]
+        std::os::windows::fs::OpenOptionsExt::custom_flags(&mut options, 0x0020_0000);
+    }
+    let file = options.open(path)?;
+    if no_follow && file.metadata()?.file_type().is_symlink() {
+        return Err(std::io::Error::other(format!("{:?} is a symlink", path)));
+    }
+    Ok(file)
+}
+/// Returns the available system memory in bytes, if it can be determined
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
 
 impl Default for LZMATarballWriter {
     fn default() -> Self {
@@ -130,8 +524,14 @@ impl LZMATarballWriter {
     /// - Default Buffer size: 64KB
     /// - Default Tar File: `%TEMP%/{filename|"archive"}-{timestamp}.tar`
     pub fn new() -> Self {
-        let tar_file_path =
-            temp_dir().join(format!("archive-{}.tmp", chrono::Utc::now().timestamp()));
+        // Writers created in the same second must not share a temporary tar file
+        static WRITER_COUNT: AtomicUsize = AtomicUsize::new(0);
+        let tar_file_path = temp_dir().join(format!(
+            "archive-{}-{}-{}.tmp",
+            clock::unix_seconds(SystemTime::now()),
+            std::process::id(),
+            WRITER_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
 
         debug!(
             "Creating new LZMATarballWriter with tar_file: {:?}",
@@ -139,12 +539,89 @@ impl LZMATarballWriter {
         );
         LZMATarballWriter {
             compression_level: 6,
-            buffer_size: 64,
+            format: CompressionFormat::Xz,
+            buffer_size: BufferSize::Kilobytes(64),
             output_file: None,
+            output_template: None,
+            rotation_policy: None,
+            output_mirrors: Vec::new(),
             tar_file: tar_file_path,
             archive_paths: Vec::new(),
+            write_manifest: false,
+            catalog: false,
+            progress_dispatch: Dispatch::Inline,
+            preserve_btime: false,
+            precise_mtime: false,
+            preserve_xattrs: false,
+            seekable_block_size: None,
+            sidecar_index: None,
+            preset_dictionary: None,
+            output_queue_depth: None,
+            output_mode: None,
+            output_owner: None,
+            keep_partial_output: false,
+            clock: Arc::new(SystemClock),
+            cancel_flag: None,
+            file_progress: None,
+            open_at_scan: false,
+            no_follow: false,
+            lock_source_files: false,
+            snapshot_provider: None,
+            background_priority: false,
+            timeout: None,
+            dedupe_identical_files: false,
+            long_path_encoding: LongPathEncoding::Gnu,
+            name_encoding: NameEncoding::Error,
+            temp_strategy: TempStrategy::default(),
+            absolute_path_policy: AbsolutePathPolicy::Strip,
+            timed_out: Arc::new(AtomicBool::new(false)),
+            job: None,
+            handles: HashMap::new(),
+            unicode_normalization: UnicodeNormalization::None,
+            skip_hidden: false,
+            store_directories: false,
+            skipped: Vec::new(),
+            sources: BTreeMap::new(),
+            exclude_patterns: Vec::new(),
         }
     }
+    /// Creates a writer from a reusable configuration and the spec of a single archive
+    pub fn from_parts(config: &WriterConfig, spec: &ArchiveSpec) -> Self {
+        let mut writer = Self::new();
+        writer
+            .set_compression_level(config.compression_level)
+            .set_format(config.format)
+            .set_buffer_size(config.buffer_size)
+            .set_write_manifest(config.write_manifest)
+            .set_catalog(config.catalog)
+            .set_preserve_btime(config.preserve_btime)
+            .set_precise_mtime(config.precise_mtime)
+            .set_preserve_xattrs(config.preserve_xattrs)
+            .set_seekable_block_size(config.seekable_block_size)
+            .set_preset_dictionary(config.preset_dictionary.clone())
+            .set_output_queue_depth(config.output_queue_depth)
+            .set_lock_source_files(config.lock_source_files)
+            .set_background_priority(config.background_priority)
+            .set_dedupe_identical_files(config.dedupe_identical_files)
+            .set_long_path_encoding(config.long_path_encoding)
+            .set_name_encoding(config.name_encoding)
+            .set_temp_strategy(config.temp_strategy.clone())
+            .set_absolute_path_policy(config.absolute_path_policy)
+            .set_unicode_normalization(config.unicode_normalization)
+            .set_progress_dispatch(config.progress_dispatch.clone())
+            .set_skip_hidden(spec.skip_hidden)
+            .set_store_directories(spec.store_directories);
+        writer.timeout = config.timeout;
+        writer.output_mode = config.output_mode;
+        writer.output_owner = config.output_owner;
+        writer.keep_partial_output = config.keep_partial_output;
+        writer.clock = config.clock.clone();
+        writer.archive_paths = spec.entries.clone();
+        writer.skipped = spec.skipped.clone();
+        writer.sources = spec.sources.clone();
+        writer.output_file = spec.output_file.clone();
+        writer
+    }
     /// Sets the compression level (clamps between 0 and 9)
     pub fn set_compression_level(&mut self, level: u8) -> &mut Self {
         self.compression_level = level.clamp(0, 9);
@@ -152,11 +629,20 @@ impl LZMATarballWriter {
         debug!("Compression level set to: {}", self.compression_level);
         self
     }
-    /// Sets the buffer size in KB
-    pub fn set_buffer_size(&mut self, size: u16) -> &mut Self {
-        self.buffer_size = size;
+    /// Sets the codec the tar is compressed with, see `CompressionFormat`. Compression levels 0 to 9 are spread
+    /// over zstd levels 1 to 19, used as is for gzip and ignored by `CompressionFormat::None`. Seekable archives and preset dictionaries are only available for xz,
+    /// compressing fails with `WriterError::IncompatibleOptions` if they are set with another format.
+    pub fn set_format(&mut self, format: CompressionFormat) -> &mut Self {
+        self.format = format;
+
+        debug!("Format set to: {:?}", self.format);
+        self
+    }
+    /// Sets the buffer size in KB, or `BufferSize::Auto` to size it from the input
+    pub fn set_buffer_size(&mut self, size: impl Into<BufferSize>) -> &mut Self {
+        self.buffer_size = size.into();
 
-        debug!("Buffer size set to: {} KB", self.buffer_size);
+        debug!("Buffer size set to: {:?}", self.buffer_size);
         self
     }
     /// Sets the temporary tar file output path
@@ -166,6 +652,255 @@ impl LZMATarballWriter {
         debug!("Tar file path set to: {:?}", self.tar_file);
         self
     }
+    /// Sets whether a `.lzma_tarball_manifest.json` entry describing the archived files is added to the archive
+    pub fn set_write_manifest(&mut self, write_manifest: bool) -> &mut Self {
+        self.write_manifest = write_manifest;
+
+        debug!("Write manifest set to: {}", self.write_manifest);
+        self
+    }
+    /// Sets the Unicode normalization form applied to archive paths
+    pub fn set_unicode_normalization(&mut self, normalization: UnicodeNormalization) -> &mut Self {
+        self.unicode_normalization = normalization;
+
+        debug!("Unicode normalization set to: {:?}", self.unicode_normalization);
+        self
+    }
+    /// Sets how progress updates are delivered, see `Dispatch`.
+    pub fn set_progress_dispatch(&mut self, progress_dispatch: Dispatch) -> &mut Self {
+        self.progress_dispatch = progress_dispatch;
+
+        debug!("Progress dispatch set to: {:?}", self.progress_dispatch);
+        self
+    }
+    /// Sets whether the creation time of each file is stored in a `LIBARCHIVE.creationtime` pax attribute.
+    /// Files on platforms or filesystems that don't expose a creation time are stored without it.
+    pub fn set_preserve_btime(&mut self, preserve_btime: bool) -> &mut Self {
+        self.preserve_btime = preserve_btime;
+
+        debug!("Preserve btime set to: {}", self.preserve_btime);
+        self
+    }
+    /// Sets whether the extended attributes of each file are stored in `SCHILY.xattr.*` pax attributes,
+    /// which `LZMATarballReader::set_unpack_xattrs` restores. Only Linux and macOS files have them.
+    pub fn set_preserve_xattrs(&mut self, preserve_xattrs: bool) -> &mut Self {
+        self.preserve_xattrs = preserve_xattrs;
+
+        debug!("Preserve xattrs set to: {}", self.preserve_xattrs);
+        self
+    }
+    /// Sets whether `compress` writes a seekable archive, compressed in independent xz blocks of `block_size`
+    /// uncompressed bytes and followed by a stream holding the offsets of every entry as `ENTRY_INDEX_FILE_NAME`.
+    /// `RemoteArchive` reads single files of such archives with range requests. Smaller blocks mean smaller
+    /// requests but a slightly worse ratio. Indexing reads the archive back, so it needs the decompression feature.
+    pub fn set_seekable_block_size(&mut self, seekable_block_size: Option<u64>) -> &mut Self {
+        self.seekable_block_size = seekable_block_size;
+
+        debug!("Seekable block size set to: {:?}", self.seekable_block_size);
+        self
+    }
+    /// Sets a file `compress` writes the entries of the archive to, so `LZMATarballReader::set_sidecar_index` can list
+    /// them without decoding the archive. Writing the index reads the archive back, so it needs the decompression feature.
+    pub fn set_sidecar_index(&mut self, sidecar_index: Option<PathBuf>) -> &mut Self {
+        self.sidecar_index = sidecar_index;
+
+        debug!("Sidecar index set to: {:?}", self.sidecar_index);
+        self
+    }
+    /// Sets a preset dictionary the encoder is primed with, so many small archives with similar contents, like
+    /// configuration snapshots, compress much better than they would on their own. A sample of typical contents
+    /// makes a good dictionary. The .xz format can't record a dictionary, so the output is a raw LZMA2 stream
+    /// instead, which only `LZMATarballReader::set_preset_dictionary` with the same dictionary can read.
+    /// It can't be combined with seekable archives or `append_stream`.
+    pub fn set_preset_dictionary(&mut self, preset_dictionary: Option<Vec<u8>>) -> &mut Self {
+        self.preset_dictionary = preset_dictionary;
+
+        debug!("Preset dictionary set to: {:?} bytes", self.preset_dictionary.as_ref().map(Vec::len));
+        self
+    }
+    /// Sets how many buffer sized chunks of compressed data are queued for the output, which is then written on a
+    /// dedicated thread. A slow output like a network share blocks compression only once the queue is full, memory
+    /// stays bounded by the queue, and progress updates set `waiting_on_output` while blocked.
+    /// `None`, the default, writes the output on the compressing thread.
+    pub fn set_output_queue_depth(&mut self, output_queue_depth: Option<usize>) -> &mut Self {
+        self.output_queue_depth = output_queue_depth;
+
+        debug!("Output queue depth set to: {:?}", self.output_queue_depth);
+        self
+    }
+    /// Sets whether modification times are stored with nanosecond precision in an `mtime` pax attribute.
+    /// The tar header only holds whole seconds, so without it restored files appear modified to tools comparing mtimes.
+    pub fn set_precise_mtime(&mut self, precise_mtime: bool) -> &mut Self {
+        self.precise_mtime = precise_mtime;
+
+        debug!("Precise mtime set to: {}", self.precise_mtime);
+        self
+    }
+    /// Sets a flag that stops compression when set to `true`, even in the middle of a large file.
+    /// A cancelled compression fails with `WriterError::Cancelled` and leaves no output behind, see `set_keep_partial_output`.
+    pub fn set_cancel_flag(&mut self, cancel_flag: Arc<AtomicBool>) -> &mut Self {
+        self.cancel_flag = Some(cancel_flag);
+
+        debug!("Cancel flag set");
+        self
+    }
+    /// Sets a callback receiving the progress of each file as it is copied into the tar, once per buffer sized chunk.
+    /// Unlike the `compress` callback it reports while the tar is created, before compression starts.
+    pub fn set_file_progress<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(FileProgress) + 'static + Send + Sync,
+    {
+        self.file_progress = Some(FileProgressCallback(Arc::new(callback)));
+
+        debug!("File progress callback set");
+        self
+    }
+    /// Sets whether files are opened as soon as they are added instead of when the tar is created.
+    /// The archive then contains the files that were scanned even if their paths are swapped before `compress`,
+    /// at the cost of keeping one open handle per file. This only affects files added after it is set.
+    pub fn set_open_at_scan(&mut self, open_at_scan: bool) -> &mut Self {
+        self.open_at_scan = open_at_scan;
+
+        debug!("Open at scan set to: {}", self.open_at_scan);
+        self
+    }
+    /// Sets whether opening a file fails if its path is a symlink, instead of following it.
+    pub fn set_no_follow(&mut self, no_follow: bool) -> &mut Self {
+        self.no_follow = no_follow;
+
+        debug!("No follow set to: {}", self.no_follow);
+        self
+    }
+    /// Sets whether a shared advisory lock is taken on each file while it is read into the archive.
+    /// Writers that take exclusive locks, like many databases, are blocked until the file is read, so it is never torn.
+    /// Locks are advisory, writers that don't lock the file are not affected.
+    pub fn set_lock_source_files(&mut self, lock_source_files: bool) -> &mut Self {
+        self.lock_source_files = lock_source_files;
+
+        debug!("Lock source files set to: {}", self.lock_source_files);
+        self
+    }
+    /// Sets a provider that creates a filesystem snapshot before the tar is written and releases it afterwards.
+    /// Files are read from the paths the provider maps them to, so the archive is consistent even if the tree changes.
+    /// Files opened by `set_open_at_scan` are read from their handles instead.
+    pub fn set_snapshot_provider(&mut self, provider: impl SnapshotProvider + 'static) -> &mut Self {
+        self.snapshot_provider = Some(Arc::new(provider));
+
+        debug!("Snapshot provider set");
+        self
+    }
+    /// Sets whether compression runs on a worker thread with lowered CPU and I/O priority,
+    /// so long archive jobs don't slow down interactive workloads.
+    /// Uses nice and the idle I/O class on Linux, the background QoS class on macOS and background mode on Windows.
+    pub fn set_background_priority(&mut self, background_priority: bool) -> &mut Self {
+        self.background_priority = background_priority;
+
+        debug!("Background priority set to: {}", self.background_priority);
+        self
+    }
+    /// Sets the time `compress` and `append_stream` may take before they fail with an `io::ErrorKind::TimedOut` error,
+    /// so slow I/O, e.g. on a degraded network filesystem, doesn't block the caller indefinitely.
+    /// Compression then runs on a worker thread. Once the timeout expires it stops at its next read, and the call returns
+    /// after it has removed its partial output, so the output path is free for a retry. An I/O call that never returns still blocks.
+    pub fn set_timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+
+        debug!("Timeout set to: {:?}", self.timeout);
+        self
+    }
+    /// Sets whether files with the same content as a file earlier in the archive are stored as hard links to it,
+    /// so duplicated artifacts are only stored once. Each file is read twice, once to hash it and once to store it.
+    /// Has no effect on catalogs, as they store no content.
+    pub fn set_dedupe_identical_files(&mut self, dedupe_identical_files: bool) -> &mut Self {
+        self.dedupe_identical_files = dedupe_identical_files;
+
+        debug!("Dedupe identical files set to: {}", self.dedupe_identical_files);
+        self
+    }
+    /// Sets how paths too long for a plain tar header are stored, see `LongPathEncoding`.
+    /// With `LongPathEncoding::Ustar`, `validate_ustar_paths` lists the paths that would fail before compressing.
+    pub fn set_long_path_encoding(&mut self, long_path_encoding: LongPathEncoding) -> &mut Self {
+        self.long_path_encoding = long_path_encoding;
+
+        debug!("Long path encoding set to: {:?}", self.long_path_encoding);
+        self
+    }
+    /// Sets how files whose names aren't valid UTF-8 are stored, see `NameEncoding`
+    pub fn set_name_encoding(&mut self, name_encoding: NameEncoding) -> &mut Self {
+        self.name_encoding = name_encoding;
+
+        debug!("Name encoding set to: {:?}", self.name_encoding);
+        self
+    }
+    /// Sets where the tar is staged before it is compressed, see `TempStrategy`.
+    /// With `TempStrategy::Disk` the temporary tar file is moved into the given directory.
+    pub fn set_temp_strategy(&mut self, temp_strategy: TempStrategy) -> &mut Self {
+        if let TempStrategy::Disk(ref directory) = temp_strategy {
+            if let Some(name) = self.tar_file.file_name() {
+                self.tar_file = directory.join(name);
+            }
+        }
+        self.temp_strategy = temp_strategy;
+
+        debug!("Temp strategy set to: {:?}", self.temp_strategy);
+        self
+    }
+    /// Sets what happens to archive paths starting with `/`, see `AbsolutePathPolicy`
+    pub fn set_absolute_path_policy(&mut self, absolute_path_policy: AbsolutePathPolicy) -> &mut Self {
+        self.absolute_path_policy = absolute_path_policy;
+
+        debug!("Absolute path policy set to: {:?}", self.absolute_path_policy);
+        self
+    }
+    /// Returns the archive paths of the added files that don't fit the name and prefix fields of a ustar header,
+    /// which tools limited to plain ustar silently truncate.
+    pub fn validate_ustar_paths(&self) -> Vec<String> {
+        self.archive_paths
+            .iter()
+            .map(|entry| self.unicode_normalization.apply(&entry.archive_path).into_owned())
+            .map(|path| self.apply_absolute_path_policy(&path).unwrap_or(path.trim_start_matches('/')).to_string())
+            .filter(|path| set_header_path(&mut Header::new_ustar(), path).is_err())
+            .collect()
+    }
+    /// Returns the path an archive path is stored as according to `absolute_path_policy`
+    pub(crate) fn apply_absolute_path_policy<'a>(&self, path: &'a str) -> Result<&'a str> {
+        let relative = path.trim_start_matches('/');
+        match self.absolute_path_policy {
+            AbsolutePathPolicy::Strip => Ok(relative),
+            AbsolutePathPolicy::Keep if relative.len() < path.len() => Ok(&path[path.len() - relative.len() - 1..]),
+            AbsolutePathPolicy::Keep => Ok(path),
+            AbsolutePathPolicy::Error if relative.len() < path.len() => {
+                error!("Archive path is absolute: {}", path);
+                bail!("Archive path {:?} is absolute", path)
+            }
+            AbsolutePathPolicy::Error => Ok(path),
+        }
+    }
+    /// Sets whether a catalog archive is written, storing only the headers of each file.
+    /// The original size and SHA-256 hash are kept in pax attributes, see the `catalog` module.
+    pub fn set_catalog(&mut self, catalog: bool) -> &mut Self {
+        self.catalog = catalog;
+
+        debug!("Catalog set to: {}", self.catalog);
+        self
+    }
+    /// Sets whether hidden files and directories are skipped when scanning directories.
+    /// Dotfiles are hidden on every platform, on Windows files with the hidden attribute are skipped as well.
+    /// This only affects directories added after it is set.
+    pub fn set_skip_hidden(&mut self, skip_hidden: bool) -> &mut Self {
+        self.skip_hidden = skip_hidden;
+
+        debug!("Skip hidden set to: {}", self.skip_hidden);
+        self
+    }
+    /// Sets whether scanned directories are stored as entries of their own, with their permissions and modification
+    /// times, which the reader applies after extracting their contents. This only affects directories added after it is set.
+    pub fn set_store_directories(&mut self, store_directories: bool) -> &mut Self {
+        self.store_directories = store_directories;
+
+        debug!("Store directories set to: {}", self.store_directories);
+        self
+    }
     pub fn with_path(
         &mut self,
         input_path: impl AsRef<Path>,
@@ -195,14 +930,24 @@ impl LZMATarballWriter {
             input_file.as_ref(),
             archive_path.as_ref()
         );
-        self.archive_paths.push(ArchiveEntry {
+        let entry = ArchiveEntry {
             filesystem_path: input_file.as_ref().to_path_buf(),
             archive_path: archive_path.as_ref().to_string(),
-        });
+        };
+        if self.is_excluded(&entry) {
+            return self;
+        }
+        self.open_handles(std::slice::from_ref(&entry.filesystem_path));
+        self.sources.insert(entry.archive_path.clone(), EntrySource::File);
+        self.archive_paths.push(entry);
         self
     }
     pub fn with_files(&mut self, input_files: &mut Vec<ArchiveEntry>) -> &mut Self {
         debug!("Appending {} files to archive", input_files.len());
+        self.exclude(input_files);
+        let paths: Vec<PathBuf> = input_files.iter().map(|entry| entry.filesystem_path.clone()).collect();
+        self.open_handles(&paths);
+        self.sources.extend(input_files.iter().map(|entry| (entry.archive_path.clone(), EntrySource::Files)));
         self.archive_paths.append(input_files);
         self
     }
@@ -224,6 +969,76 @@ impl LZMATarballWriter {
         debug!("Setting output file to: {:?}", output_file);
         std::fs::create_dir_all(output_file.parent().unwrap()).unwrap();
         self.output_file = Some(output_file);
+        self.output_template = None;
+        self
+    }
+    /// Names the output file after a template expanded by each `compress`, e.g. `backups/backup-{date}-{seq}.tar.xz`,
+    /// replacing the output file. `LZMAResult::output_file` is the expanded path. The tokens are
+    /// - `{date}`: the UTC date from the clock, e.g. `2024-05-17`
+    /// - `{hostname}`: the name of this machine
+    /// - `{seq}`: one more than the highest number of the archives in the directory matching the template, starting at 1
+    /// - `{hash}`: the first 12 hex digits of the SHA-256 hash of the archive, the archive is renamed once written
+    ///
+    /// `{seq}` and `{hash}` can only be used in the file name.
+    pub fn set_output_template(&mut self, template: impl AsRef<str>) -> &mut Self {
+        let template = template.as_ref().to_string();
+
+        debug!("Setting output template to: {:?}", template);
+        self.output_template = Some(template);
+        self.output_file = None;
+        self
+    }
+    /// Sets a policy pruning old archives from the output directory after each successful `compress`, see the `rotation` module.
+    /// Only archives named after the output template are pruned, so compressing with a policy but without a template fails.
+    /// A failure to prune is logged and doesn't fail compression.
+    pub fn set_rotation_policy(&mut self, rotation_policy: Option<RotationPolicy>) -> &mut Self {
+        self.rotation_policy = rotation_policy;
+
+        debug!("Rotation policy set to: {:?}", self.rotation_policy);
+        self
+    }
+    /// Adds a file path or an `OutputMirror::sink` receiving a copy of the compressed output while `compress` writes it,
+    /// e.g. a copy on a network share. A mirror that fails is dropped without failing compression, its file is removed
+    /// unless `set_keep_partial_output` is set, and its error is reported in `LZMAResult::mirrors`.
+    /// Mirror files are created with the output mode and owner. Only `compress` writes mirrors.
+    pub fn add_output_mirror(&mut self, mirror: impl Into<OutputMirror>) -> &mut Self {
+        let mirror = mirror.into();
+
+        debug!("Adding output mirror: {:?}", mirror);
+        self.output_mirrors.push(mirror);
+        self
+    }
+    /// Sets the permissions of the output file, e.g. `0o640`, applied when it is created so it is never
+    /// readable by more users than intended. Unix only.
+    pub fn set_output_mode(&mut self, mode: u32) -> &mut Self {
+        self.output_mode = Some(mode);
+
+        debug!("Output mode set to: {:o}", mode);
+        self
+    }
+    /// Sets whether a failed, cancelled or timed out compression leaves the partially written output behind, e.g. to
+    /// inspect it, instead of removing it. `append_stream` then leaves the partial stream at the end of the archive
+    /// instead of truncating the archive back to its original size. Removing it is the default.
+    pub fn set_keep_partial_output(&mut self, keep_partial_output: bool) -> &mut Self {
+        self.keep_partial_output = keep_partial_output;
+
+        debug!("Keep partial output set to: {}", self.keep_partial_output);
+        self
+    }
+    /// Sets the clock deciding the creation time in the manifest and the modification time stored for each file,
+    /// see `FixedClock` for reproducible archives.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) -> &mut Self {
+        self.clock = Arc::new(clock);
+
+        debug!("Clock set to: {:?}", self.clock);
+        self
+    }
+    /// Sets the owner and group of the output file, applied when it is created. Changing the owner usually needs
+    /// elevated privileges, and compression fails if it can't be changed. Unix only.
+    pub fn set_output_owner(&mut self, uid: u32, gid: u32) -> &mut Self {
+        self.output_owner = Some((uid, gid));
+
+        debug!("Output owner set to: {:?}", self.output_owner);
         self
     }
     pub fn with_filtered_directory_contents(
@@ -237,29 +1052,175 @@ impl LZMATarballWriter {
             input_directory.as_ref(),
             archive_path.as_ref()
         );
-        walkdir::WalkDir::new(&input_directory)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .filter(filter)
-            .for_each(|e| {
-                debug!("Adding file from directory: {:?}", e.path());
-                self.archive_paths.push(ArchiveEntry {
-                    filesystem_path: e.path().to_path_buf(),
-                    archive_path: format!(
-                        "{}/{}",
-                        archive_path.as_ref(),
-                        e.path()
-                            .to_path_buf()
-                            .strip_prefix(&input_directory)
-                            .unwrap()
-                            .to_str()
-                            .unwrap()
-                    ),
-                });
-            });
+        self.add_directory(input_directory.as_ref(), input_directory.as_ref(), archive_path.as_ref(), filter);
         self
     }
+    /// Adds the contents of a directory with archive paths relative to `strip_prefix`, one of its ancestors,
+    /// instead of the directory itself, e.g. `/srv/site/assets` trimmed by `/srv` is stored as `archive_path/site/assets/...`
+    pub fn with_directory_contents_trimmed(
+        &mut self,
+        input_directory: impl AsRef<Path>,
+        archive_path: impl AsRef<str>,
+        strip_prefix: impl AsRef<Path>,
+    ) -> Result<&mut Self> {
+        debug!(
+            "Adding directory contents from: {:?} under archive path: {}, trimming: {:?}",
+            input_directory.as_ref(),
+            archive_path.as_ref(),
+            strip_prefix.as_ref()
+        );
+        if !input_directory.as_ref().starts_with(strip_prefix.as_ref()) {
+            bail!("{:?} is not inside {:?}, which is trimmed from its archive paths", input_directory.as_ref(), strip_prefix.as_ref());
+        }
+        self.add_directory(input_directory.as_ref(), strip_prefix.as_ref(), archive_path.as_ref(), &|_| true);
+        Ok(self)
+    }
+    /// Scans a directory and adds its files, recording the directory as their source
+    fn add_directory(&mut self, input_directory: &Path, strip_prefix: &Path, archive_path: &str, filter: &dyn Fn(&DirEntry) -> bool) {
+        let mut entries = scan_directory(input_directory, strip_prefix, archive_path, self.skip_hidden, self.store_directories, filter, &mut self.skipped);
+        self.exclude(&mut entries);
+        let paths: Vec<PathBuf> = entries.iter().map(|entry| entry.filesystem_path.clone()).collect();
+        self.open_handles(&paths);
+        let source = EntrySource::Directory {
+            root: input_directory.to_path_buf(),
+            archive_path: archive_path.to_string(),
+        };
+        self.sources.extend(entries.iter().map(|entry| (entry.archive_path.clone(), source.clone())));
+        self.archive_paths.append(&mut entries);
+    }
+    /// Adds the files and directories listed in a newline delimited file, like `tar -T`, so file lists generated
+    /// by other tools can drive archiving. Relative paths are resolved against the working directory, and each path
+    /// is stored under itself without leading `/` and `./`. Directories are added with their contents.
+    /// Blank lines and lines starting with `#` are ignored. Nothing is added if a listed path doesn't exist.
+    pub fn set_include_file(&mut self, list: impl AsRef<Path>) -> Result<&mut Self> {
+        let list = list.as_ref();
+        debug!("Reading the include list: {:?}", list);
+        let mut included = Vec::new();
+        for (line, path) in read_list(list)? {
+            let metadata = std::fs::metadata(&path).with_context(|| format!("Failed to read {:?} listed on line {} of {:?}", path, line, list))?;
+            included.push((line, path, metadata.is_dir()));
+        }
+        for (line, path, is_dir) in included {
+            let archive_path = list_archive_path(&path);
+            if is_dir {
+                self.add_directory(Path::new(&path), Path::new(&path), &archive_path, &|_| true);
+                continue;
+            }
+            let entry = ArchiveEntry { filesystem_path: PathBuf::from(path), archive_path };
+            if self.is_excluded(&entry) {
+                continue;
+            }
+            self.open_handles(std::slice::from_ref(&entry.filesystem_path));
+            self.sources.insert(entry.archive_path.clone(), EntrySource::IncludeFile { list: list.to_path_buf(), line });
+            self.archive_paths.push(entry);
+        }
+        Ok(self)
+    }
+    /// Reads patterns from a newline delimited file, like `tar -X`, and leaves out every entry whose archive path
+    /// matches one of them, including entries already added. A pattern containing `/` matches whole archive paths
+    /// and everything beneath them, any other pattern matches a single path component anywhere, e.g. `*.log` or `target`.
+    /// `*`, `?` and `**` work as in `LZMATarballReader::route`. Blank lines and lines starting with `#` are ignored.
+    pub fn set_exclude_file(&mut self, list: impl AsRef<Path>) -> Result<&mut Self> {
+        debug!("Reading the exclude list: {:?}", list.as_ref());
+        let patterns = read_list(list.as_ref())?.into_iter().map(|(_, pattern)| pattern);
+        self.exclude_patterns.extend(patterns);
+
+        debug!("Exclude patterns set to: {:?}", self.exclude_patterns);
+        let mut entries = std::mem::take(&mut self.archive_paths);
+        self.exclude(&mut entries);
+        self.archive_paths = entries;
+        Ok(self)
+    }
+    /// Returns whether an entry matches one of the exclude patterns, recording it as skipped if it does
+    fn is_excluded(&mut self, entry: &ArchiveEntry) -> bool {
+        let path = entry.archive_path.trim_start_matches('/');
+        let excluded = self.exclude_patterns.iter().any(|pattern| {
+            if pattern.contains('/') {
+                glob_matches(pattern, path) || glob_matches(&format!("{}/**", pattern.trim_end_matches('/')), path)
+            } else {
+                path.split('/').any(|component| glob_matches(pattern, component))
+            }
+        });
+        if excluded {
+            debug!("Excluding {:?} from the archive", entry.archive_path);
+            self.sources.remove(&entry.archive_path);
+            self.skipped.push(SkippedEntry::new(entry.filesystem_path.display().to_string(), SkipReason::Filtered));
+        }
+        excluded
+    }
+    /// Removes the entries matching an exclude pattern from `entries`
+    fn exclude(&mut self, entries: &mut Vec<ArchiveEntry>) {
+        if !self.exclude_patterns.is_empty() {
+            entries.retain(|entry| !self.is_excluded(entry));
+        }
+    }
+    /// Opens the files at `paths` if `open_at_scan` is set, so `compress` reads the files that were scanned
+    fn open_handles(&mut self, paths: &[PathBuf]) {
+        if !self.open_at_scan {
+            return;
+        }
+        for path in paths {
+            let handle = match open_source(path, self.no_follow) {
+                Ok(file) => Some(Arc::new(file)),
+                Err(e) => {
+                    warn!("Failed to open {:?} while scanning: {}", path, e);
+                    None
+                }
+            };
+            self.handles.insert(path.clone(), handle);
+        }
+    }
+
+    /// Lists the entries `compress` will store with the `with_*` call that added each of them,
+    /// to find out why a file ends up in the archive, and the memory compression will take
+    pub fn plan(&self) -> CompressionPlan {
+        let entries = self
+            .archive_paths
+            .iter()
+            .map(|entry| PlannedEntry {
+                filesystem_path: entry.filesystem_path.clone(),
+                archive_path: entry.archive_path.clone(),
+                source: self.sources.get(&entry.archive_path).cloned(),
+            })
+            .collect();
+        CompressionPlan {
+            entries,
+            estimated_memory_usage: self.estimated_memory_usage(),
+        }
+    }
+    /// Returns the memory the xz encoder needs with the current compression level and seekable block size, in bytes,
+    /// as computed by liblzma. Staging the tar with `TempStrategy::Memory` takes up to its `max_bytes` on top of it.
+    /// Other formats aren't run by liblzma, so their usage is reported as 0.
+    pub fn estimated_memory_usage(&self) -> u64 {
+        if self.format != CompressionFormat::Xz {
+            return 0;
+        }
+        match self.seekable_block_size {
+            Some(block_size) => MtStreamBuilder::new().preset(self.compression_level as u32).threads(1).block_size(block_size).memusage(),
+            // SAFETY: only computes the usage of a preset, no stream is involved
+            None => unsafe { lzma_sys::lzma_easy_encoder_memusage(self.compression_level as u32) },
+        }
+    }
+    /// Describes the `with_*` call that added an entry, for error messages
+    fn describe_source(&self, entry: &ArchiveEntry) -> String {
+        match self.sources.get(&entry.archive_path) {
+            Some(source) => format!("{:?} was {}", entry.filesystem_path, source),
+            None => format!("{:?} was added to archive_paths directly", entry.filesystem_path),
+        }
+    }
+
+    /// Returns the logical size of the input, the combined size of every file that will be archived.
+    /// Unlike the size of the tar it is known before anything is written, so it serves as the progress total
+    /// when no temporary tar file exists. Files that can't be read count as empty.
+    pub fn input_size(&self) -> u64 {
+        self.archive_paths
+            .iter()
+            .map(|entry| match self.handles.get(&entry.filesystem_path) {
+                Some(Some(handle)) => handle.metadata().map_or(0, |metadata| if metadata.is_file() { metadata.len() } else { 0 }),
+                _ => std::fs::metadata(&entry.filesystem_path).map_or(0, |metadata| if metadata.is_file() { metadata.len() } else { 0 }),
+            })
+            .sum()
+    }
 
     /// Compress the input path into an LZMA-compressed file
     ///
@@ -270,6 +1231,22 @@ impl LZMATarballWriter {
     /// - `LZMAResult` on success
     /// - `Box<dyn Error>` on failure
     pub fn compress<F>(&self, callback: F) -> Result<LZMAResult>
+    where
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        if let Some(checkpoint) = &self.job {
+            return self.compress_job(checkpoint, callback);
+        }
+        self.compress_entries(callback)
+    }
+    /// Compresses the entries to the output file, ignoring any loaded job
+    pub(crate) fn compress_entries<F>(&self, callback: F) -> Result<LZMAResult>
+    where
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        self.with_timeout(move |writer| writer.with_priority(|| writer.compress_to_output(callback)))
+    }
+    fn compress_to_output<F>(&self, callback: F) -> Result<LZMAResult>
     where
         F: Fn(LZMACallbackResult) + 'static + Send + Sync,
     {
@@ -281,168 +1258,1000 @@ impl LZMATarballWriter {
             error!("No files or directories to compress");
             bail!("No files or directories to compress");
         }
-        let output_file = match self.output_file {
-            Some(ref file) => file,
-            None => {
+        if self.rotation_policy.is_some() && self.output_template.is_none() {
+            error!("A rotation policy needs an output template");
+            bail!("A rotation policy needs an output template, so only archives named after it are pruned");
+        }
+        let output_file = match (&self.output_template, &self.output_file) {
+            (Some(template), _) => output_template::expand(template, self.clock.now())?,
+            (None, Some(file)) => file.clone(),
+            (None, None) => {
                 error!("Output file not set");
                 bail!("Output file not set");
             }
         };
+        let output_file = &output_file;
+        self.check_format_options()?;
         let start = std::time::Instant::now();
 
-        debug!("Creating tar file...");
-        match self.create_tar() {
-            Ok(_) => {
-                debug!("Tar file created successfully");
-            }
-            Err(e) => {
-                error!("Failed to create tar file: {}", e);
-                bail!("Failed to create tar file: {}", e);
-            }
-        };
-
-        debug!("Compressing tar file with LZMA...");
-        match self.compress_tar(callback) {
-            Ok(_) => {
-                debug!("Tar file compressed successfully");
-            }
-            Err(e) => {
-                error!("Failed to compress tar file: {}", e);
-                bail!("Failed to compress tar file: {}", e);
+        debug!("Creating output file for compressed data: {:?}", output_file);
+        let partial = PartialOutput::new(output_file, None, self.keep_partial_output);
+        let output = self.create_output(output_file)?;
+        let mut mirrors = self.open_mirrors();
+        let (entries, tarball_size) = self.create_and_compress(TeeWriter { output, mirrors: &mut mirrors }, callback)?;
+        if self.seekable_block_size.is_some() {
+            let compressed_size = output_file.metadata()?.len();
+            self.append_entry_index(output_file)?;
+            if !mirrors.is_empty() {
+                // The index is read back from the output, so it is copied to the mirrors afterwards
+                let mut index = File::open(output_file)?;
+                index.seek(SeekFrom::Start(compressed_size))?;
+                let mut tee = TeeWriter { output: std::io::sink(), mirrors: &mut mirrors };
+                std::io::copy(&mut index, &mut tee)?;
             }
         }
-        let tarball_size = self.tar_file.metadata()?.len();
-
-        debug!("Removing tar file: {:?}", self.tar_file);
-        std::fs::remove_file(&self.tar_file).map_err(|e| {
-            let err_msg = format!("Failed to remove tar file: {}", e);
-            error!("{}", err_msg);
-            anyhow::Error::msg(err_msg)
-        })?;
-        let elapsed_time = start.elapsed();
         let size = output_file.metadata()?.len();
+        if let Some(sidecar_index) = &self.sidecar_index {
+            self.write_sidecar_index(output_file, sidecar_index)?;
+        }
+        let output_file = output_template::fill_hash(output_file)?;
+        partial.complete();
+        let mirrors = mirrors.into_iter().map(MirrorOutput::finish).collect();
+        let pruned = self.rotate(&output_file);
+        let elapsed_time = start.elapsed();
 
         debug!("Compression completed. Original size: {} bytes, Compressed size: {} bytes, Elapsed time: {:?}", tarball_size, size, elapsed_time);
         Ok(LZMAResult {
-            output_file: output_file.clone(),
+            output_file,
             size,
             original_size: tarball_size,
             elapsed_time,
+            entries,
+            skipped: self.skipped.clone(),
+            memory_usage: self.estimated_memory_usage(),
+            mirrors,
+            pruned,
         })
     }
-    /// Creates a tarball from the specified filepath
+    /// Compresses the input path and hands the compressed data to `on_chunk` as it is produced, instead of writing an output file.
+    /// Chunks are at most the buffer size, so a slow consumer like a multipart upload applies backpressure.
+    /// An error returned by `on_chunk` stops compression and is returned unchanged.
     ///
     /// # Parameters
-    /// - `filepath`: The path to the file or directory to tar
-    /// - `tar_file_path`: The path where the tar file will be created
+    /// - `on_chunk`: Receives the compressed data in order
+    /// - `callback`: A callback function to report progress
     ///
     /// # Returns
-    /// - `Ok(())` on success
+    /// - `LZMAResult` with an empty `output_file` and `size` set to the number of compressed bytes on success
     /// - `Box<dyn Error>` on failure
-    fn create_tar(&self) -> Result<()> {
-        debug!("Creating tar file: {:?}", &self.tar_file);
-        let tar_file = File::create(&self.tar_file)?;
-        let mut tar_builder = Builder::new(BufWriter::new(tar_file));
-        for archive_path in self.archive_paths.iter() {
-            debug!(
-                "Compressing file into tar: {:?}",
-                archive_path.filesystem_path
-            );
-            match Self::compress_file(archive_path, &mut tar_builder) {
-                Ok(_) => {
-                    debug!(
-                        "Successfully compressed file: {:?}",
-                        archive_path.filesystem_path
-                    );
-                }
-                Err(e) => {
-                    error!(
-                        "Failed to compress file {:?}: {}",
-                        archive_path.filesystem_path, e
-                    );
-                    bail!("Failed to compress file: {}", e);
+    pub fn compress_chunks<C, F>(&self, on_chunk: C, callback: F) -> Result<LZMAResult>
+    where
+        C: FnMut(&[u8]) -> Result<()> + 'static + Send,
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        self.with_timeout(move |writer| writer.with_priority(|| writer.compress_to_chunks(on_chunk, callback)))
+    }
+    fn compress_to_chunks<C, F>(&self, on_chunk: C, callback: F) -> Result<LZMAResult>
+    where
+        C: FnMut(&[u8]) -> Result<()> + 'static + Send,
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        debug!("Starting chunked compression with {} archive entries", self.archive_paths.len());
+        if self.archive_paths.is_empty() {
+            error!("No files or directories to compress");
+            bail!("No files or directories to compress");
+        }
+        self.check_format_options()?;
+        let start = std::time::Instant::now();
+        let chunk_size = self.buffer_size.resolve(self.input_size());
+        let mut output = ChunkWriter {
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size,
+            on_chunk,
+            written: 0,
+            failure: None,
+        };
+        let (entries, tarball_size) = match self.create_and_compress(&mut output, callback) {
+            Ok(result) => result,
+            Err(e) => {
+                if let Some(failure) = output.failure {
+                    error!("Chunk callback failed: {}", failure);
+                    return Err(failure);
                 }
+                return Err(e);
             }
+        };
+
+        let elapsed_time = start.elapsed();
+        debug!("Chunked compression completed. Original size: {} bytes, Compressed size: {} bytes, Elapsed time: {:?}", tarball_size, output.written, elapsed_time);
+        Ok(LZMAResult {
+            output_file: PathBuf::new(),
+            size: output.written,
+            original_size: tarball_size,
+            elapsed_time,
+            entries,
+            skipped: self.skipped.clone(),
+            memory_usage: self.estimated_memory_usage(),
+            mirrors: Vec::new(),
+            pruned: Vec::new(),
+        })
+    }
+    /// Compresses the input path into memory and returns the archive, e.g. to embed it in an HTTP upload or a test fixture.
+    /// Fails with `WriterError::OutputTooLarge` as soon as the archive exceeds `max_size` bytes, if set.
+    ///
+    /// # Parameters
+    /// - `max_size`: The maximum size of the compressed archive in bytes, `None` for no limit
+    ///
+    /// # Returns
+    /// - The compressed archive on success
+    /// - `Box<dyn Error>` on failure
+    pub fn compress_to_vec(&self, max_size: Option<u64>) -> Result<Vec<u8>> {
+        let archive = Arc::new(Mutex::new(Vec::new()));
+        let output = archive.clone();
+        self.compress_chunks(
+            move |chunk| {
+                let mut output = output.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                if let Some(max_size) = max_size.filter(|max_size| (output.len() + chunk.len()) as u64 > *max_size) {
+                    error!("Compressed archive exceeds {} bytes", max_size);
+                    return Err(WriterError::OutputTooLarge { max_size }.into());
+                }
+                output.extend_from_slice(chunk);
+                Ok(())
+            },
+            |_| {},
+        )?;
+        let archive = std::mem::take(&mut *archive.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+        Ok(archive)
+    }
+    /// Compresses the input path into `writer`, e.g. a socket or an HTTP response body, instead of writing an output file.
+    /// Output mirrors receive a copy as with `compress`. Seekable archives written this way have no entry index,
+    /// as it is read back from the output file.
+    ///
+    /// # Parameters
+    /// - `writer`: Receives the compressed data, it is flushed once compression completes
+    /// - `callback`: A callback function to report progress
+    ///
+    /// # Returns
+    /// - `LZMAResult` with an empty `output_file` and `size` set to the number of compressed bytes on success
+    /// - `Box<dyn Error>` on failure
+    pub fn compress_to_writer<W, F>(&self, writer: W, callback: F) -> Result<LZMAResult>
+    where
+        W: Write + Send + 'static,
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        self.with_timeout(move |this| this.with_priority(|| this.compress_into_writer(writer, callback)))
+    }
+    fn compress_into_writer<W, F>(&self, writer: W, callback: F) -> Result<LZMAResult>
+    where
+        W: Write + Send,
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        debug!("Starting compression into a writer with {} archive entries", self.archive_paths.len());
+        if self.archive_paths.is_empty() {
+            error!("No files or directories to compress");
+            bail!("No files or directories to compress");
         }
-        tar_builder.into_inner()?;
+        self.check_format_options()?;
+        let start = std::time::Instant::now();
+        let mut output = CountingWriter::new(writer);
+        let mut mirrors = self.open_mirrors();
+        let (entries, tarball_size) = self.create_and_compress(TeeWriter { output: &mut output, mirrors: &mut mirrors }, callback)?;
+        let mirrors = mirrors.into_iter().map(MirrorOutput::finish).collect();
 
-        debug!("Tar file {:?} created successfully", &self.tar_file);
+        let elapsed_time = start.elapsed();
+        debug!("Compression into a writer completed. Original size: {} bytes, Compressed size: {} bytes, Elapsed time: {:?}", tarball_size, output.count, elapsed_time);
+        Ok(LZMAResult {
+            output_file: PathBuf::new(),
+            size: output.count,
+            original_size: tarball_size,
+            elapsed_time,
+            entries,
+            skipped: self.skipped.clone(),
+            memory_usage: self.estimated_memory_usage(),
+            mirrors,
+            pruned: Vec::new(),
+        })
+    }
+    /// Appends the entries as a new, independent xz stream to the end of an existing archive.
+    /// Concatenated xz streams are valid per the xz specification, so the original data is never rewritten.
+    /// `LZMATarballReader` presents all streams as one logical archive.
+    ///
+    /// # Parameters
+    /// - `existing`: The `.tar.xz` archive to append to
+    /// - `callback`: A callback function to report progress
+    ///
+    /// # Returns
+    /// - `LZMAResult` describing the whole archive on success
+    /// - `Box<dyn Error>` on failure
+    pub fn append_stream<F>(&self, existing: impl AsRef<Path>, callback: F) -> Result<LZMAResult>
+    where
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        let existing = existing.as_ref().to_path_buf();
+        self.with_timeout(move |writer| writer.with_priority(|| writer.append_to_existing(&existing, callback)))
+    }
+    fn append_to_existing<F>(&self, existing: &Path, callback: F) -> Result<LZMAResult>
+    where
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        debug!(
+            "Appending {} archive entries as a new stream to {:?}",
+            self.archive_paths.len(),
+            existing
+        );
+        if self.archive_paths.is_empty() {
+            error!("No files or directories to compress");
+            bail!("No files or directories to compress");
+        }
+        if !existing.is_file() {
+            error!("Archive to append to not found: {:?}", existing);
+            bail!("Archive to append to not found: {:?}", existing);
+        }
+        if self.preset_dictionary.is_some() {
+            error!("Streams can't be appended with a preset dictionary");
+            bail!("Streams can't be appended with a preset dictionary");
+        }
+        self.check_format_options()?;
+        let start = std::time::Instant::now();
+
+        let output = std::fs::OpenOptions::new().append(true).open(existing)?;
+        let existing_size = output.metadata()?.len();
+        // Only the partially written stream is dropped, so the existing archive stays intact
+        let partial = PartialOutput::new(existing, Some(existing_size), self.keep_partial_output);
+        let (entries, tarball_size) = self.create_and_compress(output, callback)?;
+        partial.complete();
+
+        let elapsed_time = start.elapsed();
+        let size = existing.metadata()?.len();
+        debug!("Stream appended. Appended tar size: {} bytes, Archive size: {} bytes", tarball_size, size);
+        Ok(LZMAResult {
+            output_file: existing.to_path_buf(),
+            size,
+            original_size: tarball_size,
+            elapsed_time,
+            entries,
+            skipped: self.skipped.clone(),
+            memory_usage: self.estimated_memory_usage(),
+            mirrors: Vec::new(),
+            pruned: Vec::new(),
+        })
+    }
+    /// Applies the rotation policy to the archives next to `output_file` named after the output template,
+    /// returning the pruned archives
+    fn rotate(&self, output_file: &Path) -> Vec<PathBuf> {
+        let (Some(policy), Some(template)) = (&self.rotation_policy, &self.output_template) else {
+            return Vec::new();
+        };
+        let pattern = output_template::glob_pattern(template);
+        let dir = output_file.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        policy.apply_except(dir, &pattern, self.clock.now(), Some(output_file)).unwrap_or_else(|e| {
+            warn!("Failed to apply the rotation policy to {:?}: {:#}", dir, e);
+            Vec::new()
+        })
+    }
+    /// Creates the output file with the configured mode and owner before anything is written to it
+    fn create_output(&self, output_file: &Path) -> Result<File> {
+        let file = File::create(output_file)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Some(mode) = self.output_mode {
+                file.set_permissions(std::fs::Permissions::from_mode(mode))
+                    .with_context(|| format!("Failed to set the mode of {:?} to {:o}", output_file, mode))?;
+            }
+            if let Some((uid, gid)) = self.output_owner {
+                std::os::unix::fs::fchown(&file, Some(uid), Some(gid))
+                    .with_context(|| format!("Failed to set the owner of {:?} to {}:{}", output_file, uid, gid))?;
+            }
+        }
+        #[cfg(not(unix))]
+        if self.output_mode.is_some() || self.output_owner.is_some() {
+            warn!("The output mode and owner are only applied on Unix");
+        }
+        Ok(file)
+    }
+    /// Opens the output mirrors, a mirror that can't be opened is reported as failed
+    fn open_mirrors(&self) -> Vec<MirrorOutput<'_>> {
+        self.output_mirrors
+            .iter()
+            .enumerate()
+            .map(|(index, mirror)| {
+                let destination = mirror.describe(index);
+                match mirror {
+                    OutputMirror::Sink(sink) => MirrorOutput::new(destination, Box::new(SharedSink(sink.clone())), None),
+                    OutputMirror::Path(path) => {
+                        let partial = PartialOutput::new(path, None, self.keep_partial_output);
+                        let file = path.parent().map_or(Ok(()), std::fs::create_dir_all).map_err(anyhow::Error::from).and_then(|_| self.create_output(path));
+                        match file {
+                            Ok(file) => MirrorOutput::new(destination, Box::new(file), Some(partial)),
+                            Err(e) => MirrorOutput::failed(destination, format!("{:#}", e), Some(partial)),
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Appends a stream holding the offsets of every entry, read back from the finished archive
+    #[cfg(feature = "decompression")]
+    fn append_entry_index(&self, output_file: &Path) -> Result<()> {
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(output_file)?.set_indexed(true);
+        let index = serde_json::to_vec(&reader.entries_metadata()?)?;
+        debug!("Appending an entry index of {} bytes to {:?}", index.len(), output_file);
+        let output = File::options().append(true).open(output_file)?;
+        let mut builder = tar::Builder::new(XzEncoder::new(output, self.compression_level as u32));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(index.len() as u64);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(clock::unix_seconds(self.clock.now()));
+        builder.append_data(&mut header, ENTRY_INDEX_FILE_NAME, index.as_slice())?;
+        builder.into_inner()?.finish()?;
         Ok(())
     }
-    /// Compresses a single file into a tarball
+    #[cfg(not(feature = "decompression"))]
+    fn append_entry_index(&self, _output_file: &Path) -> Result<()> {
+        error!("Seekable archives need the decompression feature");
+        bail!("Seekable archives need the decompression feature to index their entries")
+    }
+    /// Writes the entries of the finished archive to `sidecar_index`, read back from the archive
+    #[cfg(feature = "decompression")]
+    fn write_sidecar_index(&self, output_file: &Path, sidecar_index: &Path) -> Result<()> {
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(output_file)?.set_format(self.format).set_preset_dictionary(self.preset_dictionary.clone());
+        let index = SidecarIndex {
+            archive_size: output_file.metadata()?.len(),
+            entries: reader.entries_metadata()?,
+        };
+        debug!("Writing a sidecar index of {} entries to {:?}", index.entries.len(), sidecar_index);
+        if let Some(parent) = sidecar_index.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(sidecar_index, serde_json::to_vec(&index)?).with_context(|| format!("Failed to write the sidecar index {:?}", sidecar_index))
+    }
+    #[cfg(not(feature = "decompression"))]
+    fn write_sidecar_index(&self, _output_file: &Path, _sidecar_index: &Path) -> Result<()> {
+        error!("Sidecar indexes need the decompression feature");
+        bail!("Sidecar indexes need the decompression feature to index the entries")
+    }
+    /// Creates the tar as configured by `temp_strategy` and compresses it into `output`
     ///
     /// # Parameters
+    /// - `output`: The file or sink the xz stream is written to
+    /// - `callback`: A callback function to report progress
+    ///
+    /// # Returns
+    /// - `EntryStatistics` of every file and the size of the tar on success
+    /// - `Box<dyn Error>` on failure
+    fn create_and_compress<F>(&self, output: impl Write + Send, callback: F) -> Result<(Vec<EntryStatistics>, u64)>
+    where
+        F: Fn(LZMACallbackResult) + 'static + Send + Sync,
+    {
+        self.dispatch_progress(callback, |report| self.create_and_compress_with(output, report))
+    }
+    /// Creates the tar and compresses it into `output`, reporting progress and phases through `report`
+    fn create_and_compress_with(&self, output: impl Write + Send, report: &dyn Fn(LZMACallbackResult)) -> Result<(Vec<EntryStatistics>, u64)> {
+        let progress = OutputProgress::new();
+        report(progress.enter(Phase::Scanning));
+        let compress_error = |e: anyhow::Error| {
+            if e.is::<WriterError>() {
+                return e;
+            }
+            error!("Failed to compress tar file: {}", e);
+            anyhow::anyhow!("Failed to compress tar file: {}", e)
+        };
+        if self.temp_strategy == TempStrategy::None {
+            debug!("Compressing files while the tar is written...");
+            let mut entries = Vec::new();
+            progress.total_size.store(self.input_size(), Ordering::Relaxed);
+            report(progress.enter(Phase::Compressing));
+            let tarball_size = self.compress_tar_with(TarSource::Streamed(&mut entries), output, &progress, report).map_err(compress_error)?;
+            return Ok((entries, tarball_size));
+        }
+
+        debug!("Creating tar file...");
+        report(progress.enter(Phase::Tarring));
+        let (entries, staging) = self.create_tar(self.staging_writer()).map_err(|e| {
+            if e.is::<WriterError>() {
+                return e;
+            }
+            error!("Failed to create tar file: {}", e);
+            anyhow::anyhow!("Failed to create tar file: {}", e)
+        })?;
+        let staged = staging.finish()?;
+        debug!("Tar file created successfully");
+
+        debug!("Compressing tar file with LZMA...");
+        progress.total_size.store(staged.len(), Ordering::Relaxed);
+        report(progress.enter(Phase::Compressing));
+        let tarball_size = self.compress_tar_with(TarSource::Staged(&staged), output, &progress, report).map_err(compress_error)?;
+        debug!("Tar file compressed successfully");
+        Ok((entries, tarball_size))
+    }
+    /// Creates the writer the tar is staged in according to `temp_strategy`
+    fn staging_writer(&self) -> StagingWriter {
+        let max_bytes = match self.temp_strategy {
+            TempStrategy::Memory { max_bytes } => max_bytes,
+            _ => 0,
+        };
+        StagingWriter {
+            memory: Vec::new(),
+            max_bytes,
+            path: self.tar_file.clone(),
+            file: None,
+        }
+    }
+    /// Creates a tarball from the specified filepath
     ///
-    /// (The remainder of the function is not shown.)
+    /// # Parameters
+    /// - `writer`: Where the tar is written
     ///
-    /// (Any additional logging will be added within the corresponding function body.)
+    /// # Returns
+    /// - `EntryStatistics` of every file and the writer on success
+    /// - `Box<dyn Error>` on failure
+    fn create_tar<W: Write>(&self, writer: W) -> Result<(Vec<EntryStatistics>, W)> {
+        let Some(snapshot) = self.snapshot_provider.as_deref() else {
+            return self.write_tar(None, writer);
+        };
+        let sources: Vec<&Path> = self.archive_paths.iter().map(|entry| entry.filesystem_path.as_path()).collect();
+        debug!("Creating snapshot of {} files", sources.len());
+        snapshot.create(&sources).context("Failed to create snapshot")?;
+        let result = self.write_tar(Some(snapshot), writer);
+        debug!("Releasing snapshot");
+        let released = snapshot.release().context("Failed to release snapshot");
+        let result = result?;
+        released?;
+        Ok(result)
+    }
+    /// Writes the tar to `writer`, reading files through `snapshot` if one was created
+    fn write_tar<W: Write>(&self, snapshot: Option<&dyn SnapshotProvider>, writer: W) -> Result<(Vec<EntryStatistics>, W)> {
+        if self.long_path_encoding == LongPathEncoding::Ustar {
+            let overflows = self.validate_ustar_paths();
+            if !overflows.is_empty() {
+                error!("Paths too long for ustar headers: {:?}", overflows);
+                bail!("{} paths are too long for ustar headers: {:?}", overflows.len(), overflows);
+            }
+        }
+        let mut tar_builder = Builder::new(writer);
+        let mut manifest = Manifest::created_at(self.clock.now());
+        // The archive path of the first file with each SHA-256 hash, used when deduplicating
+        let mut stored = HashMap::new();
+        for archive_path in self.archive_paths.iter() {
+            if archive_path.filesystem_path.is_dir() {
+                self.compress_directory(archive_path, &mut tar_builder)
+                    .map_err(|e| anyhow::anyhow!("Failed to store directory: {} ({})", e, self.describe_source(archive_path)))?;
+                continue;
+            }
+            debug!(
+                "Compressing file into tar: {:?}",
+                archive_path.filesystem_path
+            );
+            match self.compress_file(archive_path, snapshot, &mut stored, &mut tar_builder) {
+                Ok(file) => {
+                    debug!(
+                        "Successfully compressed file: {:?}",
+                        archive_path.filesystem_path
+                    );
+                    manifest.files.push(file);
+                }
+                Err(_) if self.is_cancelled() => {
+                    info!("Compression cancelled at file: {:?}", archive_path.filesystem_path);
+                    return Err(WriterError::Cancelled.into());
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to compress file {:?}: {}",
+                        archive_path.filesystem_path, e
+                    );
+                    bail!("Failed to compress file: {} ({})", e, self.describe_source(archive_path));
+                }
+            }
+        }
+        if self.write_manifest {
+            Self::append_manifest(&manifest, clock::unix_seconds(self.clock.now()), &mut tar_builder)?;
+        }
+        let writer = tar_builder.into_inner()?;
+
+        debug!("Tar created successfully");
+        let total_size: u64 = manifest.files.iter().map(|file| file.size).sum();
+        let entries = manifest
+            .files
+            .into_iter()
+            .enumerate()
+            .map(|(order, file)| EntryStatistics {
+                share: if total_size == 0 { 0.0 } else { file.size as f32 / total_size as f32 },
+                path: file.path,
+                size: file.size,
+                order,
+            })
+            .collect();
+        Ok((entries, writer))
+    }
     /// Compresses a single file into a tarball
     ///
     /// # Parameters
     /// - `entry`: The file entry to compress and add to the tarball
+    /// - `snapshot`: The snapshot the file is read from, if any
+    /// - `stored`: The archive path of each hash stored so far, duplicates of them are linked when deduplicating
     /// - `tar_builder`: The tar builder to use for compression
     ///
     /// # Returns
-    /// - `Ok(())` on success
+    /// - `ManifestFile` describing the appended file on success
     /// - `Box<dyn Error>` on failure
     fn compress_file(
+        &self,
         entry: &ArchiveEntry,
-        tar_builder: &mut Builder<BufWriter<File>>,
-    ) -> Result<()> {
-        let file = entry.filesystem_path.to_str().unwrap();
-        let compressed_path = entry.archive_path.as_str();
-        // trim leading slash
-        let compressed_path = compressed_path.strip_prefix("/").unwrap_or(compressed_path);
+        snapshot: Option<&dyn SnapshotProvider>,
+        stored: &mut HashMap<String, String>,
+        tar_builder: &mut Builder<impl Write>,
+    ) -> Result<ManifestFile> {
+        let file = &entry.filesystem_path;
+        let compressed_path = self.unicode_normalization.apply(&entry.archive_path);
+        let compressed_path = self.apply_absolute_path_policy(&compressed_path)?;
+        let raw_path = self.raw_archive_path(file, compressed_path)?;
 
         debug!("Starting compression of file: {:?}", file);
-        let mut stream = File::open(file)?;
+        let stream = match self.handles.get(&entry.filesystem_path) {
+            Some(Some(handle)) => {
+                // Clones of the writer share the handle, so its position is reset before reading
+                let mut stream = handle.try_clone()?;
+                stream.seek(SeekFrom::Start(0))?;
+                stream
+            }
+            Some(None) => bail!("{:?} could not be opened when it was added", file),
+            None => {
+                let source = match snapshot {
+                    Some(snapshot) => snapshot.remap(&entry.filesystem_path),
+                    None => entry.filesystem_path.clone(),
+                };
+                open_source(&source, self.no_follow).with_context(|| format!("Failed to open {:?}", source))?
+            }
+        };
+        let _lock = if self.lock_source_files {
+            debug!("Waiting for shared lock on: {:?}", file);
+            Some(SourceLock::new(&stream).with_context(|| format!("Failed to lock {:?}", file))?)
+        } else {
+            None
+        };
+        let metadata = stream.metadata()?;
+        let mut header = match self.long_path_encoding {
+            LongPathEncoding::Gnu => Header::new_gnu(),
+            LongPathEncoding::Pax | LongPathEncoding::Ustar => Header::new_ustar(),
+        };
+        header.set_metadata_in_mode(&metadata, HeaderMode::Complete);
+        let mtime = self.clock.file_mtime(&metadata);
+        if let Some(mtime) = mtime {
+            header.set_mtime(clock::unix_seconds(mtime));
+        }
+        let mut attributes = Vec::new();
+        if let Some(raw_path) = raw_path {
+            attributes.push((HDRCHARSET_KEY.to_string(), b"BINARY".to_vec()));
+            attributes.push((PATH_KEY.to_string(), raw_path));
+        }
+        if self.precise_mtime {
+            // Whole seconds are already exact in the header
+            match mtime.filter(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).is_ok_and(|d| d.subsec_nanos() != 0)) {
+                Some(mtime) => attributes.extend(format_timestamp(mtime).map(|mtime| (MTIME_KEY.to_string(), mtime.into_bytes()))),
+                None => debug!("Modification time has no sub-second part: {:?}", file),
+            }
+        }
+        if self.preserve_btime {
+            match metadata.created().ok().and_then(format_timestamp) {
+                Some(btime) => attributes.push((BTIME_KEY.to_string(), btime.into_bytes())),
+                None => debug!("Creation time is not available for: {:?}", file),
+            }
+        }
+        if self.preserve_xattrs {
+            let xattrs = read_xattrs(&stream).with_context(|| format!("Failed to read extended attributes of {:?}", file))?;
+            debug!("Storing {} extended attributes of: {:?}", xattrs.len(), file);
+            attributes.extend(xattrs.into_iter().map(|(name, value)| (format!("{}{}", XATTR_PREFIX, name), value)));
+        }
 
         debug!("File opened successfully: {:?}", file);
-        tar_builder.append_file(compressed_path, &mut stream)?;
+        // The hash is only needed by the manifest, the catalog and deduplication, which hashes the file upfront
+        let mut known_sha256 = None;
+        if self.dedupe_identical_files && !self.catalog {
+            let mut hashing = HashingReader::new(&stream);
+            std::io::copy(&mut hashing, &mut std::io::sink())?;
+            let (size, sha256) = hashing.finish();
+            (&stream).seek(SeekFrom::Start(0))?;
+            if let Some(original) = stored.get(&sha256) {
+                debug!("{:?} is identical to {:?}, storing a hard link", compressed_path, original);
+                header.set_entry_type(EntryType::Link);
+                header.set_size(0);
+                self.append_entry(&mut header, compressed_path, Some(original), attributes, std::io::empty(), tar_builder)?;
+                return Ok(ManifestFile {
+                    path: compressed_path.to_string(),
+                    size,
+                    sha256,
+                });
+            }
+            stored.insert(sha256.clone(), compressed_path.to_string());
+            known_sha256 = Some(sha256);
+        }
+        let mut stream = if self.catalog || (self.write_manifest && known_sha256.is_none()) {
+            HashingReader::new(stream)
+        } else {
+            HashingReader::counting(stream)
+        };
+        let mut chunks = ChunkedReader {
+            inner: &mut stream,
+            path: compressed_path,
+            chunk_size: self.buffer_size.resolve(metadata.len()) as u64,
+            bytes_processed: 0,
+            total_bytes: metadata.len(),
+            writer: self,
+            file_progress: self.file_progress.as_ref(),
+        };
+        let (size, sha256) = if self.catalog {
+            std::io::copy(&mut chunks, &mut std::io::sink())?;
+            let (size, sha256) = stream.finish();
+            attributes.push((CATALOG_SIZE_KEY.to_string(), size.to_string().into_bytes()));
+            attributes.push((CATALOG_SHA256_KEY.to_string(), sha256.clone().into_bytes()));
+            header.set_size(0);
+            self.append_entry(&mut header, compressed_path, None, attributes, std::io::empty(), tar_builder)?;
+            (size, sha256)
+        } else {
+            self.append_entry(&mut header, compressed_path, None, attributes, &mut chunks, tar_builder)?;
+            let (size, sha256) = stream.finish();
+            (size, known_sha256.unwrap_or(sha256))
+        };
 
         debug!("File appended to tar: {:?}", compressed_path);
+        Ok(ManifestFile {
+            path: compressed_path.to_string(),
+            size,
+            sha256,
+        })
+    }
+
+    /// Appends a directory entry with the permissions and modification time of the directory, see `set_store_directories`
+    fn compress_directory(&self, entry: &ArchiveEntry, tar_builder: &mut Builder<impl Write>) -> Result<()> {
+        let directory = &entry.filesystem_path;
+        let path = self.unicode_normalization.apply(&entry.archive_path);
+        let path = self.apply_absolute_path_policy(&path)?;
+        let raw_path = self.raw_archive_path(directory, path)?;
+        debug!("Storing directory: {:?}", directory);
+        let metadata = std::fs::metadata(directory).with_context(|| format!("Failed to read metadata of {:?}", directory))?;
+        let mut header = match self.long_path_encoding {
+            LongPathEncoding::Gnu => Header::new_gnu(),
+            LongPathEncoding::Pax | LongPathEncoding::Ustar => Header::new_ustar(),
+        };
+        header.set_metadata_in_mode(&metadata, HeaderMode::Complete);
+        header.set_size(0);
+        if let Some(mtime) = self.clock.file_mtime(&metadata) {
+            header.set_mtime(clock::unix_seconds(mtime));
+        }
+        let mut attributes = Vec::new();
+        if let Some(mut raw_path) = raw_path {
+            raw_path.push(b'/');
+            attributes.push((HDRCHARSET_KEY.to_string(), b"BINARY".to_vec()));
+            attributes.push((PATH_KEY.to_string(), raw_path));
+        }
+        self.append_entry(&mut header, &format!("{}/", path), None, attributes, std::io::empty(), tar_builder)
+    }
+
+    /// Applies `name_encoding` to a file whose name isn't valid UTF-8
+    ///
+    /// # Returns
+    /// - The archive path with the trailing components that name the file taken from its raw name, with `NameEncoding::Raw`
+    /// - `None` if the name is valid UTF-8 or is stored lossily
+    fn raw_archive_path(&self, file: &Path, archive_path: &str) -> Result<Option<Vec<u8>>> {
+        if file.to_str().is_some() {
+            return Ok(None);
+        }
+        match self.name_encoding {
+            NameEncoding::Error => bail!("{:?} is not valid UTF-8, see `set_name_encoding` to archive it", file),
+            NameEncoding::Lossy => {
+                debug!("Storing the name of {:?} lossily as {:?}", file, archive_path);
+                Ok(None)
+            }
+            NameEncoding::Raw => {
+                // The archive path ends with the lossy path relative to the scanned directory, those components are restored
+                let mut components: Vec<Vec<u8>> = archive_path.split('/').map(|component| component.as_bytes().to_vec()).collect();
+                for (component, name) in components.iter_mut().rev().zip(file.iter().rev()) {
+                    if *component != name.to_string_lossy().as_bytes() {
+                        break;
+                    }
+                    *component = name.as_encoded_bytes().to_vec();
+                }
+                let raw_path = components.join(&b'/');
+                debug!("Storing the raw name of {:?}", file);
+                Ok(Some(raw_path).filter(|raw_path| raw_path != archive_path.as_bytes()))
+            }
+        }
+    }
+
+    /// Appends an entry along with its pax attributes, storing a long path according to `long_path_encoding`
+    ///
+    /// # Parameters
+    /// - `header`: The header of the entry, its path and link name are set here
+    /// - `path`: The archive path of the entry
+    /// - `link`: The target of a hard link entry
+    /// - `attributes`: The pax attributes of the entry, long paths are added to them
+    /// - `data`: The content of the entry
+    /// - `tar_builder`: The tar builder to append the entry to
+    fn append_entry(
+        &self,
+        header: &mut Header,
+        path: &str,
+        link: Option<&str>,
+        mut attributes: Vec<(String, Vec<u8>)>,
+        data: impl Read,
+        tar_builder: &mut Builder<impl Write>,
+    ) -> Result<()> {
+        // `tar` refuses absolute paths, so they are always stored in the header here
+        if self.long_path_encoding == LongPathEncoding::Gnu && !path.starts_with('/') {
+            Self::append_pax_attributes(path, &attributes, tar_builder)?;
+            match link {
+                Some(link) => tar_builder.append_link(header, path, link)?,
+                None => tar_builder.append_data(header, path, data)?,
+            }
+            return Ok(());
+        }
+        // A ustar header splits the path between its name and prefix fields, the link name has no prefix
+        if let Err(e) = set_header_path(header, path) {
+            if self.long_path_encoding == LongPathEncoding::Ustar {
+                bail!("{:?} is too long for a ustar header: {}", path, e);
+            }
+            attributes.push((PATH_KEY.to_string(), path.as_bytes().to_vec()));
+            let relative = path.trim_start_matches('/');
+            header.set_path(truncate_at_char_boundary(relative, 100))?;
+        }
+        if let Some(link) = link {
+            if let Err(e) = header.set_link_name(link) {
+                if self.long_path_encoding == LongPathEncoding::Ustar {
+                    bail!("Link target {:?} is too long for a ustar header: {}", link, e);
+                }
+                attributes.push((LINKPATH_KEY.to_string(), link.as_bytes().to_vec()));
+                header.set_link_name(truncate_at_char_boundary(link, 100))?;
+            }
+        }
+        Self::append_pax_attributes(path, &attributes, tar_builder)?;
+        header.set_cksum();
+        tar_builder.append(header, data)?;
         Ok(())
     }
 
-    /// Compresses a tar file into an LZMA-compressed file
+    /// Appends a pax extended header applying to the next entry, unless there are no attributes
+    ///
+    /// # Parameters
+    /// - `path`: The archive path of the entry the attributes belong to
+    /// - `attributes`: The pax attributes as key value pairs
+    /// - `tar_builder`: The tar builder to append the header to
+    fn append_pax_attributes(
+        path: &str,
+        attributes: &[(String, Vec<u8>)],
+        tar_builder: &mut Builder<impl Write>,
+    ) -> Result<()> {
+        if attributes.is_empty() {
+            return Ok(());
+        }
+        append_pax_header(tar_builder, path, attributes)?;
+        Ok(())
+    }
+
+    /// Appends the manifest as a JSON entry in the root of the tarball
+    ///
+    /// # Parameters
+    /// - `manifest`: The manifest describing the archived files
+    /// - `tar_builder`: The tar builder to append the manifest to
+    fn append_manifest(
+        manifest: &Manifest,
+        mtime: u64,
+        tar_builder: &mut Builder<impl Write>,
+    ) -> Result<()> {
+        let json = serde_json::to_vec_pretty(manifest)?;
+        let mut header = Header::new_gnu();
+        header.set_size(json.len() as u64);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(mtime);
+        tar_builder.append_data(&mut header, MANIFEST_FILE_NAME, json.as_slice())?;
+
+        debug!("Manifest appended to tar with {} files", manifest.files.len());
+        Ok(())
+    }
+
+    /// Runs `work`, handing the progress it reports to `callback` as configured by `set_progress_dispatch`
     ///
     /// # Parameters
     /// - `callback`: A callback function to report progress
+    /// - `work`: Receives the function progress is reported through
     ///
     /// # Returns
-    /// - `Ok(())` on success
+    /// - What `work` returned on success
     /// - `Box<dyn Error>` on failure
-    fn compress_tar<F>(&self, callback: F) -> Result<()>
+    fn dispatch_progress<F, T>(&self, callback: F, work: impl FnOnce(&dyn Fn(LZMACallbackResult)) -> Result<T>) -> Result<T>
     where
         F: Fn(LZMACallbackResult) + 'static + Send + Sync,
     {
-        debug!("Opening tar file for compression: {:?}", self.tar_file);
-        let mut input_file = BufReader::new(File::open(&self.tar_file)?);
+        match &self.progress_dispatch {
+            Dispatch::Inline => work(&callback),
+            Dispatch::DedicatedThread => {
+                let (sender, receiver) = mpsc::channel();
+                let dispatcher = std::thread::spawn(move || receiver.into_iter().for_each(callback));
+                let result = work(&|progress| {
+                    let _ = sender.send(progress);
+                });
+                drop(sender);
+                if dispatcher.join().is_err() {
+                    bail!("Progress callback panicked");
+                }
+                result
+            }
+            Dispatch::Channel(sender) => work(&|progress| {
+                // The receiver may have been dropped if the caller isn't interested in progress anymore
+                let _ = sender.send(progress);
+            }),
+        }
+    }
 
-        let output_file = match &self.output_file {
-            Some(file) => {
-                debug!("Creating output file for compressed data: {:?}", file);
-                BufWriter::new(File::create(file)?)
+    /// Fails with `WriterError::IncompatibleOptions` if a preset dictionary or a seekable block size is set
+    /// with a format other than xz, or both are set, instead of silently leaving one out.
+    fn check_format_options(&self) -> Result<()> {
+        let xz_option = match (&self.preset_dictionary, self.seekable_block_size) {
+            (Some(_), Some(_)) => {
+                error!("A preset dictionary can't be used with a seekable block size");
+                return Err(WriterError::IncompatibleOptions { option: "a preset dictionary", conflict: "a seekable block size".to_string() }.into());
             }
-            None => {
-                error!("Output file not set in compress_tar");
-                bail!("Output file not set")
+            (Some(_), None) => "a preset dictionary",
+            (None, Some(_)) => "a seekable block size",
+            (None, None) => return Ok(()),
+        };
+        if self.format != CompressionFormat::Xz {
+            error!("{} can't be used with the {:?} format", xz_option, self.format);
+            return Err(WriterError::IncompatibleOptions { option: xz_option, conflict: format!("the {:?} format", self.format) }.into());
+        }
+        Ok(())
+    }
+
+    /// Runs `work` on a clone of the writer on a worker thread if a timeout is set,
+    /// failing with an `io::ErrorKind::TimedOut` error once it expires.
+    /// The clone is then cancelled, and this returns once it has stopped and cleaned up its output.
+    fn with_timeout<T, W>(&self, work: W) -> Result<T>
+    where
+        T: Send + 'static,
+        W: FnOnce(&Self) -> Result<T> + 'static + Send,
+    {
+        let Some(timeout) = self.timeout else {
+            return work(self);
+        };
+        let mut worker = self.clone();
+        worker.timed_out = Arc::new(AtomicBool::new(false));
+        let timed_out = worker.timed_out.clone();
+        let (sender, receiver) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let _ = sender.send(work(&worker));
+        });
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => {
+                let _ = handle.join();
+                result
             }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                timed_out.store(true, Ordering::Relaxed);
+                error!("Compression timed out after {:?}", timeout);
+                // The worker owns the output until it has stopped and cleaned it up, so a retry can't race with it
+                let finished = receiver.recv();
+                let _ = handle.join();
+                match finished {
+                    // It completed before noticing the timeout, so the output is whole
+                    Ok(Ok(result)) => Ok(result),
+                    _ => Err(anyhow::Error::new(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("compression timed out after {:?}", timeout),
+                    ))),
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => bail!("Compression thread panicked"),
+        }
+    }
+
+    /// Runs `work` on a dedicated thread with lowered priority if `background_priority` is set
+    fn with_priority<T: Send>(&self, work: impl FnOnce() -> Result<T> + Send) -> Result<T> {
+        if !self.background_priority {
+            return work();
+        }
+        std::thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                if let Err(e) = priority::lower_current_thread() {
+                    warn!("Failed to lower thread priority: {}", e);
+                }
+                work()
+            });
+            worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.timed_out.load(Ordering::Relaxed) || self.cancel_flag.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Compresses the tar, reporting progress through `report`.
+    /// With an output queue, the output is written on a dedicated thread fed through a bounded queue.
+    fn compress_tar_with(&self, source: TarSource, output_file: impl Write + Send, progress: &OutputProgress, report: &dyn Fn(LZMACallbackResult)) -> Result<u64> {
+        let Some(depth) = self.output_queue_depth else {
+            let output = SinkWriter { inner: output_file, progress };
+            return self.compress_into(source, BufWriter::new(output), progress, report);
         };
+        let chunk_size = self.buffer_size.resolve(progress.total_size.load(Ordering::Relaxed));
+        debug!("Queueing up to {} chunks of {}KB for the output", depth, chunk_size / 1024);
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(depth);
+        let shared = progress;
+        std::thread::scope(|scope| {
+            let sink = scope.spawn(move || -> std::io::Result<()> {
+                let mut output = SinkWriter { inner: output_file, progress: shared };
+                for chunk in receiver {
+                    output.write_all(&chunk)?;
+                }
+                output.flush()
+            });
+            let queue = QueueWriter { sender, progress: shared, report };
+            let result = self.compress_into(source, BufWriter::with_capacity(chunk_size, queue), shared, report);
+            // A failed sink closes the queue, so its error explains why compression failed
+            sink.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)).context("Failed to write the compressed output")?;
+            result
+        })
+    }
 
-        let mut compressor = XzEncoder::new(output_file, self.compression_level as u32);
-        let mut buffer = vec![0; 1024 * (self.buffer_size as usize)];
+    /// Compresses the tar into `output`, which is already buffered
+    fn compress_into(&self, source: TarSource, output: impl Write, progress: &OutputProgress, report: &dyn Fn(LZMACallbackResult)) -> Result<u64> {
+        // The options only valid for xz were rejected by `check_format_options` for the other formats
+        let mut compressor = match self.format {
+            CompressionFormat::Zstd => Compressor::zstd(output, self.compression_level)?,
+            CompressionFormat::Gzip => Compressor::gzip(output, self.compression_level)?,
+            CompressionFormat::None => Compressor::Plain(output),
+            CompressionFormat::Bzip2 => {
+                error!("Writing bzip2 archives isn't supported");
+                bail!("Writing bzip2 archives isn't supported, use another format");
+            }
+            CompressionFormat::Xz => match (&self.preset_dictionary, self.seekable_block_size) {
+                (Some(dictionary), _) => Compressor::Dictionary(DictionaryEncoder::new(output, self.compression_level as u32, dictionary)?),
+                (None, Some(block_size)) => {
+                    let stream = MtStreamBuilder::new().preset(self.compression_level as u32).threads(1).block_size(block_size).encoder()?;
+                    Compressor::Xz(XzEncoder::new_stream(output, stream))
+                }
+                (None, None) => Compressor::Xz(XzEncoder::new(output, self.compression_level as u32)),
+            },
+        };
+        let staged = match source {
+            TarSource::Staged(staged) => staged,
+            TarSource::Streamed(entries) => {
+                let writer = ProgressWriter {
+                    inner: &mut compressor,
+                    progress,
+                    report,
+                };
+                let (created, _) = self.create_tar(writer)?;
+                *entries = created;
+                report(progress.enter(Phase::Finalizing));
+                compressor.finish()?.flush()?;
 
-        let total_size = std::fs::metadata(&self.tar_file)?.len();
+                debug!("Compression complete!");
+                return Ok(progress.bytes_processed.load(Ordering::Relaxed));
+            }
+        };
+        let mut input_file = staged.reader()?;
+        let total_size = staged.len();
+        let buffer_size = self.buffer_size.resolve(total_size);
+        let mut buffer = vec![0; buffer_size];
 
         debug!(
             "Balling up the tar with {}KB Buffer, total size: {} bytes",
-            self.buffer_size, total_size
+            buffer_size / 1024,
+            total_size
         );
 
         let mut bytes_processed = 0;
-        let start = std::time::Instant::now();
         loop {
+            if self.is_cancelled() {
+                info!("Compression cancelled after {} bytes", bytes_processed);
+                return Err(WriterError::Cancelled.into());
+            }
             let bytes_read = input_file.read(&mut buffer)?;
             if bytes_read == 0 {
                 debug!("Reached end of tar file during compression");
@@ -450,28 +2259,538 @@ impl LZMATarballWriter {
             }
             compressor.write_all(&buffer[..bytes_read])?;
             bytes_processed += bytes_read as u64;
-            let elapsed_seconds = start.elapsed().as_secs();
-            if elapsed_seconds > 0 {
-                let bytes_per_second = bytes_processed / elapsed_seconds;
-                let percentage = bytes_processed as f32 / total_size as f32;
-
-                debug!(
-                    "Compression progress: {} bytes processed, {} bytes/s, {:.2}% complete",
-                    bytes_processed,
-                    bytes_per_second,
-                    percentage * 100.0
-                );
-                callback(LZMACallbackResult {
-                    bytes_processed,
-                    bytes_per_second,
-                    percentage,
-                });
+            progress.bytes_processed.store(bytes_processed, Ordering::Relaxed);
+            if let Some(update) = progress.report(false) {
+                report(update);
             }
         }
 
-        compressor.finish()?;
+        report(progress.enter(Phase::Finalizing));
+        compressor.finish()?.flush()?;
 
         debug!("Compression complete!");
+        Ok(bytes_processed)
+    }
+}
+
+/// Progress of a compression shared between the compressor and the thread writing the output
+struct OutputProgress {
+    /// When the current phase started, the speed is measured from there
+    start: Mutex<std::time::Instant>,
+    phase: Mutex<Phase>,
+    /// The expected number of input bytes, either the tar size or the planned `input_size`, set once compression starts
+    total_size: AtomicU64,
+    /// The number of input bytes compressed so far
+    bytes_processed: AtomicU64,
+    /// The number of compressed bytes written to the output so far
+    bytes_written: AtomicU64,
+}
+
+impl OutputProgress {
+    fn new() -> Self {
+        Self {
+            start: Mutex::new(std::time::Instant::now()),
+            phase: Mutex::new(Phase::Scanning),
+            total_size: AtomicU64::new(0),
+            bytes_processed: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts `phase`, returning the update announcing it
+    fn enter(&self, phase: Phase) -> LZMACallbackResult {
+        debug!("Compression phase: {:?}", phase);
+        *self.phase.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = phase;
+        if phase == Phase::Compressing {
+            *self.start.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = std::time::Instant::now();
+        }
+        self.update(0, false)
+    }
+
+    /// Builds a progress update once at least a second has passed since compression started,
+    /// or right away when compression starts waiting on the output
+    ///
+    /// # Parameters
+    /// - `waiting_on_output`: Whether compression is blocked on a full output queue
+    fn report(&self, waiting_on_output: bool) -> Option<LZMACallbackResult> {
+        let bytes_processed = self.bytes_processed.load(Ordering::Relaxed);
+        let elapsed = self.start.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).elapsed();
+        match bytes_processed.checked_div(elapsed.as_secs()) {
+            Some(bytes_per_second) => Some(self.update(bytes_per_second, waiting_on_output)),
+            None if waiting_on_output => Some(self.update(0, true)),
+            None => None,
+        }
+    }
+
+    /// Builds the update for the current phase and counters
+    fn update(&self, bytes_per_second: u64, waiting_on_output: bool) -> LZMACallbackResult {
+        let bytes_processed = self.bytes_processed.load(Ordering::Relaxed);
+        let bytes_written = self.bytes_written.load(Ordering::Relaxed);
+        let total_size = self.total_size.load(Ordering::Relaxed);
+        let phase = *self.phase.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // A planned total doesn't include tar headers, so it can be exceeded slightly
+        let percentage = if total_size == 0 { 0.0 } else { (bytes_processed as f32 / total_size as f32).min(1.0) };
+
+        debug!(
+            "Compression progress: {} bytes processed, {} bytes written, {} bytes/s, {:.2}% complete{}",
+            bytes_processed,
+            bytes_written,
+            bytes_per_second,
+            percentage * 100.0,
+            if waiting_on_output { ", waiting on output" } else { "" }
+        );
+        LZMACallbackResult {
+            bytes_processed,
+            bytes_per_second,
+            percentage,
+            bytes_written,
+            waiting_on_output,
+            phase,
+        }
+    }
+}
+
+/// Writes the compressed data to the output, counting it in `progress`
+struct SinkWriter<'a, W> {
+    inner: W,
+    progress: &'a OutputProgress,
+}
+
+impl<W: Write> Write for SinkWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.bytes_written.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hands the compressed data to the thread writing the output, blocking while its queue is full
+struct QueueWriter<'a> {
+    sender: SyncSender<Vec<u8>>,
+    progress: &'a OutputProgress,
+    report: &'a dyn Fn(LZMACallbackResult),
+}
+
+impl Write for QueueWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let closed = || std::io::Error::new(std::io::ErrorKind::BrokenPipe, "output stopped accepting data");
+        let chunk = match self.sender.try_send(buf.to_vec()) {
+            Ok(()) => return Ok(buf.len()),
+            Err(TrySendError::Full(chunk)) => chunk,
+            Err(TrySendError::Disconnected(_)) => return Err(closed()),
+        };
+        if let Some(update) = self.progress.report(true) {
+            (self.report)(update);
+        }
+        self.sender.send(chunk).map_err(|_| closed())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
         Ok(())
     }
 }
+
+/// The encoder `compress_into` writes the tar to
+enum Compressor<W: Write> {
+    Xz(XzEncoder<W>),
+    /// A raw LZMA2 stream primed with the preset dictionary
+    Dictionary(DictionaryEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    /// The tar is written without compression
+    Plain(W),
+}
+
+impl<W: Write> Compressor<W> {
+    #[cfg(feature = "zstd")]
+    fn zstd(output: W, compression_level: u8) -> Result<Self> {
+        Ok(Compressor::Zstd(zstd::Encoder::new(output, CompressionFormat::zstd_level(compression_level))?))
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn zstd(_output: W, _compression_level: u8) -> Result<Self> {
+        error!("The zstd format needs the zstd feature");
+        bail!("The zstd format needs the zstd feature")
+    }
+
+    #[cfg(feature = "gzip")]
+    fn gzip(output: W, compression_level: u8) -> Result<Self> {
+        Ok(Compressor::Gzip(flate2::write::GzEncoder::new(output, flate2::Compression::new(compression_level as u32))))
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    fn gzip(_output: W, _compression_level: u8) -> Result<Self> {
+        error!("The gzip format needs the gzip feature");
+        bail!("The gzip format needs the gzip feature")
+    }
+
+    fn finish(self) -> std::io::Result<W> {
+        match self {
+            Compressor::Xz(encoder) => encoder.finish(),
+            Compressor::Dictionary(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Compressor::Zstd(encoder) => encoder.finish(),
+            #[cfg(feature = "gzip")]
+            Compressor::Gzip(encoder) => encoder.finish(),
+            Compressor::Plain(output) => Ok(output),
+        }
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Compressor::Xz(encoder) => encoder.write(buf),
+            Compressor::Dictionary(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Compressor::Zstd(encoder) => encoder.write(buf),
+            #[cfg(feature = "gzip")]
+            Compressor::Gzip(encoder) => encoder.write(buf),
+            Compressor::Plain(output) => output.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Compressor::Xz(encoder) => encoder.flush(),
+            Compressor::Dictionary(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Compressor::Zstd(encoder) => encoder.flush(),
+            #[cfg(feature = "gzip")]
+            Compressor::Gzip(encoder) => encoder.flush(),
+            Compressor::Plain(output) => output.flush(),
+        }
+    }
+}
+
+/// An output being written, removed or truncated back to `original_size` when dropped before it is complete.
+/// Being a guard, it also cleans up when a progress callback panics.
+pub(crate) struct PartialOutput<'a> {
+    path: &'a Path,
+    /// The size of an archive that is appended to, `None` for an output created by this compression
+    original_size: Option<u64>,
+    keep: bool,
+}
+
+impl<'a> PartialOutput<'a> {
+    pub(crate) fn new(path: &'a Path, original_size: Option<u64>, keep: bool) -> Self {
+        Self { path, original_size, keep }
+    }
+
+    /// Marks the output as complete, so it is kept
+    pub(crate) fn complete(mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for PartialOutput<'_> {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        debug!("Removing the partial output at {:?}", self.path);
+        let _ = match self.original_size {
+            Some(size) => File::options().write(true).open(self.path).and_then(|file| file.set_len(size)),
+            None => std::fs::remove_file(self.path),
+        };
+    }
+}
+
+/// Where `compress_tar` reads the tar from
+enum TarSource<'a> {
+    /// A tar created before compressing
+    Staged(&'a StagedTar),
+    /// The tar is created while compressing and the statistics of its entries are stored here
+    Streamed(&'a mut Vec<EntryStatistics>),
+}
+
+/// Stages the tar in memory until it grows beyond `max_bytes`, then spills it to the file at `path`
+struct StagingWriter {
+    memory: Vec<u8>,
+    max_bytes: u64,
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+impl StagingWriter {
+    /// Flushes the staged tar so it can be read back
+    fn finish(mut self) -> std::io::Result<StagedTar> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(StagedTar::Memory(std::mem::take(&mut self.memory)));
+        };
+        // The staged tar removes the file from now on
+        let staged = StagedTar::Disk(self.path.clone());
+        file.flush()?;
+        Ok(staged)
+    }
+}
+
+impl Write for StagingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.file.is_none() && (self.memory.len() + buf.len()) as u64 > self.max_bytes {
+            debug!("Spilling tar of {} bytes to {:?}", self.memory.len(), self.path);
+            let mut file = BufWriter::new(File::create(&self.path)?);
+            file.write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.file = Some(file);
+        }
+        match self.file {
+            Some(ref mut file) => file.write(buf),
+            None => self.memory.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for StagingWriter {
+    fn drop(&mut self) {
+        // Only reached with a file if creating the tar failed
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A tar staged by `StagingWriter`, a file on disk is removed when dropped
+enum StagedTar {
+    Memory(Vec<u8>),
+    Disk(PathBuf),
+}
+
+impl StagedTar {
+    fn len(&self) -> u64 {
+        match self {
+            StagedTar::Memory(data) => data.len() as u64,
+            StagedTar::Disk(path) => path.metadata().map(|metadata| metadata.len()).unwrap_or_default(),
+        }
+    }
+
+    fn reader(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        match self {
+            StagedTar::Memory(data) => Ok(Box::new(data.as_slice())),
+            StagedTar::Disk(path) => {
+                debug!("Opening tar file for compression: {:?}", path);
+                Ok(Box::new(BufReader::new(File::open(path)?)))
+            }
+        }
+    }
+}
+
+impl Drop for StagedTar {
+    fn drop(&mut self) {
+        if let StagedTar::Disk(path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Reports compression progress for a tar that is compressed while it is written
+struct ProgressWriter<'a, W> {
+    inner: W,
+    progress: &'a OutputProgress,
+    report: &'a dyn Fn(LZMACallbackResult),
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.bytes_processed.fetch_add(written as u64, Ordering::Relaxed);
+        if let Some(progress) = self.progress.report(false) {
+            (self.report)(progress);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Collects compressed data into chunks of `chunk_size` and hands them to `on_chunk`
+struct ChunkWriter<C> {
+    buffer: Vec<u8>,
+    chunk_size: usize,
+    on_chunk: C,
+    written: u64,
+    /// The error returned by `on_chunk`, kept so it can be returned unchanged
+    failure: Option<anyhow::Error>,
+}
+
+impl<C: FnMut(&[u8]) -> Result<()>> ChunkWriter<C> {
+    fn emit(&mut self, length: usize) -> std::io::Result<()> {
+        // Buffered writers retry on drop, but the consumer already gave up
+        if self.failure.is_some() {
+            return Err(std::io::Error::other("chunk callback failed"));
+        }
+        if let Err(e) = (self.on_chunk)(&self.buffer[..length]) {
+            self.failure = Some(e);
+            return Err(std::io::Error::other("chunk callback failed"));
+        }
+        self.written += length as u64;
+        self.buffer.drain(..length);
+        Ok(())
+    }
+}
+
+impl<C: FnMut(&[u8]) -> Result<()>> Write for ChunkWriter<C> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let length = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+        if self.buffer.len() == self.chunk_size {
+            self.emit(self.chunk_size)?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.emit(self.buffer.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// A shared advisory lock on a source file, released when dropped
+struct SourceLock(File);
+
+impl SourceLock {
+    fn new(file: &File) -> std::io::Result<Self> {
+        // The lock belongs to the open file, so locking a duplicate handle locks `file` as well
+        let handle = file.try_clone()?;
+        lock_shared(&handle)?;
+        Ok(Self(handle))
+    }
+}
+
+impl Drop for SourceLock {
+    fn drop(&mut self) {
+        // Handles opened at scan time outlive the read, so the lock is released explicitly
+        let _ = unlock(&self.0);
+    }
+}
+
+/// Takes a shared `flock` on `file`, waiting for exclusive locks to be released.
+#[cfg(unix)]
+fn lock_shared(file: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: the descriptor is valid for as long as `file` is borrowed
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn unlock(file: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: the descriptor is valid for as long as `file` is borrowed
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+#[repr(C)]
+struct Overlapped {
+    internal: usize,
+    internal_high: usize,
+    offset: u32,
+    offset_high: u32,
+    event: *mut std::ffi::c_void,
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn LockFileEx(file: *mut std::ffi::c_void, flags: u32, reserved: u32, low: u32, high: u32, overlapped: *mut Overlapped) -> i32;
+    fn UnlockFileEx(file: *mut std::ffi::c_void, reserved: u32, low: u32, high: u32, overlapped: *mut Overlapped) -> i32;
+}
+
+/// Takes a shared `LockFileEx` lock on the whole of `file`, waiting for exclusive locks to be released.
+#[cfg(windows)]
+fn lock_shared(file: &File) -> std::io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    let mut overlapped = Overlapped { internal: 0, internal_high: 0, offset: 0, offset_high: 0, event: std::ptr::null_mut() };
+    // SAFETY: the handle is valid for as long as `file` is borrowed, and the call is synchronous, so `overlapped` outlives it
+    if unsafe { LockFileEx(file.as_raw_handle(), 0, 0, u32::MAX, u32::MAX, &mut overlapped) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn unlock(file: &File) -> std::io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    let mut overlapped = Overlapped { internal: 0, internal_high: 0, offset: 0, offset_high: 0, event: std::ptr::null_mut() };
+    // SAFETY: the handle is valid for as long as `file` is borrowed, and the call is synchronous, so `overlapped` outlives it
+    if unsafe { UnlockFileEx(file.as_raw_handle(), 0, u32::MAX, u32::MAX, &mut overlapped) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lock_shared(_file: &File) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "file locks are not supported on this platform"))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn unlock(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Reads a file for the tar, reporting progress after every chunk and stopping when cancelled
+struct ChunkedReader<'a, R> {
+    inner: R,
+    path: &'a str,
+    chunk_size: u64,
+    bytes_processed: u64,
+    total_bytes: u64,
+    /// Checked before every read, so a cancelled or timed out compression stops within a chunk
+    writer: &'a LZMATarballWriter,
+    file_progress: Option<&'a FileProgressCallback>,
+}
+
+impl<R: Read> Read for ChunkedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.writer.is_cancelled() {
+            return Err(std::io::Error::other(WriterError::Cancelled));
+        }
+        let bytes_read = self.inner.read(buf)?;
+        let previous_chunk = self.bytes_processed / self.chunk_size.max(1);
+        self.bytes_processed += bytes_read as u64;
+        let finished = bytes_read == 0 || self.bytes_processed >= self.total_bytes;
+        if let Some(callback) = self.file_progress {
+            if bytes_read > 0 && (finished || self.bytes_processed / self.chunk_size.max(1) != previous_chunk) {
+                (callback.0)(FileProgress {
+                    path: self.path.to_string(),
+                    bytes_processed: self.bytes_processed,
+                    total_bytes: self.total_bytes,
+                });
+            }
+        }
+        Ok(bytes_read)
+    }
+}
+
+// Configured writers are moved across threads by job queues, so they must stay `Send + Sync`
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<LZMATarballWriter>();
+    assert_send_sync::<LZMAResult>();
+    assert_send_sync::<LZMACallbackResult>();
+};
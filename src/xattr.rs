@@ -0,0 +1,76 @@
+//! Reads the extended attributes of source files, stored as pax `SCHILY.xattr.*` records.
+
+use std::fs::File;
+use std::io;
+
+/// The prefix of the pax attributes holding extended attributes, as written by GNU tar and libarchive.
+pub(crate) const XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Returns the extended attributes of `file` as name value pairs, in the order the filesystem lists them.
+/// Filesystems without extended attributes have none.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) fn read_xattrs(file: &File) -> io::Result<Vec<(String, Vec<u8>)>> {
+    use std::ffi::CStr;
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let names = match read_buffer(|buffer, size| unsafe { list(fd, buffer, size) }) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut attributes = Vec::new();
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let name = [name, &[0]].concat();
+        let name = CStr::from_bytes_with_nul(&name).map_err(io::Error::other)?;
+        let value = read_buffer(|buffer, size| unsafe { get(fd, name.as_ptr(), buffer, size) })?;
+        attributes.push((name.to_string_lossy().into_owned(), value));
+    }
+    Ok(attributes)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn read_xattrs(_file: &File) -> io::Result<Vec<(String, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+/// Calls `read` once to size the buffer and again to fill it, retrying if the data grew in between.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read_buffer(read: impl Fn(*mut libc::c_void, usize) -> libc::ssize_t) -> io::Result<Vec<u8>> {
+    loop {
+        let size = read(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buffer = vec![0u8; size as usize];
+        let filled = read(buffer.as_mut_ptr().cast(), buffer.len());
+        if filled >= 0 {
+            buffer.truncate(filled as usize);
+            return Ok(buffer);
+        }
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ERANGE) {
+            return Err(error);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn list(fd: libc::c_int, buffer: *mut libc::c_void, size: usize) -> libc::ssize_t {
+    libc::flistxattr(fd, buffer.cast(), size)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn list(fd: libc::c_int, buffer: *mut libc::c_void, size: usize) -> libc::ssize_t {
+    libc::flistxattr(fd, buffer.cast(), size, 0)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn get(fd: libc::c_int, name: *const libc::c_char, buffer: *mut libc::c_void, size: usize) -> libc::ssize_t {
+    libc::fgetxattr(fd, name, buffer, size)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn get(fd: libc::c_int, name: *const libc::c_char, buffer: *mut libc::c_void, size: usize) -> libc::ssize_t {
+    libc::fgetxattr(fd, name, buffer, size, 0, 0)
+}
@@ -0,0 +1,144 @@
+//! # xz Block Index
+//! Every xz stream ends with an index of the blocks it contains. Reading the indexes from the end of the
+//! archive locates each block in the compressed file and in the decompressed tar stream, so a single entry
+//! can be fetched with a range request starting at the block that holds it.
+
+use anyhow::{bail, Context, Result};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use xz2::read::XzDecoder;
+
+const HEADER_MAGIC: &[u8; 6] = b"\xFD7zXZ\0";
+const FOOTER_MAGIC: &[u8; 2] = b"YZ";
+/// The size of the stream header and of the stream footer
+pub(crate) const HEADER_SIZE: u64 = 12;
+
+/// A compressed block of an xz file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct XzBlock {
+    /// The offset of the stream holding the block, whose header is needed to decode it
+    pub(crate) stream_offset: u64,
+    /// The offset of the block in the compressed file
+    pub(crate) compressed_offset: u64,
+    /// The size of the block in the compressed file, including its padding
+    pub(crate) compressed_size: u64,
+    /// The offset in the decompressed data, across all streams, at which the block starts
+    pub(crate) uncompressed_offset: u64,
+    pub(crate) uncompressed_size: u64,
+}
+
+/// The blocks of every stream in an xz file, in file order.
+#[derive(Debug, Default)]
+pub(crate) struct XzIndex {
+    blocks: Vec<XzBlock>,
+}
+
+impl XzIndex {
+    /// Reads the stream indexes of `file`, walking its streams from the end.
+    pub(crate) fn read<R: Read + Seek>(file: &mut R) -> Result<Self> {
+        let mut position = file.seek(SeekFrom::End(0))?;
+        // Each stream's blocks, last stream first
+        let mut streams = Vec::new();
+        while position > 0 {
+            // Streams may be followed by padding made of zero words
+            let word = read_at(file, position.checked_sub(4).context("Truncated xz file")?, 4)?;
+            if word == [0; 4] {
+                position -= 4;
+                continue;
+            }
+            let footer = read_at(file, position.checked_sub(HEADER_SIZE).context("Truncated xz file")?, HEADER_SIZE as usize)?;
+            if &footer[10..] != FOOTER_MAGIC {
+                bail!("Missing xz stream footer before offset {}", position);
+            }
+            let index_size = (u32::from_le_bytes(footer[4..8].try_into()?) as u64 + 1) * 4;
+            let index_start = (position - HEADER_SIZE).checked_sub(index_size).context("Truncated xz index")?;
+            let records = parse_index(&read_at(file, index_start, index_size as usize)?)?;
+            let blocks_size: u64 = records.iter().map(|(unpadded, _)| unpadded.div_ceil(4) * 4).sum();
+            let stream_start = index_start.checked_sub(blocks_size + HEADER_SIZE).context("Truncated xz stream")?;
+            if read_at(file, stream_start, HEADER_MAGIC.len())? != HEADER_MAGIC {
+                bail!("Missing xz stream header at offset {}", stream_start);
+            }
+            let mut offset = stream_start + HEADER_SIZE;
+            let mut blocks = Vec::with_capacity(records.len());
+            for (unpadded, uncompressed_size) in records {
+                let compressed_size = unpadded.div_ceil(4) * 4;
+                blocks.push(XzBlock {
+                    stream_offset: stream_start,
+                    compressed_offset: offset,
+                    compressed_size,
+                    uncompressed_offset: 0,
+                    uncompressed_size,
+                });
+                offset += compressed_size;
+            }
+            streams.push(blocks);
+            position = stream_start;
+        }
+        let mut index = XzIndex::default();
+        let mut uncompressed_offset = 0;
+        for mut block in streams.into_iter().rev().flatten() {
+            block.uncompressed_offset = uncompressed_offset;
+            uncompressed_offset += block.uncompressed_size;
+            index.blocks.push(block);
+        }
+        Ok(index)
+    }
+
+    /// Returns the blocks in file order.
+    pub(crate) fn blocks(&self) -> &[XzBlock] {
+        &self.blocks
+    }
+
+    /// Returns the block holding `offset` of the decompressed data.
+    pub(crate) fn block_at(&self, offset: u64) -> Option<XzBlock> {
+        let blocks_before = self.blocks.partition_point(|block| block.uncompressed_offset <= offset);
+        blocks_before.checked_sub(1).map(|i| self.blocks[i])
+    }
+}
+
+impl XzBlock {
+    /// Decodes the first `length` bytes of the block from its `stream_header` and `data`, the block as stored in the file.
+    /// The block is decoded as the start of a stream, which stops before the missing index is needed.
+    pub(crate) fn decode(&self, stream_header: &[u8], data: &[u8], length: u64) -> Result<Vec<u8>> {
+        let mut decoder = XzDecoder::new(Cursor::new([stream_header, data].concat()));
+        let mut decoded = vec![0; length.min(self.uncompressed_size) as usize];
+        decoder.read_exact(&mut decoded).context("Failed to decode xz block")?;
+        Ok(decoded)
+    }
+}
+
+fn read_at<R: Read + Seek>(file: &mut R, offset: u64, length: usize) -> Result<Vec<u8>> {
+    let mut buffer = vec![0; length];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buffer).context("Truncated xz file")?;
+    Ok(buffer)
+}
+
+/// Parses an index into the unpadded and uncompressed size of each block.
+fn parse_index(index: &[u8]) -> Result<Vec<(u64, u64)>> {
+    if index.first() != Some(&0) {
+        bail!("Invalid xz index indicator");
+    }
+    let mut position = 1;
+    let count = read_varint(index, &mut position)?;
+    let mut records = Vec::new();
+    for _ in 0..count {
+        let unpadded = read_varint(index, &mut position)?;
+        let uncompressed = read_varint(index, &mut position)?;
+        records.push((unpadded, uncompressed));
+    }
+    Ok(records)
+}
+
+/// Reads a multibyte integer: seven bits per byte, least significant first, at most nine bytes.
+fn read_varint(data: &[u8], position: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..63).step_by(7) {
+        let byte = *data.get(*position).context("Truncated xz index")?;
+        *position += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Invalid xz index integer")
+}
@@ -2,13 +2,26 @@
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
+    use std::collections::HashMap;
     use std::env::current_dir;
     use std::fs::{self, File};
     use std::io::Write;
     use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
 
     // Import the reader from your library. Adjust the path as needed.
-    use lzma_tarball::reader::LZMATarballReader;
+    use lzma_tarball::catalog::Difference;
+    use lzma_tarball::error::ReaderError;
+    use lzma_tarball::normalization::UnicodeNormalization;
+    use lzma_tarball::phase::Phase;
+    use lzma_tarball::skip::{SkipReason, SkippedEntry};
+    use lzma_tarball::reader::{
+        ChangeDetection, DuplicatePathPolicy, EntryOffsets, EntryType, FlattenConflictPolicy, HardlinkPolicy, HeaderFormat, LZMATarballReader, ListingStyle, SearchHit, SearchOptions,
+        ShadowedEntry, WindowsNamePolicy,
+    };
+    use lzma_tarball::remote::{RemoteArchive, ENTRY_INDEX_FILE_NAME};
+    use lzma_tarball::streaming::StreamingExtractor;
 
     #[test]
     fn test_extract_to_directory() {
@@ -35,8 +48,1729 @@ mod tests {
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0], "hello.txt");
     }
-    
-    
+
+    #[test]
+    fn test_entries_to_json_and_csv() {
+        let archive_file = create_archive_in_temp_dir("listing").unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&reader.entries_to_json().unwrap()).unwrap();
+        assert_eq!(json[0]["path"], "hello.txt");
+        assert_eq!(json[0]["entry_type"], "file");
+        assert_eq!(json[0]["size"], 13);
+
+        let csv = reader.entries_to_csv().unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("path,entry_type,size"));
+        assert!(lines.next().unwrap().starts_with("hello.txt,file,13,"));
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn test_streaming_extractor() {
+        let archive_file = create_archive_in_temp_dir("streaming").unwrap();
+        let output = archive_file.parent().unwrap().join("output");
+        let mut reader = LZMATarballReader::new();
+        reader.set_output_directory(&output).unwrap();
+        let mut extractor = StreamingExtractor::new(&reader).unwrap();
+        for chunk in fs::read(&archive_file).unwrap().chunks(7) {
+            extractor.feed(chunk).unwrap();
+        }
+        let result = extractor.finish().unwrap();
+        assert_eq!(result.files, vec!["hello.txt"]);
+        assert_eq!(fs::read_to_string(output.join("hello.txt")).unwrap(), "Hello, world!");
+
+        let mut extractor = StreamingExtractor::new(&reader).unwrap();
+        let _ = extractor.feed(&[0xff; 1024]);
+        assert!(extractor.finish().is_err());
+    }
+
+    #[test]
+    fn test_largest_entries() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-largest");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("sizes.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            for (name, size) in [("small.bin", 1), ("large.bin", 300), ("medium.bin", 20), ("also-medium.bin", 20)] {
+                builder.append_data(&mut raw_header(size as u64), name, vec![0u8; size].as_slice())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let largest: Vec<(String, u64)> = reader.largest_entries(3).unwrap().into_iter().map(|entry| (entry.path, entry.size)).collect();
+        assert_eq!(
+            largest,
+            vec![("large.bin".to_string(), 300), ("medium.bin".to_string(), 20), ("also-medium.bin".to_string(), 20)]
+        );
+    }
+
+    #[test]
+    fn test_non_utf8_entry_paths() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-non_utf8");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("names.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut header = raw_header(4);
+            header.as_old_mut().name[..8].copy_from_slice(b"bad\xff.txt");
+            header.set_cksum();
+            builder.append(&header, b"data".as_slice())?;
+            builder.append_data(&mut raw_header(4), "good.txt", b"data".as_slice())?;
+            Ok(())
+        })
+        .unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let paths = reader.entry_paths().unwrap();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].as_bytes(), b"bad\xff.txt");
+        assert_eq!(paths[0].to_str(), None);
+        assert_eq!(paths[0].to_string_lossy(), "bad\u{FFFD}.txt");
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::ffi::OsStrExt::as_bytes(paths[0].to_path_buf().as_os_str()), b"bad\xff.txt");
+        assert_eq!(paths[1].to_str(), Some("good.txt"));
+        assert_eq!(reader.entries().unwrap(), vec!["bad\u{FFFD}.txt".to_string(), "good.txt".to_string()]);
+    }
+
+    #[test]
+    fn test_routes() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-routes");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("package.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut directory = raw_header(0);
+            directory.set_entry_type(tar::EntryType::Directory);
+            builder.append_data(&mut directory, "etc/", std::io::empty())?;
+            for name in ["etc/app.conf", "usr/bin/tool", "usr/share/doc/notes.md", "README"] {
+                builder.append_data(&mut raw_header(4), name, b"data".as_slice())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader
+            .set_archive(&archive_file)
+            .unwrap()
+            .set_output_directory(dir.join("root"))
+            .unwrap()
+            .route("**/*.md", dir.join("docs"))
+            .route("etc/**", dir.join("staging"))
+            .route("usr/**", dir.join("opt"));
+        reader.decompress().unwrap();
+        assert!(dir.join("staging").join("etc").join("app.conf").is_file());
+        assert!(dir.join("opt").join("usr").join("bin").join("tool").is_file());
+        assert!(dir.join("docs").join("usr").join("share").join("doc").join("notes.md").is_file());
+        assert!(dir.join("root").join("README").is_file());
+        assert!(!dir.join("root").join("etc").exists());
+        assert!(!dir.join("root").join("usr").exists());
+    }
+
+    #[test]
+    fn test_extract_list_file() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-extract-list");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("package.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            for name in ["etc/app.conf", "usr/bin/tool", "usr/share/doc/notes.md", "README"] {
+                builder.append_data(&mut raw_header(4), name, b"data".as_slice())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let list = dir.join("files.txt");
+        fs::write(&list, "# deploy\n./usr/bin\n\n**/*.md\n/README\n").unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(dir.join("output")).unwrap();
+        reader.set_extract_list_file(&list).unwrap();
+        assert_eq!(reader.entries().unwrap(), vec!["usr/bin/tool", "usr/share/doc/notes.md", "README"]);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, vec!["usr/bin/tool", "usr/share/doc/notes.md", "README"]);
+        assert_eq!(result.skipped.len(), 1);
+        assert!(!dir.join("output").join("etc").exists());
+
+        fs::write(&list, "README\nmissing.txt\n").unwrap();
+        let error = reader.set_overwrite(true).set_extract_list_file(&list).unwrap().decompress().unwrap_err();
+        assert!(error.to_string().contains("missing.txt"), "{}", error);
+    }
+
+    #[test]
+    fn test_include_exclude_globs() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-globs");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("project.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            for name in ["Cargo.toml", "docs/guide.md", "docs/drafts/todo.md", "src/lib.rs", "src/Cargo.toml"] {
+                builder.append_data(&mut raw_header(4), name, b"data".as_slice())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(dir.join("output")).unwrap();
+        reader.set_include_globs(&["docs/**", "*.toml"]);
+        assert_eq!(reader.entries().unwrap(), vec!["Cargo.toml", "docs/guide.md", "docs/drafts/todo.md"]);
+
+        reader.set_exclude_globs(&["/docs/drafts"]);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, vec!["Cargo.toml", "docs/guide.md"]);
+        assert_eq!(result.skipped.len(), 3);
+        assert!(!dir.join("output").join("src").exists());
+        assert!(!dir.join("output").join("docs").join("drafts").exists());
+
+        reader.set_include_globs(&[]).set_exclude_globs(&["**/Cargo.toml"]);
+        assert_eq!(reader.entries().unwrap(), vec!["docs/guide.md", "docs/drafts/todo.md", "src/lib.rs"]);
+    }
+
+    #[test]
+    fn test_entry_header() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-entry-header");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("headers.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_pax_extensions([("path", b"renamed.txt".as_slice()), ("comment", b"third party".as_slice())])?;
+            builder.append_data(&mut raw_header(4), "short.txt", b"data".as_slice())?;
+            let mut device = tar::Header::new_ustar();
+            device.set_entry_type(tar::EntryType::Char);
+            device.set_device_major(4)?;
+            device.set_device_minor(64)?;
+            device.set_size(0);
+            device.set_mode(0o620);
+            device.set_uid(0);
+            device.set_gid(5);
+            device.set_mtime(1_700_000_000);
+            builder.append_data(&mut device, "dev/ttyS0", std::io::empty())
+        })
+        .unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+
+        let file = reader.entry_header("./renamed.txt").unwrap();
+        assert_eq!(file.path, "renamed.txt");
+        assert_eq!(file.header_path, "short.txt");
+        assert_eq!(file.header_format, HeaderFormat::Gnu);
+        assert_eq!(file.typeflag, b'0');
+        assert_eq!(file.size, 4);
+        assert_eq!(file.pax.get("comment").map(String::as_str), Some("third party"));
+        let device = reader.entry_header("dev/ttyS0").unwrap();
+        assert_eq!(device.header_format, HeaderFormat::Ustar);
+        assert_eq!(device.typeflag, b'3');
+        assert_eq!((device.device_major, device.device_minor), (Some(4), Some(64)));
+        assert!(device.pax.is_empty());
+        assert!(reader.entry_header("missing").is_err());
+    }
+
+    #[test]
+    fn test_touch() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-touch");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("old.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut directory = raw_header(0);
+            directory.set_entry_type(tar::EntryType::Directory);
+            directory.set_mode(0o755);
+            builder.append_data(&mut directory, "src/", std::io::empty())?;
+            builder.append_data(&mut raw_header(4), "src/main.rs", b"main".as_slice())
+        })
+        .unwrap();
+        let archived = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(dir.join("kept")).unwrap();
+        reader.decompress().unwrap();
+        assert_eq!(fs::metadata(dir.join("kept/src/main.rs")).unwrap().modified().unwrap(), archived);
+
+        let before = std::time::SystemTime::now() - std::time::Duration::from_secs(1);
+        reader.set_output_directory(dir.join("touched")).unwrap().set_touch(true).decompress().unwrap();
+        assert!(fs::metadata(dir.join("touched/src/main.rs")).unwrap().modified().unwrap() >= before);
+        assert!(fs::metadata(dir.join("touched/src")).unwrap().modified().unwrap() >= before);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let archive_file = create_archive_in_temp_dir("from_reader").unwrap();
+        let output = archive_file.with_file_name("output");
+        let mut reader = LZMATarballReader::from_reader(std::io::Cursor::new(fs::read(&archive_file).unwrap())).unwrap();
+        reader.set_output_directory(&output).unwrap();
+        assert_eq!(reader.decompress().unwrap().files, vec!["hello.txt"]);
+        assert_eq!(fs::read_to_string(output.join("hello.txt")).unwrap(), "Hello, world!");
+        let error = reader.entries().unwrap_err();
+        assert!(error.to_string().contains("already read"), "{}", error);
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append_data(&mut raw_header(4), "plain.txt", b"data".as_slice()).unwrap();
+        let reader = LZMATarballReader::from_reader(std::io::Cursor::new(builder.into_inner().unwrap())).unwrap();
+        assert_eq!(reader.detected_format(), Some(lzma_tarball::format::CompressionFormat::None));
+        assert_eq!(reader.entries().unwrap(), vec!["plain.txt"]);
+    }
+
+    #[test]
+    fn test_sidecar_index() {
+        let archive_file = create_archive_in_temp_dir("sidecar_index").unwrap();
+        let sidecar = archive_file.with_file_name("index.json");
+        lzma_tarball::writer::LZMATarballWriter::new()
+            .set_output(&archive_file)
+            .set_sidecar_index(Some(sidecar.clone()))
+            .with_file(archive_file.with_file_name("hello.txt"), "/hello.txt")
+            .compress(|_| {})
+            .unwrap();
+        // The archive is corrupted without changing its size, so only the index can list it
+        let mut archive = fs::read(&archive_file).unwrap();
+        let middle = archive.len() / 2;
+        archive[middle..middle + 8].fill(0xff);
+        fs::write(&archive_file, &archive).unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_sidecar_index(Some(sidecar));
+        assert_eq!(reader.entries().unwrap(), vec!["hello.txt"]);
+        assert!(reader.contains("hello.txt").unwrap());
+        assert!(reader.set_sidecar_index(None).entries().is_err());
+
+        archive.truncate(middle);
+        fs::write(&archive_file, &archive).unwrap();
+        reader.set_sidecar_index(Some(archive_file.with_file_name("index.json")));
+        assert!(reader.entries().is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        let archive_file = create_archive_in_temp_dir("contains").unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        assert!(reader.contains("hello.txt").unwrap());
+        assert!(reader.contains("./hello.txt").unwrap());
+        assert!(!reader.contains("hello").unwrap());
+    }
+
+    #[test]
+    fn test_detect_format() {
+        use lzma_tarball::format::CompressionFormat;
+
+        let archive_file = create_archive_in_temp_dir("detect_format").unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        assert_eq!(reader.detected_format(), Some(CompressionFormat::Xz));
+
+        let plain = archive_file.with_file_name("plain.tar.xz");
+        let mut builder = tar::Builder::new(File::create(&plain).unwrap());
+        builder.append_data(&mut raw_header(4), "plain.txt", b"data".as_slice()).unwrap();
+        builder.into_inner().unwrap().sync_all().unwrap();
+        reader.set_archive(&plain).unwrap();
+        assert_eq!(reader.detected_format(), Some(CompressionFormat::None));
+        assert_eq!(reader.entries().unwrap(), vec!["plain.txt"]);
+
+        let bzip2 = archive_file.with_file_name("test.tar.bz2");
+        fs::write(&bzip2, b"BZh91AY&SY").unwrap();
+        reader.set_archive(&bzip2).unwrap();
+        assert_eq!(reader.detected_format(), Some(CompressionFormat::Bzip2));
+        assert!(reader.entries().unwrap_err().to_string().contains("bzip2"));
+
+        let unknown = archive_file.with_file_name("unknown.bin");
+        fs::write(&unknown, b"not an archive").unwrap();
+        reader.set_archive(&unknown).unwrap();
+        assert_eq!(reader.detected_format(), None);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_decompress_async() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct ThreadWaker(std::thread::Thread);
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+            let mut context = Context::from_waker(&waker);
+            let mut future = pin!(future);
+            loop {
+                match future.as_mut().poll(&mut context) {
+                    Poll::Ready(output) => return output,
+                    Poll::Pending => std::thread::park(),
+                }
+            }
+        }
+
+        let archive_file = create_archive_in_temp_dir("decompress_async").unwrap();
+        let output = archive_file.with_file_name("output");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+        let (decompression, progress) = reader.decompress_async();
+        let progress = std::thread::spawn(move || {
+            let mut progress = pin!(progress);
+            let mut updates = Vec::new();
+            while let Some(update) = block_on(std::future::poll_fn(|cx| futures_core::Stream::poll_next(progress.as_mut(), cx))) {
+                updates.push(update);
+            }
+            updates
+        });
+        let result = block_on(decompression).unwrap();
+        assert_eq!(result.files, vec!["hello.txt"]);
+        assert_eq!(fs::read(output.join("hello.txt")).unwrap(), b"Hello, world!");
+        assert!(!progress.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_summary() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-summary");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("summary.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut directory = raw_header(0);
+            directory.set_entry_type(tar::EntryType::Directory);
+            builder.append_data(&mut directory, "a/b/", std::io::empty())?;
+            for (name, size) in [("a/b/c/deep.bin", 10), ("top.bin", 5), ("a/b/c/other.bin", 7), ("top.bin", 3)] {
+                builder.append_data(&mut raw_header(size as u64), name, vec![0u8; size].as_slice())?;
+            }
+            let mut link = raw_header(0);
+            link.set_entry_type(tar::EntryType::Symlink);
+            builder.append_link(&mut link, "a/link", "top.bin")?;
+            Ok(())
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let summary = reader.summary().unwrap();
+        assert_eq!(summary.entry_count, 5);
+        assert_eq!(summary.total_size, 20);
+        assert_eq!(
+            summary.counts_by_type,
+            HashMap::from([(EntryType::Directory, 1), (EntryType::File, 3), (EntryType::Symlink, 1)])
+        );
+        assert_eq!(summary.deepest_path.as_deref(), Some("a/b/c/deep.bin"));
+        assert_eq!(summary.max_depth, 4);
+
+        reader.set_entry_types(&[EntryType::Symlink]);
+        let summary = reader.summary().unwrap();
+        assert_eq!((summary.entry_count, summary.total_size, summary.max_depth), (1, 0, 2));
+
+        // The globs filter the summary like the listing
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_include_globs(&["top.bin"]);
+        let summary = reader.summary().unwrap();
+        assert_eq!((summary.entry_count, summary.total_size, summary.max_depth), (1, 3, 1));
+        assert_eq!(summary.entry_count, reader.entries_metadata().unwrap().len());
+    }
+
+    #[test]
+    fn test_compare_with_dir() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-compare");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("release.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            for (name, content) in [("same.txt", "same"), ("gone.txt", "gone"), ("resized.txt", "short"), ("edited.txt", "abcd"), ("nested/dir.txt", "file")] {
+                builder.append_data(&mut raw_header(content.len() as u64), name, content.as_bytes())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let output = dir.join("deployment");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+        reader.decompress().unwrap();
+        assert_eq!(reader.compare_with_dir(&output).unwrap(), vec![]);
+
+        fs::remove_file(output.join("gone.txt")).unwrap();
+        fs::write(output.join("resized.txt"), "much longer").unwrap();
+        fs::write(output.join("edited.txt"), "abce").unwrap();
+        fs::remove_file(output.join("nested/dir.txt")).unwrap();
+        fs::create_dir(output.join("nested/dir.txt")).unwrap();
+        fs::write(output.join("nested/extra.txt"), "extra").unwrap();
+        let differences = reader.compare_with_dir(&output).unwrap();
+        assert_eq!(
+            differences,
+            vec![
+                Difference::HashDiffers { path: "edited.txt".to_string() },
+                Difference::Missing { path: "gone.txt".to_string() },
+                Difference::NotAFile { path: "nested/dir.txt".to_string() },
+                Difference::SizeDiffers { path: "resized.txt".to_string(), archived: 5, on_disk: 11 },
+                Difference::Extra { path: "nested/extra.txt".to_string() },
+            ]
+        );
+        assert_eq!(differences[4].path(), "nested/extra.txt");
+    }
+
+    #[test]
+    fn test_decompress_changed_only() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-changed-only");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("release.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            for (name, content) in [("same.txt", "same"), ("gone.txt", "gone"), ("resized.txt", "short"), ("touched.txt", "abcd")] {
+                builder.append_data(&mut raw_header(content.len() as u64), name, content.as_bytes())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let output = dir.join("deployment");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+        reader.decompress().unwrap();
+
+        fs::remove_file(output.join("gone.txt")).unwrap();
+        fs::write(output.join("resized.txt"), "much longer").unwrap();
+        // Same size and modification time, only a hash comparison notices the change
+        fs::write(output.join("touched.txt"), "abce").unwrap();
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        File::options().write(true).open(output.join("touched.txt")).unwrap().set_modified(mtime).unwrap();
+
+        let mut written = reader.decompress_changed_only(&output).unwrap().files;
+        written.sort();
+        assert_eq!(written, vec!["gone.txt", "resized.txt"]);
+        assert_eq!(fs::read_to_string(output.join("resized.txt")).unwrap(), "short");
+        assert_eq!(fs::read_to_string(output.join("touched.txt")).unwrap(), "abce");
+
+        reader.set_change_detection(ChangeDetection::Hash);
+        assert_eq!(reader.decompress_changed_only(&output).unwrap().files, vec!["touched.txt"]);
+        assert_eq!(fs::read_to_string(output.join("touched.txt")).unwrap(), "abcd");
+        assert!(reader.decompress_changed_only(&output).unwrap().files.is_empty());
+    }
+
+    #[test]
+    fn test_link_transform() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-link-transform");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("image.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_data(&mut raw_header(3), "opt/app/lib/lib.so", "lib".as_bytes())?;
+            for (name, target) in [("opt/app/current", "/opt/app/lib"), ("opt/app/relative", "lib/lib.so")] {
+                let mut link = raw_header(0);
+                link.set_entry_type(tar::EntryType::Symlink);
+                builder.append_link(&mut link, name, target)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        for sandbox in [false, true] {
+            let output = dir.join(format!("output-{}", sandbox));
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+            reader.set_sandbox(sandbox && cfg!(target_os = "linux"));
+            reader.set_link_transform(|target| match target.strip_prefix("/opt/app") {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => target.to_path_buf(),
+            });
+            reader.decompress().unwrap();
+            assert_eq!(fs::read_link(output.join("opt/app/current")).unwrap(), Path::new("lib"));
+            assert_eq!(fs::read_link(output.join("opt/app/relative")).unwrap(), Path::new("lib/lib.so"));
+            assert_eq!(fs::read_to_string(output.join("opt/app/current/lib.so")).unwrap(), "lib");
+        }
+    }
+
+    #[test]
+    fn test_copy_entries_to() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-copy-entries");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.tar.xz");
+        let long_name = format!("{}/long.txt", "d".repeat(150));
+        write_raw_archive(&source, |builder| {
+            let records = pax_record("mtime", "1700000000.5");
+            let mut pax = raw_header(records.len() as u64);
+            pax.set_entry_type(tar::EntryType::XHeader);
+            builder.append_data(&mut pax, "PaxHeaders/keep.txt", records.as_bytes())?;
+            builder.append_data(&mut raw_header(4), "keep.txt", "keep".as_bytes())?;
+            builder.append_data(&mut raw_header(4), "drop.txt", "drop".as_bytes())?;
+            builder.append_data(&mut raw_header(4), &long_name, "long".as_bytes())?;
+            let mut link = raw_header(0);
+            link.set_entry_type(tar::EntryType::Symlink);
+            builder.append_link(&mut link, "link", "keep.txt")?;
+            // A format 0.0 sparse map repeats its keys, so their order must be kept
+            let records: String = [("GNU.sparse.size", "20"), ("GNU.sparse.offset", "0"), ("GNU.sparse.numbytes", "2"), ("GNU.sparse.offset", "18"), ("GNU.sparse.numbytes", "2")]
+                .iter()
+                .map(|(key, value)| pax_record(key, value))
+                .collect();
+            let mut pax = raw_header(records.len() as u64);
+            pax.set_entry_type(tar::EntryType::XHeader);
+            builder.append_data(&mut pax, "PaxHeaders/sparse.bin", records.as_bytes())?;
+            builder.append_data(&mut raw_header(4), "sparse.bin", "abcd".as_bytes())
+        })
+        .unwrap();
+
+        let copy = dir.join("copy.tar.xz");
+        let mut builder = tar::Builder::new(xz2::write::XzEncoder::new(File::create(&copy).unwrap(), 1));
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&source).unwrap();
+        assert_eq!(reader.copy_entries_to(&mut builder, |entry| entry.path != "drop.txt").unwrap(), 4);
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&copy).unwrap().set_output_directory(dir.join("output")).unwrap();
+        assert_eq!(reader.entries().unwrap(), vec!["keep.txt", long_name.as_str(), "link", "sparse.bin"]);
+        reader.decompress().unwrap();
+        let output = dir.join("output");
+        assert_eq!(fs::read_to_string(output.join(&long_name)).unwrap(), "long");
+        assert_eq!(fs::read_link(output.join("link")).unwrap(), Path::new("keep.txt"));
+        assert_eq!(fs::read(output.join("sparse.bin")).unwrap(), [b"ab".as_slice(), &[0; 16], b"cd"].concat());
+        let mtime = fs::metadata(output.join("keep.txt")).unwrap().modified().unwrap();
+        assert_eq!(mtime.duration_since(std::time::UNIX_EPOCH).unwrap(), std::time::Duration::new(1_700_000_000, 500_000_000));
+    }
+
+    #[test]
+    fn test_decompress_to_memory() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-memory");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("archive.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            for (name, content) in [("config.toml", "old"), ("docs/readme.md", "# Readme"), ("config.toml", "new")] {
+                builder.append_data(&mut raw_header(content.len() as u64), name, content.as_bytes())?;
+            }
+            let mut header = raw_header(0);
+            header.set_entry_type(tar::EntryType::Directory);
+            builder.append_data(&mut header, "docs/", std::io::empty())?;
+            Ok(())
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let files = reader.decompress_to_memory(None).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[Path::new("config.toml")], b"new");
+        assert_eq!(files[Path::new("docs/readme.md")], b"# Readme");
+        assert!(reader.decompress_to_memory(Some(11)).is_ok());
+        assert!(reader.decompress_to_memory(Some(10)).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_search_contents() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-search");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("backup.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            for (name, content) in [
+                ("logs/app.log", "started\r\nERROR: disk full\nstopped\nerror: retry"),
+                ("data.bin", "\0\0ERROR inside binary"),
+                ("notes.txt", "nothing to see"),
+            ] {
+                builder.append_data(&mut raw_header(content.len() as u64), name, content.as_bytes())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let hits = reader.search_contents("ERROR", &SearchOptions::default()).unwrap();
+        assert_eq!(hits, vec![SearchHit { path: "logs/app.log".to_string(), line_number: 2, line: "ERROR: disk full".to_string() }]);
+
+        let options = SearchOptions { ignore_case: true, include_binary: true, ..Default::default() };
+        let hits: Vec<(String, usize)> = reader.search_contents("error", &options).unwrap().into_iter().map(|hit| (hit.path, hit.line_number)).collect();
+        assert_eq!(hits, vec![("logs/app.log".to_string(), 2), ("logs/app.log".to_string(), 4), ("data.bin".to_string(), 1)]);
+
+        let options = SearchOptions { ignore_case: true, max_hits: Some(1), ..Default::default() };
+        assert_eq!(reader.search_contents("error", &options).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_stats_by_extension() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-extension-stats");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("stats.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            for (name, size) in [("a.jpg", 10), ("photos/b.JPG", 20), ("c.txt", 3), ("Makefile", 7), ("photos.d/.hidden", 1)] {
+                builder.append_data(&mut raw_header(size as u64), name, vec![0u8; size].as_slice())?;
+            }
+            let mut directory = raw_header(0);
+            directory.set_entry_type(tar::EntryType::Directory);
+            builder.append_data(&mut directory, "photos.d/", std::io::empty())?;
+            Ok(())
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let stats: Vec<(String, usize, u64)> = reader
+            .stats_by_extension()
+            .unwrap()
+            .into_iter()
+            .map(|(extension, stats)| (extension, stats.count, stats.total_size))
+            .collect();
+        assert_eq!(stats, vec![("".to_string(), 2, 8), ("jpg".to_string(), 2, 30), ("txt".to_string(), 1, 3)]);
+    }
+
+    #[test]
+    fn test_windows_name_policy() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-windows-names");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("names.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_data(&mut raw_header(2), "ok.txt", "ok".as_bytes())?;
+            builder.append_data(&mut raw_header(3), "a:b?.txt", "abc".as_bytes())?;
+            builder.append_data(&mut raw_header(3), "con.txt", "con".as_bytes())?;
+            builder.append_data(&mut raw_header(4), "dir./file", "file".as_bytes())?;
+            Ok(())
+        })
+        .unwrap();
+
+        let output = dir.join("sanitize");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+        reader.set_windows_name_policy(WindowsNamePolicy::Sanitize);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, ["ok.txt", "a%3Ab%3F.txt", "con_.txt", "dir%2E/file"]);
+        assert_eq!(fs::read_to_string(output.join("a%3Ab%3F.txt")).unwrap(), "abc");
+        assert_eq!(fs::read_to_string(output.join("con_.txt")).unwrap(), "con");
+        assert_eq!(fs::read_to_string(output.join("dir%2E/file")).unwrap(), "file");
+
+        let output = dir.join("skip");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+        reader.set_windows_name_policy(WindowsNamePolicy::Skip);
+        assert_eq!(reader.decompress().unwrap().files, ["ok.txt"]);
+        assert!(!output.join("con.txt").exists());
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(dir.join("error")).unwrap();
+        reader.set_windows_name_policy(WindowsNamePolicy::Error);
+        let error = reader.decompress().unwrap_err().to_string();
+        assert!(error.contains("a:b?.txt"), "{}", error);
+
+        // Names that only differ before sanitizing are duplicates
+        let archive_file = dir.join("duplicates.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_data(&mut raw_header(5), "a:b", "first".as_bytes())?;
+            builder.append_data(&mut raw_header(6), "a%3Ab", "second".as_bytes())
+        })
+        .unwrap();
+        let output = dir.join("duplicates");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+        reader.set_windows_name_policy(WindowsNamePolicy::Sanitize);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, ["a%3Ab"]);
+        assert_eq!(result.shadowed, vec![ShadowedEntry { path: "a%3Ab".to_string(), index: 0 }]);
+        assert_eq!(fs::read_to_string(output.join("a%3Ab")).unwrap(), "second");
+
+        reader.set_output_directory(dir.join("duplicates-error")).unwrap();
+        reader.set_duplicate_path_policy(DuplicatePathPolicy::Error);
+        assert!(reader.decompress().is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_permission_mapper() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-permission-mapper");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("modes.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut directory = raw_header(0);
+            directory.set_entry_type(tar::EntryType::Directory);
+            directory.set_mode(0o700);
+            builder.append_data(&mut directory, "bin", std::io::empty())?;
+            let mut tool = raw_header(4);
+            tool.set_mode(0o4755);
+            builder.append_data(&mut tool, "bin/tool", "tool".as_bytes())?;
+            let mut secret = raw_header(6);
+            secret.set_mode(0o600);
+            builder.append_data(&mut secret, "secret", "secret".as_bytes())?;
+            Ok(())
+        })
+        .unwrap();
+
+        for sandbox in [false, true] {
+            let output = dir.join(format!("output-{}", sandbox));
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+            reader.set_sandbox(sandbox && cfg!(target_os = "linux"));
+            reader.set_permission_mapper(|mode, kind| match kind {
+                EntryType::Directory => mode | 0o050,
+                _ => (mode & !0o6000) | 0o040,
+            });
+            reader.decompress().unwrap();
+            let mode = |path: &str| fs::metadata(output.join(path)).unwrap().permissions().mode() & 0o7777;
+            assert_eq!(mode("bin"), 0o750);
+            assert_eq!(mode("bin/tool"), 0o755);
+            assert_eq!(mode("secret"), 0o640);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_strip_special_mode_bits() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-strip-special-bits");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("special.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut directory = raw_header(0);
+            directory.set_entry_type(tar::EntryType::Directory);
+            directory.set_mode(0o1755);
+            builder.append_data(&mut directory, "shared", std::io::empty())?;
+            let mut tool = raw_header(4);
+            tool.set_mode(0o6755);
+            builder.append_data(&mut tool, "shared/tool", "tool".as_bytes())?;
+            Ok(())
+        })
+        .unwrap();
+
+        for (sandbox, mapper) in [(false, false), (true, false), (false, true)] {
+            let output = dir.join(format!("output-{}-{}", sandbox, mapper));
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+            reader.set_sandbox(sandbox && cfg!(target_os = "linux"));
+            reader.set_strip_special_mode_bits(true);
+            if mapper {
+                reader.set_permission_mapper(|mode, _| mode);
+            }
+            reader.decompress().unwrap();
+            let mode = |path: &str| fs::metadata(output.join(path)).unwrap().permissions().mode() & 0o7777;
+            assert_eq!(mode("shared"), 0o755);
+            assert_eq!(mode("shared/tool"), 0o755);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_hardened_links() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-hardened-links");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // Entries with a target are symlinks, names ending with a slash directories and the rest files
+        let extract = |name: &str, entries: &[(&str, Option<&str>)]| {
+            let archive_file = dir.join(format!("{}.tar.xz", name));
+            write_raw_archive(&archive_file, |builder| {
+                for (path, target) in entries {
+                    match target {
+                        Some(target) => {
+                            let mut link = raw_header(0);
+                            link.set_entry_type(tar::EntryType::Symlink);
+                            builder.append_link(&mut link, path, target)?;
+                        }
+                        None if path.ends_with('/') => {
+                            let mut directory = raw_header(0);
+                            directory.set_entry_type(tar::EntryType::Directory);
+                            builder.append_data(&mut directory, path, std::io::empty())?;
+                        }
+                        None => builder.append_data(&mut raw_header(4), path, "data".as_bytes())?,
+                    }
+                }
+                Ok(())
+            })
+            .unwrap();
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap().set_output_directory(dir.join(name)).unwrap();
+            reader.set_hardened_links(true);
+            reader.decompress()
+        };
+
+        let safe = extract("safe", &[("usr/lib/", None), ("usr/lib/keep", None), ("lib", Some("usr/lib")), ("usr/current", Some("../lib")), ("lib/data.txt", None)]);
+        assert!(safe.is_ok(), "{:?}", safe);
+        assert_eq!(fs::read_to_string(dir.join("safe/usr/lib/data.txt")).unwrap(), "data");
+
+        // "x" stays inside until "d" becomes a link, after which it points above the output directory
+        let chained = extract("chained", &[("x", Some("d/e/../..")), ("d", Some(".")), ("x/escape.txt", None)]);
+        let error = chained.unwrap_err().to_string();
+        assert!(error.contains("x/escape.txt"), "{}", error);
+        assert!(!dir.join("escape.txt").exists());
+
+        let pointing_out = extract("pointing-out", &[("up", Some("..")), ("out", Some("up/.."))]);
+        assert!(pointing_out.unwrap_err().to_string().contains("\"up\""));
+        let absolute = extract("absolute", &[("etc", Some("/etc"))]);
+        assert!(absolute.unwrap_err().to_string().contains("\"/etc\""));
+    }
+
+    #[test]
+    fn test_skipped_entries() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-skipped");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("skipped.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_data(&mut raw_header(5), "data.txt", &b"first"[..])?;
+            builder.append_data(&mut raw_header(6), "data.txt", &b"second"[..])?;
+            let mut hard_link = raw_header(0);
+            hard_link.set_entry_type(tar::EntryType::Link);
+            builder.append_link(&mut hard_link, "hard.txt", "data.txt")?;
+            let mut fifo = raw_header(0);
+            fifo.set_entry_type(tar::EntryType::Fifo);
+            builder.append_data(&mut fifo, "pipe", std::io::empty())
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(dir.join("output")).unwrap();
+        reader.set_entry_types(&[EntryType::File, EntryType::Hardlink]);
+        reader.set_duplicate_path_policy(DuplicatePathPolicy::FirstWins);
+        reader.set_hardlink_policy(HardlinkPolicy::Skip);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, ["data.txt"]);
+        assert_eq!(
+            result.skipped,
+            [
+                SkippedEntry { path: "data.txt".to_string(), reason: SkipReason::Conflict },
+                SkippedEntry { path: "hard.txt".to_string(), reason: SkipReason::HardLink },
+                SkippedEntry { path: "pipe".to_string(), reason: SkipReason::Filtered },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_listing() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-format-listing");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("listing.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut directory = raw_header(0);
+            directory.set_entry_type(tar::EntryType::Directory);
+            directory.set_mode(0o755);
+            directory.set_username("drew")?;
+            directory.set_groupname("staff")?;
+            builder.append_data(&mut directory, "bin/", std::io::empty())?;
+            let mut tool = raw_header(4);
+            tool.set_mode(0o4755);
+            builder.append_data(&mut tool, "bin/tool", "tool".as_bytes())?;
+            let mut link = raw_header(0);
+            link.set_entry_type(tar::EntryType::Symlink);
+            link.set_mode(0o777);
+            builder.append_link(&mut link, "tool", "bin/tool")
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        assert_eq!(reader.format_listing(ListingStyle::Short).unwrap(), "bin/\nbin/tool\ntool\n");
+        assert_eq!(
+            reader.format_listing(ListingStyle::Long).unwrap(),
+            concat!(
+                "drwxr-xr-x drew/staff        0 2023-11-14 22:13 bin/\n",
+                "-rwsr-xr-x 0/0               4 2023-11-14 22:13 bin/tool\n",
+                "lrwxrwxrwx 0/0               0 2023-11-14 22:13 tool -> bin/tool\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_indexed_offsets() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-indexed-offsets");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("first.tar.xz");
+        let second = dir.join("second.tar.xz");
+        write_raw_archive(&first, |builder| builder.append_data(&mut raw_header(5), "hello.txt", "hello".as_bytes())).unwrap();
+        write_raw_archive(&second, |builder| builder.append_data(&mut raw_header(6), "second.txt", "second".as_bytes())).unwrap();
+        let first_stream = fs::read(&first).unwrap();
+        let archive_file = dir.join("indexed.tar.xz");
+        fs::write(&archive_file, [first_stream.clone(), fs::read(&second).unwrap()].concat()).unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        assert!(reader.entries_metadata().unwrap().iter().all(|entry| entry.offsets.is_none()));
+
+        let offsets: Vec<EntryOffsets> = reader.set_indexed(true).entries_metadata().unwrap().into_iter().map(|entry| entry.offsets.unwrap()).collect();
+        assert_eq!(
+            offsets,
+            [
+                EntryOffsets { header_offset: 0, data_offset: 512, compressed_offset: Some(12), block_offset: Some(0) },
+                // The first stream holds a header, a data block and two end-of-archive blocks
+                EntryOffsets {
+                    header_offset: 2048,
+                    data_offset: 2560,
+                    compressed_offset: Some(first_stream.len() as u64 + 12),
+                    block_offset: Some(2048),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remote_archive() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-remote-archive");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // Incompressible data spanning several blocks
+        let mut state = 1u32;
+        let big: Vec<u8> = (0..512 * 1024)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 24) as u8
+            })
+            .collect();
+        fs::write(dir.join("big.bin"), &big).unwrap();
+        fs::write(dir.join("small.txt"), b"small file").unwrap();
+        let archive_file = dir.join("seekable.tar.xz");
+        lzma_tarball::writer::LZMATarballWriter::new()
+            .with_file(dir.join("big.bin"), "/big.bin")
+            .with_file(dir.join("small.txt"), "/small.txt")
+            .set_seekable_block_size(Some(64 * 1024))
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        // Listed from the entry index, which doesn't list itself
+        assert_eq!(reader.entries().unwrap(), ["big.bin", "small.txt"]);
+        assert_eq!(reader.entries_metadata().unwrap().last().unwrap().path, ENTRY_INDEX_FILE_NAME);
+        assert!(reader.contains("/small.txt").unwrap());
+        assert!(!reader.contains("missing.txt").unwrap());
+
+        let archive = fs::read(&archive_file).unwrap();
+        let archive_size = archive.len();
+        let served = Arc::new(AtomicU64::new(0));
+        let url = serve_ranges(archive, served.clone());
+        let remote = RemoteArchive::open(&url).unwrap();
+        let paths: Vec<&str> = remote.entries().iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["big.bin", "small.txt"]);
+
+        served.store(0, Ordering::Relaxed);
+        assert_eq!(remote.read_file("small.txt").unwrap(), b"small file");
+        assert!(served.load(Ordering::Relaxed) < archive_size as u64 / 4);
+        remote.extract_file("big.bin", dir.join("output/big.bin")).unwrap();
+        assert_eq!(fs::read(dir.join("output/big.bin")).unwrap(), big);
+        assert!(remote.read_file("missing.txt").is_err());
+    }
+
+    #[test]
+    fn test_entry_type_filter() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-entry-types");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("types.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut directory = raw_header(0);
+            directory.set_entry_type(tar::EntryType::Directory);
+            builder.append_data(&mut directory, "nested/", &b""[..])?;
+            builder.append_data(&mut raw_header(5), "nested/data.txt", &b"hello"[..])?;
+            let mut link = raw_header(0);
+            link.set_entry_type(tar::EntryType::Symlink);
+            builder.append_link(&mut link, "link.txt", "nested/data.txt")
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_entry_types(&[EntryType::File]);
+        assert_eq!(reader.entries().unwrap(), vec!["nested/data.txt"]);
+        reader.set_output_directory(dir.join("output")).unwrap();
+        assert_eq!(reader.decompress().unwrap().files, vec!["nested/data.txt"]);
+        assert!(dir.join("output/nested/data.txt").is_file());
+        assert!(dir.join("output/link.txt").symlink_metadata().is_err());
+
+        reader.set_entry_types(&[EntryType::Directory, EntryType::Symlink]);
+        assert_eq!(reader.entries().unwrap(), vec!["nested/", "link.txt"]);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-flatten");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("nested.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_data(&mut raw_header(5), "a/deep/readme.txt", &b"first"[..])?;
+            builder.append_data(&mut raw_header(6), "b/readme.txt", &b"second"[..])?;
+            builder.append_data(&mut raw_header(5), "c/other.txt", &b"other"[..])
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_flatten(true);
+        reader.set_output_directory(dir.join("error")).unwrap();
+        assert!(reader.decompress().is_err());
+
+        reader.set_output_directory(dir.join("rename")).unwrap();
+        reader.set_flatten_conflict_policy(FlattenConflictPolicy::Rename);
+        assert_eq!(reader.decompress().unwrap().files, vec!["readme.txt", "readme (1).txt", "other.txt"]);
+        assert_eq!(fs::read_to_string(dir.join("rename/readme (1).txt")).unwrap(), "second");
+        assert!(!dir.join("rename/a").exists());
+
+        reader.set_output_directory(dir.join("last-wins")).unwrap();
+        reader.set_flatten_conflict_policy(FlattenConflictPolicy::LastWins);
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("last-wins/readme.txt")).unwrap(), "second");
+
+        reader.set_output_directory(dir.join("skip")).unwrap();
+        reader.set_flatten_conflict_policy(FlattenConflictPolicy::Skip);
+        assert_eq!(reader.decompress().unwrap().files, vec!["readme.txt", "other.txt"]);
+        assert_eq!(fs::read_to_string(dir.join("skip/readme.txt")).unwrap(), "first");
+    }
+
+    #[test]
+    fn test_case_probe_leaves_files_alone() {
+        let archive_file = create_archive_in_temp_dir("case-probe").unwrap();
+        let output = archive_file.with_file_name("output");
+        fs::create_dir_all(&output).unwrap();
+        fs::write(output.join(".lzma_tarball_case_probe"), "user data").unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(output.join(".lzma_tarball_case_probe")).unwrap(), "user data");
+        let mut names: Vec<String> = fs::read_dir(&output).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        assert_eq!(names, [".lzma_tarball_case_probe", "hello.txt"]);
+    }
+
+    #[test]
+    fn test_duplicate_path_policy() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-duplicates");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("duplicates.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_data(&mut raw_header(5), "readme.txt", &b"first"[..])?;
+            builder.append_data(&mut raw_header(5), "other.txt", &b"other"[..])?;
+            builder.append_data(&mut raw_header(6), "readme.txt", &b"second"[..])
+        })
+        .unwrap();
+        let shadowed = |index| vec![ShadowedEntry { path: "readme.txt".to_string(), index }];
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("last-wins")).unwrap();
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, vec!["other.txt", "readme.txt"]);
+        assert_eq!(result.shadowed, shadowed(0));
+        assert_eq!(fs::read_to_string(dir.join("last-wins/readme.txt")).unwrap(), "second");
+
+        reader.set_output_directory(dir.join("first-wins")).unwrap();
+        reader.set_duplicate_path_policy(DuplicatePathPolicy::FirstWins);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, vec!["readme.txt", "other.txt"]);
+        assert_eq!(result.shadowed, shadowed(2));
+        assert_eq!(fs::read_to_string(dir.join("first-wins/readme.txt")).unwrap(), "first");
+
+        reader.set_output_directory(dir.join("rename")).unwrap();
+        reader.set_duplicate_path_policy(DuplicatePathPolicy::KeepAllRenamed);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, vec!["readme.txt", "other.txt", "readme (1).txt"]);
+        assert!(result.shadowed.is_empty());
+        assert_eq!(fs::read_to_string(dir.join("rename/readme.txt")).unwrap(), "first");
+        assert_eq!(fs::read_to_string(dir.join("rename/readme (1).txt")).unwrap(), "second");
+
+        reader.set_output_directory(dir.join("error")).unwrap();
+        reader.set_duplicate_path_policy(DuplicatePathPolicy::Error);
+        assert!(reader.decompress().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dir_creation_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-dir-mode");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("nested.tar.xz");
+        write_raw_archive(&archive_file, |builder| builder.append_data(&mut raw_header(5), "a/b/data.txt", &b"hello"[..])).unwrap();
+
+        let mode = |path: PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        for sandbox in [false, true] {
+            let output = dir.join(format!("output-{}", sandbox));
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap();
+            reader.set_dir_creation_mode(0o750);
+            reader.set_sandbox(sandbox && cfg!(target_os = "linux"));
+            reader.set_output_directory(&output).unwrap();
+            reader.decompress().unwrap();
+            assert_eq!(mode(output.clone()), 0o750);
+            assert_eq!(mode(output.join("a")), 0o750);
+            assert_eq!(mode(output.join("a/b")), 0o750);
+        }
+    }
+
+    #[test]
+    fn test_typed_errors_for_corrupt_archives() {
+        let archive_file = create_archive_in_temp_dir("corrupt").unwrap();
+        let bytes = fs::read(&archive_file).unwrap();
+
+        let truncated = archive_file.with_file_name("truncated.tar.xz");
+        fs::write(&truncated, &bytes[..bytes.len() / 2]).unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&truncated).unwrap();
+        let error = reader.entries().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ReaderError>(),
+            Some(ReaderError::TruncatedArchive { .. })
+        ));
+
+        let mut corrupted = bytes.clone();
+        let middle = corrupted.len() / 2;
+        corrupted[middle] ^= 0xff;
+        let corrupt = archive_file.with_file_name("corrupt.tar.xz");
+        fs::write(&corrupt, &corrupted).unwrap();
+        reader.set_archive(&corrupt).unwrap();
+        let error = reader.entries().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ReaderError>(),
+            Some(ReaderError::BadXzCheck { .. })
+        ));
+    }
+
+    #[test]
+    fn test_hardlink_policy() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-hardlink");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("links.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut header = raw_header(5);
+            builder.append_data(&mut header, "data.txt", &b"hello"[..])?;
+            let mut link = raw_header(0);
+            link.set_entry_type(tar::EntryType::Link);
+            builder.append_link(&mut link, "link.txt", "data.txt")
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("copy")).unwrap();
+        reader.set_hardlink_policy(HardlinkPolicy::CopyContent);
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("copy/link.txt")).unwrap(), "hello");
+
+        reader.set_output_directory(dir.join("skip")).unwrap();
+        reader.set_hardlink_policy(HardlinkPolicy::Skip);
+        let result = reader.decompress().unwrap();
+        assert!(!dir.join("skip/link.txt").exists());
+        assert_eq!(result.files, vec!["data.txt"]);
+    }
+
+    #[test]
+    fn test_unicode_normalization() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-normalization");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("nfd.tar.xz");
+        // "café.txt" with a decomposed "é" as written by macOS
+        let nfd_name = "cafe\u{301}.txt";
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_data(&mut raw_header(5), nfd_name, &b"hello"[..])?;
+            // The same name composed, so both entries are written to the same file
+            builder.append_data(&mut raw_header(7), "caf\u{e9}.txt", &b"goodbye"[..])
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.set_unicode_normalization(UnicodeNormalization::Nfc);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.shadowed, vec![ShadowedEntry { path: "caf\u{e9}.txt".to_string(), index: 0 }]);
+        assert_eq!(fs::read_to_string(dir.join("output/caf\u{e9}.txt")).unwrap(), "goodbye");
+        assert!(!dir.join("output").join(nfd_name).exists());
+    }
+
+    #[test]
+    fn test_decompress_with_callback() {
+        let archive_file = create_archive_in_temp_dir("callback").unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(archive_file.with_file_name("output")).unwrap();
+        let updates = std::sync::Mutex::new(Vec::new());
+        reader
+            .decompress_with_callback(|progress| updates.lock().unwrap().push(progress))
+            .unwrap();
+        let updates = updates.into_inner().unwrap();
+        let phases: Vec<Phase> = updates.iter().map(|progress| progress.phase).collect();
+        assert_eq!(phases, [Phase::Decoding, Phase::Unpacking, Phase::ApplyingMetadata]);
+        let last = updates.last().unwrap();
+        assert!(last.bytes_processed > 0);
+        assert_eq!(last.total_bytes, fs::metadata(&archive_file).unwrap().len());
+    }
+
+    #[test]
+    fn test_extract_nested() {
+        let inner = create_archive_in_temp_dir("nested").unwrap();
+        let outer = inner.with_file_name("outer.tar.xz");
+        lzma_tarball::writer::LZMATarballWriter::new()
+            .set_compression_level(1)
+            .with_file(&inner, "/bundle/inner.tar.xz")
+            .set_output(&outer)
+            .compress(|_| {})
+            .unwrap();
+
+        let output = inner.with_file_name("output");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&outer).unwrap();
+        reader.set_output_directory(&output).unwrap();
+        reader.set_extract_nested(1);
+        let result = reader.decompress().unwrap();
+        assert!(result.files.contains(&"bundle/inner/hello.txt".to_string()));
+        assert_eq!(fs::read_to_string(output.join("bundle/inner/hello.txt")).unwrap(), "Hello, world!");
+
+        reader.set_overwrite(true);
+        reader.decompress().unwrap();
+        reader.set_max_unpacked_size(Some(fs::metadata(&inner).unwrap().len()));
+        let error = reader.decompress().unwrap_err();
+        assert!(format!("{:#}", error).contains("maximum unpacked size"));
+    }
+
+    #[test]
+    fn test_extract_nested_collision() {
+        let inner = create_archive_in_temp_dir("nested-collision").unwrap();
+        let outer = inner.with_file_name("outer.tar.xz");
+        lzma_tarball::writer::LZMATarballWriter::new()
+            .set_compression_level(1)
+            .with_file(&inner, "/inner.tar.xz")
+            .with_file(inner.with_file_name("hello.txt"), "/inner/readme.txt")
+            .set_output(&outer)
+            .compress(|_| {})
+            .unwrap();
+
+        // The outer archive has entries in the directory the nested one would be extracted into
+        let output = inner.with_file_name("entries");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&outer).unwrap().set_output_directory(&output).unwrap();
+        reader.set_extract_nested(1);
+        let error = reader.decompress().unwrap_err();
+        assert!(format!("{:#}", error).contains("holds other extracted entries"));
+        assert!(!output.join("inner/hello.txt").exists());
+
+        // A directory that was there before the extraction is only used when overwriting
+        let output = inner.with_file_name("existing");
+        fs::create_dir_all(output.join("inner")).unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&outer).unwrap().set_output_directory(&output).unwrap();
+        reader.set_extract_nested(1).set_include_globs(&["inner.tar.xz"]);
+        let error = reader.decompress().unwrap_err();
+        assert!(format!("{:#}", error).contains("already exists"));
+        reader.set_overwrite(true);
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(output.join("inner/hello.txt")).unwrap(), "Hello, world!");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_extract_nested_gzip() {
+        use lzma_tarball::format::CompressionFormat;
+
+        let archive = create_archive_in_temp_dir("nested-gzip").unwrap();
+        let inner = archive.with_file_name("inner.tar.gz");
+        lzma_tarball::writer::LZMATarballWriter::new()
+            .set_format(CompressionFormat::Gzip)
+            .with_file(archive.with_file_name("hello.txt"), "/hello.txt")
+            .set_output(&inner)
+            .compress(|_| {})
+            .unwrap();
+        let outer = archive.with_file_name("outer.tar.xz");
+        lzma_tarball::writer::LZMATarballWriter::new()
+            .set_compression_level(1)
+            .with_file(&inner, "/bundle/inner.tar.gz")
+            .set_output(&outer)
+            .compress(|_| {})
+            .unwrap();
+
+        let output = archive.with_file_name("output");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&outer).unwrap().set_output_directory(&output).unwrap();
+        reader.set_extract_nested(1);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, vec!["bundle/inner.tar.gz", "bundle/inner/hello.txt"]);
+        assert_eq!(fs::read_to_string(output.join("bundle/inner/hello.txt")).unwrap(), "Hello, world!");
+    }
+
+    #[test]
+    fn test_extract_nested_with_filters() {
+        let inner = create_archive_in_temp_dir("nested-filters").unwrap();
+        let outer = inner.with_file_name("outer.tar.xz");
+        lzma_tarball::writer::LZMATarballWriter::new()
+            .set_compression_level(1)
+            .with_file(&inner, "/inner.tar.xz")
+            .with_file(inner.with_file_name("hello.txt"), "/other.txt")
+            .set_output(&outer)
+            .compress(|_| {})
+            .unwrap();
+
+        let output = inner.with_file_name("globs");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&outer).unwrap().set_output_directory(&output).unwrap();
+        reader.set_extract_nested(1).set_include_globs(&["inner.tar.xz"]);
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, vec!["inner.tar.xz", "inner/hello.txt"]);
+        assert!(!output.join("other.txt").exists());
+
+        let list = inner.with_file_name("list.txt");
+        fs::write(&list, "inner.tar.xz\n").unwrap();
+        let output = inner.with_file_name("list");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&outer).unwrap().set_output_directory(&output).unwrap();
+        reader.set_extract_nested(1).set_extract_list_file(&list).unwrap();
+        let result = reader.decompress().unwrap();
+        assert_eq!(result.files, vec!["inner.tar.xz", "inner/hello.txt"]);
+    }
+
+    #[test]
+    fn test_recover_truncated_archive() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-recover");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("damaged.tar.xz");
+        // incompressible data so truncating the archive cuts into the second entry
+        let mut seed = 42u32;
+        let noise: Vec<u8> = (0..64 * 1024)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_data(&mut raw_header(5), "first.txt", &b"hello"[..])?;
+            builder.append_data(&mut raw_header(noise.len() as u64), "second.bin", noise.as_slice())
+        })
+        .unwrap();
+        let bytes = fs::read(&archive_file).unwrap();
+        fs::write(&archive_file, &bytes[..bytes.len() / 2]).unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("output")).unwrap();
+        let result = reader.recover().unwrap();
+        assert_eq!(result.recovered, vec!["first.txt"]);
+        assert_eq!(result.lost.len(), 1);
+        assert_eq!(result.lost[0].path, "second.bin");
+        assert_eq!(fs::read_to_string(dir.join("output/first.txt")).unwrap(), "hello");
+        assert!(!dir.join("output/second.bin").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_recover_links_and_oversized_entry() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-recover-links");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("damaged.tar.xz");
+        let link_header = |kind: tar::EntryType| {
+            let mut header = raw_header(0);
+            header.set_entry_type(kind);
+            header
+        };
+        write_raw_archive(&archive_file, |builder| {
+            builder.append_data(&mut raw_header(5), "first.txt", &b"hello"[..])?;
+            builder.append_link(&mut link_header(tar::EntryType::Symlink), "link.txt", "first.txt")?;
+            builder.append_link(&mut link_header(tar::EntryType::Link), "hard.txt", "first.txt")?;
+            builder.append_link(&mut link_header(tar::EntryType::Symlink), "escape", "../outside")?;
+            builder.append_link(&mut link_header(tar::EntryType::Link), "orphan.txt", "missing.txt")?;
+            // A size that passes the checksum but is far larger than the data, as a damaged header would have
+            builder.append_data(&mut raw_header(1 << 40), "huge.bin", &b"short"[..])
+        })
+        .unwrap();
+
+        let output = dir.join("output");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(&output).unwrap();
+        let result = reader.recover().unwrap();
+        assert_eq!(result.recovered, vec!["first.txt", "link.txt", "hard.txt"]);
+        let lost: Vec<(&str, &str)> = result.lost.iter().map(|entry| (entry.path.as_str(), entry.reason.as_str())).collect();
+        assert_eq!(
+            lost,
+            vec![
+                ("escape", "symlink points outside of the output directory"),
+                ("orphan.txt", "link target was not recovered"),
+                ("huge.bin", "archive ended before the entry data"),
+            ]
+        );
+        assert_eq!(fs::read_link(output.join("link.txt")).unwrap(), Path::new("first.txt"));
+        assert_eq!(fs::read_to_string(output.join("hard.txt")).unwrap(), "hello");
+        assert!(output.join("escape").symlink_metadata().is_err() && !output.join("huge.bin").exists());
+    }
+
+    #[test]
+    fn test_read_appended_streams() {
+        let archive_file = create_archive_in_temp_dir("appended").unwrap();
+        let dir = archive_file.parent().unwrap().to_path_buf();
+        fs::write(dir.join("hello.txt"), b"Hello again!").unwrap();
+        fs::write(dir.join("second.txt"), b"Second").unwrap();
+        lzma_tarball::writer::LZMATarballWriter::new()
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .with_file(dir.join("second.txt"), "/second.txt")
+            .append_stream(&archive_file, |_| {})
+            .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        assert_eq!(reader.entries().unwrap(), vec!["hello.txt", "second.txt"]);
+
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("output/hello.txt")).unwrap(), "Hello again!");
+        assert_eq!(fs::read_to_string(dir.join("output/second.txt")).unwrap(), "Second");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_owner_names() {
+        use lzma_tarball::owner::OwnerFallback;
+        use std::os::unix::fs::MetadataExt;
+        if fs::metadata("/proc/self").map(|m| m.uid()).unwrap_or(1) != 0 {
+            return;
+        }
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-owner");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("owners.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut known = raw_header(0);
+            known.set_uid(4321);
+            known.set_gid(4321);
+            known.set_username("root")?;
+            known.set_groupname("root")?;
+            builder.append_data(&mut known, "known.txt", &b""[..])?;
+            let mut unknown = raw_header(0);
+            unknown.set_uid(4321);
+            unknown.set_gid(4321);
+            unknown.set_username("lzma-tarball-missing-user")?;
+            unknown.set_groupname("lzma-tarball-missing-group")?;
+            builder.append_data(&mut unknown, "unknown.txt", &b""[..])
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.set_resolve_owner_names(true);
+        reader.decompress().unwrap();
+        assert_eq!(fs::metadata(dir.join("output/known.txt")).unwrap().uid(), 0);
+        assert_eq!(fs::metadata(dir.join("output/unknown.txt")).unwrap().uid(), 4321);
+
+        reader.set_output_directory(dir.join("strict")).unwrap();
+        reader.set_owner_fallback(OwnerFallback::Error);
+        assert!(reader.decompress().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ownership_fallback() {
+        use lzma_tarball::owner::OwnershipFallback;
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-ownership-fallback");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive_file = dir.join("owned.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut header = raw_header(5);
+            header.set_uid(4321);
+            header.set_gid(4321);
+            builder.append_data(&mut header, "owned.txt", &b"hello"[..])
+        })
+        .unwrap();
+
+        let extract = |fallback, output: &str| {
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap();
+            reader.set_output_directory(dir.join(output)).unwrap();
+            fs::set_permissions(dir.join(output), fs::Permissions::from_mode(0o777)).unwrap();
+            reader.set_ownership_fallback(fallback);
+            run_unprivileged(|| reader.decompress().is_ok())
+        };
+        assert!(!extract(OwnershipFallback::Error, "error"));
+        assert!(extract(OwnershipFallback::Warn, "warn"));
+        assert!(extract(OwnershipFallback::Ignore, "ignore"));
+        let extracted = fs::metadata(dir.join("ignore/owned.txt")).unwrap();
+        assert_ne!(extracted.uid(), 4321);
+        assert_eq!(fs::read_to_string(dir.join("ignore/owned.txt")).unwrap(), "hello");
+    }
+
+    /// Runs `work` in a child process without root privileges when the tests run as root.
+    #[cfg(unix)]
+    fn run_unprivileged(work: impl FnOnce() -> bool) -> bool {
+        // SAFETY: getuid has no preconditions.
+        if unsafe { libc::getuid() } != 0 {
+            return work();
+        }
+        // SAFETY: the child only extracts and exits without returning to the test harness.
+        match unsafe { libc::fork() } {
+            0 => {
+                // SAFETY: dropping to nobody only affects the child process.
+                let dropped = unsafe { libc::setgid(65534) == 0 && libc::setuid(65534) == 0 };
+                let succeeded = dropped && work();
+                // SAFETY: exits the child immediately, as the test harness state was not forked.
+                unsafe { libc::_exit(if succeeded { 0 } else { 1 }) }
+            }
+            pid => {
+                let mut status = 0;
+                // SAFETY: the pid is a child of this process and status is a valid pointer.
+                unsafe { libc::waitpid(pid, &mut status, 0) };
+                libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sandbox() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-sandbox");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        let archive_file = dir.join("hostile.tar.xz");
+        write_raw_archive(&archive_file, |builder| {
+            let mut directory = raw_header(0);
+            directory.set_entry_type(tar::EntryType::Directory);
+            directory.set_mode(0o755);
+            builder.append_data(&mut directory, "nested/", &b""[..])?;
+            let mut file = raw_header(5);
+            builder.append_data(&mut file, "nested/data.txt", &b"hello"[..])?;
+            let mut link = raw_header(0);
+            link.set_entry_type(tar::EntryType::Symlink);
+            builder.append_link(&mut link, "escape", dir.join("outside"))?;
+            let mut escaping = raw_header(5);
+            builder.append_data(&mut escaping, "escape/pwned.txt", &b"owned"[..])
+        })
+        .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.set_sandbox(true);
+        let error = reader.decompress().unwrap_err();
+        assert!(format!("{:#}", error).contains("escape/pwned.txt"), "{:#}", error);
+        assert_eq!(fs::read_to_string(dir.join("output/nested/data.txt")).unwrap(), "hello");
+        assert!(!dir.join("outside/pwned.txt").exists());
+    }
+
+    #[test]
+    fn test_gnu_sparse_formats() {
+        let dir = std::env::temp_dir().join("lzma_tarball-reader-sparse");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let size = "20000";
+        let data_map = format!("{:<512}", "2\n0\n5\n10000\n5\n").replace(' ', "\0");
+        let formats = [
+            ("0.0", vec![("GNU.sparse.size", size), ("GNU.sparse.offset", "0"), ("GNU.sparse.numbytes", "5"), ("GNU.sparse.offset", "10000"), ("GNU.sparse.numbytes", "5")], String::new()),
+            ("0.1", vec![("GNU.sparse.size", size), ("GNU.sparse.map", "0,5,10000,5")], String::new()),
+            ("1.0", vec![("GNU.sparse.major", "1"), ("GNU.sparse.minor", "0"), ("GNU.sparse.name", "data/sparse.bin"), ("GNU.sparse.realsize", size)], data_map),
+        ];
+        for (format, records, map) in formats {
+            let archive_file = dir.join(format!("{}.tar.xz", format));
+            write_raw_archive(&archive_file, |builder| {
+                let records: String = records.iter().map(|(key, value)| pax_record(key, value)).collect();
+                let mut pax = raw_header(records.len() as u64);
+                pax.set_entry_type(tar::EntryType::XHeader);
+                builder.append_data(&mut pax, "PaxHeaders/sparse.bin", records.as_bytes())?;
+                let data = format!("{}helloworld", map);
+                let path = if format == "1.0" { "data/GNUSparseFile.0/sparse.bin" } else { "data/sparse.bin" };
+                builder.append_data(&mut raw_header(data.len() as u64), path, data.as_bytes())
+            })
+            .unwrap();
+
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap();
+            reader.set_output_directory(dir.join(format)).unwrap();
+            assert_eq!(reader.decompress().unwrap().files, vec!["data/sparse.bin"], "format {}", format);
+            let mut expected = vec![0u8; 20000];
+            expected[..5].copy_from_slice(b"hello");
+            expected[10000..10005].copy_from_slice(b"world");
+            let extracted = dir.join(format).join("data/sparse.bin");
+            assert!(fs::read(&extracted).unwrap() == expected, "format {}", format);
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::unix::fs::MetadataExt;
+                // The holes are left unallocated
+                assert!(fs::metadata(&extracted).unwrap().blocks() * 512 < 20000, "format {}", format);
+            }
+        }
+    }
+
+    /// Formats a pax extended header record, whose length includes itself
+    fn pax_record(key: &str, value: &str) -> String {
+        let rest = key.len() + value.len() + 3;
+        let mut length = rest + 1;
+        while length.to_string().len() + rest != length {
+            length = length.to_string().len() + rest;
+        }
+        format!("{} {}={}\n", length, key, value)
+    }
+
+    fn raw_header(size: u64) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(1_700_000_000);
+        header
+    }
+
+    /// Serves `data` over HTTP on a local port, answering `Range` requests and counting the bytes sent
+    fn serve_ranges(data: Vec<u8>, served: Arc<AtomicU64>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/archive.tar.xz", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut byte = [0; 1];
+                while !request.ends_with(b"\r\n\r\n") && std::io::Read::read(&mut stream, &mut byte).unwrap() == 1 {
+                    request.push(byte[0]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                let range = request.lines().find_map(|line| line.strip_prefix("Range: bytes=")).unwrap();
+                let (start, end) = range.split_once('-').unwrap();
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                let body = &data[start..=end];
+                served.fetch_add(body.len() as u64, Ordering::Relaxed);
+                write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                    start,
+                    end,
+                    data.len(),
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        url
+    }
+
+    fn write_raw_archive(
+        path: &Path,
+        build: impl FnOnce(&mut tar::Builder<xz2::write::XzEncoder<File>>) -> std::io::Result<()>,
+    ) -> Result<()> {
+        let mut builder = tar::Builder::new(xz2::write::XzEncoder::new(File::create(path)?, 1));
+        build(&mut builder)?;
+        builder.into_inner()?.finish()?;
+        Ok(())
+    }
+
+    fn create_archive_in_temp_dir(name: &str) -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("lzma_tarball-reader-{}", name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("hello.txt"), b"Hello, world!")?;
+
+        let archive_path = dir.join("test.tar.xz");
+        lzma_tarball::writer::LZMATarballWriter::new()
+            .set_compression_level(1)
+            .set_output(&archive_path)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .compress(|_| {})?;
+        Ok(archive_path)
+    }
 
     fn create_test_tar_xz() -> Result<PathBuf> {
         let mut archive_path = current_dir()?;
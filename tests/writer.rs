@@ -1,3 +1,1548 @@
-mod tests{
+// tests for the LZMATarballWriter
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
 
-}
\ No newline at end of file
+    use lzma_tarball::clock::FixedClock;
+    use lzma_tarball::config::{ArchiveSpec, WriterConfig};
+    use lzma_tarball::benchmark::suggest_level;
+    use lzma_tarball::catalog::{verify_tree, CATALOG_SHA256_KEY, CATALOG_SIZE_KEY};
+    use lzma_tarball::error::WriterError;
+    use lzma_tarball::job::{JobProgress, WriterJob};
+    use lzma_tarball::lint::LintIssue;
+    use lzma_tarball::phase::Phase;
+    use lzma_tarball::skip::{SkipReason, SkippedEntry};
+    use lzma_tarball::reader::{EntryType, LZMATarballReader};
+    use lzma_tarball::rotation::RotationPolicy;
+    use lzma_tarball::snapshot::SnapshotProvider;
+    use lzma_tarball::stream_writer::ArchiveStreamWriter;
+    use lzma_tarball::writer::{AbsolutePathPolicy, BufferSize, Dispatch, EntrySource, LZMATarballWriter, LongPathEncoding, NameEncoding, TempStrategy};
+
+    #[test]
+    fn test_write_manifest() {
+        let dir = setup_testing_environment("manifest").unwrap();
+        let archive_file = dir.join("manifest.tar.xz");
+        LZMATarballWriter::new()
+            .set_compression_level(1)
+            .set_write_manifest(true)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let manifest = reader.read_manifest().unwrap().unwrap();
+
+        assert_eq!(manifest.tool_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!(manifest.files[0].path, "hello.txt");
+        assert_eq!(manifest.files[0].size, 13);
+        assert_eq!(
+            manifest.files[0].sha256,
+            "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3"
+        );
+    }
+
+    #[test]
+    fn test_catalog() {
+        let dir = setup_testing_environment("catalog").unwrap();
+        let archive_file = dir.join("catalog.tar.xz");
+        LZMATarballWriter::new()
+            .set_compression_level(1)
+            .set_catalog(true)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+
+        let decoder = xz2::read::XzDecoder::new(fs::File::open(&archive_file).unwrap());
+        let mut archive = tar::Archive::new(decoder);
+        let mut entries = archive.entries().unwrap();
+        let mut entry = entries.next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap().to_str(), Some("hello.txt"));
+        assert_eq!(entry.size(), 0);
+        let attributes: Vec<(String, String)> = entry
+            .pax_extensions()
+            .unwrap()
+            .unwrap()
+            .map(|extension| {
+                let extension = extension.unwrap();
+                (extension.key().unwrap().to_string(), extension.value().unwrap().to_string())
+            })
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                (CATALOG_SIZE_KEY.to_string(), "13".to_string()),
+                (
+                    CATALOG_SHA256_KEY.to_string(),
+                    "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3".to_string()
+                ),
+            ]
+        );
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn test_preserve_btime() {
+        let dir = setup_testing_environment("btime").unwrap();
+        let archive_file = dir.join("btime.tar.xz");
+        LZMATarballWriter::new()
+            .set_preserve_btime(true)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+
+        let decoder = xz2::read::XzDecoder::new(fs::File::open(&archive_file).unwrap());
+        let mut archive = tar::Archive::new(decoder);
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        let has_btime = entry.pax_extensions().unwrap().is_some_and(|mut extensions| {
+            extensions.any(|extension| extension.unwrap().key() == Ok("LIBARCHIVE.creationtime"))
+        });
+        assert_eq!(has_btime, fs::metadata(dir.join("hello.txt")).unwrap().created().is_ok());
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.set_restore_btime(true);
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("output/hello.txt")).unwrap(), "Hello, world!");
+    }
+
+    #[test]
+    fn test_precise_mtime() {
+        let dir = setup_testing_environment("precise_mtime").unwrap();
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 123_456_789);
+        fs::File::options().write(true).open(dir.join("hello.txt")).unwrap().set_modified(mtime).unwrap();
+        let archive_file = dir.join("precise_mtime.tar.xz");
+        LZMATarballWriter::new()
+            .set_precise_mtime(true)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.decompress().unwrap();
+        assert_eq!(fs::metadata(dir.join("output/hello.txt")).unwrap().modified().unwrap(), mtime);
+
+        #[cfg(target_os = "linux")]
+        {
+            reader.set_output_directory(dir.join("sandboxed")).unwrap();
+            reader.set_sandbox(true);
+            reader.decompress().unwrap();
+            assert_eq!(fs::metadata(dir.join("sandboxed/hello.txt")).unwrap().modified().unwrap(), mtime);
+        }
+    }
+
+    #[test]
+    fn test_verify_tree() {
+        let dir = setup_testing_environment("verify").unwrap();
+        let tree = dir.join("tree");
+        fs::create_dir_all(&tree).unwrap();
+        fs::write(tree.join("same.txt"), "same").unwrap();
+        fs::write(tree.join("changed.txt"), "before").unwrap();
+        fs::write(tree.join("missing.txt"), "missing").unwrap();
+        let catalog = dir.join("catalog.tar.xz");
+        LZMATarballWriter::new()
+            .set_catalog(true)
+            .with_path(&tree, "/")
+            .unwrap()
+            .set_output(&catalog)
+            .compress(|_| {})
+            .unwrap();
+        assert!(verify_tree(&catalog, &tree).unwrap().is_clean());
+
+        fs::write(tree.join("changed.txt"), "after!").unwrap();
+        fs::remove_file(tree.join("missing.txt")).unwrap();
+        fs::write(tree.join("extra.txt"), "extra").unwrap();
+        let report = verify_tree(&catalog, &tree).unwrap();
+        assert_eq!(report.missing, vec!["missing.txt"]);
+        assert_eq!(report.changed, vec!["changed.txt"]);
+        assert_eq!(report.extra, vec!["extra.txt"]);
+    }
+
+    #[test]
+    fn test_progress_dispatch() {
+        let dir = setup_testing_environment("dispatch").unwrap();
+        fs::write(dir.join("large.bin"), vec![7u8; 4 * 1024 * 1024]).unwrap();
+        let (dispatch, receiver) = Dispatch::channel();
+        LZMATarballWriter::new()
+            .set_compression_level(0)
+            .set_buffer_size(16)
+            .set_progress_dispatch(dispatch)
+            .with_file(dir.join("large.bin"), "/large.bin")
+            .set_output(dir.join("channel.tar.xz"))
+            .compress(|_| panic!("progress is sent to the channel"))
+            .unwrap();
+        assert!(receiver.try_iter().all(|progress| progress.percentage <= 1.0));
+
+        let thread = std::thread::current().id();
+        LZMATarballWriter::new()
+            .set_progress_dispatch(Dispatch::DedicatedThread)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(dir.join("thread.tar.xz"))
+            .compress(move |_| assert_ne!(std::thread::current().id(), thread))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_move_across_threads() {
+        let dir = setup_testing_environment("threads").unwrap();
+        let archive_file = dir.join("threads.tar.xz");
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .with_filtered_directory_contents(&dir, "/", &|entry| entry.path().extension().is_some_and(|ext| ext == "txt"))
+            .set_output(&archive_file);
+        std::thread::spawn(move || writer.compress(|_| {}).unwrap()).join().unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let entries = std::thread::spawn(move || reader.entries().unwrap()).join().unwrap();
+        assert_eq!(entries, vec!["hello.txt"]);
+    }
+
+    #[test]
+    fn test_archive_stream_writer() {
+        let dir = setup_testing_environment("stream-writer").unwrap();
+        let archive_file = dir.join("stream.tar.xz");
+        let mut writer = ArchiveStreamWriter::new(fs::File::create(&archive_file).unwrap(), 1);
+        writer.add_dir("/data").unwrap();
+        writer.add_file(dir.join("hello.txt"), "/data/hello.txt").unwrap();
+        writer.add_data("data/generated.txt", "generated").unwrap();
+        let summary = writer.finish().unwrap();
+        assert_eq!(summary.entries, 3);
+        assert_eq!(summary.files.len(), 2);
+        assert_eq!(summary.files[1].size, 9);
+        assert_eq!(summary.compressed_size, fs::metadata(&archive_file).unwrap().len());
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        assert_eq!(reader.entries().unwrap(), vec!["data/", "data/hello.txt", "data/generated.txt"]);
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("output/data/generated.txt")).unwrap(), "generated");
+    }
+
+    #[test]
+    fn test_writer_config_and_spec() {
+        let dir = setup_testing_environment("config").unwrap();
+        let mut config = WriterConfig::new();
+        config.set_compression_level(1).set_write_manifest(true);
+
+        let mut spec = ArchiveSpec::new();
+        assert!(spec.validate().is_err());
+        spec.with_file(dir.join("hello.txt"), "/hello.txt");
+        assert!(spec.validate().is_err());
+        spec.set_output(dir.join("first.tar.xz"));
+        spec.validate().unwrap();
+        let stored = serde_json::to_string(&spec).unwrap();
+
+        spec.with_file(dir.join("hello.txt"), "hello.txt");
+        assert!(config.compress(&spec, |_| {}).is_err());
+
+        let mut spec: ArchiveSpec = serde_json::from_str(&stored).unwrap();
+        config.compress(&spec, |_| {}).unwrap();
+        spec.set_output(dir.join("second.tar.xz"));
+        config.compress(&spec, |_| {}).unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(dir.join("second.tar.xz")).unwrap();
+        assert_eq!(reader.read_manifest().unwrap().unwrap().files.len(), 1);
+    }
+
+    #[test]
+    fn test_writer_config_store_directories() {
+        let dir = setup_testing_environment("config_directories").unwrap();
+        let input = dir.join("data");
+        fs::create_dir_all(input.join("sub")).unwrap();
+        fs::write(input.join("sub").join("file.txt"), b"contents").unwrap();
+        let mut spec = ArchiveSpec::new();
+        spec.set_store_directories(true).with_path(&input, "/").unwrap().set_output(dir.join("directories.tar.xz"));
+        WriterConfig::new().compress(&spec, |_| {}).unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(dir.join("directories.tar.xz")).unwrap();
+        assert_eq!(reader.entries().unwrap(), vec!["sub/", "sub/file.txt"]);
+    }
+
+    #[test]
+    fn test_progress_phases() {
+        let dir = setup_testing_environment("phases").unwrap();
+        let phases = |temp_strategy: TempStrategy| {
+            let (dispatch, receiver) = Dispatch::channel();
+            LZMATarballWriter::new()
+                .set_temp_strategy(temp_strategy)
+                .set_progress_dispatch(dispatch)
+                .with_file(dir.join("hello.txt"), "/hello.txt")
+                .set_output(dir.join("phases.tar.xz"))
+                .compress(|_| {})
+                .unwrap();
+            let mut phases: Vec<Phase> = receiver.try_iter().map(|progress| progress.phase).collect();
+            phases.dedup();
+            phases
+        };
+        assert_eq!(phases(TempStrategy::default()), [Phase::Scanning, Phase::Tarring, Phase::Compressing, Phase::Finalizing]);
+        assert_eq!(phases(TempStrategy::None), [Phase::Scanning, Phase::Compressing, Phase::Finalizing]);
+    }
+
+    #[test]
+    fn test_file_progress_and_cancel() {
+        let dir = setup_testing_environment("file_progress").unwrap();
+        fs::write(dir.join("large.bin"), vec![7u8; 1024 * 1024]).unwrap();
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let reported = progress.clone();
+        LZMATarballWriter::new()
+            .set_buffer_size(64)
+            .set_file_progress(move |file| reported.lock().unwrap().push(file))
+            .with_file(dir.join("large.bin"), "/large.bin")
+            .set_output(dir.join("progress.tar.xz"))
+            .compress(|_| {})
+            .unwrap();
+        let progress = progress.lock().unwrap();
+        assert_eq!(progress.len(), 16);
+        assert!(progress.iter().all(|file| file.path == "large.bin" && file.total_bytes == 1024 * 1024));
+        assert_eq!(progress.last().unwrap().bytes_processed, 1024 * 1024);
+
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let flag = cancel_flag.clone();
+        let mut writer = LZMATarballWriter::new();
+        let error = writer
+            .set_cancel_flag(cancel_flag)
+            .set_buffer_size(64)
+            .set_file_progress(move |_| flag.store(true, Ordering::Relaxed))
+            .with_file(dir.join("large.bin"), "/large.bin")
+            .set_output(dir.join("cancelled.tar.xz"))
+            .compress(|_| {})
+            .unwrap_err();
+        assert_eq!(error.downcast_ref::<WriterError>(), Some(&WriterError::Cancelled));
+        assert!(!dir.join("cancelled.tar.xz").exists());
+        assert!(!writer.tar_file.exists());
+    }
+
+    #[test]
+    fn test_keep_partial_output() {
+        let dir = setup_testing_environment("keep_partial_output").unwrap();
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let flag = cancel_flag.clone();
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .set_cancel_flag(cancel_flag)
+            .set_keep_partial_output(true)
+            .set_file_progress(move |_| flag.store(true, Ordering::Relaxed))
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(dir.join("partial.tar.xz"));
+        assert!(writer.compress(|_| {}).is_err());
+        assert!(dir.join("partial.tar.xz").exists());
+
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .set_file_progress(|_| panic!("progress callback failed"))
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(dir.join("panicked.tar.xz"));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| writer.compress(|_| {})));
+        assert!(result.is_err());
+        assert!(!dir.join("panicked.tar.xz").exists());
+    }
+
+    #[test]
+    fn test_output_mirrors() {
+        use lzma_tarball::mirror::OutputMirror;
+
+        let dir = setup_testing_environment("output_mirrors").unwrap();
+        fs::write(dir.join("blocked"), b"not a directory").unwrap();
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let archive_file = dir.join("primary.tar.xz");
+        let result = LZMATarballWriter::new()
+            .with_file(dir.join("hello.txt"), "hello.txt")
+            .set_output(&archive_file)
+            .add_output_mirror(dir.join("mirror").join("copy.tar.xz"))
+            .add_output_mirror(OutputMirror::Sink(sink.clone()))
+            .add_output_mirror(dir.join("blocked").join("copy.tar.xz"))
+            .compress(|_| {})
+            .unwrap();
+
+        let compressed = fs::read(&archive_file).unwrap();
+        assert_eq!(fs::read(dir.join("mirror").join("copy.tar.xz")).unwrap(), compressed);
+        assert_eq!(*sink.lock().unwrap(), compressed);
+        assert_eq!(result.mirrors.len(), 3);
+        assert_eq!(result.mirrors[0].error, None);
+        assert_eq!(result.mirrors[1].destination, "sink 1");
+        assert_eq!(result.mirrors[1].error, None);
+        assert!(result.mirrors[2].error.is_some());
+    }
+
+    #[test]
+    fn test_open_at_scan() {
+        let dir = setup_testing_environment("open_at_scan").unwrap();
+        let archive_file = dir.join("scanned.tar.xz");
+        let mut writer = LZMATarballWriter::new();
+        writer.set_open_at_scan(true).with_file(dir.join("hello.txt"), "/hello.txt");
+        fs::write(dir.join("swapped.txt"), "Swapped!").unwrap();
+        fs::rename(dir.join("swapped.txt"), dir.join("hello.txt")).unwrap();
+        writer.set_output(&archive_file).compress(|_| {}).unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("output/hello.txt")).unwrap(), "Hello, world!");
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("hello.txt"), dir.join("link.txt")).unwrap();
+            let result = LZMATarballWriter::new()
+                .set_no_follow(true)
+                .with_file(dir.join("link.txt"), "/link.txt")
+                .set_output(dir.join("link.tar.xz"))
+                .compress(|_| {});
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_lock_source_files() {
+        let dir = setup_testing_environment("lock").unwrap();
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .set_open_at_scan(true)
+            .set_lock_source_files(true)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(dir.join("locked.tar.xz"))
+            .compress(|_| {})
+            .unwrap();
+
+        // The lock is released even though the writer still holds the handle
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            let file = fs::File::open(dir.join("hello.txt")).unwrap();
+            assert_eq!(unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) }, 0);
+        }
+    }
+
+    #[test]
+    fn test_dedupe_identical_files() {
+        let dir = setup_testing_environment("dedupe").unwrap();
+        fs::write(dir.join("copy.txt"), "Hello, world!").unwrap();
+        fs::write(dir.join("other.txt"), "Goodbye, world!").unwrap();
+        let archive_file = dir.join("dedupe.tar.xz");
+        LZMATarballWriter::new()
+            .set_dedupe_identical_files(true)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .with_file(dir.join("copy.txt"), "/nested/copy.txt")
+            .with_file(dir.join("other.txt"), "/other.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let entries = reader.entries_metadata().unwrap();
+        let kinds: Vec<(&str, EntryType, u64)> = entries.iter().map(|entry| (entry.path.as_str(), entry.entry_type, entry.size)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("hello.txt", EntryType::File, 13),
+                ("nested/copy.txt", EntryType::Hardlink, 0),
+                ("other.txt", EntryType::File, 15),
+            ]
+        );
+        assert_eq!(entries[1].link_name.as_deref(), Some("hello.txt"));
+
+        let output = dir.join("dedupe_output");
+        reader.set_output_directory(&output).unwrap().decompress().unwrap();
+        assert_eq!(fs::read_to_string(output.join("nested/copy.txt")).unwrap(), "Hello, world!");
+        assert_eq!(fs::read_to_string(output.join("other.txt")).unwrap(), "Goodbye, world!");
+    }
+
+    #[test]
+    fn test_long_path_encoding() {
+        let dir = setup_testing_environment("long_paths").unwrap();
+        let split_path = format!("/{}/{}/hello.txt", "a".repeat(60), "b".repeat(60));
+        // The truncated name in the pax header ends in the middle of the é
+        let long_name = format!("/{}é{}.txt", "c".repeat(99), "c".repeat(20));
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .with_file(dir.join("hello.txt"), &split_path)
+            .with_file(dir.join("hello.txt"), &long_name);
+        assert_eq!(writer.validate_ustar_paths(), vec![long_name[1..].to_string()]);
+
+        writer.set_output(dir.join("ustar.tar.xz")).set_long_path_encoding(LongPathEncoding::Ustar);
+        assert!(writer.compress(|_| {}).is_err());
+
+        // Lists the raw entry types, before long names and pax attributes are applied
+        let raw_entry_types = |archive: &Path| -> Vec<tar::EntryType> {
+            let mut archive = tar::Archive::new(xz2::read::XzDecoder::new(fs::File::open(archive).unwrap()));
+            let entries = archive.entries().unwrap().raw(true);
+            entries.map(|entry| entry.unwrap().header().entry_type()).collect()
+        };
+        use tar::EntryType::{GNULongName, Regular, XHeader};
+        // GNU headers have no prefix field, so both paths need a long name entry
+        for (encoding, entry_types) in [
+            (LongPathEncoding::Gnu, vec![GNULongName, Regular, GNULongName, Regular]),
+            (LongPathEncoding::Pax, vec![Regular, XHeader, Regular]),
+        ] {
+            let archive_file = dir.join(format!("{:?}.tar.xz", encoding));
+            writer.set_output(&archive_file).set_long_path_encoding(encoding).compress(|_| {}).unwrap();
+            assert_eq!(raw_entry_types(&archive_file), entry_types);
+
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap();
+            assert_eq!(reader.entries().unwrap(), vec![split_path[1..].to_string(), long_name[1..].to_string()]);
+        }
+    }
+
+    #[test]
+    fn test_absolute_path_policy() {
+        let dir = setup_testing_environment("absolute_paths").unwrap();
+        let long_name = format!("/{}.txt", "c".repeat(120));
+        let raw_paths = |archive: &Path| -> Vec<String> {
+            let mut archive = tar::Archive::new(xz2::read::XzDecoder::new(fs::File::open(archive).unwrap()));
+            let entries = archive.entries().unwrap();
+            entries.map(|entry| String::from_utf8_lossy(&entry.unwrap().path_bytes()).into_owned()).collect()
+        };
+        for (policy, encoding, paths) in [
+            (AbsolutePathPolicy::Strip, LongPathEncoding::Gnu, vec!["hello.txt".to_string(), long_name[1..].to_string()]),
+            (AbsolutePathPolicy::Keep, LongPathEncoding::Gnu, vec!["/hello.txt".to_string(), long_name.clone()]),
+            (AbsolutePathPolicy::Keep, LongPathEncoding::Pax, vec!["/hello.txt".to_string(), long_name.clone()]),
+        ] {
+            let archive_file = dir.join(format!("{:?}-{:?}.tar.xz", policy, encoding));
+            LZMATarballWriter::new()
+                .set_absolute_path_policy(policy)
+                .set_long_path_encoding(encoding)
+                .with_file(dir.join("hello.txt"), "//hello.txt")
+                .with_file(dir.join("hello.txt"), &long_name)
+                .set_output(&archive_file)
+                .compress(|_| {})
+                .unwrap();
+            assert_eq!(raw_paths(&archive_file), paths);
+
+            let output = dir.join(format!("output-{:?}-{:?}", policy, encoding));
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap().decompress().unwrap();
+            assert_eq!(fs::read_to_string(output.join("hello.txt")).unwrap(), "Hello, world!");
+        }
+
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .set_absolute_path_policy(AbsolutePathPolicy::Keep)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .with_file(dir.join("hello.txt"), &long_name);
+        assert_eq!(writer.validate_ustar_paths(), vec![long_name.clone()]);
+
+        let result = LZMATarballWriter::new()
+            .set_absolute_path_policy(AbsolutePathPolicy::Error)
+            .with_file(dir.join("hello.txt"), "relative.txt")
+            .with_file(dir.join("hello.txt"), "/absolute.txt")
+            .set_output(dir.join("error.tar.xz"))
+            .compress(|_| {});
+        assert!(result.unwrap_err().to_string().contains("absolute"));
+        assert!(!dir.join("error.tar.xz").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_lint() {
+        let dir = setup_testing_environment("lint").unwrap();
+        let outside = setup_testing_environment("lint_outside").unwrap();
+        std::os::unix::fs::symlink(outside.join("hello.txt"), dir.join("escape.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.join("hello.txt"), dir.join("inside.txt")).unwrap();
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("nested/other.txt"), "other").unwrap();
+
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .with_file(dir.join("nested/other.txt"), "hello.txt")
+            .with_file(dir.join("missing.txt"), "/missing.txt")
+            .with_file(dir.join("escape.txt"), "/escape.txt")
+            .with_file(dir.join("inside.txt"), "/inside.txt")
+            .with_file(dir.join("nested/other.txt"), "/nested/con.txt")
+            .with_file(dir.join("nested/other.txt"), "/what?/other.txt");
+        let report = writer.lint();
+        assert!(!report.is_clean());
+        assert!(matches!(&report.issues[0], LintIssue::Unreadable { path, .. } if *path == dir.join("missing.txt")));
+        assert_eq!(
+            report.issues[1..],
+            [
+                LintIssue::SymlinkOutsideTree {
+                    path: dir.join("escape.txt"),
+                    target: outside.join("hello.txt").canonicalize().unwrap(),
+                },
+                LintIssue::InvalidWindowsName {
+                    archive_path: "nested/con.txt".to_string(),
+                    reason: "is a reserved device name on Windows".to_string(),
+                },
+                LintIssue::InvalidWindowsName {
+                    archive_path: "what?/other.txt".to_string(),
+                    reason: "contains a character that is invalid on Windows".to_string(),
+                },
+                LintIssue::Collision {
+                    archive_path: "hello.txt".to_string(),
+                    sources: vec![dir.join("hello.txt"), dir.join("nested/other.txt")],
+                },
+            ]
+        );
+
+        let mut writer = LZMATarballWriter::new();
+        writer.with_file(dir.join("hello.txt"), "/hello.txt");
+        assert!(writer.lint().is_clean());
+    }
+
+    #[test]
+    #[cfg(feature = "log")]
+    fn test_log_level() {
+        use lzma_tarball::logging::{log_level, set_log_level, set_quiet};
+        use log::LevelFilter;
+
+        assert_eq!(log_level(), LevelFilter::Trace);
+        set_log_level(LevelFilter::Warn);
+        assert_eq!(log_level(), LevelFilter::Warn);
+        set_quiet(true);
+        assert_eq!(log_level(), LevelFilter::Off);
+        set_quiet(false);
+        assert_eq!(log_level(), LevelFilter::Warn);
+        set_log_level(LevelFilter::Trace);
+    }
+
+    /// Copies the sources into a directory, standing in for a filesystem snapshot
+    struct CopySnapshot {
+        directory: PathBuf,
+        released: Arc<AtomicBool>,
+    }
+
+    impl SnapshotProvider for CopySnapshot {
+        fn create(&self, sources: &[&Path]) -> Result<()> {
+            fs::create_dir_all(&self.directory)?;
+            for source in sources {
+                fs::copy(source, self.remap(source))?;
+                // The live file keeps changing while the snapshot is archived
+                fs::write(source, "Changed after the snapshot")?;
+            }
+            Ok(())
+        }
+
+        fn remap(&self, path: &Path) -> PathBuf {
+            self.directory.join(path.file_name().unwrap())
+        }
+
+        fn release(&self) -> Result<()> {
+            fs::remove_dir_all(&self.directory)?;
+            self.released.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_snapshot_provider() {
+        let dir = setup_testing_environment("snapshot").unwrap();
+        let archive_file = dir.join("snapshot.tar.xz");
+        let released = Arc::new(AtomicBool::new(false));
+        LZMATarballWriter::new()
+            .set_snapshot_provider(CopySnapshot { directory: dir.join("snapshot"), released: released.clone() })
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+        assert!(released.load(Ordering::Relaxed));
+        assert!(!dir.join("snapshot").exists());
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("output/hello.txt")).unwrap(), "Hello, world!");
+    }
+
+    #[test]
+    fn test_suggest_level() {
+        let dir = setup_testing_environment("suggest_level").unwrap();
+        fs::write(dir.join("sample.txt"), "The quick brown fox jumps over the lazy dog. ".repeat(1000)).unwrap();
+        let suggestion = suggest_level(&[dir.join("sample.txt")], Duration::from_secs(60)).unwrap();
+        assert_eq!(suggestion.measurements.len(), 10);
+        assert!(suggestion.measurements.iter().any(|measurement| measurement.level == suggestion.level));
+        assert!(suggestion.measurements.iter().all(|measurement| measurement.ratio < 0.1));
+
+        let suggestion = suggest_level(&[dir.join("sample.txt")], Duration::ZERO).unwrap();
+        assert_eq!(suggestion.measurements.len(), 1);
+        assert_eq!(suggestion.level, 0);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_background_priority() {
+        fn nice() -> i32 {
+            let thread_id = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+            unsafe { libc::getpriority(libc::PRIO_PROCESS, thread_id) }
+        }
+        let dir = setup_testing_environment("background_priority").unwrap();
+        let caller_nice = nice();
+        let worker_nice = Arc::new(Mutex::new(None));
+        let reported = worker_nice.clone();
+        LZMATarballWriter::new()
+            .set_background_priority(true)
+            .set_file_progress(move |_| *reported.lock().unwrap() = Some(nice()))
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(dir.join("background.tar.xz"))
+            .compress(|_| {})
+            .unwrap();
+        assert_eq!(*worker_nice.lock().unwrap(), Some(19));
+        assert_eq!(nice(), caller_nice);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_timeout() {
+        let dir = setup_testing_environment("timeout").unwrap();
+        // Opening a fifo blocks until something opens its other end, standing in for a hung filesystem
+        let fifo = dir.join("fifo");
+        let path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+        // Feeds the fifo slowly until the worker stops reading it
+        let feeder = {
+            let fifo = fifo.clone();
+            std::thread::spawn(move || {
+                let mut fifo = fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+                while std::io::Write::write_all(&mut fifo, b"data").is_ok() {
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+            })
+        };
+        let output = dir.join("timeout.tar.xz");
+        let error = LZMATarballWriter::new()
+            .set_timeout(Duration::from_millis(200))
+            .with_file(&fifo, "/fifo")
+            .set_output(&output)
+            .compress(|_| {})
+            .unwrap_err();
+        assert_eq!(error.downcast_ref::<std::io::Error>().map(|e| e.kind()), Some(std::io::ErrorKind::TimedOut));
+        // The worker has stopped and removed its partial output before the call returned
+        assert!(!output.exists());
+        feeder.join().unwrap();
+
+        let archive_file = dir.join("completed.tar.xz");
+        LZMATarballWriter::new()
+            .set_timeout(Duration::from_secs(60))
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        reader.set_output_directory(dir.join("output")).unwrap();
+        reader.set_timeout(Duration::from_secs(60));
+        let updates = std::cell::Cell::new(0);
+        reader.decompress_with_callback(|_| updates.set(updates.get() + 1)).unwrap();
+        // Decoding, the entry and applying metadata are all forwarded from the worker
+        assert_eq!(updates.get(), 3);
+        assert_eq!(fs::read_to_string(dir.join("output/hello.txt")).unwrap(), "Hello, world!");
+    }
+
+    #[test]
+    fn test_compress_chunks() {
+        let dir = setup_testing_environment("chunks").unwrap();
+        // Incompressible data, so the archive spans several chunks
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let random: Vec<u8> = (0..256 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        fs::write(dir.join("random.bin"), random).unwrap();
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+        let received = chunks.clone();
+        let result = LZMATarballWriter::new()
+            .set_compression_level(0)
+            .set_buffer_size(16)
+            .with_file(dir.join("random.bin"), "/random.bin")
+            .compress_chunks(
+                move |chunk| {
+                    received.lock().unwrap().push(chunk.to_vec());
+                    Ok(())
+                },
+                |_| {},
+            )
+            .unwrap();
+        let chunks = chunks.lock().unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 16 * 1024));
+        let archive = chunks.concat();
+        assert_eq!(result.size, archive.len() as u64);
+
+        let archive_file = dir.join("chunks.tar.xz");
+        fs::write(&archive_file, archive).unwrap();
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        assert_eq!(reader.entries().unwrap(), vec!["random.bin"]);
+
+        let error = LZMATarballWriter::new()
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .compress_chunks(|_| anyhow::bail!("upload failed"), |_| {})
+            .unwrap_err();
+        assert_eq!(error.to_string(), "upload failed");
+    }
+
+    #[test]
+    fn test_output_template() {
+        use sha2::{Digest, Sha256};
+
+        let dir = setup_testing_environment("output_template").unwrap();
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_clock(FixedClock::from_unix(1_700_000_000))
+            .set_output_template(format!("{}/backups/backup-{{date}}-{{seq}}-{{hash}}.tar.xz", dir.display()));
+        let first = writer.compress(|_| {}).unwrap().output_file;
+        let second = writer.compress(|_| {}).unwrap().output_file;
+        for (output_file, seq) in [(&first, 1), (&second, 2)] {
+            let hash = format!("{:x}", Sha256::digest(fs::read(output_file).unwrap()));
+            assert_eq!(output_file, &dir.join("backups").join(format!("backup-2023-11-14-{}-{}.tar.xz", seq, &hash[..12])));
+        }
+        assert_eq!(fs::read_dir(dir.join("backups")).unwrap().count(), 2);
+
+        writer.set_output_template(dir.join("{hostname}-{seq}.tar.xz").to_string_lossy());
+        let output_file = writer.compress(|_| {}).unwrap().output_file;
+        assert!(output_file.to_string_lossy().ends_with("-1.tar.xz"));
+        assert!(!output_file.to_string_lossy().contains('{'));
+
+        writer.set_output_template(dir.join("{time}.tar.xz").to_string_lossy());
+        assert!(writer.compress(|_| {}).is_err());
+        writer.set_output_template(dir.join("{seq}").join("archive.tar.xz").to_string_lossy());
+        assert!(writer.compress(|_| {}).is_err());
+    }
+
+    #[test]
+    fn test_rotation_policy() {
+        let dir = setup_testing_environment("rotation").unwrap();
+        let backups = dir.join("backups");
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output_template(backups.join("backup-{seq}.tar.xz").to_string_lossy())
+            .set_rotation_policy(Some(RotationPolicy::keep_last(2)));
+        assert!(writer.compress(|_| {}).unwrap().pruned.is_empty());
+        assert!(writer.compress(|_| {}).unwrap().pruned.is_empty());
+        fs::write(backups.join("notes.txt"), b"kept").unwrap();
+        let result = writer.compress(|_| {}).unwrap();
+        assert_eq!(result.pruned, vec![backups.join("backup-1.tar.xz")]);
+        assert!(result.output_file.exists());
+        assert!(backups.join("backup-2.tar.xz").exists());
+        assert!(backups.join("notes.txt").exists());
+
+        // Without a template, unrelated archives in the directory would match
+        let mut fixed = LZMATarballWriter::new();
+        fixed
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(backups.join("db.tar.xz"))
+            .set_rotation_policy(Some(RotationPolicy::keep_last(1)));
+        assert!(fixed.compress(|_| {}).is_err());
+        assert!(backups.join("backup-2.tar.xz").exists());
+
+        let old = fs::File::options().write(true).open(backups.join("backup-2.tar.xz")).unwrap();
+        old.set_modified(SystemTime::now() - Duration::from_secs(10 * 86_400)).unwrap();
+        let pruned = RotationPolicy::keep_days(7).apply(&backups, "*.tar.xz", SystemTime::now()).unwrap();
+        assert_eq!(pruned, vec![backups.join("backup-2.tar.xz")]);
+        assert!(backups.join("backup-3.tar.xz").exists());
+        // Keeping more days than fit in a `Duration` keeps everything
+        assert!(RotationPolicy::keep_days(u64::MAX).apply(&backups, "*.tar.xz", SystemTime::now()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_compress_to_vec() {
+        let dir = setup_testing_environment("to_vec").unwrap();
+        let mut writer = LZMATarballWriter::new();
+        writer.with_file(dir.join("hello.txt"), "/hello.txt");
+        let archive = writer.compress_to_vec(None).unwrap();
+        let reader = LZMATarballReader::from_reader(std::io::Cursor::new(archive.clone())).unwrap();
+        assert_eq!(reader.entries().unwrap(), vec!["hello.txt"]);
+        assert_eq!(writer.compress_to_vec(Some(archive.len() as u64)).unwrap(), archive);
+
+        let error = writer.compress_to_vec(Some(16)).unwrap_err();
+        assert_eq!(error.downcast_ref::<WriterError>(), Some(&WriterError::OutputTooLarge { max_size: 16 }));
+    }
+
+    #[test]
+    fn test_compress_to_writer() {
+        use lzma_tarball::mirror::OutputMirror;
+
+        let dir = setup_testing_environment("to_writer").unwrap();
+        let archive_file = dir.join("streamed.tar.xz");
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let result = LZMATarballWriter::new()
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .add_output_mirror(OutputMirror::Sink(sink.clone()))
+            .compress_to_writer(fs::File::create(&archive_file).unwrap(), |_| {})
+            .unwrap();
+        let compressed = fs::read(&archive_file).unwrap();
+        assert_eq!(result.output_file, PathBuf::new());
+        assert_eq!(result.size, compressed.len() as u64);
+        assert_eq!(*sink.lock().unwrap(), compressed);
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        assert_eq!(reader.entries().unwrap(), vec!["hello.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_mode_and_owner() {
+        use std::os::unix::fs::MetadataExt;
+        let dir = setup_testing_environment("output_mode").unwrap();
+        let archive_file = dir.join("private.tar.xz");
+        // An existing, wider file is tightened as well
+        fs::write(&archive_file, b"old").unwrap();
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        LZMATarballWriter::new()
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(&archive_file)
+            .set_output_mode(0o640)
+            .set_output_owner(uid, gid)
+            .compress(|_| {})
+            .unwrap();
+        let metadata = fs::metadata(&archive_file).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o640);
+        assert_eq!((metadata.uid(), metadata.gid()), (uid, gid));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_name_encoding() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let dir = setup_testing_environment("name_encoding").unwrap();
+        let input = dir.join("input");
+        fs::create_dir_all(input.join("sub")).unwrap();
+        fs::write(input.join("sub").join(OsStr::from_bytes(b"bad\xff.txt")), b"bytes").unwrap();
+        let compress = |name: &str, name_encoding: NameEncoding| {
+            let archive_file = dir.join(name);
+            LZMATarballWriter::new()
+                .with_directory_contents(&input, "files")
+                .set_name_encoding(name_encoding)
+                .set_output(&archive_file)
+                .compress(|_| {})
+                .map(|_| archive_file)
+        };
+        let error = compress("error.tar.xz", NameEncoding::Error).unwrap_err();
+        assert!(error.to_string().contains("not valid UTF-8"), "{}", error);
+
+        let paths = |archive_file: &Path| -> Vec<Vec<u8>> {
+            let decoder = xz2::read::XzDecoder::new(fs::File::open(archive_file).unwrap());
+            let mut archive = tar::Archive::new(decoder);
+            archive.entries().unwrap().map(|entry| entry.unwrap().path_bytes().into_owned()).collect()
+        };
+        let lossy = compress("lossy.tar.xz", NameEncoding::Lossy).unwrap();
+        assert_eq!(paths(&lossy), vec!["files/sub/bad\u{FFFD}.txt".as_bytes().to_vec()]);
+        let raw = compress("raw.tar.xz", NameEncoding::Raw).unwrap();
+        assert_eq!(paths(&raw), vec![b"files/sub/bad\xff.txt".to_vec()]);
+    }
+
+    #[test]
+    fn test_plan_sources() {
+        let dir = setup_testing_environment("plan_sources").unwrap();
+        let input = dir.join("input");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(input.join("scanned.txt"), b"scanned").unwrap();
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .with_file(dir.join("hello.txt"), "hello.txt")
+            .with_directory_contents(&input, "data")
+            .set_output(dir.join("plan.tar.xz"));
+        let plan = writer.plan().entries;
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan[0].source, Some(EntrySource::File));
+        assert_eq!(plan[1].archive_path, "data/scanned.txt");
+        assert_eq!(
+            plan[1].source,
+            Some(EntrySource::Directory {
+                root: input.clone(),
+                archive_path: "data".to_string()
+            })
+        );
+
+        fs::remove_file(input.join("scanned.txt")).unwrap();
+        let error = writer.compress(|_| {}).unwrap_err().to_string();
+        assert!(error.contains("found scanning"), "{}", error);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let dir = setup_testing_environment("memory_usage").unwrap();
+        let mut writer = LZMATarballWriter::new();
+        writer.with_file(dir.join("hello.txt"), "hello.txt").set_output(dir.join("memory.tar.xz"));
+        let estimate = writer.plan().estimated_memory_usage;
+        // The level 6 preset uses an 8 MiB dictionary
+        assert!(estimate > 8 * 1024 * 1024, "{}", estimate);
+        assert!(writer.clone().set_compression_level(0).estimated_memory_usage() < estimate);
+        let result = writer.compress(|_| {}).unwrap();
+        assert_eq!(result.memory_usage, estimate);
+    }
+
+    #[test]
+    fn test_preset_dictionary() {
+        let dir = setup_testing_environment("preset_dictionary").unwrap();
+        let config: String = (0..200).map(|i| format!("setting_{}=enabled\n", i)).collect();
+        fs::write(dir.join("settings.conf"), &config).unwrap();
+        let compress = |dictionary: Option<Vec<u8>>, name: &str| {
+            let mut writer = LZMATarballWriter::new();
+            writer
+                .with_file(dir.join("settings.conf"), "settings.conf")
+                .set_preset_dictionary(dictionary)
+                .set_output(dir.join(name));
+            writer.compress(|_| {}).unwrap().size
+        };
+        let plain = compress(None, "plain.tar.xz");
+        let primed = compress(Some(config.clone().into_bytes()), "primed.tar.lzma2");
+        assert!(primed < plain, "{} >= {}", primed, plain);
+
+        let output = dir.join("output");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(dir.join("primed.tar.lzma2")).unwrap().set_output_directory(&output).unwrap();
+        assert!(reader.entries().is_err());
+        reader.set_preset_dictionary(Some(config.clone().into_bytes()));
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(output.join("settings.conf")).unwrap(), config);
+        assert!(reader.set_preset_dictionary(Some(b"something else".to_vec())).entries().is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_format() {
+        use lzma_tarball::format::CompressionFormat;
+
+        let dir = setup_testing_environment("zstd_format").unwrap();
+        let archive_file = dir.join("hello.tar.zst");
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .set_format(CompressionFormat::Zstd)
+            .with_file(dir.join("hello.txt"), "hello.txt")
+            .set_output(&archive_file);
+        writer.compress(|_| {}).unwrap();
+        let compressed = fs::read(&archive_file).unwrap();
+        assert_eq!(&compressed[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+
+        let output = dir.join("output");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+        assert_eq!(reader.detected_format(), Some(CompressionFormat::Zstd));
+        reader.decompress().unwrap();
+        assert_eq!(fs::read_to_string(output.join("hello.txt")).unwrap(), "Hello, world!");
+        assert!(writer.set_seekable_block_size(Some(1024)).compress(|_| {}).is_err());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_format() {
+        use lzma_tarball::format::CompressionFormat;
+
+        let dir = setup_testing_environment("gzip_format").unwrap();
+        let archive_file = dir.join("hello.tar.gz");
+        LZMATarballWriter::new()
+            .set_format(CompressionFormat::Gzip)
+            .with_file(dir.join("hello.txt"), "hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+        let compressed = fs::read(&archive_file).unwrap();
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+
+        let output = dir.join("output");
+        LZMATarballReader::new()
+            .set_archive(&archive_file)
+            .unwrap()
+            .set_output_directory(&output)
+            .unwrap()
+            .set_format(CompressionFormat::Gzip)
+            .decompress()
+            .unwrap();
+        assert_eq!(fs::read_to_string(output.join("hello.txt")).unwrap(), "Hello, world!");
+    }
+
+    #[test]
+    fn test_uncompressed_format() {
+        use lzma_tarball::format::CompressionFormat;
+
+        let dir = setup_testing_environment("uncompressed_format").unwrap();
+        let archive_file = dir.join("hello.tar");
+        let result = LZMATarballWriter::new()
+            .set_format(CompressionFormat::None)
+            .with_file(dir.join("hello.txt"), "hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+        assert_eq!(result.size, result.original_size);
+
+        let mut archive = tar::Archive::new(fs::File::open(&archive_file).unwrap());
+        let names: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["hello.txt"]);
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_format(CompressionFormat::None);
+        assert_eq!(reader.entries().unwrap(), vec!["hello.txt"]);
+
+        // Options only xz supports are rejected instead of being left out, whichever way the archive is written
+        let mut writer = LZMATarballWriter::new();
+        writer.set_format(CompressionFormat::None).with_file(dir.join("hello.txt"), "hello.txt").set_preset_dictionary(Some(b"hello".to_vec()));
+        let error = writer.compress_to_writer(std::io::sink(), |_| {}).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<WriterError>(),
+            Some(&WriterError::IncompatibleOptions { option: "a preset dictionary", conflict: "the None format".to_string() })
+        );
+        writer.set_format(CompressionFormat::Xz).set_seekable_block_size(Some(1024));
+        let error = writer.compress_chunks(|_| Ok(()), |_| {}).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<WriterError>(),
+            Some(&WriterError::IncompatibleOptions { option: "a preset dictionary", conflict: "a seekable block size".to_string() })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_include_and_exclude_files() {
+        let dir = setup_testing_environment("include_exclude").unwrap();
+        fs::create_dir_all(dir.join("logs")).unwrap();
+        fs::write(dir.join("logs").join("app.log"), b"log").unwrap();
+        fs::write(dir.join("logs").join("keep.txt"), b"keep").unwrap();
+        fs::write(dir.join("notes.log"), b"notes").unwrap();
+        let include = dir.join("include.txt");
+        let listed = [dir.join("hello.txt"), dir.join("notes.log"), dir.join("logs")];
+        let lines: Vec<String> = listed.iter().map(|path| path.display().to_string()).collect();
+        fs::write(&include, format!("# generated\n{}\n\n", lines.join("\n"))).unwrap();
+        let exclude = dir.join("exclude.txt");
+        fs::write(&exclude, "*.log\n").unwrap();
+
+        let mut writer = LZMATarballWriter::new();
+        writer.set_include_file(&include).unwrap();
+        assert_eq!(writer.archive_paths.len(), 4);
+        writer.set_exclude_file(&exclude).unwrap();
+        let mut paths: Vec<String> = writer.archive_paths.iter().map(|entry| entry.archive_path.clone()).collect();
+        paths.sort();
+        let root = lines[0].trim_start_matches('/').trim_end_matches("/hello.txt").to_string();
+        assert_eq!(paths, vec![format!("{}/hello.txt", root), format!("{}/logs/keep.txt", root)]);
+        assert_eq!(writer.skipped.len(), 2);
+        assert!(matches!(writer.plan().entries[0].source, Some(EntrySource::IncludeFile { line: 2, .. })));
+
+        fs::write(&include, "missing.txt\n").unwrap();
+        assert!(LZMATarballWriter::new().set_include_file(&include).is_err());
+    }
+
+    #[test]
+    fn test_directory_contents_trimmed() {
+        let dir = setup_testing_environment("trimmed").unwrap();
+        let deep = dir.join("srv").join("site").join("assets");
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(deep.join("logo.svg"), b"<svg/>").unwrap();
+        let mut writer = LZMATarballWriter::new();
+        writer.with_directory_contents_trimmed(&deep, "mirror", dir.join("srv")).unwrap();
+        assert_eq!(writer.archive_paths[0].archive_path, "mirror/site/assets/logo.svg");
+        assert!(writer.with_directory_contents_trimmed(&deep, "mirror", dir.join("other")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_store_directories() {
+        let dir = setup_testing_environment("store_directories").unwrap();
+        let input = dir.join("input");
+        fs::create_dir_all(input.join("sub")).unwrap();
+        fs::write(input.join("sub").join("file.txt"), b"contents").unwrap();
+        let old = std::time::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        fs::File::open(input.join("sub")).unwrap().set_modified(old).unwrap();
+        let archive_file = dir.join("directories.tar.xz");
+        LZMATarballWriter::new()
+            .set_store_directories(true)
+            .with_directory_contents(&input, "data")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let entries = reader.entries().unwrap();
+        assert_eq!(entries, vec!["data/", "data/sub/", "data/sub/file.txt"]);
+        let output = dir.join("output");
+        reader.set_output_directory(&output).unwrap().decompress().unwrap();
+        assert_eq!(fs::metadata(output.join("data").join("sub")).unwrap().modified().unwrap(), old);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_compress_async() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct ThreadWaker(std::thread::Thread);
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+            let mut context = Context::from_waker(&waker);
+            let mut future = pin!(future);
+            loop {
+                match future.as_mut().poll(&mut context) {
+                    Poll::Ready(output) => return output,
+                    Poll::Pending => std::thread::park(),
+                }
+            }
+        }
+
+        let dir = setup_testing_environment("compress_async").unwrap();
+        let archive_file = dir.join("async.tar.xz");
+        let mut writer = LZMATarballWriter::new();
+        writer.with_file(dir.join("hello.txt"), "hello.txt").set_output(&archive_file);
+        let (compression, progress) = writer.compress_async();
+        let progress = std::thread::spawn(move || {
+            let mut progress = pin!(progress);
+            while let Some(update) = block_on(std::future::poll_fn(|cx| futures_core::Stream::poll_next(progress.as_mut(), cx))) {
+                assert!(update.percentage <= 1.0);
+            }
+        });
+        let result = block_on(compression).unwrap();
+        assert_eq!(result.output_file, archive_file);
+        assert!(archive_file.exists());
+        // The stream ends once compression has finished
+        progress.join().unwrap();
+    }
+
+    #[test]
+    fn test_fixed_clock() {
+        let dir = setup_testing_environment("fixed_clock").unwrap();
+        let compress = |name: &str| {
+            let archive_file = dir.join(name);
+            LZMATarballWriter::new()
+                .with_file(dir.join("hello.txt"), "/hello.txt")
+                .set_write_manifest(true)
+                .set_clock(FixedClock::from_unix(1_700_000_000))
+                .set_output(&archive_file)
+                .compress(|_| {})
+                .unwrap();
+            archive_file
+        };
+        let first = compress("first.tar.xz");
+        std::thread::sleep(Duration::from_millis(1100));
+        let second = compress("second.tar.xz");
+        assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&first).unwrap();
+        assert!(reader.entries_metadata().unwrap().iter().all(|entry| entry.mtime == 1_700_000_000));
+        assert_eq!(reader.read_manifest().unwrap().unwrap().created_at, "2023-11-14T22:13:20+00:00");
+    }
+
+    #[test]
+    fn test_output_queue() {
+        let dir = setup_testing_environment("output_queue").unwrap();
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let random: Vec<u8> = (0..128 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        fs::write(dir.join("random.bin"), random).unwrap();
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+        let received = chunks.clone();
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let reported = updates.clone();
+        // A slow consumer keeps the single slot queue full, so compression has to wait on it
+        let result = LZMATarballWriter::new()
+            .set_compression_level(0)
+            .set_buffer_size(4)
+            .set_output_queue_depth(Some(1))
+            .with_file(dir.join("random.bin"), "/random.bin")
+            .compress_chunks(
+                move |chunk| {
+                    let mut chunks = received.lock().unwrap();
+                    if chunks.len() < 5 {
+                        std::thread::sleep(Duration::from_millis(300));
+                    }
+                    chunks.push(chunk.to_vec());
+                    Ok(())
+                },
+                move |update| reported.lock().unwrap().push(update),
+            )
+            .unwrap();
+        let updates = updates.lock().unwrap();
+        assert!(updates.iter().any(|update| update.waiting_on_output));
+        assert!(updates.iter().all(|update| update.bytes_written <= result.size));
+        assert_eq!(result.size, chunks.lock().unwrap().concat().len() as u64);
+
+        let error = LZMATarballWriter::new()
+            .set_output_queue_depth(Some(1))
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .compress_chunks(|_| anyhow::bail!("upload failed"), |_| {})
+            .unwrap_err();
+        assert_eq!(error.to_string(), "upload failed");
+    }
+
+    #[test]
+    fn test_entry_statistics() {
+        let dir = setup_testing_environment("statistics").unwrap();
+        fs::write(dir.join("large.txt"), "a".repeat(39)).unwrap();
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .with_file(dir.join("large.txt"), "/large.txt")
+            .set_output(dir.join("statistics.tar.xz"));
+        assert_eq!(writer.input_size(), 52);
+        let result = writer.compress(|_| {}).unwrap();
+
+        assert_eq!(result.entries.len(), 2);
+        assert_eq!(result.entries[1].path, "large.txt");
+        assert_eq!(result.entries[1].size, 39);
+        assert_eq!(result.entries[1].order, 1);
+        assert_eq!(result.entries[0].share, 0.25);
+        assert_eq!(result.entries[1].share, 0.75);
+    }
+
+    #[test]
+    fn test_auto_buffer_size() {
+        assert_eq!(BufferSize::from(64).resolve(0), 64 * 1024);
+        assert_eq!(BufferSize::Auto.resolve(0), 16 * 1024);
+        let large = BufferSize::Auto.resolve(10 * 1024 * 1024 * 1024);
+        assert!(large > 16 * 1024 && large <= 8 * 1024 * 1024);
+
+        let dir = setup_testing_environment("auto_buffer").unwrap();
+        let result = LZMATarballWriter::new()
+            .set_buffer_size(BufferSize::Auto)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(dir.join("auto.tar.xz"))
+            .compress(|_| {})
+            .unwrap();
+        assert!(result.size > 0);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_preserve_xattrs() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = setup_testing_environment("preserve_xattrs").unwrap();
+        let source = CString::new(dir.join("hello.txt").as_os_str().as_bytes()).unwrap();
+        let name = CString::new("user.lzma_tarball").unwrap();
+        let value = b"binary\0\xff";
+        if unsafe { libc::setxattr(source.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) } != 0 {
+            // The filesystem doesn't support user extended attributes
+            return;
+        }
+        let archive_file = dir.join("xattrs.tar.xz");
+        LZMATarballWriter::new()
+            .set_preserve_xattrs(true)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+
+        let output = dir.join("output");
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap();
+        reader.set_unpack_xattrs(true);
+        reader.decompress().unwrap();
+        let restored = CString::new(output.join("hello.txt").as_os_str().as_bytes()).unwrap();
+        let mut buffer = [0u8; 64];
+        let size = unsafe { libc::getxattr(restored.as_ptr(), name.as_ptr(), buffer.as_mut_ptr().cast(), buffer.len()) };
+        assert_eq!(size, value.len() as isize);
+        assert_eq!(&buffer[..value.len()], value);
+    }
+
+    #[test]
+    fn test_skip_hidden() {
+        let dir = setup_testing_environment("skip_hidden").unwrap();
+        let input = dir.join("input");
+        fs::create_dir_all(input.join(".git")).unwrap();
+        fs::write(input.join(".git/config"), b"config").unwrap();
+        fs::write(input.join(".env"), b"secret").unwrap();
+        fs::write(input.join("visible.txt"), b"visible").unwrap();
+
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .set_skip_hidden(true)
+            .with_directory_contents(&input, "/input");
+        let paths: Vec<&str> = writer
+            .archive_paths
+            .iter()
+            .map(|entry| entry.archive_path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["/input/visible.txt"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_skipped_entries() {
+        let dir = setup_testing_environment("skipped_entries").unwrap();
+        let input = dir.join("input");
+        fs::create_dir_all(input.join(".cache")).unwrap();
+        fs::write(input.join(".cache/blob"), b"blob").unwrap();
+        fs::write(input.join("keep.txt"), b"keep").unwrap();
+        fs::write(input.join("drop.log"), b"drop").unwrap();
+        std::os::unix::fs::symlink("keep.txt", input.join("link.txt")).unwrap();
+
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .set_skip_hidden(true)
+            .with_filtered_directory_contents(&input, "/input", &|entry| entry.path().extension().is_none_or(|extension| extension != "log"))
+            .set_output(dir.join("skipped.tar.xz"));
+        let mut result = writer.compress(|_| {}).unwrap();
+        assert_eq!(result.entries.len(), 1);
+        result.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+        let skipped = |name: &str, reason: SkipReason| SkippedEntry { path: input.join(name).display().to_string(), reason };
+        assert_eq!(
+            result.skipped,
+            [skipped(".cache", SkipReason::Hidden), skipped("drop.log", SkipReason::Filtered), skipped("link.txt", SkipReason::NotAFile)]
+        );
+    }
+
+    #[test]
+    fn test_job_resume() {
+        let dir = setup_testing_environment("job_resume").unwrap();
+        fs::write(dir.join("second.txt"), b"Second").unwrap();
+        fs::write(dir.join("third.txt"), b"Third").unwrap();
+        let archive_file = dir.join("job.tar.xz");
+        let job_file = dir.join("job.json");
+
+        let mut writer = LZMATarballWriter::new();
+        writer
+            .set_compression_level(3)
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .with_file(dir.join("second.txt"), "/second.txt")
+            .set_output(&archive_file)
+            .set_timeout(Duration::from_secs(600));
+        writer.set_seekable_block_size(Some(1024));
+        assert!(writer.save_job(&job_file).is_err());
+        writer.set_seekable_block_size(None);
+        writer.save_job(&job_file).unwrap();
+        let mut job = WriterJob::load(&job_file).unwrap();
+        assert_eq!(job.options.compression_level, 3);
+        assert_eq!(job.options.timeout, Some(Duration::from_secs(600)));
+        job.checkpoint_entries = 1;
+        job.save(&job_file).unwrap();
+
+        let loaded = LZMATarballWriter::load_job(&job_file).unwrap();
+        let result = loaded.compress(|_| {}).unwrap();
+        let orders: Vec<usize> = result.entries.iter().map(|entry| entry.order).collect();
+        assert_eq!(orders, [0, 1]);
+        assert!((result.entries.iter().map(|entry| entry.share).sum::<f32>() - 1.0).abs() < 0.001);
+        let mut job = WriterJob::load(&job_file).unwrap();
+        let size = fs::metadata(&archive_file).unwrap().len();
+        assert_eq!(job.progress, JobProgress { completed_entries: 2, output_size: size });
+        // The loaded writer knows the job is complete
+        assert!(loaded.compress(|_| {}).unwrap().entries.is_empty());
+        assert_eq!(fs::metadata(&archive_file).unwrap().len(), size);
+
+        // Simulate a process that died partway through a third entry
+        let mut partial = fs::read(&archive_file).unwrap();
+        partial.extend_from_slice(b"partial stream");
+        fs::write(&archive_file, partial).unwrap();
+        let mut writer = LZMATarballWriter::new();
+        writer.with_file(dir.join("third.txt"), "/third.txt");
+        job.spec.entries.extend(writer.archive_paths.clone());
+        job.save(&job_file).unwrap();
+
+        let result = LZMATarballWriter::load_job(&job_file).unwrap().compress(|_| {}).unwrap();
+        assert_eq!(result.entries.len(), 1);
+        assert_eq!(WriterJob::load(&job_file).unwrap().progress.completed_entries, 3);
+        let mut reader = LZMATarballReader::new();
+        reader.set_archive(&archive_file).unwrap();
+        let mut names = reader.entries().unwrap();
+        names.sort();
+        assert_eq!(names, ["hello.txt", "second.txt", "third.txt"]);
+    }
+
+    #[test]
+    fn test_append_stream() {
+        let dir = setup_testing_environment("append_stream").unwrap();
+        fs::write(dir.join("second.txt"), b"Second").unwrap();
+        let archive_file = dir.join("append.tar.xz");
+        LZMATarballWriter::new()
+            .with_file(dir.join("hello.txt"), "/hello.txt")
+            .set_output(&archive_file)
+            .compress(|_| {})
+            .unwrap();
+        let original = fs::read(&archive_file).unwrap();
+
+        LZMATarballWriter::new()
+            .with_file(dir.join("second.txt"), "/second.txt")
+            .append_stream(&archive_file, |_| {})
+            .unwrap();
+        let appended = fs::read(&archive_file).unwrap();
+        assert_eq!(&appended[..original.len()], original.as_slice());
+
+        let decoder = xz2::read::XzDecoder::new_multi_decoder(appended.as_slice());
+        let mut archive = tar::Archive::new(decoder);
+        archive.set_ignore_zeros(true);
+        let names: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["hello.txt", "second.txt"]);
+    }
+
+    #[test]
+    fn test_temp_strategy() {
+        let dir = setup_testing_environment("temp_strategy").unwrap();
+        let staging = dir.join("staging");
+        fs::create_dir_all(&staging).unwrap();
+        let strategies = [
+            (TempStrategy::Memory { max_bytes: 1024 * 1024 }, false),
+            (TempStrategy::Memory { max_bytes: 1 }, true),
+            (TempStrategy::Disk(staging.clone()), true),
+            (TempStrategy::None, false),
+        ];
+        for (index, (strategy, staged_on_disk)) in strategies.into_iter().enumerate() {
+            let archive_file = dir.join(format!("temp_strategy_{}.tar.xz", index));
+            let mut writer = LZMATarballWriter::new();
+            writer
+                .set_tar_file(staging.join(format!("staged_{}.tar", index)))
+                .set_temp_strategy(strategy.clone())
+                .with_file(dir.join("hello.txt"), "/hello.txt")
+                .set_output(&archive_file);
+            assert_eq!(writer.tar_file.parent(), Some(staging.as_path()));
+            let tar_file = writer.tar_file.clone();
+            let existed = Arc::new(AtomicBool::new(false));
+            let existed_during = existed.clone();
+            writer.set_file_progress(move |_| existed_during.store(tar_file.exists(), Ordering::Relaxed));
+            let result = writer.compress(|_| {}).unwrap();
+
+            assert_eq!(existed.load(Ordering::Relaxed), staged_on_disk, "{:?}", strategy);
+            assert!(!writer.tar_file.exists(), "{:?}", strategy);
+            assert!(result.original_size >= 13, "{:?}", strategy);
+            let output = dir.join(format!("temp_strategy_output_{}", index));
+            let mut reader = LZMATarballReader::new();
+            reader.set_archive(&archive_file).unwrap().set_output_directory(&output).unwrap().decompress().unwrap();
+            assert_eq!(fs::read_to_string(output.join("hello.txt")).unwrap(), "Hello, world!");
+        }
+        assert_eq!(fs::read_dir(&staging).unwrap().count(), 0);
+    }
+
+    fn setup_testing_environment(name: &str) -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("lzma_tarball-writer-{}", name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("hello.txt"), b"Hello, world!")?;
+        Ok(dir)
+    }
+}